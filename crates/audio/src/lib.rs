@@ -0,0 +1,147 @@
+//! Ada Remote Audio
+//!
+//! System audio capture and playback, and Opus coding for the wire. The host
+//! captures what its desktop plays; the client plays it back.
+
+use ada_remote_core::{ProtocolMessage, Result};
+
+mod opus;
+#[cfg(target_os = "linux")]
+mod pulse;
+
+pub use opus::{frame_samples, OpusDecoder, OpusEncoder, DEFAULT_BITRATE, FRAME_DURATION_MS};
+
+/// Sample rate used end to end; Opus runs natively at 48 kHz
+pub const SAMPLE_RATE: u32 = 48_000;
+
+/// Channel count used end to end
+pub const CHANNELS: u16 = 2;
+
+/// Interleaved signed 16-bit PCM
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioBuffer {
+    pub samples: Vec<i16>,
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// Timestamp in microseconds
+    pub timestamp: u64,
+}
+
+/// Loopback capture of the system's audio output
+pub trait AudioCapture: Send {
+    /// Start capturing
+    fn start(&mut self) -> Result<()>;
+
+    /// Next buffer of one packet's length (see [`FRAME_DURATION_MS`]), or
+    /// `None` if none is ready yet. Never blocks.
+    fn read(&mut self) -> Result<Option<AudioBuffer>>;
+
+    /// Stop capturing and release the device
+    fn stop(&mut self) -> Result<()>;
+}
+
+/// Playback on the system's audio output
+pub trait AudioPlayback: Send {
+    /// Open the output
+    fn start(&mut self) -> Result<()>;
+
+    /// Queue a buffer for playback
+    fn play(&mut self, buffer: &AudioBuffer) -> Result<()>;
+
+    /// Drain queued audio and close the output
+    fn stop(&mut self) -> Result<()>;
+}
+
+/// Create the platform's audio capturer
+pub fn create_audio_capture() -> Result<Box<dyn AudioCapture>> {
+    #[cfg(target_os = "linux")]
+    {
+        Ok(Box::new(pulse::PulseCapture::new()))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        Err(ada_remote_core::Error::Session(
+            "Audio capture is not supported on this platform".to_string(),
+        ))
+    }
+}
+
+/// Create the platform's audio output
+pub fn create_audio_playback() -> Result<Box<dyn AudioPlayback>> {
+    #[cfg(target_os = "linux")]
+    {
+        Ok(Box::new(pulse::PulsePlayback::new()))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        Err(ada_remote_core::Error::Session(
+            "Audio playback is not supported on this platform".to_string(),
+        ))
+    }
+}
+
+/// Host side: encodes captured buffers into `AudioFrame` messages
+pub struct AudioSender {
+    encoder: OpusEncoder,
+}
+
+impl AudioSender {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            encoder: OpusEncoder::new(SAMPLE_RATE, CHANNELS, DEFAULT_BITRATE)?,
+        })
+    }
+
+    /// Encode one captured buffer
+    pub fn encode(&mut self, buffer: &AudioBuffer) -> Result<ProtocolMessage> {
+        Ok(ProtocolMessage::AudioFrame {
+            timestamp: buffer.timestamp,
+            sample_rate: buffer.sample_rate,
+            channels: buffer.channels,
+            data: self.encoder.encode(&buffer.samples)?,
+        })
+    }
+}
+
+/// Client side: decodes `AudioFrame` messages and plays them
+pub struct AudioReceiver {
+    decoder: OpusDecoder,
+    playback: Box<dyn AudioPlayback>,
+}
+
+impl AudioReceiver {
+    /// Wrap a started playback device
+    pub fn new(playback: Box<dyn AudioPlayback>) -> Result<Self> {
+        Ok(Self {
+            decoder: OpusDecoder::new(SAMPLE_RATE, CHANNELS)?,
+            playback,
+        })
+    }
+
+    /// Play `message` if it is an audio frame, returning whether it was one
+    pub fn handle(&mut self, message: &ProtocolMessage) -> Result<bool> {
+        let ProtocolMessage::AudioFrame {
+            timestamp, data, ..
+        } = message
+        else {
+            return Ok(false);
+        };
+
+        // Opus decodes to any output format, whatever the source was
+        let buffer = AudioBuffer {
+            samples: self.decoder.decode(data)?,
+            sample_rate: SAMPLE_RATE,
+            channels: CHANNELS,
+            timestamp: *timestamp,
+        };
+        self.playback.play(&buffer)?;
+        Ok(true)
+    }
+
+    /// Close the playback device
+    pub fn stop(&mut self) -> Result<()> {
+        self.playback.stop()
+    }
+}
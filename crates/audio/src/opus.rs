@@ -0,0 +1,232 @@
+//! Opus encoding and decoding
+//!
+//! Thin safe wrappers around libopus. Audio travels as 20 ms packets, one
+//! per [`AudioFrame`](ada_remote_core::ProtocolMessage::AudioFrame).
+
+use ada_remote_core::{Error, Result};
+
+/// Duration of one encoded packet
+pub const FRAME_DURATION_MS: u32 = 20;
+
+/// Default encoder bitrate, enough for transparent stereo desktop audio
+pub const DEFAULT_BITRATE: i32 = 96_000;
+
+/// Longest packet libopus can decode (120 ms), in samples per channel at 48 kHz
+const MAX_FRAME_SAMPLES: usize = 5760;
+
+/// Upper bound for one encoded packet, as recommended by libopus
+const MAX_PACKET_SIZE: usize = 4000;
+
+/// Samples per channel in one packet at `sample_rate`
+pub fn frame_samples(sample_rate: u32) -> usize {
+    (sample_rate * FRAME_DURATION_MS / 1000) as usize
+}
+
+fn check(code: i32, what: &str) -> Result<i32> {
+    if code < 0 {
+        Err(Error::Encoding(format!(
+            "Opus {} failed: error {}",
+            what, code
+        )))
+    } else {
+        Ok(code)
+    }
+}
+
+fn check_format(sample_rate: u32, channels: u16) -> Result<()> {
+    if !matches!(sample_rate, 8000 | 12000 | 16000 | 24000 | 48000) || !matches!(channels, 1 | 2) {
+        return Err(Error::Encoding(format!(
+            "Unsupported Opus format: {} Hz, {} channels",
+            sample_rate, channels
+        )));
+    }
+    Ok(())
+}
+
+/// Opus encoder for interleaved 16-bit PCM
+pub struct OpusEncoder {
+    state: *mut unsafe_libopus::OpusEncoder,
+    sample_rate: u32,
+    channels: u16,
+}
+
+// The encoder state is plain heap memory owned by this value
+unsafe impl Send for OpusEncoder {}
+
+impl OpusEncoder {
+    /// Create an encoder tuned for general audio
+    pub fn new(sample_rate: u32, channels: u16, bitrate: i32) -> Result<Self> {
+        check_format(sample_rate, channels)?;
+        let mut error = 0;
+        let state = unsafe {
+            unsafe_libopus::opus_encoder_create(
+                sample_rate as i32,
+                channels as i32,
+                unsafe_libopus::OPUS_APPLICATION_AUDIO,
+                &mut error,
+            )
+        };
+        check(error, "encoder creation")?;
+        let encoder = Self {
+            state,
+            sample_rate,
+            channels,
+        };
+        check(
+            unsafe {
+                unsafe_libopus::opus_encoder_ctl!(
+                    state,
+                    unsafe_libopus::OPUS_SET_BITRATE_REQUEST,
+                    bitrate
+                )
+            },
+            "bitrate change",
+        )?;
+        Ok(encoder)
+    }
+
+    /// Interleaved samples expected by [`encode`](Self::encode)
+    pub fn frame_len(&self) -> usize {
+        frame_samples(self.sample_rate) * self.channels as usize
+    }
+
+    /// Encode exactly one packet's worth of interleaved samples
+    pub fn encode(&mut self, pcm: &[i16]) -> Result<Vec<u8>> {
+        if pcm.len() != self.frame_len() {
+            return Err(Error::Encoding(format!(
+                "Expected {} samples per packet, got {}",
+                self.frame_len(),
+                pcm.len()
+            )));
+        }
+
+        let mut packet = vec![0u8; MAX_PACKET_SIZE];
+        let len = check(
+            unsafe {
+                unsafe_libopus::opus_encode(
+                    self.state,
+                    pcm.as_ptr(),
+                    frame_samples(self.sample_rate) as i32,
+                    packet.as_mut_ptr(),
+                    packet.len() as i32,
+                )
+            },
+            "encode",
+        )?;
+        packet.truncate(len as usize);
+        Ok(packet)
+    }
+}
+
+impl Drop for OpusEncoder {
+    fn drop(&mut self) {
+        unsafe { unsafe_libopus::opus_encoder_destroy(self.state) }
+    }
+}
+
+/// Opus decoder producing interleaved 16-bit PCM
+pub struct OpusDecoder {
+    state: *mut unsafe_libopus::OpusDecoder,
+    channels: u16,
+}
+
+// The decoder state is plain heap memory owned by this value
+unsafe impl Send for OpusDecoder {}
+
+impl OpusDecoder {
+    /// Create a decoder for the given output format
+    pub fn new(sample_rate: u32, channels: u16) -> Result<Self> {
+        check_format(sample_rate, channels)?;
+        let mut error = 0;
+        let state = unsafe {
+            unsafe_libopus::opus_decoder_create(sample_rate as i32, channels as i32, &mut error)
+        };
+        check(error, "decoder creation").map_err(|e| Error::Decoding(e.to_string()))?;
+        Ok(Self { state, channels })
+    }
+
+    /// Decode one packet into interleaved samples
+    pub fn decode(&mut self, packet: &[u8]) -> Result<Vec<i16>> {
+        let mut pcm = vec![0i16; MAX_FRAME_SAMPLES * self.channels as usize];
+        let samples = unsafe {
+            unsafe_libopus::opus_decode(
+                self.state,
+                packet.as_ptr(),
+                packet.len() as i32,
+                pcm.as_mut_ptr(),
+                MAX_FRAME_SAMPLES as i32,
+                0,
+            )
+        };
+        if samples < 0 {
+            return Err(Error::Decoding(format!(
+                "Opus decode failed: error {}",
+                samples
+            )));
+        }
+        pcm.truncate(samples as usize * self.channels as usize);
+        Ok(pcm)
+    }
+}
+
+impl Drop for OpusDecoder {
+    fn drop(&mut self) {
+        unsafe { unsafe_libopus::opus_decoder_destroy(self.state) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sine_wave_round_trip() {
+        let (rate, channels) = (48_000, 2);
+        let mut encoder = OpusEncoder::new(rate, channels, DEFAULT_BITRATE).unwrap();
+        let mut decoder = OpusDecoder::new(rate, channels).unwrap();
+        let frame = frame_samples(rate);
+
+        // 440 Hz at half scale on both channels, 10 packets long
+        let sine: Vec<i16> = (0..frame * 10)
+            .flat_map(|i| {
+                let t = i as f64 / rate as f64;
+                let s = ((2.0 * std::f64::consts::PI * 440.0 * t).sin() * 16384.0) as i16;
+                [s, s]
+            })
+            .collect();
+
+        let mut decoded = Vec::new();
+        for chunk in sine.chunks(encoder.frame_len()) {
+            let packet = encoder.encode(chunk).unwrap();
+            assert!(packet.len() < chunk.len() * 2 / 4);
+            decoded.extend(decoder.decode(&packet).unwrap());
+        }
+        assert_eq!(decoded.len(), sine.len());
+
+        // Compare energy after the codec delay, on the last packets
+        let lookahead = 312 * channels as usize;
+        let tail = sine.len() - 4 * encoder.frame_len();
+        let rms = |samples: &[i16]| {
+            (samples.iter().map(|&s| (s as f64).powi(2)).sum::<f64>() / samples.len() as f64).sqrt()
+        };
+        let original = &sine[tail - lookahead..sine.len() - lookahead];
+        let error: Vec<i16> = original
+            .iter()
+            .zip(&decoded[tail..])
+            .map(|(&a, &b)| a.saturating_sub(b))
+            .collect();
+        assert!(
+            rms(&error) < rms(original) * 0.1,
+            "error rms {} vs signal rms {}",
+            rms(&error),
+            rms(original)
+        );
+    }
+
+    #[test]
+    fn test_rejects_wrong_frame_size() {
+        let mut encoder = OpusEncoder::new(48_000, 1, DEFAULT_BITRATE).unwrap();
+        assert!(encoder.encode(&[0; 100]).is_err());
+        assert!(OpusEncoder::new(44_100, 2, DEFAULT_BITRATE).is_err());
+    }
+}
@@ -0,0 +1,168 @@
+//! PulseAudio backend
+//!
+//! Streams raw PCM through the `parec` and `pacat` tools, which PulseAudio
+//! and PipeWire (via pipewire-pulse) both ship, so no audio library has to
+//! be linked. Capture records the monitor of the default sink, i.e. what the
+//! desktop is playing.
+
+use crate::{AudioBuffer, AudioCapture, AudioPlayback, CHANNELS, SAMPLE_RATE};
+use ada_remote_core::{Error, Result};
+use std::io::{Read, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc;
+use std::time::Instant;
+
+fn format_args() -> [String; 4] {
+    [
+        "--raw".to_string(),
+        "--format=s16le".to_string(),
+        format!("--rate={}", SAMPLE_RATE),
+        format!("--channels={}", CHANNELS),
+    ]
+}
+
+fn spawn(command: &mut Command, name: &str) -> Result<Child> {
+    command
+        .spawn()
+        .map_err(|e| Error::Session(format!("Failed to start {}: {}", name, e)))
+}
+
+/// Loopback capture of the default output
+pub struct PulseCapture {
+    child: Option<Child>,
+    frames: Option<mpsc::Receiver<AudioBuffer>>,
+}
+
+impl PulseCapture {
+    pub fn new() -> Self {
+        Self {
+            child: None,
+            frames: None,
+        }
+    }
+}
+
+impl AudioCapture for PulseCapture {
+    fn start(&mut self) -> Result<()> {
+        let mut child = spawn(
+            Command::new("parec")
+                .arg("--device=@DEFAULT_MONITOR@")
+                .args(format_args())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null()),
+            "parec",
+        )?;
+        let mut stdout = child.stdout.take().expect("stdout is piped");
+        let (tx, rx) = mpsc::channel();
+
+        // Blocking pipe reads stay off the caller's thread
+        std::thread::spawn(move || {
+            let samples = crate::opus::frame_samples(SAMPLE_RATE) * CHANNELS as usize;
+            let mut bytes = vec![0u8; samples * 2];
+            let started = Instant::now();
+            while stdout.read_exact(&mut bytes).is_ok() {
+                let buffer = AudioBuffer {
+                    samples: bytes
+                        .chunks_exact(2)
+                        .map(|b| i16::from_le_bytes([b[0], b[1]]))
+                        .collect(),
+                    sample_rate: SAMPLE_RATE,
+                    channels: CHANNELS,
+                    timestamp: started.elapsed().as_micros() as u64,
+                };
+                if tx.send(buffer).is_err() {
+                    break;
+                }
+            }
+        });
+
+        self.child = Some(child);
+        self.frames = Some(rx);
+        tracing::info!("Audio capture started");
+        Ok(())
+    }
+
+    fn read(&mut self) -> Result<Option<AudioBuffer>> {
+        let frames = self
+            .frames
+            .as_ref()
+            .ok_or_else(|| Error::Session("Audio capture not started".to_string()))?;
+        match frames.try_recv() {
+            Ok(buffer) => Ok(Some(buffer)),
+            Err(mpsc::TryRecvError::Empty) => Ok(None),
+            Err(mpsc::TryRecvError::Disconnected) => Err(Error::TargetClosed(
+                "Audio capture stream ended".to_string(),
+            )),
+        }
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        self.frames = None;
+        if let Some(mut child) = self.child.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+        Ok(())
+    }
+}
+
+/// Playback on the default output
+pub struct PulsePlayback {
+    child: Option<Child>,
+    stdin: Option<ChildStdin>,
+}
+
+impl PulsePlayback {
+    pub fn new() -> Self {
+        Self {
+            child: None,
+            stdin: None,
+        }
+    }
+}
+
+impl AudioPlayback for PulsePlayback {
+    fn start(&mut self) -> Result<()> {
+        let mut child = spawn(
+            Command::new("pacat")
+                .arg("--playback")
+                .args(format_args())
+                .stdin(Stdio::piped())
+                .stderr(Stdio::null()),
+            "pacat",
+        )?;
+        self.stdin = child.stdin.take();
+        self.child = Some(child);
+        tracing::info!("Audio playback started");
+        Ok(())
+    }
+
+    fn play(&mut self, buffer: &AudioBuffer) -> Result<()> {
+        if (buffer.sample_rate, buffer.channels) != (SAMPLE_RATE, CHANNELS) {
+            return Err(Error::Decoding(format!(
+                "Unsupported playback format: {} Hz, {} channels",
+                buffer.sample_rate, buffer.channels
+            )));
+        }
+        let stdin = self
+            .stdin
+            .as_mut()
+            .ok_or_else(|| Error::Session("Audio playback not started".to_string()))?;
+        let bytes: Vec<u8> = buffer
+            .samples
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect();
+        stdin.write_all(&bytes)?;
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        // Closing stdin lets pacat drain and exit
+        self.stdin = None;
+        if let Some(mut child) = self.child.take() {
+            let _ = child.wait();
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,308 @@
+//! Cursor shape for client-side rendering
+//!
+//! When the cursor is not baked into captured frames, the host sends its
+//! image as a `CursorShape` message and the client draws it at the local
+//! pointer position, which hides the round trip.
+
+use ada_remote_core::{ProtocolMessage, Result};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// Current pointer image
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CursorImage {
+    pub width: u32,
+    pub height: u32,
+    pub hotspot_x: u32,
+    pub hotspot_y: u32,
+    /// Straight (not premultiplied) RGBA, row-major
+    pub rgba: Vec<u8>,
+}
+
+impl CursorImage {
+    /// Image for a hidden cursor
+    pub fn hidden() -> Self {
+        Self {
+            width: 0,
+            height: 0,
+            hotspot_x: 0,
+            hotspot_y: 0,
+            rgba: Vec::new(),
+        }
+    }
+
+    /// Hash of the shape, used to skip resending an unchanged bitmap
+    pub fn shape_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    pub fn into_message(self) -> ProtocolMessage {
+        ProtocolMessage::CursorShape {
+            width: self.width,
+            height: self.height,
+            hotspot_x: self.hotspot_x,
+            hotspot_y: self.hotspot_y,
+            rgba: self.rgba,
+        }
+    }
+}
+
+/// Reads the current cursor image from the platform
+/// (XFixesGetCursorImage on X11, GetCursorInfo/GetIconInfo on Windows)
+pub struct CursorSource {
+    #[cfg(target_os = "linux")]
+    connection: crate::linux::Connection,
+}
+
+// SAFETY: the X connection is only used through &mut self
+#[cfg(target_os = "linux")]
+unsafe impl Send for CursorSource {}
+
+impl CursorSource {
+    pub fn new() -> Result<Self> {
+        #[cfg(target_os = "linux")]
+        {
+            Ok(Self {
+                connection: crate::linux::Connection::open()?,
+            })
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            Ok(Self {})
+        }
+    }
+
+    /// Fetch the cursor image as it is now
+    pub fn current(&mut self) -> Result<CursorImage> {
+        #[cfg(target_os = "linux")]
+        {
+            self.connection.cursor_image()
+        }
+
+        #[cfg(target_os = "windows")]
+        {
+            windows::cursor_image()
+        }
+
+        #[cfg(not(any(target_os = "linux", target_os = "windows")))]
+        {
+            // TODO: NSCursor.currentSystemCursor on macOS
+            Err(ada_remote_core::Error::Session(
+                "Cursor capture is not supported on this platform".to_string(),
+            ))
+        }
+    }
+}
+
+/// Remembers the last sent shape so only changes go out
+#[derive(Debug, Default)]
+pub struct CursorTracker {
+    last_hash: Option<u64>,
+}
+
+impl CursorTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The message to send for `image`, or `None` if the client already has it
+    pub fn update(&mut self, image: CursorImage) -> Option<ProtocolMessage> {
+        let hash = image.shape_hash();
+        if self.last_hash == Some(hash) {
+            return None;
+        }
+        self.last_hash = Some(hash);
+        Some(image.into_message())
+    }
+
+    /// Forget the last shape, e.g. after the client reconnects
+    pub fn reset(&mut self) {
+        self.last_hash = None;
+    }
+}
+
+/// Convert premultiplied alpha to straight alpha in place
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+pub(crate) fn unpremultiply(rgba: &mut [u8]) {
+    for pixel in rgba.chunks_exact_mut(4) {
+        let alpha = pixel[3] as u32;
+        if alpha != 0 && alpha != 255 {
+            for channel in &mut pixel[..3] {
+                *channel = (*channel as u32 * 255 / alpha).min(255) as u8;
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod windows {
+    use super::{unpremultiply, CursorImage};
+    use ada_remote_core::{Error, Result};
+    use std::mem;
+    use windows::Win32::Foundation::HWND;
+    use windows::Win32::Graphics::Gdi::{
+        DeleteObject, GetDC, GetDIBits, GetObjectW, ReleaseDC, BITMAP, BITMAPINFO,
+        BITMAPINFOHEADER, BI_RGB, DIB_RGB_COLORS, HBITMAP,
+    };
+    use windows::Win32::UI::WindowsAndMessaging::{
+        GetCursorInfo, GetIconInfo, CURSORINFO, CURSOR_SHOWING, HICON, ICONINFO,
+    };
+
+    pub fn cursor_image() -> Result<CursorImage> {
+        let mut info = CURSORINFO {
+            cbSize: mem::size_of::<CURSORINFO>() as u32,
+            ..Default::default()
+        };
+        unsafe { GetCursorInfo(&mut info) }
+            .map_err(|e| Error::Session(format!("GetCursorInfo failed: {}", e)))?;
+        if info.flags.0 & CURSOR_SHOWING.0 == 0 || info.hCursor.is_invalid() {
+            return Ok(CursorImage::hidden());
+        }
+
+        let mut icon = ICONINFO::default();
+        unsafe { GetIconInfo(HICON(info.hCursor.0), &mut icon) }
+            .map_err(|e| Error::Session(format!("GetIconInfo failed: {}", e)))?;
+
+        let image = if icon.hbmColor.is_invalid() {
+            monochrome_image(icon.hbmMask)
+        } else {
+            color_image(icon.hbmColor)
+        };
+        unsafe {
+            DeleteObject(icon.hbmMask);
+            if !icon.hbmColor.is_invalid() {
+                DeleteObject(icon.hbmColor);
+            }
+        }
+
+        let (width, height, rgba) = image?;
+        Ok(CursorImage {
+            width,
+            height,
+            hotspot_x: icon.xHotspot,
+            hotspot_y: icon.yHotspot,
+            rgba,
+        })
+    }
+
+    /// 32-bit top-down BGRA pixels of a bitmap
+    fn bitmap_pixels(bitmap: HBITMAP) -> Result<(u32, u32, Vec<u8>)> {
+        let mut header = BITMAP::default();
+        let size = mem::size_of::<BITMAP>() as i32;
+        if unsafe { GetObjectW(bitmap, size, Some(&mut header as *mut _ as *mut _)) } == 0 {
+            return Err(Error::Session(
+                "GetObjectW failed on cursor bitmap".to_string(),
+            ));
+        }
+        let (width, height) = (header.bmWidth.max(0) as u32, header.bmHeight.max(0) as u32);
+
+        let mut info = BITMAPINFO {
+            bmiHeader: BITMAPINFOHEADER {
+                biSize: mem::size_of::<BITMAPINFOHEADER>() as u32,
+                biWidth: width as i32,
+                biHeight: -(height as i32),
+                biPlanes: 1,
+                biBitCount: 32,
+                biCompression: BI_RGB.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut pixels = vec![0u8; (width * height * 4) as usize];
+        let lines = unsafe {
+            let dc = GetDC(HWND::default());
+            let lines = GetDIBits(
+                dc,
+                bitmap,
+                0,
+                height,
+                Some(pixels.as_mut_ptr().cast()),
+                &mut info,
+                DIB_RGB_COLORS,
+            );
+            ReleaseDC(HWND::default(), dc);
+            lines
+        };
+        if lines == 0 {
+            return Err(Error::Session(
+                "GetDIBits failed on cursor bitmap".to_string(),
+            ));
+        }
+        Ok((width, height, pixels))
+    }
+
+    fn color_image(bitmap: HBITMAP) -> Result<(u32, u32, Vec<u8>)> {
+        let (width, height, mut pixels) = bitmap_pixels(bitmap)?;
+        // Cursors without an alpha channel report zero alpha everywhere
+        let has_alpha = pixels.chunks_exact(4).any(|p| p[3] != 0);
+        for pixel in pixels.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+            if !has_alpha {
+                pixel[3] = 255;
+            }
+        }
+        unpremultiply(&mut pixels);
+        Ok((width, height, pixels))
+    }
+
+    /// Monochrome cursors stack an AND mask over an XOR mask in one bitmap
+    fn monochrome_image(mask: HBITMAP) -> Result<(u32, u32, Vec<u8>)> {
+        let (width, double_height, pixels) = bitmap_pixels(mask)?;
+        let height = double_height / 2;
+        let (and_mask, xor_mask) = pixels.split_at(pixels.len() / 2);
+        let rgba = and_mask
+            .chunks_exact(4)
+            .zip(xor_mask.chunks_exact(4))
+            .flat_map(|(and, xor)| match (and[0] != 0, xor[0] != 0) {
+                (true, false) => [0, 0, 0, 0],
+                (false, true) => [255, 255, 255, 255],
+                // Inverted pixels have no RGBA equivalent; draw them black
+                _ => [0, 0, 0, 255],
+            })
+            .collect();
+        Ok((width, height, rgba))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arrow(hotspot_x: u32) -> CursorImage {
+        CursorImage {
+            width: 2,
+            height: 1,
+            hotspot_x,
+            hotspot_y: 0,
+            rgba: vec![255; 8],
+        }
+    }
+
+    #[test]
+    fn test_tracker_sends_only_changes() {
+        let mut tracker = CursorTracker::new();
+        assert!(tracker.update(arrow(0)).is_some());
+        assert!(tracker.update(arrow(0)).is_none());
+        assert!(tracker.update(arrow(1)).is_some());
+
+        tracker.reset();
+        assert!(tracker.update(arrow(1)).is_some());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_x11_cursor_image() {
+        // Only meaningful with an X server, e.g. under xvfb-run in CI
+        if std::env::var_os("DISPLAY").is_none() {
+            return;
+        }
+
+        let image = CursorSource::new().unwrap().current().unwrap();
+        assert!(image.width > 0 && image.height > 0);
+        assert_eq!(image.rgba.len(), (image.width * image.height * 4) as usize);
+        assert!(image.hotspot_x < image.width && image.hotspot_y < image.height);
+    }
+}
@@ -0,0 +1,101 @@
+//! Damage tracking by tile comparison
+//!
+//! Used where the platform offers no damage events of its own. Frames are
+//! compared in fixed-size tiles against the previous capture, and runs of
+//! changed tiles on each tile row are reported as one rectangle.
+
+use crate::Rect;
+
+/// Edge length of the comparison tiles in pixels
+const TILE_SIZE: u32 = 64;
+
+/// Tracks the previous frame to find changed regions
+pub(crate) struct DamageTracker {
+    previous: Vec<u8>,
+    width: u32,
+    height: u32,
+}
+
+impl DamageTracker {
+    pub(crate) fn new() -> Self {
+        Self {
+            previous: Vec::new(),
+            width: 0,
+            height: 0,
+        }
+    }
+
+    /// Compare an RGBA frame with the previous one and return the changed
+    /// regions. The first frame, or one at a new size, is entirely dirty.
+    pub(crate) fn update(&mut self, rgba: &[u8], width: u32, height: u32) -> Vec<Rect> {
+        let rects = if (width, height) != (self.width, self.height) || self.previous.is_empty() {
+            vec![Rect::full_frame(width, height)]
+        } else {
+            self.changed_tiles(rgba)
+        };
+
+        self.previous.clear();
+        self.previous.extend_from_slice(rgba);
+        self.width = width;
+        self.height = height;
+        rects
+    }
+
+    fn changed_tiles(&self, rgba: &[u8]) -> Vec<Rect> {
+        let stride = self.width as usize * 4;
+        let tile_changed = |x: u32, y: u32| {
+            let columns = x as usize * 4..(x + TILE_SIZE).min(self.width) as usize * 4;
+            (y..(y + TILE_SIZE).min(self.height)).any(|row| {
+                let offset = row as usize * stride;
+                let range = offset + columns.start..offset + columns.end;
+                rgba[range.clone()] != self.previous[range]
+            })
+        };
+
+        let mut rects = Vec::new();
+        for y in (0..self.height).step_by(TILE_SIZE as usize) {
+            let height = TILE_SIZE.min(self.height - y);
+            let mut run_start = None;
+            for x in (0..self.width).step_by(TILE_SIZE as usize) {
+                match (tile_changed(x, y), run_start) {
+                    (true, None) => run_start = Some(x),
+                    (false, Some(start)) => {
+                        rects.push(Rect::new(start, y, x - start, height));
+                        run_start = None;
+                    }
+                    _ => {}
+                }
+            }
+            if let Some(start) = run_start {
+                rects.push(Rect::new(start, y, self.width - start, height));
+            }
+        }
+        rects
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reports_changed_tiles() {
+        let (width, height) = (200, 100);
+        let mut frame = vec![0u8; (width * height * 4) as usize];
+        let mut tracker = DamageTracker::new();
+
+        assert_eq!(
+            tracker.update(&frame, width, height),
+            vec![Rect::full_frame(width, height)]
+        );
+        assert!(tracker.update(&frame, width, height).is_empty());
+
+        // One pixel in the last, partial tile of the second tile row
+        let offset = ((70 * width + 199) * 4) as usize;
+        frame[offset] = 255;
+        assert_eq!(
+            tracker.update(&frame, width, height),
+            vec![Rect::new(192, 64, 8, 36)]
+        );
+    }
+}
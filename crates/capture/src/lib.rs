@@ -2,10 +2,53 @@
 //!
 //! Cross-platform screen capture implementation.
 //! - Windows: DXGI Desktop Duplication API
-//! - macOS: ScreenCaptureKit / CGDisplayStream
-//! - Linux: X11 (with future PipeWire support for Wayland)
+//! - macOS: CoreGraphics, an image of the display per frame
+//! - Linux: X11, or PipeWire via xdg-desktop-portal on Wayland (`pipewire`
+//!   feature)
 
-use ada_remote_core::Result;
+use ada_remote_core::{FramePool, Result};
+
+mod cursor;
+// DXGI reports dirty rects natively
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod damage;
+mod span;
+mod stream;
+mod synthetic;
+#[cfg(all(target_os = "linux", feature = "pipewire"))]
+mod wayland;
+#[cfg(target_os = "linux")]
+mod xdamage;
+
+pub use cursor::{CursorImage, CursorSource, CursorTracker};
+pub use span::{MonitorRegion, SpanLayout};
+pub use stream::{start_stream, CaptureHandle};
+pub use synthetic::SyntheticCapturer;
+
+/// Rectangle in frame pixel coordinates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// Rectangle covering a whole frame
+    pub fn full_frame(width: u32, height: u32) -> Self {
+        Self::new(0, 0, width, height)
+    }
+}
 
 /// Represents a captured frame
 #[derive(Debug, Clone)]
@@ -18,25 +61,65 @@ pub struct CapturedFrame {
     pub height: u32,
     /// Timestamp in microseconds
     pub timestamp: u64,
+    /// Regions changed since the previous frame, `None` unless
+    /// [`CaptureConfig::track_damage`] is set. `data` always holds the full frame.
+    pub dirty_rects: Option<Vec<Rect>>,
+}
+
+impl CapturedFrame {
+    /// Mark the whole frame dirty, for backends without damage tracking
+    pub fn with_full_damage(mut self) -> Self {
+        self.dirty_rects = Some(vec![Rect::full_frame(self.width, self.height)]);
+        self
+    }
+}
+
+/// Platform window handle: XID on X11, HWND on Windows, CGWindowID on macOS
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct WindowId(pub u64);
+
+/// What to capture
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureTarget {
+    /// Monitor by index (0 = primary)
+    Monitor(usize),
+    /// Every monitor, composited into one frame laid out as in
+    /// [`ScreenCapture::span_layout`]
+    AllMonitors,
+    /// A single application window
+    Window(WindowId),
+}
+
+impl Default for CaptureTarget {
+    fn default() -> Self {
+        CaptureTarget::Monitor(0)
+    }
 }
 
 /// Screen capture configuration
 #[derive(Debug, Clone)]
 pub struct CaptureConfig {
-    /// Target monitor index (0 = primary)
-    pub monitor_index: usize,
+    /// Monitor or window to capture
+    pub target: CaptureTarget,
     /// Capture frame rate
     pub fps: u32,
     /// Whether to capture cursor
     pub capture_cursor: bool,
+    /// Report changed regions in [`CapturedFrame::dirty_rects`]
+    pub track_damage: bool,
+    /// Where backends take frame buffers from; recycle
+    /// [`CapturedFrame::data`] into it once the frame is consumed
+    pub frame_pool: FramePool,
 }
 
 impl Default for CaptureConfig {
     fn default() -> Self {
         Self {
-            monitor_index: 0,
+            target: CaptureTarget::default(),
             fps: 30,
             capture_cursor: true,
+            track_damage: false,
+            frame_pool: FramePool::default(),
         }
     }
 }
@@ -52,6 +135,20 @@ pub trait ScreenCapture: Send + Sync {
     /// Get list of available monitors
     fn list_monitors(&self) -> Result<Vec<MonitorInfo>>;
 
+    /// Layout of the frames when capturing
+    /// [`AllMonitors`](CaptureTarget::AllMonitors), for mapping positions in
+    /// them back to monitors
+    fn span_layout(&self) -> Option<SpanLayout> {
+        None
+    }
+
+    /// Get list of capturable top-level windows
+    fn list_windows(&self) -> Result<Vec<WindowInfo>> {
+        Err(ada_remote_core::Error::Session(
+            "Window capture is not supported on this platform".to_string(),
+        ))
+    }
+
     /// Clean up resources
     fn cleanup(&mut self) -> Result<()>;
 }
@@ -61,15 +158,63 @@ pub trait ScreenCapture: Send + Sync {
 pub struct MonitorInfo {
     pub index: usize,
     pub name: String,
+    /// Origin within the virtual desktop, in pixels
+    pub x: i32,
+    pub y: i32,
     pub width: u32,
     pub height: u32,
     pub is_primary: bool,
+    /// Ratio of physical pixels to logical points (2.0 on Retina/HiDPI)
+    pub scale_factor: f64,
 }
 
-/// Create a platform-specific screen capture implementation
+/// Information about a top-level window
+#[derive(Debug, Clone)]
+pub struct WindowInfo {
+    pub id: WindowId,
+    pub title: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Which capture backend to create
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CapturerKind {
+    /// The screen, through the platform's capture API
+    #[default]
+    Platform,
+    /// [`SyntheticCapturer`] test pattern of the given size, for machines
+    /// without a display
+    Synthetic { width: u32, height: u32 },
+}
+
+/// Create a platform-specific screen capture implementation.
+///
+/// Setting `ADA_REMOTE_CAPTURE=synthetic` selects a 1280x720
+/// [`SyntheticCapturer`] instead, e.g. for CI.
 pub fn create_capturer() -> Result<Box<dyn ScreenCapture>> {
+    if std::env::var_os("ADA_REMOTE_CAPTURE").is_some_and(|kind| kind == "synthetic") {
+        return Ok(Box::new(SyntheticCapturer::default()));
+    }
+    create_capturer_with(CapturerKind::Platform)
+}
+
+/// Create a capture backend of `kind`
+pub fn create_capturer_with(kind: CapturerKind) -> Result<Box<dyn ScreenCapture>> {
+    if let CapturerKind::Synthetic { width, height } = kind {
+        return Ok(Box::new(SyntheticCapturer::new(width, height)?));
+    }
+
     #[cfg(target_os = "linux")]
     {
+        #[cfg(feature = "pipewire")]
+        if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            match wayland::PipeWireCapturer::new() {
+                Ok(capturer) => return Ok(Box::new(capturer)),
+                // XWayland only sees X clients, but beats failing outright
+                Err(e) => tracing::warn!("{}, falling back to X11", e),
+            }
+        }
         Ok(Box::new(linux::X11Capturer::new()?))
     }
 
@@ -94,44 +239,717 @@ pub fn create_capturer() -> Result<Box<dyn ScreenCapture>> {
 #[cfg(target_os = "linux")]
 mod linux {
     use super::*;
+    use crate::cursor::unpremultiply;
+    use crate::damage::DamageTracker;
+    use crate::xdamage::ServerDamage;
+    use std::ffi::{CStr, CString};
+    use std::os::raw::c_int;
+    use std::ptr;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Instant;
+    use x11::{xfixes, xlib, xrandr, xshm};
+
+    /// Set by the trap handler when a request fails
+    static X_ERROR: AtomicBool = AtomicBool::new(false);
+
+    unsafe extern "C" fn trap_error(
+        _display: *mut xlib::Display,
+        _event: *mut xlib::XErrorEvent,
+    ) -> c_int {
+        X_ERROR.store(true, Ordering::SeqCst);
+        0
+    }
+
+    /// Run Xlib requests with errors trapped instead of the default handler,
+    /// which exits the process. Returns whether any request failed.
+    fn trap_errors<T>(display: *mut xlib::Display, f: impl FnOnce() -> T) -> (T, bool) {
+        unsafe {
+            X_ERROR.store(false, Ordering::SeqCst);
+            let previous = xlib::XSetErrorHandler(Some(trap_error));
+            let result = f();
+            xlib::XSync(display, xlib::False);
+            xlib::XSetErrorHandler(previous);
+            (result, X_ERROR.load(Ordering::SeqCst))
+        }
+    }
+
+    /// Owned connection to the X server
+    pub(crate) struct Connection(*mut xlib::Display);
+
+    impl Connection {
+        pub(crate) fn open() -> Result<Self> {
+            let display = unsafe { xlib::XOpenDisplay(ptr::null()) };
+            if display.is_null() {
+                let name = std::env::var("DISPLAY").unwrap_or_else(|_| "(unset)".to_string());
+                return Err(ada_remote_core::Error::Session(format!(
+                    "Failed to open X display DISPLAY={}",
+                    name
+                )));
+            }
+            Ok(Self(display))
+        }
+
+        fn root(&self) -> xlib::Window {
+            unsafe { xlib::XDefaultRootWindow(self.0) }
+        }
+
+        /// Monitor geometry from XRandR, primary first. Falls back to the
+        /// whole root window when RandR reports nothing.
+        fn monitors(&self) -> Vec<Monitor> {
+            let mut count = 0;
+            let infos =
+                unsafe { xrandr::XRRGetMonitors(self.0, self.root(), xlib::True, &mut count) };
+
+            let mut monitors = Vec::new();
+            if !infos.is_null() {
+                let infos = unsafe { std::slice::from_raw_parts(infos, count.max(0) as usize) };
+                for info in infos {
+                    let name = unsafe {
+                        let atom = xlib::XGetAtomName(self.0, info.name);
+                        if atom.is_null() {
+                            "Display".to_string()
+                        } else {
+                            let name = CStr::from_ptr(atom).to_string_lossy().into_owned();
+                            xlib::XFree(atom.cast());
+                            name
+                        }
+                    };
+                    monitors.push(Monitor {
+                        name,
+                        x: info.x,
+                        y: info.y,
+                        width: info.width.max(0) as u32,
+                        height: info.height.max(0) as u32,
+                        is_primary: info.primary != 0,
+                    });
+                }
+                unsafe { xrandr::XRRFreeMonitors(infos.as_ptr() as *mut _) };
+            }
+
+            if monitors.is_empty() {
+                let screen = unsafe { xlib::XDefaultScreen(self.0) };
+                monitors.push(Monitor {
+                    name: "Screen".to_string(),
+                    x: 0,
+                    y: 0,
+                    width: unsafe { xlib::XDisplayWidth(self.0, screen) } as u32,
+                    height: unsafe { xlib::XDisplayHeight(self.0, screen) } as u32,
+                    is_primary: true,
+                });
+            }
+
+            // Index 0 is the primary monitor
+            monitors.sort_by_key(|m| !m.is_primary);
+            monitors
+        }
+
+        fn atom(&self, name: &str) -> xlib::Atom {
+            let name = CString::new(name).expect("atom names have no NUL bytes");
+            unsafe { xlib::XInternAtom(self.0, name.as_ptr(), xlib::False) }
+        }
+
+        /// Read a window property as a list of items of the given format
+        fn read_property<T: Copy>(
+            &self,
+            window: xlib::Window,
+            property: xlib::Atom,
+            kind: xlib::Atom,
+        ) -> Option<Vec<T>> {
+            let mut actual_type = 0;
+            let mut actual_format = 0;
+            let mut count = 0;
+            let mut remaining = 0;
+            let mut data = ptr::null_mut();
+            let status = unsafe {
+                xlib::XGetWindowProperty(
+                    self.0,
+                    window,
+                    property,
+                    0,
+                    i64::MAX / 4,
+                    xlib::False,
+                    kind,
+                    &mut actual_type,
+                    &mut actual_format,
+                    &mut count,
+                    &mut remaining,
+                    &mut data,
+                )
+            };
+            if status != xlib::Success as c_int || data.is_null() {
+                return None;
+            }
+            // 32-bit items come back as longs, regardless of the C type
+            let items = (actual_type == kind).then(|| unsafe {
+                std::slice::from_raw_parts(data as *const T, count as usize).to_vec()
+            });
+            unsafe { xlib::XFree(data.cast()) };
+            items
+        }
+
+        /// Top-level windows, preferring the window manager's client list
+        fn windows(&self) -> Vec<xlib::Window> {
+            let client_list = self.atom("_NET_CLIENT_LIST");
+            if let Some(windows) =
+                self.read_property::<xlib::Window>(self.root(), client_list, xlib::XA_WINDOW)
+            {
+                return windows;
+            }
+
+            let (mut root, mut parent) = (0, 0);
+            let mut children = ptr::null_mut();
+            let mut count = 0;
+            let status = unsafe {
+                xlib::XQueryTree(
+                    self.0,
+                    self.root(),
+                    &mut root,
+                    &mut parent,
+                    &mut children,
+                    &mut count,
+                )
+            };
+            if status == 0 || children.is_null() {
+                return Vec::new();
+            }
+            let windows = unsafe { std::slice::from_raw_parts(children, count as usize) }
+                .iter()
+                .copied()
+                .filter(|&window| {
+                    self.attributes(window)
+                        .is_some_and(|attrs| attrs.map_state == xlib::IsViewable)
+                })
+                .collect();
+            unsafe { xlib::XFree(children.cast()) };
+            windows
+        }
+
+        fn attributes(&self, window: xlib::Window) -> Option<xlib::XWindowAttributes> {
+            let mut attrs = unsafe { std::mem::zeroed::<xlib::XWindowAttributes>() };
+            let status = unsafe { xlib::XGetWindowAttributes(self.0, window, &mut attrs) };
+            (status != 0).then_some(attrs)
+        }
+
+        /// Window title from _NET_WM_NAME, falling back to WM_NAME
+        fn window_title(&self, window: xlib::Window) -> Option<String> {
+            let name = self.atom("_NET_WM_NAME");
+            let utf8 = self.atom("UTF8_STRING");
+            if let Some(bytes) = self.read_property::<u8>(window, name, utf8) {
+                return Some(String::from_utf8_lossy(&bytes).into_owned());
+            }
+
+            let mut title = ptr::null_mut();
+            unsafe {
+                if xlib::XFetchName(self.0, window, &mut title) == 0 || title.is_null() {
+                    return None;
+                }
+                let name = CStr::from_ptr(title).to_string_lossy().into_owned();
+                xlib::XFree(title.cast());
+                Some(name)
+            }
+        }
+
+        /// Current cursor image via XFixes
+        pub(crate) fn cursor_image(&self) -> Result<CursorImage> {
+            let (mut event_base, mut error_base) = (0, 0);
+            if unsafe { xfixes::XFixesQueryExtension(self.0, &mut event_base, &mut error_base) }
+                == 0
+            {
+                return Err(ada_remote_core::Error::Session(
+                    "XFixes extension unavailable".to_string(),
+                ));
+            }
+
+            let image = unsafe { xfixes::XFixesGetCursorImage(self.0) };
+            if image.is_null() {
+                return Err(ada_remote_core::Error::Session(
+                    "XFixesGetCursorImage failed".to_string(),
+                ));
+            }
+
+            let cursor = unsafe {
+                let info = &*image;
+                let len = info.width as usize * info.height as usize;
+                // Premultiplied ARGB, one pixel per c_ulong whatever its width
+                let mut rgba: Vec<u8> = std::slice::from_raw_parts(info.pixels, len)
+                    .iter()
+                    .flat_map(|&pixel| {
+                        let [b, g, r, a] = (pixel as u32).to_le_bytes();
+                        [r, g, b, a]
+                    })
+                    .collect();
+                unpremultiply(&mut rgba);
+                CursorImage {
+                    width: info.width as u32,
+                    height: info.height as u32,
+                    hotspot_x: info.xhot as u32,
+                    hotspot_y: info.yhot as u32,
+                    rgba,
+                }
+            };
+            unsafe { xlib::XFree(image.cast()) };
+            Ok(cursor)
+        }
+    }
+
+    impl Drop for Connection {
+        fn drop(&mut self) {
+            unsafe { xlib::XCloseDisplay(self.0) };
+        }
+    }
+
+    /// Geometry of an X monitor within the root window
+    #[derive(Debug, Clone)]
+    struct Monitor {
+        name: String,
+        x: i32,
+        y: i32,
+        width: u32,
+        height: u32,
+        is_primary: bool,
+    }
+
+    /// XImage backed by a shared memory segment attached to the server
+    struct ShmImage {
+        image: *mut xlib::XImage,
+        segment: Box<xshm::XShmSegmentInfo>,
+    }
+
+    impl ShmImage {
+        fn create(connection: &Connection, width: u32, height: u32) -> Option<Self> {
+            let display = connection.0;
+            unsafe {
+                if xshm::XShmQueryExtension(display) == 0 {
+                    return None;
+                }
+
+                let screen = xlib::XDefaultScreen(display);
+                let mut segment = Box::new(xshm::XShmSegmentInfo {
+                    shmseg: 0,
+                    shmid: -1,
+                    shmaddr: ptr::null_mut(),
+                    readOnly: xlib::False,
+                });
+                let image = xshm::XShmCreateImage(
+                    display,
+                    xlib::XDefaultVisual(display, screen),
+                    xlib::XDefaultDepth(display, screen) as u32,
+                    xlib::ZPixmap,
+                    ptr::null_mut(),
+                    &mut *segment,
+                    width,
+                    height,
+                );
+                if image.is_null() {
+                    return None;
+                }
+
+                let size = (*image).bytes_per_line as usize * (*image).height as usize;
+                segment.shmid = libc::shmget(libc::IPC_PRIVATE, size, libc::IPC_CREAT | 0o600);
+                if segment.shmid < 0 {
+                    xlib::XDestroyImage(image);
+                    return None;
+                }
+                let address = libc::shmat(segment.shmid, ptr::null(), 0);
+                // Removed once both sides detach
+                libc::shmctl(segment.shmid, libc::IPC_RMID, ptr::null_mut());
+                if address as isize == -1 {
+                    xlib::XDestroyImage(image);
+                    return None;
+                }
+                segment.shmaddr = address.cast();
+                (*image).data = address.cast();
+
+                // Fails on remote displays that can't see our memory
+                let (attached, failed) =
+                    trap_errors(display, || xshm::XShmAttach(display, &mut *segment));
+                if attached == 0 || failed {
+                    (*image).data = ptr::null_mut();
+                    xlib::XDestroyImage(image);
+                    libc::shmdt(address);
+                    return None;
+                }
+
+                Some(Self { image, segment })
+            }
+        }
+
+        fn destroy(mut self, connection: &Connection) {
+            unsafe {
+                xshm::XShmDetach(connection.0, &mut *self.segment);
+                xlib::XSync(connection.0, xlib::False);
+                (*self.image).data = ptr::null_mut();
+                xlib::XDestroyImage(self.image);
+                libc::shmdt(self.segment.shmaddr.cast());
+            }
+        }
+    }
+
+    /// Convert a 32 bpp XImage to tightly packed RGBA using its channel masks,
+    /// into a buffer from `pool`
+    fn image_to_rgba(image: &xlib::XImage, pool: &FramePool) -> Result<Vec<u8>> {
+        if image.bits_per_pixel != 32 {
+            return Err(ada_remote_core::Error::Session(format!(
+                "Unsupported X image format: {} bits per pixel",
+                image.bits_per_pixel
+            )));
+        }
+
+        let width = image.width as usize;
+        let height = image.height as usize;
+        let stride = image.bytes_per_line as usize;
+        let shifts = [image.red_mask, image.green_mask, image.blue_mask]
+            .map(|mask| mask.trailing_zeros().min(31));
+        let big_endian = image.byte_order == xlib::MSBFirst;
+        let bytes = unsafe { std::slice::from_raw_parts(image.data as *const u8, stride * height) };
+
+        let mut data = pool.take(width * height * 4);
+        for row in bytes.chunks_exact(stride) {
+            for pixel in row[..width * 4].chunks_exact(4) {
+                let pixel = [pixel[0], pixel[1], pixel[2], pixel[3]];
+                let value = if big_endian {
+                    u32::from_be_bytes(pixel)
+                } else {
+                    u32::from_le_bytes(pixel)
+                };
+                let [r, g, b] = shifts.map(|shift| (value >> shift) as u8);
+                data.extend_from_slice(&[r, g, b, 255]);
+            }
+        }
+        Ok(data)
+    }
+
+    /// What an initialized capturer reads from
+    enum Source {
+        Monitor(Monitor),
+        /// Every monitor, in layout order
+        Span(Vec<Monitor>, SpanLayout),
+        Window(xlib::Window),
+    }
+
+    fn monitor_infos(monitors: &[Monitor]) -> Vec<MonitorInfo> {
+        monitors
+            .iter()
+            .enumerate()
+            .map(|(index, monitor)| MonitorInfo {
+                index,
+                name: monitor.name.clone(),
+                x: monitor.x,
+                y: monitor.y,
+                width: monitor.width,
+                height: monitor.height,
+                is_primary: monitor.is_primary,
+                // X11 has no per-monitor scaling
+                scale_factor: 1.0,
+            })
+            .collect()
+    }
+
+    /// How changed regions are found
+    enum Damage {
+        /// Reported by the server through XDamage
+        Server(Box<ServerDamage>),
+        /// Frames compared, on servers without XDamage
+        Compared(DamageTracker),
+    }
 
     pub struct X11Capturer {
         config: Option<CaptureConfig>,
+        connection: Option<Connection>,
+        source: Option<Source>,
+        shm: Option<ShmImage>,
+        damage: Option<Damage>,
+        started: Instant,
     }
 
+    // SAFETY: the display connection and shared image are only used through
+    // &mut self; list_monitors and list_windows open their own connection
+    unsafe impl Send for X11Capturer {}
+    unsafe impl Sync for X11Capturer {}
+
     impl X11Capturer {
         pub fn new() -> Result<Self> {
-            Ok(Self { config: None })
+            Ok(Self {
+                config: None,
+                connection: None,
+                source: None,
+                shm: None,
+                damage: None,
+                started: Instant::now(),
+            })
+        }
+
+        /// Grab a rectangle of a drawable with XGetImage
+        fn get_image(
+            connection: &Connection,
+            drawable: xlib::Drawable,
+            (x, y): (i32, i32),
+            (width, height): (u32, u32),
+            pool: &FramePool,
+        ) -> Result<Vec<u8>> {
+            let display = connection.0;
+            let (image, failed) = trap_errors(display, || unsafe {
+                xlib::XGetImage(
+                    display,
+                    drawable,
+                    x,
+                    y,
+                    width,
+                    height,
+                    xlib::XAllPlanes(),
+                    xlib::ZPixmap,
+                )
+            });
+            if image.is_null() || failed {
+                return Err(ada_remote_core::Error::Session(
+                    "XGetImage failed".to_string(),
+                ));
+            }
+
+            let data = image_to_rgba(unsafe { &*image }, pool);
+            unsafe { xlib::XDestroyImage(image) };
+            data
+        }
+
+        fn capture_monitor(
+            &self,
+            connection: &Connection,
+            monitor: &Monitor,
+            pool: &FramePool,
+        ) -> Result<Vec<u8>> {
+            let shm_data = self.shm.as_ref().and_then(|shm| {
+                let display = connection.0;
+                let (ok, failed) = trap_errors(display, || unsafe {
+                    xshm::XShmGetImage(
+                        display,
+                        connection.root(),
+                        shm.image,
+                        monitor.x,
+                        monitor.y,
+                        // AllPlanes; the binding narrows the mask to c_uint
+                        !0,
+                    )
+                });
+                (ok != 0 && !failed).then(|| image_to_rgba(unsafe { &*shm.image }, pool))
+            });
+            match shm_data {
+                Some(data) => data,
+                None => Self::get_image(
+                    connection,
+                    connection.root(),
+                    (monitor.x, monitor.y),
+                    (monitor.width, monitor.height),
+                    pool,
+                ),
+            }
+        }
+
+        /// Grab a window at its current size. Windows resize freely, so this
+        /// skips the fixed-size shared memory image.
+        fn capture_window(
+            connection: &Connection,
+            window: xlib::Window,
+            pool: &FramePool,
+        ) -> Result<(Vec<u8>, u32, u32)> {
+            let (attrs, failed) = trap_errors(connection.0, || connection.attributes(window));
+            let attrs = match attrs {
+                Some(attrs) if !failed => attrs,
+                _ => {
+                    return Err(ada_remote_core::Error::TargetClosed(format!(
+                        "Window {:#x} no longer exists",
+                        window
+                    )))
+                }
+            };
+            if attrs.map_state != xlib::IsViewable {
+                return Err(ada_remote_core::Error::TargetClosed(format!(
+                    "Window {:#x} is no longer viewable",
+                    window
+                )));
+            }
+
+            let width = attrs.width.max(1) as u32;
+            let height = attrs.height.max(1) as u32;
+            let data = Self::get_image(connection, window, (0, 0), (width, height), pool)?;
+            Ok((data, width, height))
         }
     }
 
     impl ScreenCapture for X11Capturer {
         fn init(&mut self, config: CaptureConfig) -> Result<()> {
+            self.cleanup()?;
+
+            let connection = Connection::open()?;
+            let source = match config.target {
+                CaptureTarget::Monitor(index) => {
+                    let monitor =
+                        connection
+                            .monitors()
+                            .into_iter()
+                            .nth(index)
+                            .ok_or_else(|| {
+                                ada_remote_core::Error::Session(format!(
+                                    "Monitor {} not found",
+                                    index
+                                ))
+                            })?;
+
+                    self.shm = ShmImage::create(&connection, monitor.width, monitor.height);
+                    if self.shm.is_none() {
+                        tracing::warn!("MIT-SHM unavailable, falling back to XGetImage");
+                    }
+                    tracing::info!(
+                        "X11 screen capture initialized ({} {}x{}+{}+{})",
+                        monitor.name,
+                        monitor.width,
+                        monitor.height,
+                        monitor.x,
+                        monitor.y
+                    );
+                    Source::Monitor(monitor)
+                }
+                CaptureTarget::AllMonitors => {
+                    // Monitors differ in size, so there is no shared image
+                    let monitors = connection.monitors();
+                    let layout = SpanLayout::new(&monitor_infos(&monitors))?;
+                    tracing::info!(
+                        "X11 screen capture initialized ({} monitors, {}x{}{:+}{:+})",
+                        monitors.len(),
+                        layout.width,
+                        layout.height,
+                        layout.origin.0,
+                        layout.origin.1
+                    );
+                    Source::Span(monitors, layout)
+                }
+                CaptureTarget::Window(WindowId(window)) => {
+                    Self::capture_window(&connection, window, &config.frame_pool)?;
+                    tracing::info!("X11 window capture initialized ({:#x})", window);
+                    Source::Window(window)
+                }
+            };
+
+            // TODO: Composite the cursor via XFixes when capture_cursor is set
+            self.damage = config.track_damage.then(|| {
+                let drawable = match &source {
+                    Source::Window(window) => *window,
+                    _ => connection.root(),
+                };
+                match ServerDamage::watch(drawable as u32) {
+                    Ok(damage) => Damage::Server(Box::new(damage)),
+                    Err(e) => {
+                        tracing::warn!("{}, comparing frames instead", e);
+                        Damage::Compared(DamageTracker::new())
+                    }
+                }
+            });
+            self.connection = Some(connection);
+            self.source = Some(source);
             self.config = Some(config);
-            tracing::info!("X11 screen capture initialized");
             Ok(())
         }
 
         fn capture_frame(&mut self) -> Result<CapturedFrame> {
-            // TODO: Implement X11 screen capture
-            // Use XGetImage to capture screen content
-            Err(ada_remote_core::Error::Session(
-                "X11 capture not yet implemented".to_string(),
-            ))
+            let (Some(config), Some(connection), Some(source)) =
+                (&self.config, &self.connection, &self.source)
+            else {
+                return Err(ada_remote_core::Error::Session(
+                    "Capturer not initialized".to_string(),
+                ));
+            };
+
+            if let Some(Damage::Server(damage)) = &mut self.damage {
+                damage.collect();
+            }
+
+            let pool = &config.frame_pool;
+            let (data, width, height) = match source {
+                Source::Monitor(monitor) => (
+                    self.capture_monitor(connection, monitor, pool)?,
+                    monitor.width,
+                    monitor.height,
+                ),
+                Source::Span(monitors, layout) => {
+                    let captures = monitors
+                        .iter()
+                        .map(|monitor| {
+                            Self::get_image(
+                                connection,
+                                connection.root(),
+                                (monitor.x, monitor.y),
+                                (monitor.width, monitor.height),
+                                pool,
+                            )
+                        })
+                        .collect::<Result<Vec<_>>>()?;
+                    let data = layout.compose(&captures, pool);
+                    for capture in captures {
+                        pool.recycle(capture);
+                    }
+                    (data, layout.width, layout.height)
+                }
+                Source::Window(window) => Self::capture_window(connection, *window, pool)?,
+            };
+
+            let origin = match source {
+                Source::Monitor(monitor) => (monitor.x, monitor.y),
+                Source::Span(_, layout) => layout.origin,
+                Source::Window(_) => (0, 0),
+            };
+            let dirty_rects = self.damage.as_mut().map(|damage| match damage {
+                Damage::Server(damage) => damage.update(origin, width, height),
+                Damage::Compared(damage) => damage.update(&data, width, height),
+            });
+
+            Ok(CapturedFrame {
+                data,
+                width,
+                height,
+                timestamp: self.started.elapsed().as_micros() as u64,
+                dirty_rects,
+            })
+        }
+
+        fn list_windows(&self) -> Result<Vec<WindowInfo>> {
+            let connection = Connection::open()?;
+            let (windows, _) = trap_errors(connection.0, || {
+                connection
+                    .windows()
+                    .into_iter()
+                    .filter_map(|window| {
+                        let title = connection.window_title(window)?;
+                        let attrs = connection.attributes(window)?;
+                        (!title.is_empty()).then(|| WindowInfo {
+                            id: WindowId(window),
+                            title,
+                            width: attrs.width.max(0) as u32,
+                            height: attrs.height.max(0) as u32,
+                        })
+                    })
+                    .collect()
+            });
+            Ok(windows)
         }
 
         fn list_monitors(&self) -> Result<Vec<MonitorInfo>> {
-            // TODO: Use XRandR to enumerate monitors
-            Ok(vec![MonitorInfo {
-                index: 0,
-                name: "Primary Display".to_string(),
-                width: 1920,
-                height: 1080,
-                is_primary: true,
-            }])
+            let connection = Connection::open()?;
+            Ok(monitor_infos(&connection.monitors()))
+        }
+
+        fn span_layout(&self) -> Option<SpanLayout> {
+            match &self.source {
+                Some(Source::Span(_, layout)) => Some(layout.clone()),
+                _ => None,
+            }
         }
 
         fn cleanup(&mut self) -> Result<()> {
+            if let (Some(shm), Some(connection)) = (self.shm.take(), &self.connection) {
+                shm.destroy(connection);
+            }
+            self.connection = None;
+            self.source = None;
+            self.damage = None;
             tracing::info!("X11 screen capture cleaned up");
             Ok(())
         }
@@ -160,7 +978,10 @@ mod windows {
         }
 
         fn capture_frame(&mut self) -> Result<CapturedFrame> {
-            // TODO: Implement DXGI Desktop Duplication API
+            // TODO: Implement DXGI Desktop Duplication API, reporting
+            // GetFrameDirtyRects as dirty_rects when track_damage is set.
+            // CaptureTarget::Window needs PrintWindow on the HWND instead,
+            // and AllMonitors a duplication per output composed by SpanLayout.
             Err(ada_remote_core::Error::Session(
                 "DXGI capture not yet implemented".to_string(),
             ))
@@ -171,12 +992,20 @@ mod windows {
             Ok(vec![MonitorInfo {
                 index: 0,
                 name: "Primary Display".to_string(),
+                x: 0,
+                y: 0,
                 width: 1920,
                 height: 1080,
                 is_primary: true,
+                scale_factor: 1.0,
             }])
         }
 
+        fn list_windows(&self) -> Result<Vec<WindowInfo>> {
+            // TODO: Enumerate top-level windows with EnumWindows
+            Ok(Vec::new())
+        }
+
         fn cleanup(&mut self) -> Result<()> {
             tracing::info!("DXGI screen capture cleaned up");
             Ok(())
@@ -187,43 +1016,207 @@ mod windows {
 #[cfg(target_os = "macos")]
 mod macos {
     use super::*;
+    use crate::damage::DamageTracker;
+    use core_graphics::display::{CGDirectDisplayID, CGDisplay};
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    // Screen-recording (TCC) permission, macOS 10.15+
+    #[link(name = "CoreGraphics", kind = "framework")]
+    extern "C" {
+        fn CGPreflightScreenCaptureAccess() -> bool;
+        fn CGRequestScreenCaptureAccess() -> bool;
+    }
 
     pub struct CoreGraphicsCapturer {
         config: Option<CaptureConfig>,
+        /// The captured display, or every display when spanning
+        displays: Vec<CGDisplay>,
+        layout: Option<SpanLayout>,
+        damage: Option<DamageTracker>,
     }
 
     impl CoreGraphicsCapturer {
         pub fn new() -> Result<Self> {
-            Ok(Self { config: None })
+            Ok(Self {
+                config: None,
+                displays: Vec::new(),
+                layout: None,
+                damage: None,
+            })
+        }
+
+        fn active_displays() -> Result<Vec<CGDirectDisplayID>> {
+            CGDisplay::active_displays().map_err(|e| {
+                ada_remote_core::Error::Session(format!("Failed to list displays: {}", e))
+            })
+        }
+
+        /// Grab a display as tightly packed RGBA, returning it with its size
+        fn grab(display: &CGDisplay, pool: &FramePool) -> Result<(Vec<u8>, u32, u32)> {
+            let image = display.image().ok_or_else(|| {
+                ada_remote_core::Error::Session("CGDisplayCreateImage failed".to_string())
+            })?;
+
+            if image.bits_per_pixel() != 32 {
+                return Err(ada_remote_core::Error::Session(format!(
+                    "Unsupported pixel format: {} bits per pixel",
+                    image.bits_per_pixel()
+                )));
+            }
+
+            let width = image.width();
+            let height = image.height();
+            let stride = image.bytes_per_row();
+            let bgra = image.data();
+            let bgra = bgra.bytes();
+
+            // Rows may be padded; convert BGRA to tightly packed RGBA
+            let mut data = pool.take(width * height * 4);
+            for row in bgra.chunks(stride).take(height) {
+                for pixel in row[..width * 4].chunks_exact(4) {
+                    data.extend_from_slice(&[pixel[2], pixel[1], pixel[0], pixel[3]]);
+                }
+            }
+            Ok((data, width as u32, height as u32))
         }
     }
 
     impl ScreenCapture for CoreGraphicsCapturer {
         fn init(&mut self, config: CaptureConfig) -> Result<()> {
+            // Prompts the user the first time; the grant only applies after
+            // the app is restarted
+            if !unsafe { CGPreflightScreenCaptureAccess() }
+                && !unsafe { CGRequestScreenCaptureAccess() }
+            {
+                return Err(ada_remote_core::Error::Session(
+                    "Screen recording permission denied. Grant access in System Settings \
+                     > Privacy & Security > Screen Recording"
+                        .to_string(),
+                ));
+            }
+
+            let displays = Self::active_displays()?;
+            match config.target {
+                CaptureTarget::Monitor(index) => {
+                    let id = displays.get(index).ok_or_else(|| {
+                        ada_remote_core::Error::Session(format!("Monitor {} not found", index))
+                    })?;
+                    self.displays = vec![CGDisplay::new(*id)];
+                    self.layout = None;
+                }
+                CaptureTarget::AllMonitors => {
+                    self.layout = Some(SpanLayout::new(&self.list_monitors()?)?);
+                    self.displays = displays.into_iter().map(CGDisplay::new).collect();
+                }
+                // TODO: Capture windows with CGWindowListCreateImage
+                CaptureTarget::Window(_) => {
+                    return Err(ada_remote_core::Error::Session(
+                        "Window capture is not supported on macOS yet".to_string(),
+                    ))
+                }
+            }
+
+            self.damage = config.track_damage.then(DamageTracker::new);
             self.config = Some(config);
             tracing::info!("CoreGraphics screen capture initialized");
             Ok(())
         }
 
         fn capture_frame(&mut self) -> Result<CapturedFrame> {
-            // TODO: Implement CGDisplayStream or ScreenCaptureKit
-            Err(ada_remote_core::Error::Session(
-                "macOS capture not yet implemented".to_string(),
-            ))
+            // TODO: Stream via ScreenCaptureKit (SCStream) on macOS 12.3+
+            // instead of grabbing a CGImage per frame
+            let (Some(display), Some(config)) = (self.displays.first(), &self.config) else {
+                return Err(ada_remote_core::Error::Session(
+                    "Capturer not initialized".to_string(),
+                ));
+            };
+            let pool = &config.frame_pool;
+
+            let (data, width, height) = match &self.layout {
+                Some(layout) => {
+                    let captures = self
+                        .displays
+                        .iter()
+                        .map(|display| Self::grab(display, pool).map(|(data, ..)| data))
+                        .collect::<Result<Vec<_>>>()?;
+                    let data = layout.compose(&captures, pool);
+                    for capture in captures {
+                        pool.recycle(capture);
+                    }
+                    (data, layout.width, layout.height)
+                }
+                None => Self::grab(display, pool)?,
+            };
+
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_micros() as u64)
+                .unwrap_or_default();
+
+            // Display images carry no damage, so frames are compared
+            let dirty_rects = self
+                .damage
+                .as_mut()
+                .map(|damage| damage.update(&data, width, height));
+
+            Ok(CapturedFrame {
+                data,
+                width,
+                height,
+                timestamp,
+                dirty_rects,
+            })
         }
 
         fn list_monitors(&self) -> Result<Vec<MonitorInfo>> {
-            // TODO: Enumerate displays using CoreGraphics
-            Ok(vec![MonitorInfo {
-                index: 0,
-                name: "Primary Display".to_string(),
-                width: 1920,
-                height: 1080,
-                is_primary: true,
-            }])
+            let displays = Self::active_displays()?;
+            Ok(displays
+                .into_iter()
+                .enumerate()
+                .map(|(index, id)| {
+                    let display = CGDisplay::new(id);
+                    let (width, height, scale_factor) = match display.display_mode() {
+                        Some(mode) if mode.width() > 0 => (
+                            mode.pixel_width() as u32,
+                            mode.pixel_height() as u32,
+                            mode.pixel_width() as f64 / mode.width() as f64,
+                        ),
+                        _ => (
+                            display.pixels_wide() as u32,
+                            display.pixels_high() as u32,
+                            1.0,
+                        ),
+                    };
+                    let kind = if display.is_builtin() {
+                        "Built-in Display"
+                    } else {
+                        "Display"
+                    };
+                    // Bounds are in points; mixed scales only line up
+                    // approximately in pixels
+                    let origin = display.bounds().origin;
+
+                    MonitorInfo {
+                        index,
+                        name: format!("{} {}", kind, id),
+                        x: (origin.x * scale_factor).round() as i32,
+                        y: (origin.y * scale_factor).round() as i32,
+                        width,
+                        height,
+                        is_primary: display.is_main(),
+                        scale_factor,
+                    }
+                })
+                .collect())
+        }
+
+        fn span_layout(&self) -> Option<SpanLayout> {
+            self.layout.clone()
         }
 
         fn cleanup(&mut self) -> Result<()> {
+            self.displays.clear();
+            self.layout = None;
             tracing::info!("CoreGraphics screen capture cleaned up");
             Ok(())
         }
@@ -237,8 +1230,78 @@ mod tests {
     #[test]
     fn test_capture_config_default() {
         let config = CaptureConfig::default();
-        assert_eq!(config.monitor_index, 0);
+        assert_eq!(config.target, CaptureTarget::Monitor(0));
         assert_eq!(config.fps, 30);
         assert!(config.capture_cursor);
+        assert!(!config.track_damage);
+    }
+
+    #[test]
+    fn test_full_damage_fallback() {
+        let frame = CapturedFrame {
+            data: vec![0; 4 * 3 * 4],
+            width: 4,
+            height: 3,
+            timestamp: 0,
+            dirty_rects: None,
+        };
+        assert_eq!(
+            frame.with_full_damage().dirty_rects,
+            Some(vec![Rect::new(0, 0, 4, 3)])
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_x11_lists_monitors() {
+        // Only meaningful with an X server, e.g. under xvfb-run in CI
+        if std::env::var_os("DISPLAY").is_none() {
+            return;
+        }
+
+        let capturer = create_capturer().unwrap();
+        let monitors = capturer.list_monitors().unwrap();
+        assert!(!monitors.is_empty());
+        assert!(monitors[0].is_primary);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_x11_window_capture() {
+        if std::env::var_os("DISPLAY").is_none() {
+            return;
+        }
+
+        let mut capturer = create_capturer().unwrap();
+        let windows = capturer.list_windows().unwrap();
+        assert!(windows.iter().all(|w| !w.title.is_empty()));
+
+        // An id that was never allocated behaves like a closed window
+        let result = capturer.init(CaptureConfig {
+            target: CaptureTarget::Window(WindowId(0x7fff_fff0)),
+            ..Default::default()
+        });
+        assert!(matches!(
+            result,
+            Err(ada_remote_core::Error::TargetClosed(_))
+        ));
+    }
+
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn test_macos_lists_builtin_display_scale() {
+        use core_graphics::display::CGDisplay;
+
+        let capturer = create_capturer().unwrap();
+        let monitors = capturer.list_monitors().unwrap();
+        assert!(monitors.iter().any(|m| m.is_primary));
+
+        // Built-in panels on every current Mac are Retina
+        for (monitor, id) in monitors.iter().zip(CGDisplay::active_displays().unwrap()) {
+            assert!(monitor.scale_factor >= 1.0);
+            if CGDisplay::new(id).is_builtin() {
+                assert!(monitor.scale_factor > 1.0);
+            }
+        }
     }
 }
@@ -0,0 +1,162 @@
+//! Capture spanning all monitors
+//!
+//! With [`CaptureTarget::AllMonitors`](crate::CaptureTarget::AllMonitors)
+//! every monitor is captured on its own and composited into one frame
+//! covering their bounding box, at their relative positions. Gaps between
+//! monitors are black.
+
+use crate::{MonitorInfo, Rect};
+use ada_remote_core::{FramePool, Result};
+
+/// Where a monitor sits in a spanned frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MonitorRegion {
+    /// Index as in [`ScreenCapture::list_monitors`](crate::ScreenCapture::list_monitors)
+    pub index: usize,
+    /// Area of the frame showing the monitor
+    pub rect: Rect,
+}
+
+/// Layout of the frames captured with `AllMonitors`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpanLayout {
+    pub width: u32,
+    pub height: u32,
+    /// Position of the frame's top-left corner within the virtual desktop;
+    /// negative when a monitor sits left of or above the primary
+    pub origin: (i32, i32),
+    pub monitors: Vec<MonitorRegion>,
+}
+
+impl SpanLayout {
+    /// Layout of the bounding box of `monitors`
+    pub fn new(monitors: &[MonitorInfo]) -> Result<Self> {
+        if monitors.is_empty() {
+            return Err(ada_remote_core::Error::Session(
+                "No monitors to capture".to_string(),
+            ));
+        }
+
+        let left = monitors.iter().map(|m| m.x as i64).min().unwrap_or(0);
+        let top = monitors.iter().map(|m| m.y as i64).min().unwrap_or(0);
+        let right = monitors.iter().map(|m| m.x as i64 + m.width as i64).max();
+        let bottom = monitors.iter().map(|m| m.y as i64 + m.height as i64).max();
+        let width = u32::try_from(right.unwrap_or(0) - left);
+        let height = u32::try_from(bottom.unwrap_or(0) - top);
+        let (Ok(width), Ok(height)) = (width, height) else {
+            return Err(ada_remote_core::Error::Session(
+                "Monitor layout is too large to capture".to_string(),
+            ));
+        };
+
+        Ok(Self {
+            width,
+            height,
+            origin: (left as i32, top as i32),
+            monitors: monitors
+                .iter()
+                .map(|m| MonitorRegion {
+                    index: m.index,
+                    rect: Rect::new(
+                        (m.x as i64 - left) as u32,
+                        (m.y as i64 - top) as u32,
+                        m.width,
+                        m.height,
+                    ),
+                })
+                .collect(),
+        })
+    }
+
+    /// Monitor showing frame position (`x`, `y`) and the position within
+    /// that monitor, or `None` in a gap
+    pub fn locate(&self, x: u32, y: u32) -> Option<(usize, u32, u32)> {
+        self.monitors.iter().find_map(|monitor| {
+            let rect = &monitor.rect;
+            let inside = (rect.x..rect.x + rect.width).contains(&x)
+                && (rect.y..rect.y + rect.height).contains(&y);
+            inside.then(|| (monitor.index, x - rect.x, y - rect.y))
+        })
+    }
+
+    /// Composite tightly packed RGBA captures, one per monitor in layout
+    /// order and at its size, into a frame taken from `pool`
+    pub fn compose(&self, captures: &[Vec<u8>], pool: &FramePool) -> Vec<u8> {
+        let frame_stride = self.width as usize * 4;
+        let mut frame = pool.take(frame_stride * self.height as usize);
+        frame.extend(
+            [0, 0, 0, 255]
+                .iter()
+                .cycle()
+                .take(frame_stride * self.height as usize),
+        );
+
+        for (monitor, capture) in self.monitors.iter().zip(captures) {
+            let rect = &monitor.rect;
+            let stride = rect.width as usize * 4;
+            for (row, pixels) in capture
+                .chunks_exact(stride)
+                .take(rect.height as usize)
+                .enumerate()
+            {
+                let start = (rect.y as usize + row) * frame_stride + rect.x as usize * 4;
+                frame[start..start + stride].copy_from_slice(pixels);
+            }
+        }
+        frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn monitor(index: usize, x: i32, y: i32, width: u32, height: u32) -> MonitorInfo {
+        MonitorInfo {
+            index,
+            name: format!("Monitor {}", index),
+            x,
+            y,
+            width,
+            height,
+            is_primary: index == 0,
+            scale_factor: 1.0,
+        }
+    }
+
+    #[test]
+    fn test_two_monitor_layout_composes() {
+        // A 4x2 primary with a taller 2x3 monitor to its left, one column
+        // apart and one row lower
+        let monitors = [monitor(0, 0, 0, 4, 2), monitor(1, -3, 1, 2, 3)];
+        let layout = SpanLayout::new(&monitors).unwrap();
+        assert_eq!((layout.width, layout.height), (7, 4));
+        assert_eq!(layout.origin, (-3, 0));
+        assert_eq!(layout.monitors[0].rect, Rect::new(3, 0, 4, 2));
+        assert_eq!(layout.monitors[1].rect, Rect::new(0, 1, 2, 3));
+
+        let red = [255, 0, 0, 255].repeat(4 * 2);
+        let blue = [0, 0, 255, 255].repeat(2 * 3);
+        let frame = layout.compose(&[red, blue], &FramePool::default());
+        assert_eq!(frame.len(), 7 * 4 * 4);
+
+        let pixel = |x: usize, y: usize| &frame[(y * 7 + x) * 4..][..4];
+        for y in 0..4 {
+            for x in 0..7 {
+                let expected = match layout.locate(x as u32, y as u32) {
+                    Some((0, ..)) => [255, 0, 0, 255],
+                    Some((1, ..)) => [0, 0, 255, 255],
+                    _ => [0, 0, 0, 255],
+                };
+                assert_eq!(pixel(x, y), expected, "pixel {},{}", x, y);
+            }
+        }
+        // Gap column, and below the primary
+        assert_eq!(layout.locate(2, 1), None);
+        assert_eq!(layout.locate(5, 3), None);
+        assert_eq!(layout.locate(4, 1), Some((0, 1, 1)));
+        assert_eq!(layout.locate(1, 3), Some((1, 1, 2)));
+
+        assert!(SpanLayout::new(&[]).is_err());
+    }
+}
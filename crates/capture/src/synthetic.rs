@@ -0,0 +1,163 @@
+//! Synthetic test-pattern capture
+//!
+//! Stands in for a screen on headless machines such as CI runners, so the
+//! pipeline, codecs and network can be exercised without a display server.
+//! Frames are deterministic: frame `n` always has the same pixels and a
+//! timestamp of `n` frame intervals, whatever the wall clock does. Pacing is
+//! left to the caller, e.g. [`start_stream`](crate::start_stream).
+
+use crate::{CaptureConfig, CaptureTarget, CapturedFrame, MonitorInfo, ScreenCapture};
+use ada_remote_core::{Error, Result};
+
+/// Animated test pattern of a fixed size
+#[derive(Debug)]
+pub struct SyntheticCapturer {
+    width: u32,
+    height: u32,
+    config: Option<CaptureConfig>,
+    frame: u64,
+}
+
+impl SyntheticCapturer {
+    /// Capturer of `width`x`height` frames
+    pub fn new(width: u32, height: u32) -> Result<Self> {
+        if width == 0 || height == 0 {
+            return Err(Error::Session(format!(
+                "Invalid synthetic capture size {}x{}",
+                width, height
+            )));
+        }
+        Ok(Self {
+            width,
+            height,
+            config: None,
+            frame: 0,
+        })
+    }
+
+    /// Draw frame `frame`: a diagonal gradient drifting one pixel per frame
+    /// with a white bar sweeping across
+    fn draw(&self, frame: u64, data: &mut Vec<u8>) {
+        let (width, height) = (self.width as u64, self.height as u64);
+        let bar = frame * 4 % width;
+        for y in 0..height {
+            for x in 0..width {
+                if x.abs_diff(bar) < 8 {
+                    data.extend([255, 255, 255, 255]);
+                    continue;
+                }
+                let shade = (x + y + frame) as u8;
+                data.extend([shade, (y * 255 / height) as u8, !shade, 255]);
+            }
+        }
+    }
+}
+
+impl Default for SyntheticCapturer {
+    fn default() -> Self {
+        Self::new(1280, 720).unwrap()
+    }
+}
+
+impl ScreenCapture for SyntheticCapturer {
+    fn init(&mut self, config: CaptureConfig) -> Result<()> {
+        match config.target {
+            CaptureTarget::Monitor(0) | CaptureTarget::AllMonitors => {}
+            CaptureTarget::Monitor(index) => {
+                return Err(Error::Session(format!("Monitor {} not found", index)))
+            }
+            CaptureTarget::Window(window) => {
+                return Err(Error::TargetClosed(format!("Window {:#x}", window.0)))
+            }
+        }
+        tracing::info!(
+            "Synthetic capture initialized ({}x{} at {} fps)",
+            self.width,
+            self.height,
+            config.fps
+        );
+        self.config = Some(config);
+        self.frame = 0;
+        Ok(())
+    }
+
+    fn capture_frame(&mut self) -> Result<CapturedFrame> {
+        let Some(config) = &self.config else {
+            return Err(Error::Session("Capturer not initialized".to_string()));
+        };
+
+        let mut data = config
+            .frame_pool
+            .take(self.width as usize * self.height as usize * 4);
+        self.draw(self.frame, &mut data);
+        let frame = CapturedFrame {
+            data,
+            width: self.width,
+            height: self.height,
+            timestamp: self.frame * 1_000_000 / config.fps.max(1) as u64,
+            dirty_rects: None,
+        };
+        self.frame += 1;
+
+        // Every pixel moves each frame
+        Ok(if config.track_damage {
+            frame.with_full_damage()
+        } else {
+            frame
+        })
+    }
+
+    fn list_monitors(&self) -> Result<Vec<MonitorInfo>> {
+        Ok(vec![MonitorInfo {
+            index: 0,
+            name: "Synthetic".to_string(),
+            x: 0,
+            y: 0,
+            width: self.width,
+            height: self.height,
+            is_primary: true,
+            scale_factor: 1.0,
+        }])
+    }
+
+    fn cleanup(&mut self) -> Result<()> {
+        self.config = None;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frames_have_configured_size_and_advance() {
+        let mut capturer = SyntheticCapturer::new(64, 48).unwrap();
+        assert!(capturer.capture_frame().is_err());
+        capturer
+            .init(CaptureConfig {
+                fps: 25,
+                ..Default::default()
+            })
+            .unwrap();
+
+        let monitors = capturer.list_monitors().unwrap();
+        assert_eq!((monitors[0].width, monitors[0].height), (64, 48));
+
+        let frames: Vec<CapturedFrame> =
+            (0..3).map(|_| capturer.capture_frame().unwrap()).collect();
+        for frame in &frames {
+            assert_eq!((frame.width, frame.height), (64, 48));
+            assert_eq!(frame.data.len(), 64 * 48 * 4);
+        }
+        let timestamps: Vec<u64> = frames.iter().map(|f| f.timestamp).collect();
+        assert_eq!(timestamps, [0, 40_000, 80_000]);
+        assert_ne!(frames[0].data, frames[1].data);
+
+        // Restarting replays the same frames
+        capturer.init(CaptureConfig::default()).unwrap();
+        assert_eq!(capturer.capture_frame().unwrap().data, frames[0].data);
+
+        assert!(SyntheticCapturer::new(0, 48).is_err());
+    }
+}
@@ -0,0 +1,532 @@
+//! Wayland screen capture
+//!
+//! Compositors don't let clients read the screen directly. A screencast is
+//! requested from xdg-desktop-portal over D-Bus, which shows a permission
+//! dialog, and frames then arrive over the PipeWire remote it hands back.
+
+use crate::damage::DamageTracker;
+use crate::{CaptureConfig, CaptureTarget, CapturedFrame, MonitorInfo, ScreenCapture};
+use ada_remote_core::Result;
+use ashpd::desktop::screencast::{CursorMode, Screencast, SourceType};
+use ashpd::desktop::{PersistMode, Session};
+use ashpd::WindowIdentifier;
+use pipewire as pw;
+use pw::spa;
+use std::os::fd::OwnedFd;
+use std::sync::mpsc;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How long the user has to answer the portal's permission dialog
+const PORTAL_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long to wait for the compositor's first frame
+const FIRST_FRAME_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Stream granted by the portal
+struct PortalStream {
+    fd: OwnedFd,
+    node_id: u32,
+}
+
+/// Latest frame handed over from the PipeWire thread
+#[derive(Default)]
+struct FrameSlot {
+    state: Mutex<SlotState>,
+    ready: Condvar,
+}
+
+#[derive(Default)]
+struct SlotState {
+    frame: Option<(Vec<u8>, u32, u32)>,
+    closed: bool,
+}
+
+impl FrameSlot {
+    fn put(&self, frame: (Vec<u8>, u32, u32)) {
+        self.state.lock().unwrap().frame = Some(frame);
+        self.ready.notify_all();
+    }
+
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.ready.notify_all();
+    }
+}
+
+/// Running screencast: the portal session and the PipeWire stream, each
+/// on its own thread
+struct Screencasting {
+    slot: Arc<FrameSlot>,
+    stop_portal: mpsc::Sender<()>,
+    stop_stream: pw::channel::Sender<()>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl Screencasting {
+    fn stop(self) {
+        let _ = self.stop_stream.send(());
+        let _ = self.stop_portal.send(());
+        for thread in self.threads {
+            let _ = thread.join();
+        }
+    }
+}
+
+pub struct PipeWireCapturer {
+    config: Option<CaptureConfig>,
+    screencast: Option<Screencasting>,
+    last_frame: Option<(Vec<u8>, u32, u32)>,
+    damage: Option<DamageTracker>,
+    started: Instant,
+}
+
+impl PipeWireCapturer {
+    /// Fails when no screencast portal is reachable on the session bus
+    pub fn new() -> Result<Self> {
+        pollster::block_on(Screencast::new()).map_err(|e| {
+            ada_remote_core::Error::Session(format!("Screencast portal unavailable: {}", e))
+        })?;
+        pw::init();
+
+        Ok(Self {
+            config: None,
+            screencast: None,
+            last_frame: None,
+            damage: None,
+            started: Instant::now(),
+        })
+    }
+}
+
+/// Ask the portal for a screencast, blocking until the user answers the
+/// dialog. The session is closed once `stop` fires.
+fn run_portal(
+    sources: SourceType,
+    granted: mpsc::Sender<Result<PortalStream>>,
+    stop: mpsc::Receiver<()>,
+) {
+    pollster::block_on(async {
+        match start_screencast(sources).await {
+            Ok((session, stream)) => {
+                let _ = granted.send(Ok(stream));
+                // Keep the session open for as long as we capture
+                let _ = stop.recv();
+                let _ = session.close().await;
+            }
+            Err(e) => {
+                let _ = granted.send(Err(e));
+            }
+        }
+    });
+}
+
+async fn start_screencast(
+    sources: SourceType,
+) -> Result<(Session<'static, Screencast<'static>>, PortalStream)> {
+    let portal_error =
+        |e: ashpd::Error| ada_remote_core::Error::Session(format!("Screencast portal: {}", e));
+
+    let proxy = Screencast::new().await.map_err(portal_error)?;
+    let session = proxy.create_session().await.map_err(portal_error)?;
+    proxy
+        .select_sources(
+            &session,
+            CursorMode::Embedded,
+            sources.into(),
+            false,
+            None,
+            PersistMode::DoNot,
+        )
+        .await
+        .map_err(portal_error)?;
+
+    let streams = proxy
+        .start(&session, &WindowIdentifier::default())
+        .await
+        .and_then(|request| request.response())
+        .map_err(portal_error)?;
+    let node_id = streams
+        .streams()
+        .first()
+        .map(|stream| stream.pipe_wire_node_id())
+        .ok_or_else(|| {
+            ada_remote_core::Error::Session("Screencast portal returned no streams".to_string())
+        })?;
+    let fd = proxy
+        .open_pipe_wire_remote(&session)
+        .await
+        .map_err(portal_error)?;
+
+    Ok((session, PortalStream { fd, node_id }))
+}
+
+/// Receive frames from the portal's PipeWire node until `stop` fires
+fn run_stream(
+    portal: PortalStream,
+    fps: u32,
+    slot: Arc<FrameSlot>,
+    stop: pw::channel::Receiver<()>,
+) -> std::result::Result<(), pw::Error> {
+    let mainloop = pw::main_loop::MainLoop::new(None)?;
+    let context = pw::context::Context::new(&mainloop)?;
+    let core = context.connect_fd(portal.fd, None)?;
+
+    let stream = pw::stream::Stream::new(
+        &core,
+        "ada-remote-capture",
+        pw::properties::properties! {
+            *pw::keys::MEDIA_TYPE => "Video",
+            *pw::keys::MEDIA_CATEGORY => "Capture",
+            *pw::keys::MEDIA_ROLE => "Screen",
+        },
+    )?;
+
+    let closed = slot.clone();
+    let _listener = stream
+        .add_local_listener_with_user_data(spa::param::video::VideoInfoRaw::default())
+        .state_changed(move |_, _, _, state| match state {
+            pw::stream::StreamState::Error(message) => {
+                tracing::warn!("PipeWire stream failed: {}", message);
+                closed.close();
+            }
+            pw::stream::StreamState::Unconnected => closed.close(),
+            _ => {}
+        })
+        .param_changed(|_, format, id, param| {
+            let Some(param) = param else {
+                return;
+            };
+            if id != spa::param::ParamType::Format.as_raw() {
+                return;
+            }
+            if format.parse(param).is_ok() {
+                let size = format.size();
+                tracing::info!(
+                    "PipeWire stream format {:?} {}x{}",
+                    format.format(),
+                    size.width,
+                    size.height
+                );
+            }
+        })
+        .process(move |stream, format| {
+            let Some(mut buffer) = stream.dequeue_buffer() else {
+                return;
+            };
+            let Some(data) = buffer.datas_mut().first_mut() else {
+                return;
+            };
+
+            let size = format.size();
+            let chunk = data.chunk();
+            let offset = chunk.offset() as usize;
+            let stride = chunk.stride().unsigned_abs() as usize;
+            let Some(bytes) = data.data() else {
+                return;
+            };
+            let Some(pixels) = bytes.get(offset..) else {
+                return;
+            };
+            if let Some(rgba) = to_rgba(pixels, size.width, size.height, stride, format.format()) {
+                slot.put((rgba, size.width, size.height));
+            }
+        })
+        .register()?;
+
+    let format = video_format_param(fps);
+    let mut params = [spa::pod::Pod::from_bytes(&format).expect("serialized format pod")];
+    stream.connect(
+        spa::utils::Direction::Input,
+        Some(portal.node_id),
+        pw::stream::StreamFlags::AUTOCONNECT | pw::stream::StreamFlags::MAP_BUFFERS,
+        &mut params,
+    )?;
+
+    let quit = mainloop.downgrade();
+    let _stop = stop.attach(mainloop.loop_(), move |()| {
+        if let Some(mainloop) = quit.upgrade() {
+            mainloop.quit();
+        }
+    });
+    mainloop.run();
+    Ok(())
+}
+
+/// EnumFormat pod offering the 32 bpp layouts compositors commonly produce
+fn video_format_param(fps: u32) -> Vec<u8> {
+    use spa::param::format::{FormatProperties, MediaSubtype, MediaType};
+    use spa::param::video::VideoFormat;
+    use spa::utils::{Fraction, Rectangle};
+
+    let object = spa::pod::object!(
+        spa::utils::SpaTypes::ObjectParamFormat,
+        spa::param::ParamType::EnumFormat,
+        spa::pod::property!(FormatProperties::MediaType, Id, MediaType::Video),
+        spa::pod::property!(FormatProperties::MediaSubtype, Id, MediaSubtype::Raw),
+        spa::pod::property!(
+            FormatProperties::VideoFormat,
+            Choice,
+            Enum,
+            Id,
+            VideoFormat::BGRx,
+            VideoFormat::BGRx,
+            VideoFormat::BGRA,
+            VideoFormat::RGBx,
+            VideoFormat::RGBA,
+        ),
+        spa::pod::property!(
+            FormatProperties::VideoSize,
+            Choice,
+            Range,
+            Rectangle,
+            Rectangle {
+                width: 1920,
+                height: 1080
+            },
+            Rectangle {
+                width: 1,
+                height: 1
+            },
+            Rectangle {
+                width: 8192,
+                height: 8192
+            }
+        ),
+        spa::pod::property!(
+            FormatProperties::VideoFramerate,
+            Choice,
+            Range,
+            Fraction,
+            Fraction { num: fps, denom: 1 },
+            Fraction { num: 0, denom: 1 },
+            Fraction { num: 240, denom: 1 }
+        ),
+    );
+
+    spa::pod::serialize::PodSerializer::serialize(
+        std::io::Cursor::new(Vec::new()),
+        &spa::pod::Value::Object(object),
+    )
+    .expect("format pod serializes")
+    .0
+    .into_inner()
+}
+
+/// Repack a 32 bpp PipeWire buffer as tightly packed RGBA
+fn to_rgba(
+    pixels: &[u8],
+    width: u32,
+    height: u32,
+    stride: usize,
+    format: spa::param::video::VideoFormat,
+) -> Option<Vec<u8>> {
+    use spa::param::video::VideoFormat;
+
+    let bgr = match format {
+        VideoFormat::BGRx | VideoFormat::BGRA => true,
+        VideoFormat::RGBx | VideoFormat::RGBA => false,
+        _ => return None,
+    };
+    let width = width as usize;
+    let height = height as usize;
+    let stride = if stride == 0 { width * 4 } else { stride };
+    if width == 0 || pixels.len() < stride * (height - 1) + width * 4 {
+        return None;
+    }
+
+    let mut rgba = Vec::with_capacity(width * height * 4);
+    for row in pixels.chunks(stride).take(height) {
+        for pixel in row[..width * 4].chunks_exact(4) {
+            let (r, b) = if bgr {
+                (pixel[2], pixel[0])
+            } else {
+                (pixel[0], pixel[2])
+            };
+            // The x variants leave alpha undefined
+            rgba.extend_from_slice(&[r, pixel[1], b, 255]);
+        }
+    }
+    Some(rgba)
+}
+
+impl ScreenCapture for PipeWireCapturer {
+    fn init(&mut self, config: CaptureConfig) -> Result<()> {
+        self.cleanup()?;
+
+        // The portal dialog decides which monitor or window is shared; the
+        // target only picks the kind of source offered
+        let sources = match config.target {
+            CaptureTarget::Monitor(_) => SourceType::Monitor,
+            CaptureTarget::Window(_) => SourceType::Window,
+            // Outputs and their layout are hidden from clients
+            CaptureTarget::AllMonitors => {
+                return Err(ada_remote_core::Error::Session(
+                    "Capturing all monitors is not supported on Wayland".to_string(),
+                ))
+            }
+        };
+
+        let (granted_tx, granted_rx) = mpsc::channel();
+        let (stop_portal, stop_portal_rx) = mpsc::channel();
+        let portal_thread = thread::Builder::new()
+            .name("screencast-portal".to_string())
+            .spawn(move || run_portal(sources, granted_tx, stop_portal_rx))
+            .map_err(|e| {
+                ada_remote_core::Error::Session(format!("Failed to spawn portal thread: {}", e))
+            })?;
+
+        let portal = match granted_rx.recv_timeout(PORTAL_TIMEOUT) {
+            Ok(result) => result?,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                return Err(ada_remote_core::Error::Session(
+                    "Timed out waiting for screen sharing permission".to_string(),
+                ))
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                return Err(ada_remote_core::Error::Session(
+                    "Screencast portal request was abandoned".to_string(),
+                ))
+            }
+        };
+        let node_id = portal.node_id;
+
+        let slot = Arc::new(FrameSlot::default());
+        let (stop_stream, stop_stream_rx) = pw::channel::channel();
+        let stream_slot = slot.clone();
+        let fps = config.fps;
+        let stream_thread = thread::Builder::new()
+            .name("pipewire-stream".to_string())
+            .spawn(move || {
+                if let Err(e) = run_stream(portal, fps, stream_slot.clone(), stop_stream_rx) {
+                    tracing::error!("PipeWire stream error: {}", e);
+                }
+                stream_slot.close();
+            })
+            .map_err(|e| {
+                let _ = stop_portal.send(());
+                ada_remote_core::Error::Session(format!("Failed to spawn PipeWire thread: {}", e))
+            })?;
+
+        tracing::info!("PipeWire screen capture initialized (node {})", node_id);
+        self.screencast = Some(Screencasting {
+            slot,
+            stop_portal,
+            stop_stream,
+            threads: vec![portal_thread, stream_thread],
+        });
+        self.damage = config.track_damage.then(DamageTracker::new);
+        self.config = Some(config);
+        Ok(())
+    }
+
+    fn capture_frame(&mut self) -> Result<CapturedFrame> {
+        let (Some(screencast), Some(config)) = (&self.screencast, &self.config) else {
+            return Err(ada_remote_core::Error::Session(
+                "Capturer not initialized".to_string(),
+            ));
+        };
+        let pool = &config.frame_pool;
+
+        let mut state = screencast.slot.state.lock().unwrap();
+        if self.last_frame.is_none() {
+            state = screencast
+                .slot
+                .ready
+                .wait_timeout_while(state, FIRST_FRAME_TIMEOUT, |s| {
+                    s.frame.is_none() && !s.closed
+                })
+                .unwrap()
+                .0;
+        }
+        if state.closed {
+            return Err(ada_remote_core::Error::TargetClosed(
+                "Screencast was stopped".to_string(),
+            ));
+        }
+        // Compositors only send frames when something changed, so repeat
+        // the last one in between
+        if let Some(frame) = state.frame.take() {
+            if let Some((previous, _, _)) = self.last_frame.replace(frame) {
+                pool.recycle(previous);
+            }
+        }
+        drop(state);
+
+        let Some((last, width, height)) = &self.last_frame else {
+            return Err(ada_remote_core::Error::Session(
+                "Timed out waiting for the first PipeWire frame".to_string(),
+            ));
+        };
+        let (width, height) = (*width, *height);
+        let mut data = pool.take(last.len());
+        data.extend_from_slice(last);
+        let dirty_rects = self
+            .damage
+            .as_mut()
+            .map(|damage| damage.update(&data, width, height));
+
+        Ok(CapturedFrame {
+            data,
+            width,
+            height,
+            timestamp: self.started.elapsed().as_micros() as u64,
+            dirty_rects,
+        })
+    }
+
+    fn list_monitors(&self) -> Result<Vec<MonitorInfo>> {
+        // Wayland hides output layout from clients; the portal dialog is
+        // where the user picks a monitor
+        Ok(vec![MonitorInfo {
+            index: 0,
+            name: "Portal selection".to_string(),
+            x: 0,
+            y: 0,
+            width: 0,
+            height: 0,
+            is_primary: true,
+            scale_factor: 1.0,
+        }])
+    }
+
+    fn cleanup(&mut self) -> Result<()> {
+        if let Some(screencast) = self.screencast.take() {
+            screencast.stop();
+        }
+        self.last_frame = None;
+        tracing::info!("PipeWire screen capture cleaned up");
+        Ok(())
+    }
+}
+
+impl Drop for PipeWireCapturer {
+    fn drop(&mut self) {
+        if let Some(screencast) = self.screencast.take() {
+            screencast.stop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_rgba_swaps_bgr_and_drops_padding() {
+        // 1x2 BGRx with 4 bytes of row padding
+        let pixels = [30, 20, 10, 0, 0, 0, 0, 0, 3, 2, 1, 0];
+        let rgba = to_rgba(&pixels, 1, 2, 8, spa::param::video::VideoFormat::BGRx).unwrap();
+        assert_eq!(rgba, vec![10, 20, 30, 255, 1, 2, 3, 255]);
+    }
+
+    #[test]
+    fn test_portal_construction() {
+        // Needs a Wayland session with xdg-desktop-portal running
+        if std::env::var_os("WAYLAND_DISPLAY").is_none() {
+            return;
+        }
+        assert!(PipeWireCapturer::new().is_ok());
+    }
+}
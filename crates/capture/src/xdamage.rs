@@ -0,0 +1,146 @@
+//! Damage reported by the X server through the DAMAGE extension
+//!
+//! The server sends a rectangle for every drawing operation on the watched
+//! drawable, so X11 frames need not be compared. x11rb speaks the protocol on
+//! a connection of its own; Xlib's binding would need libXdamage at build time.
+
+use crate::Rect;
+use ada_remote_core::Result;
+use x11rb::connection::Connection as _;
+use x11rb::protocol::damage::{self, ConnectionExt as _};
+use x11rb::protocol::xproto::{ConnectionExt as _, Rectangle};
+use x11rb::protocol::Event;
+use x11rb::rust_connection::RustConnection;
+
+/// More pending rectangles than this are reported as the whole frame
+const MAX_RECTS: usize = 64;
+
+fn damage_error(error: impl std::fmt::Display) -> ada_remote_core::Error {
+    ada_remote_core::Error::Session(format!("XDamage unavailable: {}", error))
+}
+
+/// Damage reported for one drawable
+pub(crate) struct ServerDamage {
+    connection: RustConnection,
+    damage: damage::Damage,
+    pending: Vec<Rectangle>,
+    /// Size of the previous frame; `None` makes the next one entirely dirty
+    size: Option<(u32, u32)>,
+}
+
+impl ServerDamage {
+    /// Watch `drawable`, including its children, on a new connection
+    pub(crate) fn watch(drawable: u32) -> Result<Self> {
+        let (connection, _) = RustConnection::connect(None).map_err(damage_error)?;
+        connection
+            .damage_query_version(1, 1)
+            .map_err(damage_error)?
+            .reply()
+            .map_err(damage_error)?;
+        let damage = connection.generate_id().map_err(damage_error)?;
+        connection
+            .damage_create(damage, drawable, damage::ReportLevel::RAW_RECTANGLES)
+            .map_err(damage_error)?
+            .check()
+            .map_err(damage_error)?;
+
+        Ok(Self {
+            connection,
+            damage,
+            pending: Vec::new(),
+            size: None,
+        })
+    }
+
+    /// Take the damage reported so far. Called before grabbing a frame, so
+    /// anything drawn after the grab is reported with the next one.
+    pub(crate) fn collect(&mut self) {
+        if let Err(e) = self.drain() {
+            tracing::warn!("Lost XDamage events: {}", e);
+            self.size = None;
+        }
+        if self.pending.len() > MAX_RECTS {
+            self.pending.clear();
+            self.size = None;
+        }
+    }
+
+    fn drain(&mut self) -> Result<()> {
+        // The reply follows every event generated before it
+        self.connection
+            .get_input_focus()
+            .map_err(damage_error)?
+            .reply()
+            .map_err(damage_error)?;
+        while let Some(event) = self.connection.poll_for_event().map_err(damage_error)? {
+            if let Event::DamageNotify(notify) = event {
+                self.pending.push(notify.area);
+            }
+        }
+        Ok(())
+    }
+
+    /// Collected damage within a `width`x`height` frame showing the drawable
+    /// from `origin`. The first frame, or one at a new size, is entirely dirty.
+    pub(crate) fn update(&mut self, origin: (i32, i32), width: u32, height: u32) -> Vec<Rect> {
+        let areas = std::mem::take(&mut self.pending);
+        if self.size.replace((width, height)) != Some((width, height)) {
+            return vec![Rect::full_frame(width, height)];
+        }
+        areas
+            .iter()
+            .filter_map(|area| clip(area, origin, width, height))
+            .collect()
+    }
+}
+
+impl Drop for ServerDamage {
+    fn drop(&mut self) {
+        let _ = self.connection.damage_destroy(self.damage);
+        let _ = self.connection.flush();
+    }
+}
+
+/// Part of `area` inside a `width`x`height` frame at `origin`, in frame
+/// coordinates
+fn clip(area: &Rectangle, (x, y): (i32, i32), width: u32, height: u32) -> Option<Rect> {
+    let left = (area.x as i32 - x).max(0);
+    let top = (area.y as i32 - y).max(0);
+    let right = (area.x as i32 + area.width as i32 - x).min(width as i32);
+    let bottom = (area.y as i32 + area.height as i32 - y).min(height as i32);
+    (left < right && top < bottom).then(|| {
+        Rect::new(
+            left as u32,
+            top as u32,
+            (right - left) as u32,
+            (bottom - top) as u32,
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clips_damage_to_frame() {
+        let area = |x, y, width, height| Rectangle {
+            x,
+            y,
+            width,
+            height,
+        };
+
+        // A monitor at (1920, 0) sees only its half of the rectangle
+        assert_eq!(
+            clip(&area(1900, 10, 40, 20), (1920, 0), 1280, 1024),
+            Some(Rect::new(0, 10, 20, 20))
+        );
+        // Spanned frames start left of the primary monitor
+        assert_eq!(
+            clip(&area(-100, 0, 50, 50), (-1280, 0), 3200, 1080),
+            Some(Rect::new(1180, 0, 50, 50))
+        );
+        assert_eq!(clip(&area(2000, 0, 10, 10), (0, 0), 1920, 1080), None);
+    }
+}
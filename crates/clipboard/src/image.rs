@@ -0,0 +1,82 @@
+//! PNG encoding of clipboard images
+//!
+//! Clipboard images are mostly screenshots and UI, which PNG shrinks well
+//! without loss. Only 8-bit RGBA is produced and accepted.
+
+use ada_remote_core::{Error, Result};
+use std::io::Cursor;
+
+fn png_error(e: impl std::fmt::Display) -> Error {
+    Error::Session(format!("Clipboard image: {}", e))
+}
+
+/// Bytes of straight RGBA for `width`x`height`, `None` on overflow
+pub(crate) fn rgba_len(width: u32, height: u32) -> Option<usize> {
+    (width as usize)
+        .checked_mul(height as usize)?
+        .checked_mul(4)
+}
+
+/// PNG of `width`x`height` RGBA pixels
+pub(crate) fn encode_png(width: u32, height: u32, rgba: &[u8]) -> Result<Vec<u8>> {
+    if rgba_len(width, height) != Some(rgba.len()) {
+        return Err(png_error(format!(
+            "{} bytes of RGBA do not make {}x{} pixels",
+            rgba.len(),
+            width,
+            height
+        )));
+    }
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(png_error)?;
+    writer.write_image_data(rgba).map_err(png_error)?;
+    writer.finish().map_err(png_error)?;
+    Ok(png)
+}
+
+/// RGBA pixels of a PNG said to be `width`x`height`. The size is checked
+/// before anything is decoded, so `max_size` bounds the memory used.
+pub(crate) fn decode_png(png: &[u8], width: u32, height: u32, max_size: usize) -> Result<Vec<u8>> {
+    let len = check_image_size(width, height, max_size)?;
+    let mut decoder = png::Decoder::new_with_limits(
+        Cursor::new(png),
+        png::Limits {
+            // Room for the row buffers next to the image
+            bytes: len.saturating_mul(2).max(1024 * 1024),
+        },
+    );
+    decoder.set_transformations(png::Transformations::IDENTITY);
+    let mut reader = decoder.read_info().map_err(png_error)?;
+
+    let info = reader.info();
+    if (info.width, info.height) != (width, height) {
+        return Err(png_error(format!(
+            "PNG is {}x{} but announced as {}x{}",
+            info.width, info.height, width, height
+        )));
+    }
+    if (info.color_type, info.bit_depth) != (png::ColorType::Rgba, png::BitDepth::Eight) {
+        return Err(png_error(format!(
+            "unsupported {:?} {:?}-bit PNG",
+            info.color_type, info.bit_depth
+        )));
+    }
+
+    let mut rgba = vec![0; len];
+    reader.next_frame(&mut rgba).map_err(png_error)?;
+    Ok(rgba)
+}
+
+/// RGBA length of a `width`x`height` image, failing above `max_size`
+pub(crate) fn check_image_size(width: u32, height: u32, max_size: usize) -> Result<usize> {
+    match rgba_len(width, height) {
+        Some(len) if len <= max_size => Ok(len),
+        _ => Err(Error::Session(format!(
+            "Clipboard image of {}x{} pixels exceeds the {} byte limit",
+            width, height, max_size
+        ))),
+    }
+}
@@ -0,0 +1,189 @@
+//! Ada Remote Clipboard
+//!
+//! OS clipboard access and synchronization of its contents between peers.
+
+use ada_remote_core::{Error, Result};
+
+mod image;
+mod sync;
+
+pub use sync::{ClipboardSync, CHUNK_SIZE, DEFAULT_MAX_SIZE};
+
+/// Clipboard contents
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ClipboardContent {
+    Text(String),
+    /// Straight-alpha RGBA, row-major
+    Image {
+        width: u32,
+        height: u32,
+        rgba: Vec<u8>,
+    },
+    /// Paths of copied files
+    Files(Vec<String>),
+}
+
+/// Access to a clipboard
+pub trait Clipboard: Send {
+    /// Current contents, `None` if empty or in an unsupported format
+    fn get(&mut self) -> Result<Option<ClipboardContent>>;
+
+    /// Replace the contents
+    fn set(&mut self, content: ClipboardContent) -> Result<()>;
+
+    /// Current text, `None` if the clipboard holds no text
+    fn get_text(&mut self) -> Result<Option<String>> {
+        Ok(match self.get()? {
+            Some(ClipboardContent::Text(text)) => Some(text),
+            _ => None,
+        })
+    }
+
+    fn set_text(&mut self, text: &str) -> Result<()> {
+        self.set(ClipboardContent::Text(text.to_string()))
+    }
+}
+
+/// Open the system clipboard
+pub fn create_clipboard() -> Result<Box<dyn Clipboard>> {
+    Ok(Box::new(SystemClipboard::new()?))
+}
+
+fn clipboard_error(e: arboard::Error) -> Error {
+    Error::Session(format!("Clipboard error: {}", e))
+}
+
+/// The OS clipboard (X11/Wayland, Win32, NSPasteboard) through arboard
+pub struct SystemClipboard {
+    inner: arboard::Clipboard,
+}
+
+impl SystemClipboard {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            inner: arboard::Clipboard::new().map_err(clipboard_error)?,
+        })
+    }
+}
+
+/// Whether `e` only means the clipboard holds something else
+fn not_available(e: &arboard::Error) -> bool {
+    matches!(
+        e,
+        arboard::Error::ContentNotAvailable | arboard::Error::ClipboardNotSupported
+    )
+}
+
+impl Clipboard for SystemClipboard {
+    fn get(&mut self) -> Result<Option<ClipboardContent>> {
+        // File managers offer the names as text too, so files go first
+        match self.inner.get().file_list() {
+            Ok(paths) if !paths.is_empty() => {
+                return Ok(Some(ClipboardContent::Files(
+                    paths
+                        .iter()
+                        .map(|path| path.to_string_lossy().into_owned())
+                        .collect(),
+                )))
+            }
+            Ok(_) => {}
+            Err(e) if not_available(&e) => {}
+            Err(e) => return Err(clipboard_error(e)),
+        }
+        match self.inner.get_text() {
+            Ok(text) => return Ok(Some(ClipboardContent::Text(text))),
+            Err(arboard::Error::ContentNotAvailable) => {}
+            Err(e) => return Err(clipboard_error(e)),
+        }
+        match self.inner.get_image() {
+            Ok(image) => Ok(Some(ClipboardContent::Image {
+                width: image.width as u32,
+                height: image.height as u32,
+                rgba: image.bytes.into_owned(),
+            })),
+            Err(arboard::Error::ContentNotAvailable) => Ok(None),
+            Err(e) => Err(clipboard_error(e)),
+        }
+    }
+
+    fn set(&mut self, content: ClipboardContent) -> Result<()> {
+        match content {
+            ClipboardContent::Text(text) => self.inner.set_text(text),
+            ClipboardContent::Image {
+                width,
+                height,
+                rgba,
+            } => self.inner.set_image(arboard::ImageData {
+                width: width as usize,
+                height: height as usize,
+                bytes: rgba.into(),
+            }),
+            ClipboardContent::Files(paths) => self.inner.set().file_list(&paths),
+        }
+        .map_err(clipboard_error)
+    }
+}
+
+/// In-process clipboard, for headless hosts and tests
+#[derive(Debug, Default)]
+pub struct MemoryClipboard {
+    content: Option<ClipboardContent>,
+}
+
+impl MemoryClipboard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Clipboard for MemoryClipboard {
+    fn get(&mut self) -> Result<Option<ClipboardContent>> {
+        Ok(self.content.clone())
+    }
+
+    fn set(&mut self, content: ClipboardContent) -> Result<()> {
+        self.content = Some(content);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_round_trip() {
+        let mut clipboard = MemoryClipboard::new();
+        assert_eq!(clipboard.get_text().unwrap(), None);
+        clipboard.set_text("héllo ✓").unwrap();
+        assert_eq!(clipboard.get_text().unwrap().as_deref(), Some("héllo ✓"));
+    }
+
+    #[test]
+    fn test_image_survives_png() {
+        let rgba: Vec<u8> = (0..6 * 4 * 4).map(|i| (i * 7) as u8).collect();
+        let png = image::encode_png(6, 4, &rgba).unwrap();
+        assert_eq!(image::decode_png(&png, 6, 4, 1024).unwrap(), rgba);
+
+        // Announced sizes are checked before decoding
+        let err = image::decode_png(&png, 6, 4, 95).unwrap_err();
+        assert!(err.to_string().contains("exceeds the 95 byte limit"));
+        assert!(image::decode_png(&png, 4, 6, 1024).is_err());
+        assert!(image::encode_png(6, 5, &rgba).is_err());
+    }
+
+    #[test]
+    fn test_system_clipboard_round_trip() {
+        // Needs a display server to own the selection, e.g. xvfb-run in CI
+        if cfg!(target_os = "linux") && std::env::var_os("DISPLAY").is_none() {
+            return;
+        }
+
+        let mut clipboard = create_clipboard().unwrap();
+        clipboard.set_text("ada-remote clipboard test").unwrap();
+        assert_eq!(
+            clipboard.get_text().unwrap().as_deref(),
+            Some("ada-remote clipboard test")
+        );
+    }
+}
@@ -0,0 +1,351 @@
+//! Clipboard synchronization over the protocol
+//!
+//! Text goes out as one `Clipboard` message, or as `ClipboardChunk`s when it
+//! is larger than [`CHUNK_SIZE`]. Images go as one PNG `ClipboardImage` and
+//! file lists as `ClipboardFiles`. The last content sent or received is
+//! remembered by hash, so content that arrived from the peer is not echoed
+//! back when the local clipboard is next polled.
+
+use crate::image::{check_image_size, decode_png, encode_png};
+use crate::{Clipboard, ClipboardContent};
+use ada_remote_core::{Error, ProtocolMessage, Result};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// Largest text carried by a single message
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Default limit on synchronized content; for images it applies to the
+/// decoded RGBA
+pub const DEFAULT_MAX_SIZE: usize = 10 * 1024 * 1024;
+
+fn content_hash(content: &ClipboardContent) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Chunked text being reassembled
+struct Incoming {
+    sequence: u32,
+    next_index: u32,
+    count: u32,
+    data: Vec<u8>,
+}
+
+/// Synchronizes one clipboard with the peer's
+pub struct ClipboardSync {
+    max_size: usize,
+    /// Content both sides are known to have
+    last_hash: Option<u64>,
+    next_sequence: u32,
+    incoming: Option<Incoming>,
+}
+
+impl Default for ClipboardSync {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_SIZE)
+    }
+}
+
+impl ClipboardSync {
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            max_size,
+            last_hash: None,
+            next_sequence: 0,
+            incoming: None,
+        }
+    }
+
+    fn check_size(&self, len: usize) -> Result<()> {
+        if len > self.max_size {
+            return Err(Error::Session(format!(
+                "Clipboard content of {} bytes exceeds the {} byte limit",
+                len, self.max_size
+            )));
+        }
+        Ok(())
+    }
+
+    /// Messages carrying `text` to the peer; empty if the peer has it already
+    pub fn outgoing(&mut self, text: &str) -> Result<Vec<ProtocolMessage>> {
+        self.outgoing_content(&ClipboardContent::Text(text.to_string()))
+    }
+
+    /// Messages carrying `content` to the peer; empty if the peer has it
+    /// already
+    pub fn outgoing_content(&mut self, content: &ClipboardContent) -> Result<Vec<ProtocolMessage>> {
+        match content {
+            ClipboardContent::Text(text) => self.check_size(text.len())?,
+            ClipboardContent::Image { width, height, .. } => {
+                check_image_size(*width, *height, self.max_size)?;
+            }
+            ClipboardContent::Files(paths) => {
+                self.check_size(paths.iter().map(String::len).sum())?
+            }
+        }
+        let hash = content_hash(content);
+        if self.last_hash == Some(hash) {
+            return Ok(Vec::new());
+        }
+
+        let messages = match content {
+            ClipboardContent::Text(text) => self.text_messages(text),
+            ClipboardContent::Image {
+                width,
+                height,
+                rgba,
+            } => vec![ProtocolMessage::ClipboardImage {
+                width: *width,
+                height: *height,
+                png: encode_png(*width, *height, rgba)?,
+            }],
+            ClipboardContent::Files(paths) => vec![ProtocolMessage::ClipboardFiles {
+                paths: paths.clone(),
+            }],
+        };
+        self.last_hash = Some(hash);
+        Ok(messages)
+    }
+
+    fn text_messages(&mut self, text: &str) -> Vec<ProtocolMessage> {
+        if text.len() <= CHUNK_SIZE {
+            return vec![ProtocolMessage::Clipboard {
+                content: text.to_string(),
+            }];
+        }
+
+        let sequence = self.next_sequence;
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+        let chunks: Vec<&[u8]> = text.as_bytes().chunks(CHUNK_SIZE).collect();
+        let count = chunks.len() as u32;
+        chunks
+            .into_iter()
+            .enumerate()
+            .map(|(index, data)| ProtocolMessage::ClipboardChunk {
+                sequence,
+                index: index as u32,
+                count,
+                data: data.to_vec(),
+            })
+            .collect()
+    }
+
+    /// Feed a received message, returning the content once it is complete.
+    /// Other messages are ignored.
+    pub fn incoming(&mut self, message: &ProtocolMessage) -> Result<Option<ClipboardContent>> {
+        let content = match message {
+            ProtocolMessage::Clipboard { content } => {
+                self.check_size(content.len())?;
+                ClipboardContent::Text(content.clone())
+            }
+            ProtocolMessage::ClipboardChunk {
+                sequence,
+                index,
+                count,
+                data,
+            } => match self.add_chunk(*sequence, *index, *count, data)? {
+                Some(text) => ClipboardContent::Text(text),
+                None => return Ok(None),
+            },
+            ProtocolMessage::ClipboardImage { width, height, png } => {
+                self.check_size(png.len())?;
+                ClipboardContent::Image {
+                    width: *width,
+                    height: *height,
+                    rgba: decode_png(png, *width, *height, self.max_size)?,
+                }
+            }
+            ProtocolMessage::ClipboardFiles { paths } => {
+                self.check_size(paths.iter().map(String::len).sum())?;
+                ClipboardContent::Files(paths.clone())
+            }
+            _ => return Ok(None),
+        };
+
+        self.last_hash = Some(content_hash(&content));
+        Ok(Some(content))
+    }
+
+    fn add_chunk(
+        &mut self,
+        sequence: u32,
+        index: u32,
+        count: u32,
+        data: &[u8],
+    ) -> Result<Option<String>> {
+        if index == 0 {
+            self.incoming = Some(Incoming {
+                sequence,
+                next_index: 0,
+                count,
+                data: Vec::new(),
+            });
+        }
+
+        let incoming = match self.incoming.as_mut() {
+            Some(incoming)
+                if incoming.sequence == sequence
+                    && incoming.next_index == index
+                    && incoming.count == count =>
+            {
+                incoming
+            }
+            _ => {
+                self.incoming = None;
+                return Err(Error::Session(format!(
+                    "Unexpected clipboard chunk {} of {} in sequence {}",
+                    index, count, sequence
+                )));
+            }
+        };
+
+        incoming.data.extend_from_slice(data);
+        incoming.next_index += 1;
+        let len = incoming.data.len();
+        let done = incoming.next_index == incoming.count;
+        if let Err(e) = self.check_size(len) {
+            self.incoming = None;
+            return Err(e);
+        }
+        if !done {
+            return Ok(None);
+        }
+
+        let incoming = self.incoming.take().expect("checked above");
+        String::from_utf8(incoming.data)
+            .map(Some)
+            .map_err(|_| Error::Session("Clipboard text is not valid UTF-8".to_string()))
+    }
+
+    /// Messages for the local clipboard's content if it changed since the
+    /// last sync. Oversized content is logged and skipped.
+    pub fn poll(&mut self, clipboard: &mut dyn Clipboard) -> Result<Vec<ProtocolMessage>> {
+        let Some(content) = clipboard.get()? else {
+            return Ok(Vec::new());
+        };
+        match self.outgoing_content(&content) {
+            Ok(messages) => Ok(messages),
+            Err(e) => {
+                tracing::warn!("Not synchronizing clipboard: {}", e);
+                // Do not warn again until the content changes
+                self.last_hash = Some(content_hash(&content));
+                Ok(Vec::new())
+            }
+        }
+    }
+
+    /// Feed a received message and write completed content to `clipboard`,
+    /// returning whether it was written
+    pub fn apply(
+        &mut self,
+        clipboard: &mut dyn Clipboard,
+        message: &ProtocolMessage,
+    ) -> Result<bool> {
+        match self.incoming(message)? {
+            Some(content) => {
+                clipboard.set(content)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryClipboard;
+
+    #[test]
+    fn test_round_trip_without_echo() {
+        let (mut host, mut host_sync) = (MemoryClipboard::new(), ClipboardSync::default());
+        let (mut client, mut client_sync) = (MemoryClipboard::new(), ClipboardSync::default());
+
+        host.set_text("copied on the host").unwrap();
+        let messages = host_sync.poll(&mut host).unwrap();
+        assert_eq!(messages.len(), 1);
+        for message in &messages {
+            client_sync.apply(&mut client, message).unwrap();
+        }
+        assert_eq!(
+            client.get_text().unwrap().as_deref(),
+            Some("copied on the host")
+        );
+
+        // Neither side sends it again
+        assert!(client_sync.poll(&mut client).unwrap().is_empty());
+        assert!(host_sync.poll(&mut host).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_large_text_is_chunked() {
+        let text = "ü".repeat(CHUNK_SIZE);
+        let mut sender = ClipboardSync::default();
+        let messages = sender.outgoing(&text).unwrap();
+        assert_eq!(messages.len(), 2);
+
+        // Chunks split UTF-8 sequences; only the reassembled text is decoded
+        let mut receiver = ClipboardSync::default();
+        assert_eq!(receiver.incoming(&messages[0]).unwrap(), None);
+        assert_eq!(
+            receiver.incoming(&messages[1]).unwrap(),
+            Some(ClipboardContent::Text(text))
+        );
+
+        // Out of order chunks are refused
+        let mut receiver = ClipboardSync::default();
+        assert!(receiver.incoming(&messages[1]).is_err());
+    }
+
+    #[test]
+    fn test_oversized_content_rejected() {
+        let mut sync = ClipboardSync::new(1024);
+        let err = sync.outgoing(&"x".repeat(1025)).unwrap_err();
+        assert!(err.to_string().contains("exceeds the 1024 byte limit"));
+
+        let message = ProtocolMessage::Clipboard {
+            content: "x".repeat(1025),
+        };
+        assert!(sync.incoming(&message).is_err());
+    }
+
+    #[test]
+    fn test_image_round_trip_and_limit() {
+        let (width, height) = (16, 8);
+        let rgba: Vec<u8> = (0..width * height * 4)
+            .map(|i| (i / 4 % 7 * 30) as u8)
+            .collect();
+        let image = ClipboardContent::Image {
+            width,
+            height,
+            rgba: rgba.clone(),
+        };
+        let (mut host, mut host_sync) = (MemoryClipboard::new(), ClipboardSync::default());
+        host.set(image.clone()).unwrap();
+        let messages = host_sync.poll(&mut host).unwrap();
+        assert_eq!(messages.len(), 1);
+        let ProtocolMessage::ClipboardImage { png, .. } = &messages[0] else {
+            panic!("unexpected message: {:?}", messages[0]);
+        };
+        assert!(png.len() < rgba.len());
+
+        let wire = ProtocolMessage::from_bytes(&messages[0].to_bytes().unwrap()).unwrap();
+        let (mut client, mut client_sync) = (MemoryClipboard::new(), ClipboardSync::default());
+        assert!(client_sync.apply(&mut client, &wire).unwrap());
+        assert_eq!(client.get().unwrap(), Some(image.clone()));
+        assert!(client_sync.poll(&mut client).unwrap().is_empty());
+
+        // 16x8 RGBA is 512 bytes
+        let mut small = ClipboardSync::new(511);
+        let err = small.outgoing_content(&image).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("image of 16x8 pixels exceeds the 511 byte limit"));
+        assert!(small.incoming(&wire).is_err());
+        let mut clipboard = MemoryClipboard::new();
+        clipboard.set(image).unwrap();
+        assert!(small.poll(&mut clipboard).unwrap().is_empty());
+    }
+}
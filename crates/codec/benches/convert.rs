@@ -0,0 +1,40 @@
+//! RGBA→YUV conversion throughput
+//!
+//! `cargo bench -p ada-remote-codec --bench convert`
+
+use ada_remote_codec::{rgba_to_i420, rgba_to_nv12, ColorRange, ColorSpace};
+use std::hint::black_box;
+use std::time::Instant;
+
+const ITERATIONS: u32 = 50;
+
+type Convert = fn(&[u8], usize, usize, ColorSpace, ColorRange) -> Vec<u8>;
+
+fn main() {
+    for (width, height) in [(1280, 720), (1920, 1080), (3840, 2160)] {
+        let rgba: Vec<u8> = (0..width * height * 4)
+            .map(|i| (i * 7 % 251) as u8)
+            .collect();
+        for (name, convert) in [("i420", rgba_to_i420 as Convert), ("nv12", rgba_to_nv12)] {
+            let start = Instant::now();
+            for _ in 0..ITERATIONS {
+                black_box(convert(
+                    black_box(&rgba),
+                    width,
+                    height,
+                    ColorSpace::Bt709,
+                    ColorRange::Limited,
+                ));
+            }
+            let per_frame = start.elapsed() / ITERATIONS;
+            println!(
+                "{}x{} rgba→{}: {:.2} ms/frame, {:.0} fps",
+                width,
+                height,
+                name,
+                per_frame.as_secs_f64() * 1000.0,
+                1.0 / per_frame.as_secs_f64()
+            );
+        }
+    }
+}
@@ -0,0 +1,213 @@
+//! Asynchronous encoding
+//!
+//! [`VideoEncoder::encode`] blocks for as long as the codec takes, which
+//! would stall the tasks sharing a Tokio worker. [`BlockingEncoder`] runs it
+//! on the blocking thread pool instead, one frame at a time and in the order
+//! frames were submitted, since encoders carry state from frame to frame.
+
+use crate::{EncodedFrame, RawFrame, VideoEncoder};
+use ada_remote_core::{Error, Result};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+
+/// Future resolving to an encoded frame
+pub type EncodeFuture = Pin<Box<dyn Future<Output = Result<EncodedFrame>> + Send>>;
+
+/// Video encoder that doesn't block the async runtime
+pub trait AsyncVideoEncoder: Send + Sync {
+    /// Submit `frame` for encoding. Frames are encoded in the order of the
+    /// calls, whether or not their futures are polled.
+    fn encode(&self, frame: RawFrame) -> EncodeFuture;
+
+    /// Frames submitted but not yet encoded
+    fn in_flight(&self) -> usize;
+}
+
+struct Shared {
+    encoder: Mutex<Box<dyn VideoEncoder>>,
+    /// Ticket of the frame allowed to encode next
+    turn: Mutex<u64>,
+    turn_changed: Condvar,
+    in_flight: AtomicUsize,
+}
+
+impl Shared {
+    fn encode_in_turn(&self, ticket: u64, frame: RawFrame) -> Result<EncodedFrame> {
+        let turn = self.turn.lock().unwrap();
+        drop(
+            self.turn_changed
+                .wait_while(turn, |turn| *turn != ticket)
+                .unwrap(),
+        );
+
+        // Hand over to the next frame even if the encoder panics
+        let _done = Done(self);
+        self.encoder.lock().unwrap().encode(frame)
+    }
+}
+
+/// Ends the current frame's turn
+struct Done<'a>(&'a Shared);
+
+impl Drop for Done<'_> {
+    fn drop(&mut self) {
+        let shared = self.0;
+        *shared.turn.lock().unwrap() += 1;
+        shared.in_flight.fetch_sub(1, Ordering::SeqCst);
+        shared.turn_changed.notify_all();
+    }
+}
+
+/// [`AsyncVideoEncoder`] running a [`VideoEncoder`] on Tokio's blocking
+/// thread pool
+pub struct BlockingEncoder {
+    shared: Arc<Shared>,
+    next_ticket: AtomicU64,
+}
+
+impl BlockingEncoder {
+    pub fn new(encoder: Box<dyn VideoEncoder>) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                encoder: Mutex::new(encoder),
+                turn: Mutex::new(0),
+                turn_changed: Condvar::new(),
+                in_flight: AtomicUsize::new(0),
+            }),
+            next_ticket: AtomicU64::new(0),
+        }
+    }
+
+    /// Use the wrapped encoder directly, for init and reconfiguration. This
+    /// waits for a frame being encoded to finish.
+    pub fn with_encoder<R>(&self, f: impl FnOnce(&mut dyn VideoEncoder) -> R) -> R {
+        f(self.shared.encoder.lock().unwrap().as_mut())
+    }
+}
+
+impl AsyncVideoEncoder for BlockingEncoder {
+    fn encode(&self, frame: RawFrame) -> EncodeFuture {
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return Box::pin(async {
+                Err(Error::Encoding(
+                    "Async encoding needs a Tokio runtime".to_string(),
+                ))
+            });
+        };
+
+        let ticket = self.next_ticket.fetch_add(1, Ordering::SeqCst);
+        self.shared.in_flight.fetch_add(1, Ordering::SeqCst);
+        let shared = Arc::clone(&self.shared);
+        let task = runtime.spawn_blocking(move || shared.encode_in_turn(ticket, frame));
+        Box::pin(async move {
+            task.await
+                .map_err(|e| Error::Encoding(format!("Encoder task failed: {}", e)))?
+        })
+    }
+
+    fn in_flight(&self) -> usize {
+        self.shared.in_flight.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EncoderConfig, PixelFormat};
+    use std::time::Duration;
+
+    /// Encoder that takes a while and records the order it saw frames in
+    struct SlowEncoder {
+        seen: Arc<Mutex<Vec<u64>>>,
+    }
+
+    impl VideoEncoder for SlowEncoder {
+        fn init(&mut self, _config: EncoderConfig) -> Result<()> {
+            Ok(())
+        }
+
+        fn encode(&mut self, frame: RawFrame) -> Result<EncodedFrame> {
+            // Later frames finish faster, so completion order alone would
+            // scramble them
+            std::thread::sleep(Duration::from_millis(8 - frame.timestamp % 8));
+            self.seen.lock().unwrap().push(frame.timestamp);
+            Ok(EncodedFrame {
+                data: frame.data,
+                timestamp: frame.timestamp,
+                is_keyframe: frame.timestamp == 0,
+                temporal_layer: 0,
+            })
+        }
+
+        fn force_keyframe(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn reconfigure(&mut self, _width: u32, _height: u32) -> Result<()> {
+            Ok(())
+        }
+
+        fn set_bitrate(&mut self, _bitrate: u32) -> Result<()> {
+            Ok(())
+        }
+
+        fn effective_config(&self) -> Option<EncoderConfig> {
+            None
+        }
+
+        fn cleanup(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_concurrent_encodes_keep_frame_order() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let encoder = BlockingEncoder::new(Box::new(SlowEncoder {
+            seen: Arc::clone(&seen),
+        }));
+
+        let pending: Vec<_> = (0..16)
+            .map(|timestamp| {
+                encoder.encode(RawFrame {
+                    data: vec![timestamp as u8; 4],
+                    format: PixelFormat::Rgba,
+                    width: 1,
+                    height: 1,
+                    timestamp,
+                })
+            })
+            .collect();
+        assert!(encoder.in_flight() > 0);
+
+        // Await in reverse, each future on its own task
+        let tasks: Vec<_> = pending.into_iter().map(tokio::spawn).collect();
+        for (timestamp, task) in tasks.into_iter().enumerate().rev() {
+            let encoded = task.await.unwrap().unwrap();
+            assert_eq!(encoded.timestamp, timestamp as u64);
+            assert_eq!(encoded.data, vec![timestamp as u8; 4]);
+        }
+
+        assert_eq!(*seen.lock().unwrap(), (0..16).collect::<Vec<_>>());
+        assert_eq!(encoder.in_flight(), 0);
+    }
+
+    #[test]
+    fn test_encode_outside_runtime_fails() {
+        let encoder = BlockingEncoder::new(crate::create_encoder(crate::CodecType::Raw).unwrap());
+        let frame = RawFrame {
+            data: vec![0; 4],
+            format: PixelFormat::Rgba,
+            width: 1,
+            height: 1,
+            timestamp: 0,
+        };
+        let result = tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(encoder.encode(frame));
+        assert!(result.is_err());
+        assert_eq!(encoder.in_flight(), 0);
+    }
+}
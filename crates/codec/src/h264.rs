@@ -0,0 +1,929 @@
+//! H.264 encoder and decoder backed by OpenH264
+
+use crate::convert::{from_i420, i420_planes, i420_to_rgba, to_i420, I420Frame};
+use crate::scene::SceneChangeDetector;
+use crate::stats::StatsTracker;
+use crate::VideoDecoder;
+use crate::{ColorRange, ColorSpace, DecoderConfig, EncoderStats, PixelFormat, RateControl};
+use crate::{EncodedFrame, EncoderConfig, RawFrame, VideoEncoder};
+use ada_remote_core::{FramePool, Result};
+use openh264::decoder::Decoder;
+use openh264::encoder::{Encoder, FrameType, RateControlMode, UsageType};
+use openh264::formats::{YUVSlices, YUVSource};
+use openh264::{OpenH264API, Timestamp};
+
+pub(crate) struct H264Encoder {
+    config: Option<EncoderConfig>,
+    encoder: Option<Encoder>,
+    force_idr: bool,
+    /// Position within the current keyframe interval
+    gop_position: u32,
+    /// Whether the max bitrate still has to be applied to the running encoder
+    max_bitrate_pending: bool,
+    stats: StatsTracker,
+    scene_detector: SceneChangeDetector,
+    /// Where consumed frame buffers go back to
+    pool: Option<FramePool>,
+}
+
+/// OpenH264 settings implementing a rate control mode
+struct RateControlParams {
+    mode: RateControlMode,
+    bitrate_bps: u32,
+    max_bitrate_bps: u32,
+}
+
+fn rate_control_params(rate_control: RateControl) -> Result<RateControlParams> {
+    match rate_control {
+        RateControl::Cbr { kbps } => Ok(RateControlParams {
+            mode: RateControlMode::Bitrate,
+            bitrate_bps: kbps.saturating_mul(1000),
+            max_bitrate_bps: kbps.saturating_mul(1000),
+        }),
+        RateControl::Vbr {
+            target_kbps,
+            max_kbps,
+        } => Ok(RateControlParams {
+            mode: RateControlMode::Quality,
+            bitrate_bps: target_kbps.saturating_mul(1000),
+            max_bitrate_bps: max_kbps.saturating_mul(1000),
+        }),
+        RateControl::ConstantQuality { .. } => Err(ada_remote_core::Error::Encoding(
+            "Constant quality is not supported by OpenH264".to_string(),
+        )),
+    }
+}
+
+/// Set a bitrate option on a running encoder, returning the OpenH264 status
+fn set_bitrate_option(
+    encoder: &mut Encoder,
+    option: openh264_sys2::ENCODER_OPTION,
+    bps: u32,
+) -> i32 {
+    let mut info = openh264_sys2::SBitrateInfo {
+        iLayer: openh264_sys2::SPATIAL_LAYER_ALL,
+        iBitrate: bps.min(i32::MAX as u32) as i32,
+    };
+    // SAFETY: the bitrate options take a pointer to SBitrateInfo, which
+    // outlives the call. They are only accepted once the encoder has seen
+    // its first frame.
+    unsafe {
+        encoder
+            .raw_api()
+            .set_option(option, std::ptr::addr_of_mut!(info).cast())
+    }
+}
+
+/// Write the color matrix and range into the SPS VUI so that any decoder
+/// reconstructs matching colors, and set up the temporal layers.
+///
+/// The bindings initialize OpenH264 on the first frame with default VUI
+/// settings and one temporal layer, and OpenH264 only rebuilds its SPS on a
+/// reset, which VUI changes alone don't trigger. So this encodes a throwaway
+/// frame, then applies the settings together with a flipped option that
+/// forces the reset, and flips it back.
+fn configure_stream(encoder: &mut Encoder, config: &EncoderConfig) -> Result<()> {
+    let width = config.width as usize;
+    let height = config.height as usize;
+    if width & 1 == 1 || height & 1 == 1 {
+        // Rejected on the first frame anyway
+        return Ok(());
+    }
+    let y = vec![128u8; width * height];
+    let chroma = vec![128u8; (width / 2) * (height / 2)];
+    let gray = YUVSlices::new(
+        (&y, &chroma, &chroma),
+        (width, height),
+        (width, width / 2, width / 2),
+    );
+    encoder.encode(&gray).map_err(|e| {
+        ada_remote_core::Error::Encoding(format!("Failed to start H.264 encoder: {}", e))
+    })?;
+
+    let mut params = openh264_sys2::SEncParamExt::default();
+    let option = openh264_sys2::ENCODER_OPTION_SVC_ENCODE_PARAM_EXT;
+    // SAFETY: PARAM_EXT reads and writes an SEncParamExt, which outlives the
+    // calls
+    let status = unsafe {
+        let api = encoder.raw_api();
+        let mut status = api.get_option(option, std::ptr::addr_of_mut!(params).cast());
+        let (primaries, transfer, matrix) = match config.color_space() {
+            ColorSpace::Bt601 => (
+                openh264_sys2::CP_SMPTE170M,
+                openh264_sys2::TRC_SMPTE170M,
+                openh264_sys2::CM_SMPTE170M,
+            ),
+            ColorSpace::Bt709 => (
+                openh264_sys2::CP_BT709,
+                openh264_sys2::TRC_BT709,
+                openh264_sys2::CM_BT709,
+            ),
+        };
+        let layer = &mut params.sSpatialLayers[0];
+        layer.bVideoSignalTypePresent = true;
+        layer.uiVideoFormat = openh264_sys2::VF_UNDEF as u8;
+        layer.bFullRange = config.color_range == ColorRange::Full;
+        layer.bColorDescriptionPresent = true;
+        layer.uiColorPrimaries = primaries as u8;
+        layer.uiTransferCharacteristics = transfer as u8;
+        layer.uiColorMatrix = matrix as u8;
+        // OpenH264 follows the same dyadic pattern as svc::layer_ids
+        params.iTemporalLayerNum = config.svc_temporal_layers.max(1) as i32;
+
+        let mut reset = params;
+        reset.bEnableBackgroundDetection = !params.bEnableBackgroundDetection;
+        status |= api.set_option(option, std::ptr::addr_of_mut!(reset).cast());
+        status | api.set_option(option, std::ptr::addr_of_mut!(params).cast())
+    };
+    if status != 0 {
+        return Err(ada_remote_core::Error::Encoding(
+            "Failed to set the H.264 color description and temporal layers".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+impl H264Encoder {
+    pub(crate) fn new() -> Self {
+        Self {
+            config: None,
+            encoder: None,
+            force_idr: false,
+            gop_position: 0,
+            max_bitrate_pending: false,
+            stats: StatsTracker::new(),
+            scene_detector: SceneChangeDetector::new(),
+            pool: None,
+        }
+    }
+
+    fn open(config: &EncoderConfig) -> Result<Encoder> {
+        let params = rate_control_params(config.rate_control())?;
+        // OpenH264's screen content tools assume a single reference, which
+        // temporal layering breaks
+        let usage = if config.svc_temporal_layers > 1 {
+            UsageType::CameraVideoRealTime
+        } else {
+            UsageType::ScreenContentRealTime
+        };
+        let encoder_config = openh264::encoder::EncoderConfig::new()
+            .usage_type(usage)
+            .rate_control_mode(params.mode)
+            .set_bitrate_bps(params.bitrate_bps)
+            .max_frame_rate(config.fps as f32)
+            .enable_skip_frame(false);
+
+        let mut encoder = Encoder::with_api_config(OpenH264API::from_source(), encoder_config)
+            .map_err(|e| {
+                ada_remote_core::Error::Encoding(format!("Failed to open H.264 encoder: {}", e))
+            })?;
+        configure_stream(&mut encoder, config)?;
+        Ok(encoder)
+    }
+}
+
+impl VideoEncoder for H264Encoder {
+    fn init(&mut self, config: EncoderConfig) -> Result<()> {
+        config.validate()?;
+        let config = config.clamp_to_limits();
+        self.encoder = Some(Self::open(&config)?);
+        // The first frame is always an IDR
+        self.force_idr = false;
+        self.gop_position = 0;
+        self.max_bitrate_pending = true;
+        self.stats = StatsTracker::new();
+        self.scene_detector.reset();
+        tracing::info!(
+            "H.264 encoder initialized ({:?} {:?} range, {:?})",
+            config.color_space(),
+            config.color_range,
+            config.chroma
+        );
+        self.config = Some(config);
+        Ok(())
+    }
+
+    fn encode(&mut self, frame: RawFrame) -> Result<EncodedFrame> {
+        let (Some(config), Some(encoder)) = (&self.config, &mut self.encoder) else {
+            return Err(ada_remote_core::Error::Encoding(
+                "H.264 encoder not initialized".to_string(),
+            ));
+        };
+
+        config.check_resolution(&frame)?;
+        let width = frame.width as usize;
+        let height = frame.height as usize;
+        if width & 1 == 1 || height & 1 == 1 {
+            return Err(ada_remote_core::Error::Encoding(format!(
+                "H.264 requires even dimensions, got {}x{}",
+                width, height
+            )));
+        }
+        let expected = frame.format.frame_size(frame.width, frame.height);
+        if frame.data.len() != expected {
+            return Err(ada_remote_core::Error::Encoding(format!(
+                "Frame size mismatch: expected {} bytes for {}x{} {:?}, got {}",
+                expected,
+                width,
+                height,
+                frame.format,
+                frame.data.len()
+            )));
+        }
+
+        // I420 is fed to OpenH264 as-is; anything else is converted first
+        let converted;
+        let planes = match frame.format {
+            PixelFormat::I420 => i420_planes(&frame.data, width, height),
+            _ => {
+                converted = to_i420(&frame, config.color_space(), config.color_range);
+                (&converted.y[..], &converted.u[..], &converted.v[..])
+            }
+        };
+        let chroma_width = width / 2;
+        let source = YUVSlices::new(planes, (width, height), (width, chroma_width, chroma_width));
+
+        // Scheduled IDRs are independent of force_keyframe() calls
+        let mut force_idr = std::mem::take(&mut self.force_idr);
+        if let Some(interval) = config.keyframe_interval {
+            force_idr |= self.gop_position == 0;
+            self.gop_position = (self.gop_position + 1) % interval;
+        }
+        if let Some(threshold) = config.scene_change_threshold {
+            let difference = self.scene_detector.difference(planes.0, width, height);
+            if difference > threshold {
+                tracing::debug!("Scene change ({:.2}), forcing keyframe", difference);
+                force_idr = true;
+            }
+        }
+        if force_idr {
+            encoder.force_intra_frame();
+        }
+
+        let bitstream = encoder
+            .encode_at(&source, Timestamp::from_millis(frame.timestamp / 1000))
+            .map_err(|e| ada_remote_core::Error::Encoding(format!("H.264 encode failed: {}", e)))?;
+
+        // Parameter sets come as a layer of their own, always tagged 0
+        let temporal_layer = (0..bitstream.num_layers())
+            .filter_map(|i| bitstream.layer(i))
+            .map(|layer| layer.raw_info().uiTemporalId)
+            .max()
+            .unwrap_or(0);
+        let encoded = EncodedFrame {
+            is_keyframe: matches!(bitstream.frame_type(), FrameType::IDR | FrameType::I),
+            data: bitstream.to_vec(),
+            timestamp: frame.timestamp,
+            temporal_layer,
+        };
+
+        if std::mem::take(&mut self.max_bitrate_pending) {
+            let params = rate_control_params(config.rate_control())?;
+            let option = openh264_sys2::ENCODER_OPTION_MAX_BITRATE;
+            if set_bitrate_option(encoder, option, params.max_bitrate_bps) != 0 {
+                tracing::warn!("Failed to apply H.264 max bitrate");
+            }
+        }
+
+        let mut statistics = openh264_sys2::SEncoderStatistics::default();
+        // SAFETY: GET_STATISTICS fills the SEncoderStatistics it points to
+        let status = unsafe {
+            encoder.raw_api().get_option(
+                openh264_sys2::ENCODER_OPTION_GET_STATISTICS,
+                std::ptr::addr_of_mut!(statistics).cast(),
+            )
+        };
+        if status == 0 {
+            self.stats.set_last_qp(statistics.uiAverageFrameQP);
+        }
+        self.stats.record(&encoded);
+
+        if let Some(pool) = &self.pool {
+            pool.recycle(frame.data);
+        }
+        Ok(encoded)
+    }
+
+    fn force_keyframe(&mut self) -> Result<()> {
+        self.force_idr = true;
+        Ok(())
+    }
+
+    fn set_frame_pool(&mut self, pool: FramePool) {
+        self.pool = Some(pool);
+    }
+
+    fn reconfigure(&mut self, width: u32, height: u32) -> Result<()> {
+        let Some(config) = &mut self.config else {
+            return Err(ada_remote_core::Error::Encoding(
+                "H.264 encoder not initialized".to_string(),
+            ));
+        };
+        if width & 1 == 1 || height & 1 == 1 {
+            return Err(ada_remote_core::Error::Encoding(format!(
+                "H.264 requires even dimensions, got {}x{}",
+                width, height
+            )));
+        }
+        *config = config.resized(width, height)?;
+        // OpenH264 would reinitialize in place on the next frame, but with
+        // default VUI; reopening keeps the color description and starts with
+        // an IDR
+        self.encoder = Some(Self::open(config)?);
+        self.max_bitrate_pending = true;
+        self.scene_detector.reset();
+        tracing::info!("H.264 encoder reconfigured to {}x{}", width, height);
+        Ok(())
+    }
+
+    fn set_bitrate(&mut self, bitrate: u32) -> Result<()> {
+        let Some(config) = &mut self.config else {
+            return Err(ada_remote_core::Error::Encoding(
+                "H.264 encoder not initialized".to_string(),
+            ));
+        };
+        config.set_target_bitrate(bitrate)?;
+        let params = rate_control_params(config.rate_control())?;
+
+        let encoder = self.encoder.as_mut().expect("encoder opened in init");
+        let status = set_bitrate_option(
+            encoder,
+            openh264_sys2::ENCODER_OPTION_MAX_BITRATE,
+            params.max_bitrate_bps,
+        ) | set_bitrate_option(
+            encoder,
+            openh264_sys2::ENCODER_OPTION_BITRATE,
+            params.bitrate_bps,
+        );
+        if status != 0 {
+            // Not running yet; the new rates are applied on reopen
+            *encoder = Self::open(config)?;
+            self.max_bitrate_pending = true;
+        }
+        Ok(())
+    }
+
+    fn effective_config(&self) -> Option<EncoderConfig> {
+        self.config.clone()
+    }
+
+    fn stats(&self) -> EncoderStats {
+        self.stats.stats()
+    }
+
+    fn cleanup(&mut self) -> Result<()> {
+        self.encoder = None;
+        tracing::info!("H.264 encoder cleaned up");
+        Ok(())
+    }
+}
+
+/// Whether OpenH264 can open an encoder and a decoder
+pub(crate) fn probe() -> (bool, bool) {
+    let config = openh264::encoder::EncoderConfig::new();
+    let can_encode = Encoder::with_api_config(OpenH264API::from_source(), config).is_ok();
+    let can_decode = Decoder::new().is_ok();
+    (can_encode, can_decode)
+}
+
+/// NAL unit type of an IDR slice
+const NAL_TYPE_IDR: u8 = 5;
+
+pub(crate) struct H264Decoder {
+    config: Option<DecoderConfig>,
+    decoder: Option<Decoder>,
+    seen_keyframe: bool,
+}
+
+impl H264Decoder {
+    pub(crate) fn new() -> Self {
+        Self {
+            config: None,
+            decoder: None,
+            seen_keyframe: false,
+        }
+    }
+}
+
+/// Whether an Annex-B access unit contains an IDR slice
+fn contains_idr(data: &[u8]) -> bool {
+    openh264::nal_units(data).any(|nal| {
+        // Skip the 00 00 01 / 00 00 00 01 start code
+        let header = nal.iter().skip_while(|b| **b == 0).nth(1);
+        header.is_some_and(|h| h & 0x1F == NAL_TYPE_IDR)
+    })
+}
+
+impl VideoDecoder for H264Decoder {
+    fn init(&mut self, config: DecoderConfig) -> Result<()> {
+        let decoder = Decoder::new().map_err(|e| {
+            ada_remote_core::Error::Decoding(format!("Failed to open H.264 decoder: {}", e))
+        })?;
+        self.decoder = Some(decoder);
+        self.seen_keyframe = false;
+        self.config = Some(config);
+        tracing::info!("H.264 decoder initialized");
+        Ok(())
+    }
+
+    fn decode(&mut self, frame: EncodedFrame) -> Result<RawFrame> {
+        let (Some(config), Some(decoder)) = (&self.config, &mut self.decoder) else {
+            return Err(ada_remote_core::Error::Decoding(
+                "H.264 decoder not initialized".to_string(),
+            ));
+        };
+
+        if !self.seen_keyframe {
+            if !contains_idr(&frame.data) {
+                return Err(ada_remote_core::Error::KeyframeNeeded(
+                    "Cannot start decoding without a keyframe".to_string(),
+                ));
+            }
+            self.seen_keyframe = true;
+        }
+
+        // SPS/PPS arrive inline ahead of the IDR slice. A corrupt frame
+        // breaks the frames predicted from it, so they are refused until
+        // the next keyframe rather than decoded into garbage.
+        let decoded = match decoder.decode(&frame.data) {
+            Ok(decoded) => decoded,
+            Err(e) => {
+                self.seen_keyframe = false;
+                return Err(ada_remote_core::Error::KeyframeNeeded(format!(
+                    "H.264 decode failed: {}",
+                    e
+                )));
+            }
+        };
+        let yuv = decoded.ok_or_else(|| {
+            ada_remote_core::Error::Decoding("H.264 decoder produced no picture".to_string())
+        })?;
+
+        let (width, height) = yuv.dimensions();
+        let planes = (yuv.y(), yuv.u(), yuv.v());
+        let data = match config.output_format {
+            PixelFormat::Rgba => {
+                let space = config.color_space(height as u32);
+                i420_to_rgba(
+                    planes,
+                    yuv.strides(),
+                    width,
+                    height,
+                    space,
+                    config.color_range,
+                )
+            }
+            format => from_i420(
+                I420Frame::from_strided(planes, yuv.strides(), width, height),
+                format,
+                height,
+                config.color_space(height as u32),
+                config.color_range,
+            ),
+        };
+
+        Ok(RawFrame {
+            data,
+            format: config.output_format,
+            width: width as u32,
+            height: height as u32,
+            timestamp: frame.timestamp,
+        })
+    }
+
+    fn cleanup(&mut self) -> Result<()> {
+        self.decoder = None;
+        tracing::info!("H.264 decoder cleaned up");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DecoderError;
+
+    fn gradient_frame(width: u32, height: u32, shift: u32, timestamp: u64) -> RawFrame {
+        let mut data = Vec::with_capacity((width * height * 4) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                data.extend_from_slice(&[
+                    ((x + shift) * 255 / width) as u8,
+                    (y * 255 / height) as u8,
+                    128,
+                    255,
+                ]);
+            }
+        }
+        RawFrame {
+            data,
+            format: PixelFormat::Rgba,
+            width,
+            height,
+            timestamp,
+        }
+    }
+
+    fn init_encoder_at(width: u32, height: u32) -> H264Encoder {
+        let mut encoder = H264Encoder::new();
+        encoder
+            .init(EncoderConfig {
+                width,
+                height,
+                ..Default::default()
+            })
+            .unwrap();
+        encoder
+    }
+
+    fn init_encoder() -> H264Encoder {
+        init_encoder_at(128, 96)
+    }
+
+    #[test]
+    fn test_h264_encodes_keyframe_then_smaller_deltas() {
+        let mut encoder = init_encoder();
+
+        let first = encoder.encode(gradient_frame(128, 96, 0, 0)).unwrap();
+        assert!(first.is_keyframe);
+        assert_eq!(&first.data[..4], &[0, 0, 0, 1]);
+
+        for i in 1..5 {
+            let frame = encoder
+                .encode(gradient_frame(128, 96, i, i as u64 * 33_000))
+                .unwrap();
+            assert!(!frame.is_keyframe);
+            assert!(frame.data.len() < first.data.len());
+        }
+    }
+
+    fn init_decoder() -> H264Decoder {
+        let mut decoder = H264Decoder::new();
+        decoder.init(DecoderConfig::default()).unwrap();
+        decoder
+    }
+
+    #[test]
+    fn test_h264_round_trip() {
+        let mut encoder = init_encoder();
+        let mut decoder = init_decoder();
+
+        for i in 0..3 {
+            let original = gradient_frame(128, 96, i, i as u64);
+            let decoded = decoder
+                .decode(encoder.encode(original.clone()).unwrap())
+                .unwrap();
+            assert_eq!((decoded.width, decoded.height), (128, 96));
+            assert_eq!(decoded.timestamp, i as u64);
+
+            let total_error: u64 = original
+                .data
+                .iter()
+                .zip(&decoded.data)
+                .map(|(a, b)| a.abs_diff(*b) as u64)
+                .sum();
+            let average_error = total_error as f64 / original.data.len() as f64;
+            assert!(average_error < 6.0, "average error {}", average_error);
+        }
+    }
+
+    fn color_patch(width: u32, height: u32, rgb: [u8; 3]) -> RawFrame {
+        RawFrame {
+            data: [rgb[0], rgb[1], rgb[2], 255].repeat((width * height) as usize),
+            format: PixelFormat::Rgba,
+            width,
+            height,
+            timestamp: 0,
+        }
+    }
+
+    /// Largest channel difference from `rgb` at the center of a decoded patch
+    fn patch_drift(space: ColorSpace, decoder_space: ColorSpace, rgb: [u8; 3]) -> u8 {
+        let mut encoder = H264Encoder::new();
+        encoder
+            .init(EncoderConfig {
+                width: 64,
+                height: 64,
+                color_space: Some(space),
+                color_range: ColorRange::Limited,
+                ..Default::default()
+            })
+            .unwrap();
+        let mut decoder = H264Decoder::new();
+        decoder
+            .init(DecoderConfig {
+                color_space: Some(decoder_space),
+                color_range: ColorRange::Limited,
+                ..Default::default()
+            })
+            .unwrap();
+
+        let encoded = encoder.encode(color_patch(64, 64, rgb)).unwrap();
+        assert!(encoded.is_keyframe);
+        let decoded = decoder.decode(encoded).unwrap();
+        let center = ((32 * 64 + 32) * 4) as usize;
+        (0..3)
+            .map(|c| decoded.data[center + c].abs_diff(rgb[c]))
+            .max()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_h264_color_settings_round_trip() {
+        let rgb = [200, 40, 90];
+        for space in [ColorSpace::Bt601, ColorSpace::Bt709] {
+            let drift = patch_drift(space, space, rgb);
+            assert!(drift <= 3, "{:?} drifted by {}", space, drift);
+        }
+
+        // Decoding with the other matrix shifts the color visibly
+        let drift = patch_drift(ColorSpace::Bt709, ColorSpace::Bt601, rgb);
+        assert!(drift > 10, "mismatch drifted only by {}", drift);
+    }
+
+    #[test]
+    fn test_h264_signals_color_description() {
+        let sps = |space| {
+            let mut encoder = H264Encoder::new();
+            encoder
+                .init(EncoderConfig {
+                    width: 64,
+                    height: 64,
+                    color_space: Some(space),
+                    ..Default::default()
+                })
+                .unwrap();
+            let encoded = encoder.encode(color_patch(64, 64, [0, 0, 0])).unwrap();
+            let sps = openh264::nal_units(&encoded.data)
+                .find(|nal| {
+                    let header = nal.iter().skip_while(|b| **b == 0).nth(1);
+                    header.is_some_and(|h| h & 0x1F == 7)
+                })
+                .expect("keyframe carries an SPS")
+                .to_vec();
+            sps
+        };
+        assert_ne!(sps(ColorSpace::Bt601), sps(ColorSpace::Bt709));
+    }
+
+    #[test]
+    fn test_h264_yuv_input_and_output() {
+        let mut encoder = init_encoder();
+        let mut decoder = H264Decoder::new();
+        decoder
+            .init(DecoderConfig {
+                output_format: PixelFormat::Nv12,
+                ..Default::default()
+            })
+            .unwrap();
+
+        let rgba = gradient_frame(128, 96, 0, 0);
+        let yuv = to_i420(&rgba, ColorSpace::Bt601, ColorRange::Limited);
+        let i420 = RawFrame {
+            data: from_i420(
+                yuv,
+                PixelFormat::I420,
+                96,
+                ColorSpace::Bt601,
+                ColorRange::Limited,
+            ),
+            format: PixelFormat::I420,
+            ..rgba
+        };
+
+        let decoded = decoder
+            .decode(encoder.encode(i420.clone()).unwrap())
+            .unwrap();
+        assert_eq!(decoded.format, PixelFormat::Nv12);
+        assert_eq!(decoded.data.len(), PixelFormat::Nv12.frame_size(128, 96));
+
+        let luma_error: u64 = i420.data[..128 * 96]
+            .iter()
+            .zip(&decoded.data)
+            .map(|(a, b)| a.abs_diff(*b) as u64)
+            .sum();
+        assert!(luma_error / ((128 * 96) as u64) < 4);
+    }
+
+    #[test]
+    fn test_h264_base_temporal_layer_decodes_alone() {
+        let mut encoder = H264Encoder::new();
+        encoder
+            .init(EncoderConfig {
+                width: 128,
+                height: 96,
+                svc_temporal_layers: 3,
+                ..Default::default()
+            })
+            .unwrap();
+        let encoded: Vec<EncodedFrame> = (0..12)
+            .map(|i| {
+                encoder
+                    .encode(gradient_frame(128, 96, i, i as u64 * 33_000))
+                    .unwrap()
+            })
+            .collect();
+        let layers: Vec<u8> = encoded.iter().map(|frame| frame.temporal_layer).collect();
+        assert_eq!(layers, [0, 2, 1, 2, 0, 2, 1, 2, 0, 2, 1, 2]);
+
+        // A congested link keeps only the base layer: a quarter of the
+        // frames, predicted from one another
+        let mut decoder = init_decoder();
+        let base: Vec<EncodedFrame> = encoded
+            .into_iter()
+            .filter(|frame| frame.temporal_layer == 0)
+            .collect();
+        assert!(base[1..].iter().all(|frame| !frame.is_keyframe));
+        for (i, frame) in base.into_iter().enumerate() {
+            let decoded = decoder.decode(frame).unwrap();
+            assert_eq!((decoded.width, decoded.height), (128, 96));
+            assert_eq!(decoded.timestamp, i as u64 * 4 * 33_000);
+        }
+    }
+
+    #[test]
+    fn test_h264_decoder_rejects_leading_delta_frame() {
+        let mut encoder = init_encoder();
+        encoder.encode(gradient_frame(128, 96, 0, 0)).unwrap();
+        let delta = encoder.encode(gradient_frame(128, 96, 1, 1)).unwrap();
+        assert!(!delta.is_keyframe);
+
+        let err = init_decoder().decode(delta).unwrap_err();
+        assert!(err.to_string().contains("keyframe"));
+        assert_eq!(DecoderError::classify(&err), DecoderError::NeedsKeyframe);
+    }
+
+    #[test]
+    fn test_h264_keyframe_interval() {
+        let mut encoder = H264Encoder::new();
+        encoder
+            .init(EncoderConfig {
+                width: 128,
+                height: 96,
+                keyframe_interval: Some(10),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let keyframes: Vec<u32> = (0..25)
+            .filter(|&i| {
+                encoder
+                    .encode(gradient_frame(128, 96, i, i as u64))
+                    .unwrap()
+                    .is_keyframe
+            })
+            .collect();
+        assert_eq!(keyframes, vec![0, 10, 20]);
+    }
+
+    #[test]
+    fn test_h264_force_keyframe_and_set_bitrate() {
+        let mut encoder = init_encoder();
+        encoder.encode(gradient_frame(128, 96, 0, 0)).unwrap();
+
+        encoder.set_bitrate(500).unwrap();
+        assert_eq!(encoder.effective_config().unwrap().bitrate, 500);
+        assert!(
+            !encoder
+                .encode(gradient_frame(128, 96, 1, 1))
+                .unwrap()
+                .is_keyframe
+        );
+
+        encoder.force_keyframe().unwrap();
+        assert!(
+            encoder
+                .encode(gradient_frame(128, 96, 2, 2))
+                .unwrap()
+                .is_keyframe
+        );
+        assert!(
+            !encoder
+                .encode(gradient_frame(128, 96, 3, 3))
+                .unwrap()
+                .is_keyframe
+        );
+    }
+
+    #[test]
+    fn test_rate_control_translation() {
+        let cbr = rate_control_params(RateControl::Cbr { kbps: 1500 }).unwrap();
+        assert!(matches!(cbr.mode, RateControlMode::Bitrate));
+        assert_eq!(
+            (cbr.bitrate_bps, cbr.max_bitrate_bps),
+            (1_500_000, 1_500_000)
+        );
+
+        let vbr = rate_control_params(RateControl::Vbr {
+            target_kbps: 1000,
+            max_kbps: 4000,
+        })
+        .unwrap();
+        assert!(matches!(vbr.mode, RateControlMode::Quality));
+        assert_eq!(
+            (vbr.bitrate_bps, vbr.max_bitrate_bps),
+            (1_000_000, 4_000_000)
+        );
+
+        assert!(rate_control_params(RateControl::ConstantQuality { q: 20 }).is_err());
+    }
+
+    #[test]
+    fn test_h264_vbr_encodes() {
+        let mut encoder = H264Encoder::new();
+        encoder
+            .init(EncoderConfig {
+                width: 128,
+                height: 96,
+                rate_control: Some(RateControl::Vbr {
+                    target_kbps: 500,
+                    max_kbps: 1000,
+                }),
+                ..Default::default()
+            })
+            .unwrap();
+        for i in 0..3 {
+            encoder
+                .encode(gradient_frame(128, 96, i, i as u64))
+                .unwrap();
+        }
+
+        encoder.set_bitrate(800).unwrap();
+        assert_eq!(
+            encoder.effective_config().unwrap().rate_control(),
+            RateControl::Vbr {
+                target_kbps: 800,
+                max_kbps: 1000
+            }
+        );
+    }
+
+    #[test]
+    fn test_h264_stats() {
+        let mut encoder = init_encoder();
+        for i in 0..30 {
+            encoder
+                .encode(gradient_frame(128, 96, i, i as u64 * 33_333))
+                .unwrap();
+        }
+
+        let stats = encoder.stats();
+        assert_eq!(stats.frames_encoded, 30);
+        assert_eq!(stats.keyframes_emitted, 1);
+        assert!(stats.bytes_out > 0);
+        assert!(stats.average_bitrate_kbps > 0);
+        assert!(stats.last_qp.is_some());
+    }
+
+    #[test]
+    fn test_h264_reconfigure_mid_stream() {
+        let mut encoder = init_encoder_at(1280, 720);
+        encoder.encode(gradient_frame(1280, 720, 0, 0)).unwrap();
+        assert!(
+            !encoder
+                .encode(gradient_frame(1280, 720, 1, 1))
+                .unwrap()
+                .is_keyframe
+        );
+
+        // Frames at the new size are rejected until reconfigure is called
+        let err = encoder
+            .encode(gradient_frame(1920, 1080, 2, 2))
+            .unwrap_err();
+        assert!(err.to_string().contains("reconfigure"));
+
+        encoder.reconfigure(1920, 1080).unwrap();
+        let encoded = encoder.encode(gradient_frame(1920, 1080, 3, 3)).unwrap();
+        assert!(encoded.is_keyframe);
+
+        let decoded = init_decoder().decode(encoded).unwrap();
+        assert_eq!((decoded.width, decoded.height), (1920, 1080));
+    }
+
+    #[test]
+    fn test_h264_scene_change_forces_keyframe() {
+        let mut encoder = H264Encoder::new();
+        encoder
+            .init(EncoderConfig {
+                width: 128,
+                height: 96,
+                scene_change_threshold: Some(0.3),
+                ..Default::default()
+            })
+            .unwrap();
+
+        encoder.encode(gradient_frame(128, 96, 0, 0)).unwrap();
+        assert!(
+            !encoder
+                .encode(gradient_frame(128, 96, 1, 1))
+                .unwrap()
+                .is_keyframe
+        );
+
+        let mut inverted = gradient_frame(128, 96, 1, 2);
+        for pixel in inverted.data.chunks_exact_mut(4) {
+            pixel[..3].iter_mut().for_each(|c| *c = 255 - *c);
+        }
+        assert!(encoder.encode(inverted).unwrap().is_keyframe);
+    }
+}
@@ -0,0 +1,232 @@
+//! Jitter buffer between the network and the decoder
+//!
+//! Frames are held for a short delay so that ones arriving out of order can
+//! be put back in timestamp order, then released one per frame interval. The
+//! delay follows the measured arrival jitter (the RFC 3550 estimator).
+
+use crate::EncodedFrame;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+/// Jitter buffer limits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JitterConfig {
+    /// Release rate
+    pub fps: u32,
+    pub min_delay: Duration,
+    pub max_delay: Duration,
+    /// Frames held at most; the oldest is dropped beyond this
+    pub max_frames: usize,
+}
+
+impl Default for JitterConfig {
+    fn default() -> Self {
+        Self {
+            fps: 30,
+            min_delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(200),
+            max_frames: 32,
+        }
+    }
+}
+
+/// Reorders and paces encoded frames by timestamp
+#[derive(Debug)]
+pub struct JitterBuffer {
+    config: JitterConfig,
+    /// Held frames by timestamp, with their arrival time
+    frames: BTreeMap<u64, (EncodedFrame, Instant)>,
+    delay: Duration,
+    /// Smoothed arrival jitter in microseconds
+    jitter_us: f64,
+    last_arrival: Option<(Instant, u64)>,
+    last_released: Option<u64>,
+    next_release: Option<Instant>,
+    dropped: u64,
+}
+
+impl JitterBuffer {
+    pub fn new(config: JitterConfig) -> Self {
+        Self {
+            delay: config.min_delay,
+            config,
+            frames: BTreeMap::new(),
+            jitter_us: 0.0,
+            last_arrival: None,
+            last_released: None,
+            next_release: None,
+            dropped: 0,
+        }
+    }
+
+    /// Frames currently held
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Frames dropped as late, duplicated or over capacity
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Current hold time
+    pub fn delay(&self) -> Duration {
+        self.delay
+    }
+
+    fn frame_interval(&self) -> Duration {
+        Duration::from_secs(1) / self.config.fps.max(1)
+    }
+
+    /// Add a frame that arrived at `now`
+    pub fn push(&mut self, frame: EncodedFrame, now: Instant) {
+        let timestamp = frame.timestamp;
+        self.update_jitter(timestamp, now);
+
+        // Anything at or before the last released frame can no longer be
+        // shown in order
+        let late = self.last_released.is_some_and(|last| timestamp <= last);
+        if late || self.frames.contains_key(&timestamp) {
+            self.dropped += 1;
+            return;
+        }
+
+        self.frames.insert(timestamp, (frame, now));
+        while self.frames.len() > self.config.max_frames {
+            self.frames.pop_first();
+            self.dropped += 1;
+        }
+    }
+
+    fn update_jitter(&mut self, timestamp: u64, now: Instant) {
+        if let Some((last_arrival, last_timestamp)) = self.last_arrival {
+            // Difference in transit time between this frame and the previous
+            let arrival_us = now.saturating_duration_since(last_arrival).as_micros() as f64;
+            let spacing_us = timestamp as f64 - last_timestamp as f64;
+            let transit_us = (arrival_us - spacing_us).abs();
+            self.jitter_us += (transit_us - self.jitter_us) / 16.0;
+
+            let target = Duration::from_micros((3.0 * self.jitter_us) as u64);
+            self.delay = target.clamp(self.config.min_delay, self.config.max_delay);
+        }
+        if self.last_arrival.is_none_or(|(_, last)| timestamp > last) {
+            self.last_arrival = Some((now, timestamp));
+        }
+    }
+
+    /// The next frame in timestamp order, once the longest held frame has
+    /// waited out the delay and a frame interval has passed since the last
+    pub fn pop(&mut self, now: Instant) -> Option<EncodedFrame> {
+        let held_since = self.frames.values().map(|(_, arrived)| *arrived).min()?;
+        if now < held_since + self.delay {
+            return None;
+        }
+        if self.next_release.is_some_and(|next| now < next) {
+            return None;
+        }
+
+        let (timestamp, (frame, _)) = self.frames.pop_first()?;
+        self.last_released = Some(timestamp);
+        let interval = self.frame_interval();
+        // Keep a steady cadence, but do not bank time while idle
+        self.next_release = Some(match self.next_release {
+            Some(next) if now < next + interval => next + interval,
+            _ => now + interval,
+        });
+        Some(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(index: u64) -> EncodedFrame {
+        EncodedFrame {
+            data: vec![index as u8],
+            timestamp: index * 33_333,
+            is_keyframe: index == 0,
+            temporal_layer: 0,
+        }
+    }
+
+    /// Pop everything releasable from `start`, stepping a frame at a time
+    fn drain(buffer: &mut JitterBuffer, start: Instant, steps: u32) -> Vec<u8> {
+        let mut out = Vec::new();
+        for step in 0..steps {
+            let now = start + Duration::from_millis(34) * step;
+            while let Some(frame) = buffer.pop(now) {
+                out.push(frame.data[0]);
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn test_reorders_shuffled_frames() {
+        let mut buffer = JitterBuffer::new(JitterConfig::default());
+        let start = Instant::now();
+        for index in [1, 0, 3, 2, 5, 4] {
+            buffer.push(frame(index), start);
+        }
+        assert_eq!(buffer.depth(), 6);
+
+        // Nothing comes out before the delay
+        assert!(buffer.pop(start).is_none());
+        let delay = buffer.delay();
+        assert_eq!(
+            drain(&mut buffer, start + delay, 10),
+            vec![0, 1, 2, 3, 4, 5]
+        );
+        assert_eq!(buffer.dropped(), 0);
+    }
+
+    #[test]
+    fn test_drops_late_and_duplicate_frames() {
+        let mut buffer = JitterBuffer::new(JitterConfig::default());
+        let start = Instant::now();
+        for index in 0..3 {
+            buffer.push(frame(index), start);
+        }
+        let after = start + Duration::from_millis(500);
+        assert_eq!(drain(&mut buffer, after, 3), vec![0, 1, 2]);
+
+        // A frame older than the last one shown, and a duplicate
+        buffer.push(frame(1), after);
+        buffer.push(frame(4), after);
+        buffer.push(frame(4), after);
+        assert_eq!(buffer.dropped(), 2);
+        assert_eq!(buffer.depth(), 1);
+        assert_eq!(
+            drain(&mut buffer, after + Duration::from_secs(1), 2),
+            vec![4]
+        );
+    }
+
+    #[test]
+    fn test_delay_follows_jitter() {
+        let start = Instant::now();
+
+        let mut steady = JitterBuffer::new(JitterConfig::default());
+        for index in 0..50 {
+            steady.push(frame(index), start + Duration::from_micros(index * 33_333));
+        }
+        assert_eq!(steady.delay(), JitterConfig::default().min_delay);
+
+        // Arrivals alternately 30 ms early and late
+        let mut jittery = JitterBuffer::new(JitterConfig::default());
+        for index in 0..50 {
+            let skew = if index % 2 == 0 { 0 } else { 60_000 };
+            jittery.push(
+                frame(index),
+                start + Duration::from_micros(index * 33_333 + skew),
+            );
+        }
+        assert!(
+            jittery.delay() > Duration::from_millis(50),
+            "{:?}",
+            jittery.delay()
+        );
+        assert!(jittery.delay() <= JitterConfig::default().max_delay);
+    }
+}
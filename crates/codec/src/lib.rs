@@ -1,21 +1,243 @@
 //! Ada Remote Video Codec
 //!
-//! Video encoding and decoding using H.264 and VP9.
+//! Video encoding and decoding using H.264 (OpenH264), VP9 and AV1.
 //! Hardware acceleration used when available.
 
-use ada_remote_core::Result;
+use ada_remote_core::{Error, FramePool, Result, VideoQuality};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+mod async_encoder;
+mod convert;
+mod h264;
+mod jitter;
+mod probe;
+mod scale;
+mod scene;
+mod stats;
+mod svc;
+
+use convert::convert_frame;
+use h264::{H264Decoder, H264Encoder};
+use stats::StatsTracker;
+use svc::TemporalPattern;
+
+pub use async_encoder::{AsyncVideoEncoder, BlockingEncoder, EncodeFuture};
+pub use convert::{rgba_to_i420, rgba_to_nv12};
+pub use jitter::{JitterBuffer, JitterConfig};
+pub use probe::{probe_codecs, CodecCapability};
+pub use scale::scale_rgba;
+pub use stats::EncoderStats;
+pub use svc::MAX_TEMPORAL_LAYERS;
 
 /// Video codec type
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum CodecType {
     /// H.264 (AVC) - widely supported, good hardware acceleration
     H264,
     /// VP9 - royalty-free, good compression
     VP9,
+    /// AV1 - best compression for bandwidth-constrained links, limited hardware support
+    AV1,
+    /// Uncompressed RGBA pass-through - lossless, for debugging and fast LANs
+    Raw,
+}
+
+impl CodecType {
+    /// Whether the codec profile we target supports the given chroma subsampling
+    pub fn supports_chroma(&self, chroma: ChromaSubsampling) -> bool {
+        match (self, chroma) {
+            (_, ChromaSubsampling::Yuv420) => true,
+            // H.264 4:4:4 needs the High 4:4:4 Predictive profile, which
+            // hardware encoders and most decoders don't support
+            (CodecType::H264, ChromaSubsampling::Yuv444) => false,
+            // VP9 profile 1 supports 4:4:4
+            (CodecType::VP9, ChromaSubsampling::Yuv444) => true,
+            // AV1 High profile supports 4:4:4
+            (CodecType::AV1, ChromaSubsampling::Yuv444) => true,
+            // Raw frames are never subsampled
+            (CodecType::Raw, ChromaSubsampling::Yuv444) => true,
+        }
+    }
+
+    /// Whether the codec backend supports the given rate control mode
+    ///
+    /// | Codec | CBR | VBR | Constant quality |
+    /// |-------|-----|-----|------------------|
+    /// | H.264 (OpenH264) | yes | yes | no |
+    /// | VP9 (libvpx) | yes | yes | q 0-63 |
+    /// | AV1 | yes | yes | q 0-255 |
+    /// | Raw | ignored | ignored | ignored |
+    pub fn supports_rate_control(&self, rate_control: RateControl) -> bool {
+        match (self, rate_control) {
+            (_, RateControl::Cbr { .. } | RateControl::Vbr { .. }) => true,
+            // OpenH264 exposes no fixed-QP mode
+            (CodecType::H264, RateControl::ConstantQuality { .. }) => false,
+            (CodecType::VP9, RateControl::ConstantQuality { q }) => q <= 63,
+            (CodecType::AV1, RateControl::ConstantQuality { .. }) => true,
+            (CodecType::Raw, RateControl::ConstantQuality { .. }) => true,
+        }
+    }
+
+    /// Whether the codec backend can split the stream into temporal layers
+    pub fn supports_temporal_layers(&self) -> bool {
+        match self {
+            // OpenH264 temporal scalability
+            CodecType::H264 => true,
+            // Every raw frame stands alone, so any subset decodes
+            CodecType::Raw => true,
+            // No encoder behind these yet
+            CodecType::VP9 | CodecType::AV1 => false,
+        }
+    }
+
+    /// Resolution, frame rate and bitrate limits for the codec level we target
+    pub fn limits(&self) -> CodecLimits {
+        match self {
+            // OpenH264 rejects anything above 3840x2160
+            CodecType::H264 => CodecLimits {
+                max_width: 3840,
+                max_height: 2160,
+                max_fps: 60,
+                min_bitrate: 100,
+                max_bitrate: 50_000,
+            },
+            // Level 6.1
+            CodecType::VP9 => CodecLimits {
+                max_width: 8192,
+                max_height: 4352,
+                max_fps: 120,
+                min_bitrate: 100,
+                max_bitrate: 80_000,
+            },
+            // Level 6.0
+            CodecType::AV1 => CodecLimits {
+                max_width: 8192,
+                max_height: 4352,
+                max_fps: 60,
+                min_bitrate: 50,
+                max_bitrate: 60_000,
+            },
+            CodecType::Raw => CodecLimits {
+                max_width: 8192,
+                max_height: 8192,
+                max_fps: 240,
+                min_bitrate: 1,
+                max_bitrate: u32::MAX,
+            },
+        }
+    }
+}
+
+/// Encoding limits of a codec
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CodecLimits {
+    /// Maximum frame width in pixels
+    pub max_width: u32,
+    /// Maximum frame height in pixels
+    pub max_height: u32,
+    /// Maximum frame rate
+    pub max_fps: u32,
+    /// Minimum bitrate in kbps
+    pub min_bitrate: u32,
+    /// Maximum bitrate in kbps
+    pub max_bitrate: u32,
+}
+
+/// YUV color range used for RGBA→YUV conversion
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ColorRange {
+    /// Limited (TV) range: Y in 16-235, UV in 16-240
+    #[default]
+    Limited,
+    /// Full (PC) range: 0-255, better for screen content
+    Full,
+}
+
+/// YUV matrix coefficients used for RGBA↔YUV conversion
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ColorSpace {
+    /// BT.601, the standard-definition convention
+    Bt601,
+    /// BT.709, the HD convention
+    #[default]
+    Bt709,
+}
+
+impl ColorSpace {
+    /// Conventional matrix for a picture `height` lines tall: BT.709 from
+    /// 720p up, BT.601 below
+    pub fn for_height(height: u32) -> Self {
+        if height >= 720 {
+            ColorSpace::Bt709
+        } else {
+            ColorSpace::Bt601
+        }
+    }
+}
+
+/// Chroma subsampling used for RGBA→YUV conversion
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ChromaSubsampling {
+    /// 4:2:0 - half resolution chroma, smallest bitrate
+    #[default]
+    Yuv420,
+    /// 4:4:4 - full resolution chroma, keeps text crisp
+    Yuv444,
+}
+
+/// Encoder rate control mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RateControl {
+    /// Constant bitrate, for live streams on constrained links
+    Cbr { kbps: u32 },
+    /// Variable bitrate around a target, capped at a maximum
+    Vbr { target_kbps: u32, max_kbps: u32 },
+    /// Constant quality at a fixed quantizer, for recordings
+    ConstantQuality { q: u8 },
+}
+
+impl RateControl {
+    /// Target bitrate in kbps, if the mode has one
+    pub fn target_kbps(&self) -> Option<u32> {
+        match self {
+            RateControl::Cbr { kbps } => Some(*kbps),
+            RateControl::Vbr { target_kbps, .. } => Some(*target_kbps),
+            RateControl::ConstantQuality { .. } => None,
+        }
+    }
+}
+
+/// Pixel layout of a raw frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum PixelFormat {
+    /// Packed 8-bit RGBA
+    #[default]
+    Rgba,
+    /// Y plane followed by an interleaved UV plane at half resolution
+    Nv12,
+    /// Y, U and V planes, chroma at half resolution
+    I420,
+}
+
+impl PixelFormat {
+    /// Size in bytes of a tightly packed frame
+    pub fn frame_size(&self, width: u32, height: u32) -> usize {
+        let (width, height) = (width as usize, height as usize);
+        match self {
+            PixelFormat::Rgba => width * height * 4,
+            PixelFormat::Nv12 | PixelFormat::I420 => {
+                width * height + 2 * width.div_ceil(2) * height.div_ceil(2)
+            }
+        }
+    }
 }
 
 /// Encoder configuration
-#[derive(Debug, Clone)]
+///
+/// Deserialization runs [`EncoderConfig::validate`], so a config received
+/// from a file or a peer is always usable.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(remote = "Self", default)]
 pub struct EncoderConfig {
     /// Codec to use
     pub codec: CodecType,
@@ -29,6 +251,265 @@ pub struct EncoderConfig {
     pub bitrate: u32,
     /// Enable hardware acceleration if available
     pub use_hardware_accel: bool,
+    /// Color range for RGBA→YUV conversion
+    pub color_range: ColorRange,
+    /// Color matrix for RGBA→YUV conversion; `None` follows the resolution,
+    /// see [`ColorSpace::for_height`]
+    pub color_space: Option<ColorSpace>,
+    /// Chroma subsampling for RGBA→YUV conversion
+    pub chroma: ChromaSubsampling,
+    /// Emit an IDR every n frames; `None` uses the codec default
+    pub keyframe_interval: Option<u32>,
+    /// Emit an IDR when the mean luma difference from the previous frame,
+    /// from 0.0 to 1.0, exceeds this; `None` disables scene-change detection
+    pub scene_change_threshold: Option<f32>,
+    /// Rate control mode; `None` means CBR at `bitrate`
+    pub rate_control: Option<RateControl>,
+    /// Temporal layers to split the stream into, from 1 (no layering) to
+    /// [`MAX_TEMPORAL_LAYERS`]. Each frame's layer is in
+    /// [`EncodedFrame::temporal_layer`].
+    pub svc_temporal_layers: u8,
+}
+
+impl EncoderConfig {
+    /// Check that the configuration is usable by the selected codec
+    pub fn validate(&self) -> Result<()> {
+        if self.width == 0 || self.height == 0 {
+            return Err(ada_remote_core::Error::Encoding(format!(
+                "Invalid dimensions {}x{}",
+                self.width, self.height
+            )));
+        }
+        if self.fps == 0 {
+            return Err(ada_remote_core::Error::Encoding(
+                "Frame rate must be greater than zero".to_string(),
+            ));
+        }
+        if self.bitrate == 0 {
+            return Err(ada_remote_core::Error::Encoding(
+                "Bitrate must be greater than zero".to_string(),
+            ));
+        }
+        if self.keyframe_interval == Some(0) {
+            return Err(ada_remote_core::Error::Encoding(
+                "Keyframe interval must be greater than zero".to_string(),
+            ));
+        }
+        if let Some(threshold) = self.scene_change_threshold {
+            if !(threshold > 0.0 && threshold <= 1.0) {
+                return Err(ada_remote_core::Error::Encoding(format!(
+                    "Scene change threshold must be in (0, 1], got {}",
+                    threshold
+                )));
+            }
+        }
+        if !(1..=MAX_TEMPORAL_LAYERS).contains(&self.svc_temporal_layers) {
+            return Err(ada_remote_core::Error::Encoding(format!(
+                "Temporal layers must be between 1 and {}, got {}",
+                MAX_TEMPORAL_LAYERS, self.svc_temporal_layers
+            )));
+        }
+        if self.svc_temporal_layers > 1 && !self.codec.supports_temporal_layers() {
+            return Err(ada_remote_core::Error::Encoding(format!(
+                "Temporal layers are not supported by {:?}",
+                self.codec
+            )));
+        }
+        if !self.codec.supports_chroma(self.chroma) {
+            return Err(ada_remote_core::Error::Encoding(format!(
+                "{:?} chroma subsampling is not supported by {:?}",
+                self.chroma, self.codec
+            )));
+        }
+        match self.rate_control() {
+            RateControl::Cbr { kbps: 0 } | RateControl::Vbr { target_kbps: 0, .. } => {
+                return Err(ada_remote_core::Error::Encoding(
+                    "Bitrate must be greater than zero".to_string(),
+                ));
+            }
+            RateControl::Vbr {
+                target_kbps,
+                max_kbps,
+            } if max_kbps < target_kbps => {
+                return Err(ada_remote_core::Error::Encoding(format!(
+                    "VBR maximum {} kbps is below the target {} kbps",
+                    max_kbps, target_kbps
+                )));
+            }
+            rate_control if !self.codec.supports_rate_control(rate_control) => {
+                return Err(ada_remote_core::Error::Encoding(format!(
+                    "{:?} rate control is not supported by {:?}",
+                    rate_control, self.codec
+                )));
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Copy of the configuration at a new resolution, checked against the codec limits
+    pub fn resized(&self, width: u32, height: u32) -> Result<Self> {
+        let config = Self {
+            width,
+            height,
+            ..self.clone()
+        };
+        config.validate()?;
+
+        let limits = self.codec.limits();
+        if width > limits.max_width || height > limits.max_height {
+            return Err(ada_remote_core::Error::Encoding(format!(
+                "{}x{} exceeds the {:?} limit of {}x{}",
+                width, height, self.codec, limits.max_width, limits.max_height
+            )));
+        }
+        Ok(config)
+    }
+
+    /// Fail if a frame does not match the configured resolution
+    pub(crate) fn check_resolution(&self, frame: &RawFrame) -> Result<()> {
+        if (frame.width, frame.height) != (self.width, self.height) {
+            return Err(ada_remote_core::Error::Encoding(format!(
+                "Frame is {}x{} but the encoder is configured for {}x{}; call reconfigure first",
+                frame.width, frame.height, self.width, self.height
+            )));
+        }
+        Ok(())
+    }
+
+    /// Effective color matrix
+    pub fn color_space(&self) -> ColorSpace {
+        self.color_space
+            .unwrap_or(ColorSpace::for_height(self.height))
+    }
+
+    /// Effective rate control mode
+    pub fn rate_control(&self) -> RateControl {
+        self.rate_control
+            .unwrap_or(RateControl::Cbr { kbps: self.bitrate })
+    }
+
+    /// Change the target bitrate, keeping the rate control mode
+    ///
+    /// Fails in constant quality mode, which has no bitrate target.
+    pub fn set_target_bitrate(&mut self, kbps: u32) -> Result<()> {
+        let limits = self.codec.limits();
+        let kbps = kbps.clamp(limits.min_bitrate, limits.max_bitrate);
+        self.rate_control = match self.rate_control {
+            None => None,
+            Some(RateControl::Cbr { .. }) => Some(RateControl::Cbr { kbps }),
+            Some(RateControl::Vbr { max_kbps, .. }) => Some(RateControl::Vbr {
+                target_kbps: kbps,
+                max_kbps: max_kbps.max(kbps),
+            }),
+            Some(RateControl::ConstantQuality { .. }) => {
+                return Err(ada_remote_core::Error::Encoding(
+                    "Cannot set bitrate in constant quality mode".to_string(),
+                ));
+            }
+        };
+        self.bitrate = kbps;
+        Ok(())
+    }
+}
+
+impl EncoderConfig {
+    /// Clamp resolution, frame rate and bitrate to the codec limits,
+    /// logging every adjustment
+    pub fn clamp_to_limits(mut self) -> Self {
+        let limits = self.codec.limits();
+
+        let width = self.width.min(limits.max_width);
+        let height = self.height.min(limits.max_height);
+        if (width, height) != (self.width, self.height) {
+            tracing::warn!(
+                "Clamping {:?} resolution {}x{} to {}x{}",
+                self.codec,
+                self.width,
+                self.height,
+                width,
+                height
+            );
+            self.width = width;
+            self.height = height;
+        }
+
+        let fps = self.fps.min(limits.max_fps);
+        if fps != self.fps {
+            tracing::warn!("Clamping {:?} fps {} to {}", self.codec, self.fps, fps);
+            self.fps = fps;
+        }
+
+        // An explicit rate control target takes precedence over `bitrate`
+        let target = self.rate_control().target_kbps().unwrap_or(self.bitrate);
+        let bitrate = target.clamp(limits.min_bitrate, limits.max_bitrate);
+        if bitrate != target {
+            tracing::warn!(
+                "Clamping {:?} bitrate {} kbps to {} kbps",
+                self.codec,
+                target,
+                bitrate
+            );
+        }
+        self.bitrate = bitrate;
+        self.rate_control = match self.rate_control {
+            Some(RateControl::Cbr { .. }) => Some(RateControl::Cbr { kbps: bitrate }),
+            Some(RateControl::Vbr { max_kbps, .. }) => Some(RateControl::Vbr {
+                target_kbps: bitrate,
+                max_kbps: max_kbps.clamp(bitrate, limits.max_bitrate),
+            }),
+            other => other,
+        };
+
+        self
+    }
+}
+
+/// Adaptive presets from the highest: each applies from three quarters of
+/// its bitrate, so the `Adaptive` baseline sits at Medium
+const ADAPTIVE_STEPS: [VideoQuality; 3] =
+    [VideoQuality::High, VideoQuality::Medium, VideoQuality::Low];
+
+impl EncoderConfig {
+    /// Configuration for `codec` at the preset of `quality`: 720p30 at
+    /// 1 Mbps for Low, 1080p30 at 2.5 Mbps for Medium, 1080p60 at 6 Mbps for
+    /// High. `Adaptive` starts at 1080p30 and 2 Mbps.
+    pub fn from_quality(quality: VideoQuality, codec: CodecType) -> Self {
+        let (width, height, fps, bitrate) = match quality {
+            VideoQuality::Low => (1280, 720, 30, 1000),
+            VideoQuality::Medium => (1920, 1080, 30, 2500),
+            VideoQuality::High => (1920, 1080, 60, 6000),
+            // Start in the middle and let the connection decide
+            VideoQuality::Adaptive => (1920, 1080, 30, 2000),
+        };
+        Self {
+            codec,
+            width,
+            height,
+            fps,
+            bitrate,
+            ..Default::default()
+        }
+    }
+
+    /// Copy for an `Adaptive` bitrate change to `kbps`, stepping resolution
+    /// and frame rate to the preset that bitrate can carry. Compare the
+    /// result's size and rate to see whether the encoder needs reconfiguring.
+    pub fn adapted_to(&self, kbps: u32) -> Result<Self> {
+        let step = ADAPTIVE_STEPS
+            .into_iter()
+            .map(|quality| Self::from_quality(quality, self.codec))
+            .find(|preset| kbps >= preset.bitrate / 4 * 3)
+            .unwrap_or_else(|| Self::from_quality(VideoQuality::Low, self.codec));
+        let mut config = Self {
+            width: step.width,
+            height: step.height,
+            fps: step.fps,
+            ..self.clone()
+        };
+        config.set_target_bitrate(kbps)?;
+        Ok(config)
+    }
 }
 
 impl Default for EncoderConfig {
@@ -40,17 +521,55 @@ impl Default for EncoderConfig {
             fps: 30,
             bitrate: 2000, // 2 Mbps
             use_hardware_accel: true,
+            color_range: ColorRange::Limited,
+            color_space: None,
+            chroma: ChromaSubsampling::Yuv420,
+            keyframe_interval: None,
+            scene_change_threshold: None,
+            rate_control: None,
+            svc_temporal_layers: 1,
         }
     }
 }
 
+// `remote = "Self"` turns the derives into inherent functions so these
+// impls can wrap them with validation.
+impl Serialize for EncoderConfig {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        EncoderConfig::serialize(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for EncoderConfig {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let config = EncoderConfig::deserialize(deserializer)?;
+        config.validate().map_err(serde::de::Error::custom)?;
+        Ok(config)
+    }
+}
+
 /// Decoder configuration
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct DecoderConfig {
     /// Expected codec
     pub codec: CodecType,
     /// Enable hardware acceleration if available
     pub use_hardware_accel: bool,
+    /// Color range the stream was encoded with
+    pub color_range: ColorRange,
+    /// Color matrix the stream was encoded with; `None` follows the
+    /// resolution like [`EncoderConfig::color_space`]
+    pub color_space: Option<ColorSpace>,
+    /// Pixel format of decoded frames
+    pub output_format: PixelFormat,
+}
+
+impl DecoderConfig {
+    /// Effective color matrix for frames `height` lines tall
+    pub fn color_space(&self, height: u32) -> ColorSpace {
+        self.color_space.unwrap_or(ColorSpace::for_height(height))
+    }
 }
 
 impl Default for DecoderConfig {
@@ -58,6 +577,9 @@ impl Default for DecoderConfig {
         Self {
             codec: CodecType::H264,
             use_hardware_accel: true,
+            color_range: ColorRange::Limited,
+            color_space: None,
+            output_format: PixelFormat::Rgba,
         }
     }
 }
@@ -65,8 +587,10 @@ impl Default for DecoderConfig {
 /// Raw video frame (unencoded)
 #[derive(Debug, Clone)]
 pub struct RawFrame {
-    /// RGBA pixel data
+    /// Pixel data, tightly packed in `format`
     pub data: Vec<u8>,
+    /// Pixel layout of `data`
+    pub format: PixelFormat,
     /// Frame width
     pub width: u32,
     /// Frame height
@@ -84,6 +608,9 @@ pub struct EncodedFrame {
     pub timestamp: u64,
     /// Whether this is a keyframe
     pub is_keyframe: bool,
+    /// Temporal layer, 0 for the base layer. Frames above a layer can be
+    /// dropped without breaking the decoding of the layers below.
+    pub temporal_layer: u8,
 }
 
 /// Video encoder trait
@@ -97,19 +624,56 @@ pub trait VideoEncoder: Send + Sync {
     /// Force generation of a keyframe
     fn force_keyframe(&mut self) -> Result<()>;
 
+    /// Switch to a new resolution mid-stream; the next frame is a keyframe
+    fn reconfigure(&mut self, width: u32, height: u32) -> Result<()>;
+
     /// Adjust bitrate dynamically
     fn set_bitrate(&mut self, bitrate: u32) -> Result<()>;
 
+    /// Configuration in effect after clamping to codec limits,
+    /// `None` before init
+    fn effective_config(&self) -> Option<EncoderConfig>;
+
+    /// Encoder statistics since init
+    fn stats(&self) -> EncoderStats {
+        EncoderStats::default()
+    }
+
+    /// Recycle the buffers of encoded [`RawFrame`]s into `pool`, so capture
+    /// can reuse them. Encoders that don't are free to ignore it.
+    fn set_frame_pool(&mut self, _pool: FramePool) {}
+
     /// Clean up resources
     fn cleanup(&mut self) -> Result<()>;
 }
 
+/// What a failed [`VideoDecoder::decode`] means for the rest of the stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecoderError {
+    /// Only this frame is lost; the next ones decode
+    Recoverable,
+    /// The reference pictures are gone, so nothing decodes until a keyframe
+    NeedsKeyframe,
+}
+
+impl DecoderError {
+    /// Classify a decode error; decoders report lost references as
+    /// [`Error::KeyframeNeeded`]
+    pub fn classify(error: &Error) -> Self {
+        match error {
+            Error::KeyframeNeeded(_) => DecoderError::NeedsKeyframe,
+            _ => DecoderError::Recoverable,
+        }
+    }
+}
+
 /// Video decoder trait
 pub trait VideoDecoder: Send + Sync {
     /// Initialize the decoder
     fn init(&mut self, config: DecoderConfig) -> Result<()>;
 
-    /// Decode an encoded frame
+    /// Decode an encoded frame. Failures that leave the decoder waiting for
+    /// a keyframe are [`Error::KeyframeNeeded`]; see [`DecoderError`].
     fn decode(&mut self, frame: EncodedFrame) -> Result<RawFrame>;
 
     /// Clean up resources
@@ -121,6 +685,8 @@ pub fn create_encoder(codec: CodecType) -> Result<Box<dyn VideoEncoder>> {
     match codec {
         CodecType::H264 => Ok(Box::new(H264Encoder::new())),
         CodecType::VP9 => Ok(Box::new(VP9Encoder::new())),
+        CodecType::AV1 => Ok(Box::new(Av1Encoder::new())),
+        CodecType::Raw => Ok(Box::new(RawEncoder::new())),
     }
 }
 
@@ -129,32 +695,74 @@ pub fn create_decoder(codec: CodecType) -> Result<Box<dyn VideoDecoder>> {
     match codec {
         CodecType::H264 => Ok(Box::new(H264Decoder::new())),
         CodecType::VP9 => Ok(Box::new(VP9Decoder::new())),
+        CodecType::AV1 => Ok(Box::new(Av1Decoder::new())),
+        CodecType::Raw => Ok(Box::new(RawDecoder::new())),
     }
 }
 
-// Stub implementations - will be replaced with FFmpeg bindings
+// VP9 stubs - will be replaced with FFmpeg bindings
+
+/// libavcodec options implementing a rate control mode
+fn avcodec_rate_control_options(rate_control: RateControl) -> Vec<(&'static str, String)> {
+    match rate_control {
+        RateControl::Cbr { kbps } => vec![
+            ("b", format!("{}k", kbps)),
+            ("minrate", format!("{}k", kbps)),
+            ("maxrate", format!("{}k", kbps)),
+            ("bufsize", format!("{}k", kbps)),
+        ],
+        RateControl::Vbr {
+            target_kbps,
+            max_kbps,
+        } => vec![
+            ("b", format!("{}k", target_kbps)),
+            ("maxrate", format!("{}k", max_kbps)),
+            ("bufsize", format!("{}k", max_kbps * 2)),
+        ],
+        RateControl::ConstantQuality { q } => vec![("b", "0".to_string()), ("crf", q.to_string())],
+    }
+}
 
-struct H264Encoder {
+struct VP9Encoder {
     config: Option<EncoderConfig>,
+    // TODO: Record frames once encoding is implemented
+    stats: StatsTracker,
 }
 
-impl H264Encoder {
+impl VP9Encoder {
     fn new() -> Self {
-        Self { config: None }
+        Self {
+            config: None,
+            stats: StatsTracker::new(),
+        }
     }
 }
 
-impl VideoEncoder for H264Encoder {
+impl VideoEncoder for VP9Encoder {
     fn init(&mut self, config: EncoderConfig) -> Result<()> {
+        config.validate()?;
+        let config = config.clamp_to_limits();
+        // TODO: Map keyframe_interval to the FFmpeg gop_size
+        tracing::debug!(
+            "VP9 rate control options: {:?}",
+            avcodec_rate_control_options(config.rate_control())
+        );
+        tracing::info!(
+            "VP9 encoder initialized ({:?} {:?} range, {:?}, keyframe interval {:?})",
+            config.color_space(),
+            config.color_range,
+            config.chroma,
+            config.keyframe_interval
+        );
         self.config = Some(config);
-        tracing::info!("H.264 encoder initialized");
+        self.stats = StatsTracker::new();
         Ok(())
     }
 
     fn encode(&mut self, _frame: RawFrame) -> Result<EncodedFrame> {
-        // TODO: Implement H.264 encoding using FFmpeg
+        // TODO: Implement VP9 encoding using FFmpeg
         Err(ada_remote_core::Error::Encoding(
-            "H.264 encoding not yet implemented".to_string(),
+            "VP9 encoding not yet implemented".to_string(),
         ))
     }
 
@@ -162,37 +770,102 @@ impl VideoEncoder for H264Encoder {
         Ok(())
     }
 
-    fn set_bitrate(&mut self, _bitrate: u32) -> Result<()> {
+    fn reconfigure(&mut self, width: u32, height: u32) -> Result<()> {
+        let Some(config) = &mut self.config else {
+            return Err(ada_remote_core::Error::Encoding(
+                "VP9 encoder not initialized".to_string(),
+            ));
+        };
+        *config = config.resized(width, height)?;
         Ok(())
     }
 
+    fn set_bitrate(&mut self, bitrate: u32) -> Result<()> {
+        match &mut self.config {
+            Some(config) => config.set_target_bitrate(bitrate),
+            None => Ok(()),
+        }
+    }
+
+    fn effective_config(&self) -> Option<EncoderConfig> {
+        self.config.clone()
+    }
+
+    fn stats(&self) -> EncoderStats {
+        self.stats.stats()
+    }
+
     fn cleanup(&mut self) -> Result<()> {
-        tracing::info!("H.264 encoder cleaned up");
+        tracing::info!("VP9 encoder cleaned up");
         Ok(())
     }
 }
 
-struct VP9Encoder {
+struct VP9Decoder {
+    config: Option<DecoderConfig>,
+}
+
+impl VP9Decoder {
+    fn new() -> Self {
+        Self { config: None }
+    }
+}
+
+impl VideoDecoder for VP9Decoder {
+    fn init(&mut self, config: DecoderConfig) -> Result<()> {
+        self.config = Some(config);
+        tracing::info!("VP9 decoder initialized");
+        Ok(())
+    }
+
+    fn decode(&mut self, _frame: EncodedFrame) -> Result<RawFrame> {
+        // TODO: Implement VP9 decoding using FFmpeg
+        Err(ada_remote_core::Error::Decoding(
+            "VP9 decoding not yet implemented".to_string(),
+        ))
+    }
+
+    fn cleanup(&mut self) -> Result<()> {
+        tracing::info!("VP9 decoder cleaned up");
+        Ok(())
+    }
+}
+
+// AV1 stubs - will be replaced with rav1e/dav1d bindings
+
+struct Av1Encoder {
     config: Option<EncoderConfig>,
 }
 
-impl VP9Encoder {
+impl Av1Encoder {
     fn new() -> Self {
         Self { config: None }
     }
 }
 
-impl VideoEncoder for VP9Encoder {
+impl VideoEncoder for Av1Encoder {
     fn init(&mut self, config: EncoderConfig) -> Result<()> {
+        config.validate()?;
+        let mut config = config.clamp_to_limits();
+        if config.use_hardware_accel {
+            // TODO: Probe for AV1 hardware encoders (NVENC, QSV, AMF)
+            tracing::warn!("No AV1 hardware encoder available, falling back to software");
+            config.use_hardware_accel = false;
+        }
+        tracing::info!(
+            "AV1 encoder initialized ({:?} {:?} range, {:?})",
+            config.color_space(),
+            config.color_range,
+            config.chroma
+        );
         self.config = Some(config);
-        tracing::info!("VP9 encoder initialized");
         Ok(())
     }
 
     fn encode(&mut self, _frame: RawFrame) -> Result<EncodedFrame> {
-        // TODO: Implement VP9 encoding using FFmpeg
+        // TODO: Implement AV1 encoding using rav1e
         Err(ada_remote_core::Error::Encoding(
-            "VP9 encoding not yet implemented".to_string(),
+            "AV1 encoding not yet implemented".to_string(),
         ))
     }
 
@@ -200,72 +873,220 @@ impl VideoEncoder for VP9Encoder {
         Ok(())
     }
 
+    fn reconfigure(&mut self, width: u32, height: u32) -> Result<()> {
+        let Some(config) = &mut self.config else {
+            return Err(ada_remote_core::Error::Encoding(
+                "AV1 encoder not initialized".to_string(),
+            ));
+        };
+        *config = config.resized(width, height)?;
+        Ok(())
+    }
+
     fn set_bitrate(&mut self, _bitrate: u32) -> Result<()> {
         Ok(())
     }
 
+    fn effective_config(&self) -> Option<EncoderConfig> {
+        self.config.clone()
+    }
+
     fn cleanup(&mut self) -> Result<()> {
-        tracing::info!("VP9 encoder cleaned up");
+        tracing::info!("AV1 encoder cleaned up");
         Ok(())
     }
 }
 
-struct H264Decoder {
+struct Av1Decoder {
     config: Option<DecoderConfig>,
 }
 
-impl H264Decoder {
+impl Av1Decoder {
     fn new() -> Self {
         Self { config: None }
     }
 }
 
-impl VideoDecoder for H264Decoder {
-    fn init(&mut self, config: DecoderConfig) -> Result<()> {
+impl VideoDecoder for Av1Decoder {
+    fn init(&mut self, mut config: DecoderConfig) -> Result<()> {
+        if config.use_hardware_accel {
+            tracing::warn!("No AV1 hardware decoder available, falling back to software");
+            config.use_hardware_accel = false;
+        }
         self.config = Some(config);
-        tracing::info!("H.264 decoder initialized");
+        tracing::info!("AV1 decoder initialized");
         Ok(())
     }
 
     fn decode(&mut self, _frame: EncodedFrame) -> Result<RawFrame> {
-        // TODO: Implement H.264 decoding using FFmpeg
+        // TODO: Implement AV1 decoding using dav1d
         Err(ada_remote_core::Error::Decoding(
-            "H.264 decoding not yet implemented".to_string(),
+            "AV1 decoding not yet implemented".to_string(),
         ))
     }
 
     fn cleanup(&mut self) -> Result<()> {
-        tracing::info!("H.264 decoder cleaned up");
+        tracing::info!("AV1 decoder cleaned up");
         Ok(())
     }
 }
 
-struct VP9Decoder {
+/// Size of the width/height header prepended to raw frames
+const RAW_HEADER_SIZE: usize = 8;
+
+/// Pass-through encoder: every frame is a keyframe carrying the RGBA pixels
+/// behind a little-endian width/height header. YUV input is converted to RGBA.
+struct RawEncoder {
+    config: Option<EncoderConfig>,
+    pool: Option<FramePool>,
+    temporal: TemporalPattern,
+}
+
+impl RawEncoder {
+    fn new() -> Self {
+        Self {
+            config: None,
+            pool: None,
+            temporal: TemporalPattern::new(1),
+        }
+    }
+}
+
+impl VideoEncoder for RawEncoder {
+    fn init(&mut self, config: EncoderConfig) -> Result<()> {
+        config.validate()?;
+        self.temporal = TemporalPattern::new(config.svc_temporal_layers);
+        self.config = Some(config.clamp_to_limits());
+        tracing::info!("Raw encoder initialized");
+        Ok(())
+    }
+
+    fn encode(&mut self, frame: RawFrame) -> Result<EncodedFrame> {
+        let expected = frame.format.frame_size(frame.width, frame.height);
+        if frame.data.len() != expected {
+            return Err(ada_remote_core::Error::Encoding(format!(
+                "Frame size mismatch: expected {} bytes for {}x{} {:?}, got {}",
+                expected,
+                frame.width,
+                frame.height,
+                frame.format,
+                frame.data.len()
+            )));
+        }
+        if let Some(config) = &self.config {
+            config.check_resolution(&frame)?;
+        }
+        let (space, range) = match &self.config {
+            Some(config) => (config.color_space(), config.color_range),
+            None => (ColorSpace::for_height(frame.height), ColorRange::default()),
+        };
+        let frame = convert_frame(frame, PixelFormat::Rgba, space, range);
+
+        let mut data = Vec::with_capacity(RAW_HEADER_SIZE + frame.data.len());
+        data.extend_from_slice(&frame.width.to_le_bytes());
+        data.extend_from_slice(&frame.height.to_le_bytes());
+        data.extend_from_slice(&frame.data);
+        if let Some(pool) = &self.pool {
+            pool.recycle(frame.data);
+        }
+
+        Ok(EncodedFrame {
+            data,
+            timestamp: frame.timestamp,
+            is_keyframe: true,
+            temporal_layer: self.temporal.next_layer(),
+        })
+    }
+
+    fn force_keyframe(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn reconfigure(&mut self, width: u32, height: u32) -> Result<()> {
+        let Some(config) = &mut self.config else {
+            return Err(ada_remote_core::Error::Encoding(
+                "Raw encoder not initialized".to_string(),
+            ));
+        };
+        *config = config.resized(width, height)?;
+        Ok(())
+    }
+
+    fn set_bitrate(&mut self, _bitrate: u32) -> Result<()> {
+        Ok(())
+    }
+
+    fn effective_config(&self) -> Option<EncoderConfig> {
+        self.config.clone()
+    }
+
+    fn set_frame_pool(&mut self, pool: FramePool) {
+        self.pool = Some(pool);
+    }
+
+    fn cleanup(&mut self) -> Result<()> {
+        tracing::info!("Raw encoder cleaned up");
+        Ok(())
+    }
+}
+
+struct RawDecoder {
     config: Option<DecoderConfig>,
 }
 
-impl VP9Decoder {
+impl RawDecoder {
     fn new() -> Self {
         Self { config: None }
     }
 }
 
-impl VideoDecoder for VP9Decoder {
+impl VideoDecoder for RawDecoder {
     fn init(&mut self, config: DecoderConfig) -> Result<()> {
         self.config = Some(config);
-        tracing::info!("VP9 decoder initialized");
+        tracing::info!("Raw decoder initialized");
         Ok(())
     }
 
-    fn decode(&mut self, _frame: EncodedFrame) -> Result<RawFrame> {
-        // TODO: Implement VP9 decoding using FFmpeg
-        Err(ada_remote_core::Error::Decoding(
-            "VP9 decoding not yet implemented".to_string(),
+    fn decode(&mut self, frame: EncodedFrame) -> Result<RawFrame> {
+        if frame.data.len() < RAW_HEADER_SIZE {
+            return Err(ada_remote_core::Error::Decoding(
+                "Raw frame too short".to_string(),
+            ));
+        }
+        let width = u32::from_le_bytes(frame.data[0..4].try_into().unwrap());
+        let height = u32::from_le_bytes(frame.data[4..8].try_into().unwrap());
+
+        let expected = width as usize * height as usize * 4;
+        let pixels = &frame.data[RAW_HEADER_SIZE..];
+        if pixels.len() != expected {
+            return Err(ada_remote_core::Error::Decoding(format!(
+                "Raw frame size mismatch: expected {} bytes for {}x{}, got {}",
+                expected,
+                width,
+                height,
+                pixels.len()
+            )));
+        }
+
+        let config = self.config.as_ref().cloned().unwrap_or_default();
+        let frame = RawFrame {
+            data: pixels.to_vec(),
+            format: PixelFormat::Rgba,
+            width,
+            height,
+            timestamp: frame.timestamp,
+        };
+        let space = config.color_space(height);
+        Ok(convert_frame(
+            frame,
+            config.output_format,
+            space,
+            config.color_range,
         ))
     }
 
     fn cleanup(&mut self) -> Result<()> {
-        tracing::info!("VP9 decoder cleaned up");
+        tracing::info!("Raw decoder cleaned up");
         Ok(())
     }
 }
@@ -281,5 +1102,257 @@ mod tests {
         assert_eq!(config.width, 1920);
         assert_eq!(config.height, 1080);
         assert_eq!(config.fps, 30);
+        assert_eq!(config.color_range, ColorRange::Limited);
+        assert_eq!(config.chroma, ChromaSubsampling::Yuv420);
+        assert_eq!(config.color_space(), ColorSpace::Bt709);
+
+        let sd = EncoderConfig {
+            width: 640,
+            height: 480,
+            ..config
+        };
+        assert_eq!(sd.color_space(), ColorSpace::Bt601);
+        assert_eq!(DecoderConfig::default().color_space(480), ColorSpace::Bt601);
+    }
+
+    #[test]
+    fn test_quality_presets() {
+        let preset = |quality| {
+            let config = EncoderConfig::from_quality(quality, CodecType::VP9);
+            assert_eq!(config.codec, CodecType::VP9);
+            config.validate().unwrap();
+            (config.width, config.height, config.fps, config.bitrate)
+        };
+        assert_eq!(preset(VideoQuality::Low), (1280, 720, 30, 1000));
+        assert_eq!(preset(VideoQuality::Medium), (1920, 1080, 30, 2500));
+        assert_eq!(preset(VideoQuality::High), (1920, 1080, 60, 6000));
+        assert_eq!(preset(VideoQuality::Adaptive), (1920, 1080, 30, 2000));
+    }
+
+    #[test]
+    fn test_adaptive_steps_between_presets() {
+        let baseline = EncoderConfig {
+            rate_control: Some(RateControl::Vbr {
+                target_kbps: 2000,
+                max_kbps: 3000,
+            }),
+            ..EncoderConfig::from_quality(VideoQuality::Adaptive, CodecType::H264)
+        };
+        let step = |kbps| {
+            let config = baseline.adapted_to(kbps).unwrap();
+            (config.width, config.height, config.fps, config.bitrate)
+        };
+        // The baseline stays at its own resolution
+        assert_eq!(step(2000), (1920, 1080, 30, 2000));
+        assert_eq!(step(1800), (1280, 720, 30, 1800));
+        assert_eq!(step(300), (1280, 720, 30, 300));
+        assert_eq!(step(4500), (1920, 1080, 60, 4500));
+        // Everything else carries over
+        assert_eq!(
+            baseline.adapted_to(4500).unwrap().rate_control,
+            Some(RateControl::Vbr {
+                target_kbps: 4500,
+                max_kbps: 4500,
+            })
+        );
+
+        let constant = EncoderConfig {
+            rate_control: Some(RateControl::ConstantQuality { q: 30 }),
+            ..baseline
+        };
+        assert!(constant.adapted_to(1000).is_err());
+    }
+
+    #[test]
+    fn test_chroma_444_honored_or_rejected() {
+        let mut vp9 = create_encoder(CodecType::VP9).unwrap();
+        let config = EncoderConfig {
+            codec: CodecType::VP9,
+            color_range: ColorRange::Full,
+            chroma: ChromaSubsampling::Yuv444,
+            ..Default::default()
+        };
+        assert!(vp9.init(config).is_ok());
+
+        let mut h264 = create_encoder(CodecType::H264).unwrap();
+        let config = EncoderConfig {
+            chroma: ChromaSubsampling::Yuv444,
+            ..Default::default()
+        };
+        let err = h264.init(config).unwrap_err();
+        assert!(err.to_string().contains("Yuv444"));
+        assert!(err.to_string().contains("H264"));
+    }
+
+    #[test]
+    fn test_encoder_config_serde() {
+        let config = EncoderConfig {
+            codec: CodecType::VP9,
+            chroma: ChromaSubsampling::Yuv444,
+            ..Default::default()
+        };
+        let json = serde_json::to_string(&config).unwrap();
+        let parsed: EncoderConfig = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.codec, CodecType::VP9);
+        assert_eq!(parsed.chroma, ChromaSubsampling::Yuv444);
+        assert_eq!(parsed.width, config.width);
+
+        let err = serde_json::from_str::<EncoderConfig>(r#"{"width": 0}"#).unwrap_err();
+        assert!(err.to_string().contains("Invalid dimensions"));
+    }
+
+    #[test]
+    fn test_out_of_range_config_is_clamped() {
+        let mut encoder = create_encoder(CodecType::H264).unwrap();
+        assert!(encoder.effective_config().is_none());
+
+        encoder
+            .init(EncoderConfig {
+                width: 7680,
+                height: 4320,
+                fps: 100,
+                bitrate: 1,
+                ..Default::default()
+            })
+            .unwrap();
+
+        let effective = encoder.effective_config().unwrap();
+        let limits = CodecType::H264.limits();
+        assert_eq!(effective.fps, limits.max_fps);
+        assert_eq!(effective.width, limits.max_width);
+        assert_eq!(effective.height, limits.max_height);
+        assert_eq!(effective.bitrate, limits.min_bitrate);
+    }
+
+    #[test]
+    fn test_raw_codec_round_trip() {
+        let mut encoder = create_encoder(CodecType::Raw).unwrap();
+        let mut decoder = create_decoder(CodecType::Raw).unwrap();
+        encoder
+            .init(EncoderConfig {
+                codec: CodecType::Raw,
+                width: 4,
+                height: 2,
+                ..Default::default()
+            })
+            .unwrap();
+        decoder
+            .init(DecoderConfig {
+                codec: CodecType::Raw,
+                ..Default::default()
+            })
+            .unwrap();
+
+        let frame = RawFrame {
+            data: (0..32).collect(),
+            format: PixelFormat::Rgba,
+            width: 4,
+            height: 2,
+            timestamp: 1234,
+        };
+        let encoded = encoder.encode(frame.clone()).unwrap();
+        assert!(encoded.is_keyframe);
+
+        let decoded = decoder.decode(encoded).unwrap();
+        assert_eq!(decoded.data, frame.data);
+        assert_eq!((decoded.width, decoded.height), (4, 2));
+        assert_eq!(decoded.timestamp, 1234);
+    }
+
+    #[test]
+    fn test_temporal_layer_validation() {
+        let layered = |codec, svc_temporal_layers| EncoderConfig {
+            codec,
+            svc_temporal_layers,
+            ..Default::default()
+        };
+        assert!(layered(CodecType::H264, 3).validate().is_ok());
+        assert!(layered(CodecType::H264, 0).validate().is_err());
+        assert!(layered(CodecType::H264, MAX_TEMPORAL_LAYERS + 1)
+            .validate()
+            .is_err());
+        assert!(layered(CodecType::VP9, 2).validate().is_err());
+        assert!(layered(CodecType::VP9, 1).validate().is_ok());
+    }
+
+    #[test]
+    fn test_av1_falls_back_to_software() {
+        let mut encoder = create_encoder(CodecType::AV1).unwrap();
+        encoder
+            .init(EncoderConfig {
+                codec: CodecType::AV1,
+                ..EncoderConfig::default()
+            })
+            .unwrap();
+        assert!(!encoder.effective_config().unwrap().use_hardware_accel);
+
+        let mut decoder = create_decoder(CodecType::AV1).unwrap();
+        decoder
+            .init(DecoderConfig {
+                codec: CodecType::AV1,
+                ..DecoderConfig::default()
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_rate_control_validation() {
+        let config = EncoderConfig {
+            rate_control: Some(RateControl::ConstantQuality { q: 30 }),
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+        assert!(EncoderConfig {
+            codec: CodecType::VP9,
+            ..config.clone()
+        }
+        .validate()
+        .is_ok());
+
+        let vbr = EncoderConfig {
+            rate_control: Some(RateControl::Vbr {
+                target_kbps: 3000,
+                max_kbps: 1000,
+            }),
+            ..Default::default()
+        };
+        assert!(vbr.validate().unwrap_err().to_string().contains("below"));
+
+        // Without an explicit mode, `bitrate` is a CBR target
+        assert_eq!(
+            EncoderConfig::default().rate_control(),
+            RateControl::Cbr { kbps: 2000 }
+        );
+    }
+
+    #[test]
+    fn test_avcodec_rate_control_options() {
+        let cbr = avcodec_rate_control_options(RateControl::Cbr { kbps: 2000 });
+        assert!(cbr.contains(&("maxrate", "2000k".to_string())));
+        assert!(cbr.contains(&("minrate", "2000k".to_string())));
+
+        let vbr = avcodec_rate_control_options(RateControl::Vbr {
+            target_kbps: 1000,
+            max_kbps: 3000,
+        });
+        assert!(vbr.contains(&("b", "1000k".to_string())));
+        assert!(vbr.contains(&("maxrate", "3000k".to_string())));
+
+        let cq = avcodec_rate_control_options(RateControl::ConstantQuality { q: 31 });
+        assert_eq!(cq, vec![("b", "0".to_string()), ("crf", "31".to_string())]);
+    }
+
+    #[test]
+    fn test_set_bitrate_rejected_in_constant_quality() {
+        let mut encoder = create_encoder(CodecType::VP9).unwrap();
+        encoder
+            .init(EncoderConfig {
+                codec: CodecType::VP9,
+                rate_control: Some(RateControl::ConstantQuality { q: 30 }),
+                ..Default::default()
+            })
+            .unwrap();
+        let err = encoder.set_bitrate(1000).unwrap_err();
+        assert!(err.to_string().contains("constant quality"));
     }
 }
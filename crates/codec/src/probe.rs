@@ -0,0 +1,68 @@
+//! Codec capability detection
+
+use crate::{h264, CodecType};
+use serde::{Deserialize, Serialize};
+
+/// What this machine can do with a codec
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CodecCapability {
+    /// Codec described
+    pub codec: CodecType,
+    /// Frames can be encoded
+    pub can_encode: bool,
+    /// Frames can be decoded
+    pub can_decode: bool,
+    /// Encoding runs on dedicated hardware
+    pub hardware_encode: bool,
+    /// Decoding runs on dedicated hardware
+    pub hardware_decode: bool,
+    /// Largest supported resolution
+    pub max_resolution: (u32, u32),
+}
+
+/// Probe the codec backends available on this machine
+///
+/// Each backend is actually opened rather than assumed, so a missing or
+/// broken library shows up here instead of at session start.
+pub fn probe_codecs() -> Vec<CodecCapability> {
+    let (h264_encode, h264_decode) = h264::probe();
+
+    [
+        (CodecType::H264, h264_encode, h264_decode),
+        // TODO: Probe libavcodec once the VP9 and AV1 backends land
+        (CodecType::VP9, false, false),
+        (CodecType::AV1, false, false),
+        (CodecType::Raw, true, true),
+    ]
+    .into_iter()
+    .map(|(codec, can_encode, can_decode)| {
+        let limits = codec.limits();
+        CodecCapability {
+            codec,
+            can_encode,
+            can_decode,
+            // OpenH264 is software-only and no hardware backend is built in yet
+            hardware_encode: false,
+            hardware_decode: false,
+            max_resolution: (limits.max_width, limits.max_height),
+        }
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_probe_reports_software_h264() {
+        let capabilities = probe_codecs();
+        let h264 = capabilities
+            .iter()
+            .find(|c| c.codec == CodecType::H264)
+            .unwrap();
+        assert!(h264.can_encode);
+        assert!(h264.can_decode);
+        assert_eq!(h264.max_resolution, (3840, 2160));
+    }
+}
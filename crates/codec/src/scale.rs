@@ -0,0 +1,69 @@
+//! RGBA downscaling
+//!
+//! Each output pixel averages the block of input pixels it covers, which
+//! keeps thin lines and text legible where skipping pixels would drop them.
+
+/// Append `rgba` scaled from `width`x`height` to `out_width`x`out_height`
+/// to `out`. Meant for shrinking; enlarging repeats pixels.
+///
+/// # Panics
+///
+/// If `rgba` holds fewer than `width * height` pixels.
+pub fn scale_rgba(
+    rgba: &[u8],
+    width: usize,
+    height: usize,
+    out: &mut Vec<u8>,
+    out_width: usize,
+    out_height: usize,
+) {
+    assert!(rgba.len() >= width * height * 4, "RGBA buffer too small");
+    out.reserve(out_width * out_height * 4);
+
+    // Input rows or columns covered by output row or column `i`
+    let span = |i: usize, len: usize, out_len: usize| {
+        let start = i * len / out_len;
+        start..((i + 1) * len / out_len).max(start + 1)
+    };
+
+    for out_row in 0..out_height {
+        let rows = span(out_row, height, out_height);
+        for out_col in 0..out_width {
+            let cols = span(out_col, width, out_width);
+            let mut sum = [0u32; 4];
+            for row in rows.clone() {
+                let pixels = &rgba[(row * width + cols.start) * 4..(row * width + cols.end) * 4];
+                for pixel in pixels.chunks_exact(4) {
+                    for (total, &value) in sum.iter_mut().zip(pixel) {
+                        *total += value as u32;
+                    }
+                }
+            }
+            let count = (rows.len() * cols.len()) as u32;
+            out.extend(sum.map(|total| ((total + count / 2) / count) as u8));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_halving_averages_blocks() {
+        // 4x2 of alternating black and white columns, then a red 2x2 block
+        let mut rgba = Vec::new();
+        for _ in 0..2 {
+            rgba.extend([0, 0, 0, 255, 255, 255, 255, 255]);
+            rgba.extend([255, 0, 0, 255, 255, 0, 0, 255]);
+        }
+        let mut out = Vec::new();
+        scale_rgba(&rgba, 4, 2, &mut out, 2, 1);
+        assert_eq!(out, [128, 128, 128, 255, 255, 0, 0, 255]);
+
+        // Uneven ratios still cover every input pixel once
+        let mut out = Vec::new();
+        scale_rgba(&[10; 5 * 3 * 4], 5, 3, &mut out, 2, 2);
+        assert_eq!(out, [10; 2 * 2 * 4]);
+    }
+}
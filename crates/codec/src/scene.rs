@@ -0,0 +1,68 @@
+//! Scene-change detection
+//!
+//! Compares a downsampled copy of each frame's luma plane with the previous
+//! one, so abrupt content switches can be coded as keyframes instead of
+//! oversized delta frames.
+
+/// Distance between sampled luma pixels in both directions
+const SAMPLE_STEP: usize = 8;
+
+/// Tracks the previous frame's downsampled luma
+pub(crate) struct SceneChangeDetector {
+    previous: Option<Vec<u8>>,
+}
+
+impl SceneChangeDetector {
+    pub(crate) fn new() -> Self {
+        Self { previous: None }
+    }
+
+    /// Forget the previous frame, e.g. after a resolution change
+    pub(crate) fn reset(&mut self) {
+        self.previous = None;
+    }
+
+    /// Record a frame and return its mean absolute luma difference from the
+    /// previous one, from 0.0 (identical) to 1.0
+    pub(crate) fn difference(&mut self, luma: &[u8], width: usize, height: usize) -> f32 {
+        let samples: Vec<u8> = (0..height)
+            .step_by(SAMPLE_STEP)
+            .flat_map(|row| {
+                luma[row * width..(row + 1) * width]
+                    .iter()
+                    .step_by(SAMPLE_STEP)
+            })
+            .copied()
+            .collect();
+
+        let difference = match &self.previous {
+            Some(previous) if previous.len() == samples.len() && !samples.is_empty() => {
+                let sad: u64 = previous
+                    .iter()
+                    .zip(&samples)
+                    .map(|(a, b)| a.abs_diff(*b) as u64)
+                    .sum();
+                sad as f32 / (samples.len() as f32 * 255.0)
+            }
+            _ => 0.0,
+        };
+        self.previous = Some(samples);
+        difference
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_difference_between_frames() {
+        let mut detector = SceneChangeDetector::new();
+        assert_eq!(detector.difference(&[0; 64 * 64], 64, 64), 0.0);
+        assert_eq!(detector.difference(&[0; 64 * 64], 64, 64), 0.0);
+        assert_eq!(detector.difference(&[255; 64 * 64], 64, 64), 1.0);
+
+        detector.reset();
+        assert_eq!(detector.difference(&[0; 64 * 64], 64, 64), 0.0);
+    }
+}
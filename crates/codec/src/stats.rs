@@ -0,0 +1,110 @@
+//! Encoder statistics
+
+use crate::EncodedFrame;
+use std::collections::VecDeque;
+
+/// Window over which the average bitrate is computed, in microseconds
+const BITRATE_WINDOW_US: u64 = 2_000_000;
+
+/// Encoder performance counters
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EncoderStats {
+    /// Frames encoded since init
+    pub frames_encoded: u64,
+    /// Keyframes emitted since init
+    pub keyframes_emitted: u64,
+    /// Total encoded bytes
+    pub bytes_out: u64,
+    /// Average bitrate over the last two seconds of timestamps
+    pub average_bitrate_kbps: u32,
+    /// Quantizer of the last frame, if the codec reports it
+    pub last_qp: Option<u32>,
+}
+
+/// Accumulates [`EncoderStats`] from encoded frames
+pub(crate) struct StatsTracker {
+    stats: EncoderStats,
+    /// Timestamp and size of the frames inside the bitrate window
+    window: VecDeque<(u64, usize)>,
+}
+
+impl StatsTracker {
+    pub(crate) fn new() -> Self {
+        Self {
+            stats: EncoderStats::default(),
+            window: VecDeque::new(),
+        }
+    }
+
+    /// Account for an encoded frame
+    pub(crate) fn record(&mut self, frame: &EncodedFrame) {
+        self.stats.frames_encoded += 1;
+        self.stats.keyframes_emitted += frame.is_keyframe as u64;
+        self.stats.bytes_out += frame.data.len() as u64;
+
+        // A timestamp going backwards means the clock was reset
+        if self
+            .window
+            .back()
+            .is_some_and(|(ts, _)| *ts > frame.timestamp)
+        {
+            self.window.clear();
+        }
+        self.window.push_back((frame.timestamp, frame.data.len()));
+        while let Some((oldest, _)) = self.window.front() {
+            if frame.timestamp - oldest <= BITRATE_WINDOW_US {
+                break;
+            }
+            self.window.pop_front();
+        }
+
+        // The oldest frame marks the start of the span, so its bytes were
+        // sent before the window opened
+        let span_us = frame.timestamp - self.window[0].0;
+        let bytes: usize = self.window.iter().skip(1).map(|(_, len)| len).sum();
+        self.stats.average_bitrate_kbps =
+            (bytes as u64 * 8 * 1000).checked_div(span_us).unwrap_or(0) as u32;
+    }
+
+    pub(crate) fn set_last_qp(&mut self, qp: u32) {
+        self.stats.last_qp = Some(qp);
+    }
+
+    pub(crate) fn stats(&self) -> EncoderStats {
+        self.stats.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(timestamp: u64, len: usize) -> EncodedFrame {
+        EncodedFrame {
+            data: vec![0; len],
+            timestamp,
+            is_keyframe: timestamp == 0,
+            temporal_layer: 0,
+        }
+    }
+
+    #[test]
+    fn test_bitrate_reflects_recent_window() {
+        let mut tracker = StatsTracker::new();
+        // 10 KB per 100 ms = 800 kbps for five seconds
+        for i in 0..50 {
+            tracker.record(&frame(i * 100_000, 10_000));
+        }
+        assert_eq!(tracker.stats().average_bitrate_kbps, 800);
+
+        // Then 1 KB per 100 ms = 80 kbps; the old rate ages out
+        for i in 50..100 {
+            tracker.record(&frame(i * 100_000, 1_000));
+        }
+        let stats = tracker.stats();
+        assert_eq!(stats.average_bitrate_kbps, 80);
+        assert_eq!(stats.frames_encoded, 100);
+        assert_eq!(stats.keyframes_emitted, 1);
+        assert_eq!(stats.bytes_out, 550_000);
+    }
+}
@@ -0,0 +1,61 @@
+//! Temporal scalability
+//!
+//! With temporal layers, frames of layer n only reference frames of layers
+//! up to n, so dropping every frame above some layer leaves a decodable
+//! stream at a fraction of the frame rate. The layer of each frame follows a
+//! fixed repeating pattern.
+
+/// Most temporal layers an encoder can be configured with
+pub const MAX_TEMPORAL_LAYERS: u8 = 3;
+
+/// Layer of each frame within one period of the pattern. Each layer doubles
+/// the frame rate of the layers below it.
+pub(crate) fn layer_ids(layers: u8) -> &'static [u8] {
+    match layers {
+        0 | 1 => &[0],
+        2 => &[0, 1],
+        _ => &[0, 2, 1, 2],
+    }
+}
+
+/// Assigns consecutive frames to temporal layers
+#[derive(Debug, Clone)]
+pub(crate) struct TemporalPattern {
+    ids: &'static [u8],
+    position: usize,
+}
+
+impl TemporalPattern {
+    pub fn new(layers: u8) -> Self {
+        Self {
+            ids: layer_ids(layers),
+            position: 0,
+        }
+    }
+
+    /// Layer of the next frame
+    pub fn next_layer(&mut self) -> u8 {
+        let layer = self.ids[self.position];
+        self.position = (self.position + 1) % self.ids.len();
+        layer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layer_patterns() {
+        let mut two = TemporalPattern::new(2);
+        let layers: Vec<u8> = (0..6).map(|_| two.next_layer()).collect();
+        assert_eq!(layers, [0, 1, 0, 1, 0, 1]);
+
+        let mut three = TemporalPattern::new(3);
+        let layers: Vec<u8> = (0..8).map(|_| three.next_layer()).collect();
+        assert_eq!(layers, [0, 2, 1, 2, 0, 2, 1, 2]);
+
+        let mut one = TemporalPattern::new(1);
+        assert!((0..4).all(|_| one.next_layer() == 0));
+    }
+}
@@ -2,23 +2,99 @@
 //!
 //! Core types, traits, and protocol definitions for Ada Remote.
 
-use serde::{Deserialize, Serialize};
+use bincode::Options;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::fmt;
+use std::io::{Read, Write};
+use std::str::FromStr;
 use uuid::Uuid;
 
-/// Session identifier - unique ID for each remote session
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
-pub struct SessionId(Uuid);
+mod limits;
+mod pool;
+
+pub use limits::{
+    MAX_CURSOR_SIZE, MAX_DIMENSION, MAX_FILE_CHUNKS, MAX_FILE_CHUNK_SIZE, MAX_FILE_NAME_LEN,
+    MAX_FILE_SIZE, MAX_PATH_LEN, MAX_SHORT_TEXT_LEN,
+};
+pub use pool::{FramePool, DEFAULT_POOL_SIZE};
+
+/// Version of the peer-to-peer protocol spoken by this build
+pub const PROTOCOL_VERSION: u32 = 3;
+
+/// Oldest peer protocol version this build still interoperates with; binary
+/// frames gained a flag byte in version 2 and `Hello` a key in version 3
+pub const MIN_PROTOCOL_VERSION: u32 = 3;
+
+/// Why a peer speaking `peer_version` must be refused, or `None` if the two
+/// can talk. A newer peer is accepted here; it applies its own minimum.
+pub fn protocol_incompatibility(peer_version: u32) -> Option<String> {
+    (peer_version < MIN_PROTOCOL_VERSION).then(|| {
+        format!(
+            "Peer protocol version {} is older than the minimum supported version {}",
+            peer_version, MIN_PROTOCOL_VERSION
+        )
+    })
+}
+
+/// Number of distinct session codes
+const SESSION_CODE_SPACE: u32 = 1_000_000_000;
+
+/// Session identifier - the 9-digit code users exchange to connect
+///
+/// The code is the whole identity: what the UI shows parses back to the same
+/// session, and it is what signaling and the relay key sessions by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SessionId(u32);
 
 impl SessionId {
     /// Generate a new random session ID
     pub fn new() -> Self {
-        Self(Uuid::new_v4())
+        Self((Uuid::new_v4().as_u128() % SESSION_CODE_SPACE as u128) as u32)
+    }
+
+    /// Session ID for a numeric code, if it fits in nine digits
+    pub fn from_code(code: u32) -> Option<Self> {
+        (code < SESSION_CODE_SPACE).then_some(Self(code))
     }
 
-    /// Create from a string representation
-    pub fn from_string(s: &str) -> std::result::Result<Self, uuid::Error> {
-        Ok(Self(Uuid::parse_str(s)?))
+    /// Numeric session code
+    pub fn code(&self) -> u32 {
+        self.0
+    }
+
+    /// Deterministically derive a session ID from a seed.
+    ///
+    /// Intended for tests and reproducible scenarios only - the result is
+    /// trivially predictable, so never use it for real sessions.
+    pub fn from_seed(seed: u64) -> Self {
+        // One SplitMix64 step to spread the seed over the code space
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        Self((z % SESSION_CODE_SPACE as u64) as u32)
+    }
+
+    /// Parse a code as entered by a user; spaces and dashes are ignored
+    pub fn from_string(s: &str) -> Result<Self> {
+        let digits: String = s.chars().filter(|c| !matches!(c, ' ' | '-')).collect();
+        if digits.len() != 9 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(Error::Session(format!("Invalid session code: {}", s)));
+        }
+        digits
+            .parse()
+            .map(Self)
+            .map_err(|_| Error::Session(format!("Invalid session code: {}", s)))
+    }
+}
+
+impl FromStr for SessionId {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::from_string(s)
     }
 }
 
@@ -30,8 +106,21 @@ impl Default for SessionId {
 
 impl fmt::Display for SessionId {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        // Display as 9-digit code for easier user entry
-        write!(f, "{:09}", (self.0.as_u128() % 1_000_000_000) as u32)
+        write!(f, "{:09}", self.0)
+    }
+}
+
+// Carried as the displayed code so every component agrees on the format
+impl Serialize for SessionId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for SessionId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let code = String::deserialize(deserializer)?;
+        Self::from_string(&code).map_err(serde::de::Error::custom)
     }
 }
 
@@ -46,6 +135,57 @@ pub enum ConnectionMode {
     FileTransfer,
 }
 
+impl ConnectionMode {
+    /// Whether the remote side may inject keyboard and mouse input
+    pub fn allows_input(self) -> bool {
+        self == ConnectionMode::FullControl
+    }
+}
+
+/// Lowercase with separators dropped, so "View-Only" and "view_only" match
+fn setting_key(s: &str) -> String {
+    s.chars()
+        .filter(|c| !matches!(c, ' ' | '-' | '_'))
+        .collect::<String>()
+        .to_lowercase()
+}
+
+impl FromStr for ConnectionMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match setting_key(s).as_str() {
+            "viewonly" => Ok(ConnectionMode::ViewOnly),
+            "fullcontrol" => Ok(ConnectionMode::FullControl),
+            "filetransfer" => Ok(ConnectionMode::FileTransfer),
+            _ => Err(Error::Session(format!(
+                "Unknown connection mode '{}', expected view_only, full_control or file_transfer",
+                s
+            ))),
+        }
+    }
+}
+
+/// Transport used to reach the remote peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConnectionType {
+    /// WebRTC data channel (preferred)
+    WebRTC,
+    /// QUIC fallback
+    QUIC,
+}
+
+/// How the client reports pointer movement
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum PointerMode {
+    /// Absolute positions; the local cursor stays visible
+    #[default]
+    Absolute,
+    /// Relative deltas with the local cursor hidden and locked, for games
+    /// and 3D apps that re-center the pointer
+    Relative,
+}
+
 /// Session configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SessionConfig {
@@ -54,53 +194,246 @@ pub struct SessionConfig {
     pub password_hash: Option<String>,
     pub clipboard_sync: bool,
     pub quality: VideoQuality,
+    /// Stream the host's audio output
+    #[serde(default)]
+    pub audio: bool,
+    #[serde(default)]
+    pub pointer_mode: PointerMode,
+}
+
+/// Optional features a peer supports and is willing to enable
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Capabilities {
+    /// Accepts keyboard/mouse input
+    pub input: bool,
+    /// Audio streaming
+    pub audio: bool,
+    /// File transfer
+    pub file_transfer: bool,
+    /// Clipboard synchronization
+    pub clipboard: bool,
+    /// Capture of more than one monitor
+    pub multi_monitor: bool,
+    /// Decompression of deflated frames
+    pub compression: bool,
+}
+
+impl Capabilities {
+    /// Features implemented by this build
+    pub fn supported() -> Self {
+        Self {
+            input: true,
+            audio: true,
+            file_transfer: true,
+            clipboard: true,
+            multi_monitor: false,
+            compression: true,
+        }
+    }
+
+    /// Supported features, restricted to what the session configuration allows
+    pub fn for_session(config: &SessionConfig) -> Self {
+        let supported = Self::supported();
+        Self {
+            input: supported.input && config.mode.allows_input(),
+            file_transfer: supported.file_transfer && config.mode != ConnectionMode::ViewOnly,
+            clipboard: supported.clipboard && config.clipboard_sync,
+            audio: supported.audio && config.audio,
+            ..supported
+        }
+    }
+
+    /// Features both peers agreed on
+    pub fn negotiate(&self, peer: &Capabilities) -> Capabilities {
+        Capabilities {
+            input: self.input && peer.input,
+            audio: self.audio && peer.audio,
+            file_transfer: self.file_transfer && peer.file_transfer,
+            clipboard: self.clipboard && peer.clipboard,
+            multi_monitor: self.multi_monitor && peer.multi_monitor,
+            compression: self.compression && peer.compression,
+        }
+    }
+
+    /// Compression to send frames with once these features are agreed
+    pub fn frame_compression(&self) -> Compression {
+        if self.compression {
+            Compression::Deflate
+        } else {
+            Compression::None
+        }
+    }
 }
 
 /// Video quality settings
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
 pub enum VideoQuality {
-    Low,      // 720p, 30fps, high compression
-    Medium,   // 1080p, 30fps, medium compression
-    High,     // 1080p, 60fps, low compression
+    Low,    // 720p, 30fps, high compression
+    Medium, // 1080p, 30fps, medium compression
+    High,   // 1080p, 60fps, low compression
+    #[default]
     Adaptive, // Adjust based on network conditions
 }
 
-impl Default for VideoQuality {
-    fn default() -> Self {
-        Self::Adaptive
+impl FromStr for VideoQuality {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match setting_key(s).as_str() {
+            "low" => Ok(VideoQuality::Low),
+            "medium" => Ok(VideoQuality::Medium),
+            "high" => Ok(VideoQuality::High),
+            "adaptive" => Ok(VideoQuality::Adaptive),
+            _ => Err(Error::Session(format!(
+                "Unknown video quality '{}', expected low, medium, high or adaptive",
+                s
+            ))),
+        }
     }
 }
 
+/// Largest binary frame accepted by [`ProtocolMessage::from_bytes`]
+pub const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+/// Length of the big-endian `u32` prefix of a binary frame
+pub const FRAME_HEADER_LEN: usize = 4;
+
+/// Smallest frame body worth compressing
+pub const COMPRESSION_THRESHOLD: usize = 1024;
+
+/// Flag byte of a frame whose body is plain bincode
+const FRAME_PLAIN: u8 = 0;
+
+/// Flag byte of a frame whose body is deflated bincode
+const FRAME_DEFLATE: u8 = 1;
+
+/// How binary frames are compressed, as agreed through [`Capabilities`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    /// Deflate bodies of at least [`COMPRESSION_THRESHOLD`] bytes when that
+    /// makes them smaller. Video and audio, compressed already, are left
+    /// as they are.
+    Deflate,
+}
+
 /// Message types for the Ada Remote protocol
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum ProtocolMessage {
     /// Request to establish a new session
     SessionRequest {
         session_id: SessionId,
         password: Option<String>,
         mode: ConnectionMode,
+        /// Client's `PROTOCOL_VERSION`, checked before anything else
+        protocol_version: u32,
     },
-    /// Response to session request
+    /// Response to session request; a version mismatch is refused with a
+    /// reason from [`protocol_incompatibility`]
     SessionResponse {
         accepted: bool,
         reason: Option<String>,
     },
+    /// Client's long-term identity key, to pair once the password has been
+    /// accepted or to authenticate as an already paired device
+    PairRequest {
+        public_key: [u8; 32],
+        device_name: String,
+    },
+    /// Host's long-term identity key and a fresh nonce for the client to
+    /// sign
+    PairChallenge {
+        public_key: [u8; 32],
+        device_name: String,
+        nonce: [u8; 32],
+    },
+    /// Client's proof for the last challenge, answered with a
+    /// `SessionResponse`
+    PairResponse { proof: [u8; 32] },
+    /// First message from each peer, advertising its protocol version and
+    /// optional features, with a fresh X25519 public key for the short
+    /// authentication string
+    Hello {
+        protocol_version: u32,
+        capabilities: Capabilities,
+        public_key: [u8; 32],
+    },
     /// Heartbeat to keep connection alive
     Heartbeat,
-    /// Video frame data
+    /// Round-trip probe; `sent_at` is the sender's clock in microseconds
+    Ping { seq: u64, sent_at: u64 },
+    /// Echo of a `Ping`, unchanged
+    Pong { seq: u64, sent_at: u64 },
+    /// Clock offset probe. The client sends its clock in `t0` with `t1` zero;
+    /// the host echoes `t0` with its own clock in `t1`. Microseconds since
+    /// the Unix epoch.
+    ClockSync { t0: u64, t1: u64 },
+    /// The active transport path changed (e.g. fell back to QUIC or a relay)
+    TransportChanged {
+        connection_type: ConnectionType,
+        relayed: bool,
+    },
+    /// Client view size in logical points and its display scale. The host
+    /// streams at up to `width * scale` by `height * scale` pixels.
+    DisplayConfig { width: u32, height: u32, scale: f32 },
+    /// Video frame data. `captured_at` and `sent_at` are host clock
+    /// readings, in microseconds since the Unix epoch, for latency tracking.
     VideoFrame {
         timestamp: u64,
+        captured_at: u64,
+        sent_at: u64,
+        /// Whether the frame decodes on its own; other frames may be
+        /// dropped under backpressure
+        is_keyframe: bool,
+        /// Temporal layer; dropping every frame above a layer leaves a
+        /// decodable stream at a lower frame rate
+        temporal_layer: u8,
+        data: Vec<u8>,
+    },
+    /// Client request for a keyframe, after a decode error it cannot
+    /// recover from until the next one
+    KeyframeRequest,
+    /// Opus-encoded audio packet
+    AudioFrame {
+        timestamp: u64,
+        sample_rate: u32,
+        channels: u16,
         data: Vec<u8>,
     },
+    /// New pointer image, sent when the shape changes so the client can draw
+    /// the cursor locally. A 0x0 image hides it.
+    CursorShape {
+        width: u32,
+        height: u32,
+        hotspot_x: u32,
+        hotspot_y: u32,
+        rgba: Vec<u8>,
+    },
     /// Input event (keyboard/mouse)
     InputEvent {
         event_type: InputEventType,
         data: Vec<u8>,
     },
     /// Clipboard data
-    Clipboard {
-        content: String,
+    Clipboard { content: String },
+    /// Part `index` of `count` of clipboard text too large for one message,
+    /// as UTF-8 bytes
+    ClipboardChunk {
+        sequence: u32,
+        index: u32,
+        count: u32,
+        data: Vec<u8>,
     },
+    /// Clipboard image of `width`x`height` pixels, PNG encoded
+    ClipboardImage {
+        width: u32,
+        height: u32,
+        png: Vec<u8>,
+    },
+    /// Files on the clipboard, as paths on the sender. Their contents go
+    /// through file transfer.
+    ClipboardFiles { paths: Vec<String> },
     /// File transfer initiation
     FileTransferStart {
         file_name: String,
@@ -113,14 +446,120 @@ pub enum ProtocolMessage {
         chunk_index: u64,
         data: Vec<u8>,
     },
-    /// File transfer complete
-    FileTransferComplete {
+    /// File transfer complete, with the SHA-256 of the whole file
+    FileTransferComplete { transfer_id: Uuid, sha256: Vec<u8> },
+    /// Sent by a receiver reconnecting mid-transfer: it holds the first
+    /// `offset` bytes, and the sender continues from chunk `next_chunk`
+    FileTransferResume {
         transfer_id: Uuid,
+        next_chunk: u64,
+        offset: u64,
     },
+    /// File transfer abandoned by either side
+    FileTransferCancel { transfer_id: Uuid, reason: String },
     /// Session termination
-    Disconnect {
-        reason: String,
-    },
+    Disconnect { reason: String },
+}
+
+impl ProtocolMessage {
+    /// Encode as an uncompressed binary frame for the data path: the length
+    /// of the rest as a big-endian `u32`, a flag byte telling whether the
+    /// body is compressed, then the bincode body. Signaling stays JSON.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        self.to_bytes_with(Compression::None)
+    }
+
+    /// Encode as a binary frame, compressed as `compression` allows
+    pub fn to_bytes_with(&self, compression: Compression) -> Result<Vec<u8>> {
+        let options = binary_options();
+        let len = options.serialized_size(self)? as usize;
+        let body_start = FRAME_HEADER_LEN + 1;
+        let mut frame = Vec::with_capacity(body_start + len);
+        frame.extend_from_slice(&[0; FRAME_HEADER_LEN]);
+        frame.push(FRAME_PLAIN);
+        options.serialize_into(&mut frame, self)?;
+
+        let compress = compression == Compression::Deflate
+            && len >= COMPRESSION_THRESHOLD
+            && !matches!(
+                self,
+                ProtocolMessage::VideoFrame { .. } | ProtocolMessage::AudioFrame { .. }
+            );
+        if compress {
+            let mut encoder = DeflateEncoder::new(Vec::new(), flate2::Compression::fast());
+            encoder.write_all(&frame[body_start..])?;
+            let deflated = encoder.finish()?;
+            if deflated.len() < len {
+                frame.truncate(body_start);
+                frame[FRAME_HEADER_LEN] = FRAME_DEFLATE;
+                frame.extend_from_slice(&deflated);
+            }
+        }
+
+        let len = (frame.len() - FRAME_HEADER_LEN) as u32;
+        frame[..FRAME_HEADER_LEN].copy_from_slice(&len.to_be_bytes());
+        Ok(frame)
+    }
+
+    /// Length of the uncompressed frame [`to_bytes`](Self::to_bytes) would
+    /// produce, without encoding it
+    pub fn encoded_len(&self) -> Result<usize> {
+        Ok(FRAME_HEADER_LEN + 1 + binary_options().serialized_size(self)? as usize)
+    }
+
+    /// Decode one frame produced by [`to_bytes_with`](Self::to_bytes_with),
+    /// compressed or not, and [`validate`](Self::validate) it
+    pub fn from_bytes(frame: &[u8]) -> Result<Self> {
+        let message = Self::decode_frame(frame)?;
+        message.validate()?;
+        Ok(message)
+    }
+
+    fn decode_frame(frame: &[u8]) -> Result<Self> {
+        let header = frame
+            .get(..FRAME_HEADER_LEN)
+            .ok_or_else(|| Error::Decoding("Truncated frame header".to_string()))?;
+        let len = Self::frame_body_len(header.try_into().unwrap())?;
+        let body = &frame[FRAME_HEADER_LEN..];
+        if body.len() != len {
+            return Err(Error::Decoding(format!(
+                "Frame body is {} bytes, header says {}",
+                body.len(),
+                len
+            )));
+        }
+        match body.split_first() {
+            Some((&FRAME_PLAIN, body)) => Ok(binary_options().deserialize(body)?),
+            Some((&FRAME_DEFLATE, body)) => {
+                // Bounded so a small frame cannot inflate without limit
+                let mut inflated = Vec::new();
+                DeflateDecoder::new(body)
+                    .take(MAX_FRAME_SIZE as u64 + 1)
+                    .read_to_end(&mut inflated)
+                    .map_err(|e| Error::Decoding(format!("Invalid compressed frame: {}", e)))?;
+                if inflated.len() > MAX_FRAME_SIZE {
+                    return Err(Error::Decoding("Decompressed frame too large".to_string()));
+                }
+                Ok(binary_options().deserialize(&inflated)?)
+            }
+            Some((flag, _)) => Err(Error::Decoding(format!("Unknown frame flag {}", flag))),
+            None => Err(Error::Decoding("Empty frame".to_string())),
+        }
+    }
+
+    /// Body length announced by a frame header, for reading frames off a
+    /// stream
+    pub fn frame_body_len(header: [u8; FRAME_HEADER_LEN]) -> Result<usize> {
+        let len = u32::from_be_bytes(header) as usize;
+        if len > MAX_FRAME_SIZE {
+            return Err(Error::Decoding(format!("Frame too large: {} bytes", len)));
+        }
+        Ok(len)
+    }
+}
+
+fn binary_options() -> impl Options + Copy {
+    bincode::DefaultOptions::new().with_limit(MAX_FRAME_SIZE as u64)
 }
 
 /// Input event types
@@ -129,9 +568,12 @@ pub enum InputEventType {
     KeyPress,
     KeyRelease,
     MouseMove,
+    MouseMoveRelative,
     MouseButtonPress,
     MouseButtonRelease,
     MouseScroll,
+    TypeText,
+    MouseWarp,
 }
 
 /// Result type for Ada Remote operations
@@ -143,6 +585,9 @@ pub enum Error {
     #[error("Network error: {0}")]
     Network(String),
 
+    #[error("Connection closed by peer: {0}")]
+    ConnectionClosed(String),
+
     #[error("Authentication failed: {0}")]
     Authentication(String),
 
@@ -155,11 +600,23 @@ pub enum Error {
     #[error("Decoding error: {0}")]
     Decoding(String),
 
+    #[error("Keyframe needed: {0}")]
+    KeyframeNeeded(String),
+
+    #[error("Capture target closed: {0}")]
+    TargetClosed(String),
+
+    #[error("Input not permitted: {0}")]
+    InputDenied(String),
+
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
 
     #[error("Serialization error: {0}")]
     Serialization(#[from] serde_json::Error),
+
+    #[error("Binary serialization error: {0}")]
+    Binary(#[from] bincode::Error),
 }
 
 #[cfg(test)]
@@ -179,4 +636,298 @@ mod tests {
         let display = format!("{}", id);
         assert_eq!(display.len(), 9);
     }
+
+    #[test]
+    fn test_capabilities_negotiation() {
+        let host = Capabilities {
+            audio: false,
+            ..Capabilities::supported()
+        };
+        let client = Capabilities {
+            audio: true,
+            ..Capabilities::supported()
+        };
+
+        let agreed = host.negotiate(&client);
+        assert!(!agreed.audio);
+        assert!(agreed.clipboard);
+        assert_eq!(agreed, client.negotiate(&host));
+        assert_eq!(agreed.frame_compression(), Compression::Deflate);
+
+        let legacy = Capabilities {
+            compression: false,
+            ..client
+        };
+        let agreed = host.negotiate(&legacy);
+        assert_eq!(agreed.frame_compression(), Compression::None);
+    }
+
+    #[test]
+    fn test_view_only_host_advertises_no_input() {
+        let config = SessionConfig {
+            session_id: SessionId::from_seed(1),
+            mode: ConnectionMode::ViewOnly,
+            password_hash: None,
+            clipboard_sync: true,
+            quality: VideoQuality::default(),
+            audio: false,
+            pointer_mode: PointerMode::Absolute,
+        };
+
+        let capabilities = Capabilities::for_session(&config);
+        assert!(!capabilities.input);
+        assert!(!capabilities.negotiate(&Capabilities::supported()).input);
+    }
+
+    #[test]
+    fn test_protocol_version_compatibility() {
+        assert_eq!(protocol_incompatibility(PROTOCOL_VERSION), None);
+        assert_eq!(protocol_incompatibility(PROTOCOL_VERSION + 1), None);
+
+        let reason = protocol_incompatibility(MIN_PROTOCOL_VERSION - 1).unwrap();
+        let minimum = format!("minimum supported version {}", MIN_PROTOCOL_VERSION);
+        assert!(reason.contains(&format!("version {}", MIN_PROTOCOL_VERSION - 1)));
+        assert!(reason.contains(&minimum));
+    }
+
+    #[test]
+    fn test_binary_round_trip_every_variant() {
+        let transfer_id = Uuid::new_v4();
+        let messages = vec![
+            ProtocolMessage::SessionRequest {
+                session_id: SessionId::from_seed(3),
+                password: Some("hash".to_string()),
+                mode: ConnectionMode::ViewOnly,
+                protocol_version: PROTOCOL_VERSION,
+            },
+            ProtocolMessage::SessionResponse {
+                accepted: false,
+                reason: Some("busy".to_string()),
+            },
+            ProtocolMessage::PairRequest {
+                public_key: [1; 32],
+                device_name: "laptop".to_string(),
+            },
+            ProtocolMessage::PairChallenge {
+                public_key: [2; 32],
+                device_name: "desktop".to_string(),
+                nonce: [3; 32],
+            },
+            ProtocolMessage::PairResponse { proof: [4; 32] },
+            ProtocolMessage::Hello {
+                protocol_version: PROTOCOL_VERSION,
+                capabilities: Capabilities::supported(),
+                public_key: [5; 32],
+            },
+            ProtocolMessage::Heartbeat,
+            ProtocolMessage::Ping {
+                seq: 3,
+                sent_at: 1_000,
+            },
+            ProtocolMessage::Pong {
+                seq: 3,
+                sent_at: 1_000,
+            },
+            ProtocolMessage::ClockSync { t0: 10, t1: 20 },
+            ProtocolMessage::TransportChanged {
+                connection_type: ConnectionType::QUIC,
+                relayed: true,
+            },
+            ProtocolMessage::DisplayConfig {
+                width: 1280,
+                height: 720,
+                scale: 1.5,
+            },
+            ProtocolMessage::VideoFrame {
+                timestamp: 42,
+                captured_at: 1_700_000_000_000_000,
+                sent_at: 1_700_000_000_004_000,
+                is_keyframe: true,
+                temporal_layer: 0,
+                data: vec![1, 2, 3],
+            },
+            ProtocolMessage::KeyframeRequest,
+            ProtocolMessage::AudioFrame {
+                timestamp: 42,
+                sample_rate: 48_000,
+                channels: 2,
+                data: vec![4, 5],
+            },
+            ProtocolMessage::CursorShape {
+                width: 1,
+                height: 1,
+                hotspot_x: 0,
+                hotspot_y: 0,
+                rgba: vec![255, 255, 255, 255],
+            },
+            ProtocolMessage::InputEvent {
+                event_type: InputEventType::MouseScroll,
+                data: vec![0, 255],
+            },
+            ProtocolMessage::Clipboard {
+                content: "copied".to_string(),
+            },
+            ProtocolMessage::ClipboardChunk {
+                sequence: 1,
+                index: 0,
+                count: 2,
+                data: b"cop".to_vec(),
+            },
+            ProtocolMessage::ClipboardImage {
+                width: 1,
+                height: 1,
+                png: vec![0x89, b'P', b'N', b'G'],
+            },
+            ProtocolMessage::ClipboardFiles {
+                paths: vec!["/home/ada/report.pdf".to_string()],
+            },
+            ProtocolMessage::FileTransferStart {
+                file_name: "a.txt".to_string(),
+                file_size: 10,
+                transfer_id,
+            },
+            ProtocolMessage::FileTransferChunk {
+                transfer_id,
+                chunk_index: 1,
+                data: vec![9; 10],
+            },
+            ProtocolMessage::FileTransferComplete {
+                transfer_id,
+                sha256: vec![7; 32],
+            },
+            ProtocolMessage::FileTransferResume {
+                transfer_id,
+                next_chunk: 3,
+                offset: 3 * 65536,
+            },
+            ProtocolMessage::FileTransferCancel {
+                transfer_id,
+                reason: "cancelled".to_string(),
+            },
+            ProtocolMessage::Disconnect {
+                reason: "bye".to_string(),
+            },
+        ];
+
+        for message in messages {
+            let frame = message.to_bytes().unwrap();
+            assert_eq!(message.encoded_len().unwrap(), frame.len());
+            assert_eq!(ProtocolMessage::from_bytes(&frame).unwrap(), message);
+            assert!(ProtocolMessage::from_bytes(&frame[..frame.len() - 1]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_large_clipboard_compressed() {
+        let message = ProtocolMessage::Clipboard {
+            content: "fn main() {}\n".repeat(1000),
+        };
+        let plain = message.to_bytes().unwrap();
+        let compressed = message.to_bytes_with(Compression::Deflate).unwrap();
+        assert_eq!(compressed[FRAME_HEADER_LEN], FRAME_DEFLATE);
+        assert!(compressed.len() * 10 < plain.len());
+        assert_eq!(ProtocolMessage::from_bytes(&compressed).unwrap(), message);
+        assert_eq!(ProtocolMessage::from_bytes(&plain).unwrap(), message);
+    }
+
+    #[test]
+    fn test_small_and_media_frames_not_compressed() {
+        let small = ProtocolMessage::Clipboard {
+            content: "copied".to_string(),
+        };
+        let frame = small.to_bytes_with(Compression::Deflate).unwrap();
+        assert_eq!(frame, small.to_bytes().unwrap());
+        assert_eq!(frame[FRAME_HEADER_LEN], FRAME_PLAIN);
+
+        let video = ProtocolMessage::VideoFrame {
+            timestamp: 1,
+            captured_at: 0,
+            sent_at: 0,
+            is_keyframe: true,
+            temporal_layer: 0,
+            data: vec![0; 64 * 1024],
+        };
+        let frame = video.to_bytes_with(Compression::Deflate).unwrap();
+        assert_eq!(frame[FRAME_HEADER_LEN], FRAME_PLAIN);
+    }
+
+    #[test]
+    fn test_compressed_frame_size_limited() {
+        let mut encoder = DeflateEncoder::new(Vec::new(), flate2::Compression::best());
+        encoder.write_all(&vec![0; MAX_FRAME_SIZE + 1]).unwrap();
+        let body = encoder.finish().unwrap();
+
+        let mut frame = ((body.len() + 1) as u32).to_be_bytes().to_vec();
+        frame.push(FRAME_DEFLATE);
+        frame.extend_from_slice(&body);
+        assert!(ProtocolMessage::from_bytes(&frame).is_err());
+    }
+
+    #[test]
+    fn test_binary_video_frame_smaller_than_json() {
+        let message = ProtocolMessage::VideoFrame {
+            timestamp: 1,
+            captured_at: 0,
+            sent_at: 0,
+            is_keyframe: false,
+            temporal_layer: 0,
+            data: (0..100 * 1024).map(|i| i as u8).collect(),
+        };
+        let binary = message.to_bytes().unwrap().len();
+        let json = serde_json::to_vec(&message).unwrap().len();
+        assert!(binary < 100 * 1024 + 32);
+        assert!(binary * 3 < json, "binary {} vs json {}", binary, json);
+    }
+
+    #[test]
+    fn test_session_id_from_seed() {
+        assert_eq!(SessionId::from_seed(42), SessionId::from_seed(42));
+        assert_ne!(SessionId::from_seed(42), SessionId::from_seed(43));
+        assert!(SessionId::from_seed(0).code() < SESSION_CODE_SPACE);
+    }
+
+    #[test]
+    fn test_session_id_display_round_trip() {
+        let id = SessionId::new();
+        assert_eq!(SessionId::from_string(&id.to_string()).unwrap(), id);
+        assert_eq!(
+            SessionId::from_string("012 345-678").unwrap().code(),
+            12_345_678
+        );
+        assert!(SessionId::from_string("12345678").is_err());
+        assert!(SessionId::from_string("12345678x").is_err());
+
+        let json = serde_json::to_string(&SessionId::from_code(42).unwrap()).unwrap();
+        assert_eq!(json, "\"000000042\"");
+        assert_eq!(serde_json::from_str::<SessionId>(&json).unwrap().code(), 42);
+    }
+
+    #[test]
+    fn test_parse_settings() {
+        assert_eq!("high".parse::<VideoQuality>().unwrap(), VideoQuality::High);
+        assert_eq!("Low".parse::<VideoQuality>().unwrap(), VideoQuality::Low);
+        assert_eq!(
+            "ADAPTIVE".parse::<VideoQuality>().unwrap(),
+            VideoQuality::Adaptive
+        );
+        assert_eq!(
+            "medium".parse::<VideoQuality>().unwrap(),
+            VideoQuality::Medium
+        );
+        let error = "ultra".parse::<VideoQuality>().unwrap_err().to_string();
+        assert!(error.contains("'ultra'"), "{}", error);
+        assert!("".parse::<VideoQuality>().is_err());
+
+        for (input, mode) in [
+            ("ViewOnly", ConnectionMode::ViewOnly),
+            ("view_only", ConnectionMode::ViewOnly),
+            ("full-control", ConnectionMode::FullControl),
+            ("File Transfer", ConnectionMode::FileTransfer),
+        ] {
+            assert_eq!(input.parse::<ConnectionMode>().unwrap(), mode);
+        }
+        let error = "admin".parse::<ConnectionMode>().unwrap_err().to_string();
+        assert!(error.contains("'admin'"), "{}", error);
+        assert!("view".parse::<ConnectionMode>().is_err());
+    }
 }
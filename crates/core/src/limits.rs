@@ -0,0 +1,275 @@
+//! Limits on decoded protocol messages
+//!
+//! Messages come from a peer or relay that may be compromised, so after
+//! decoding, [`ProtocolMessage::from_bytes`] checks every field that sizes
+//! an allocation or indexes into state against these limits. The frame size
+//! already bounds what decoding itself allocates.
+
+use crate::{Error, ProtocolMessage, Result, MAX_FRAME_SIZE};
+
+/// Largest file transfer chunk
+pub const MAX_FILE_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Longest file name, in bytes, as most file systems allow
+pub const MAX_FILE_NAME_LEN: usize = 255;
+
+/// Longest path in a clipboard file list, in bytes
+pub const MAX_PATH_LEN: usize = 4096;
+
+/// Largest file that can be transferred
+pub const MAX_FILE_SIZE: u64 = 1 << 40;
+
+/// Highest chunk count of a transfer
+pub const MAX_FILE_CHUNKS: u64 = 1 << 32;
+
+/// Longest reason, password or device name, in bytes
+pub const MAX_SHORT_TEXT_LEN: usize = 1024;
+
+/// Largest frame, view or image side in pixels
+pub const MAX_DIMENSION: u32 = 16384;
+
+/// Largest cursor side in pixels
+pub const MAX_CURSOR_SIZE: u32 = 256;
+
+fn invalid(message: String) -> Error {
+    Error::Decoding(message)
+}
+
+fn check_len(field: &str, len: usize, max: usize) -> Result<()> {
+    if len > max {
+        return Err(invalid(format!(
+            "{} of {} bytes exceeds the {} byte limit",
+            field, len, max
+        )));
+    }
+    Ok(())
+}
+
+fn check_text(field: &str, text: Option<&str>) -> Result<()> {
+    check_len(field, text.map_or(0, str::len), MAX_SHORT_TEXT_LEN)
+}
+
+fn check_dimensions(field: &str, width: u32, height: u32, max: u32) -> Result<()> {
+    if width > max || height > max {
+        return Err(invalid(format!(
+            "{} of {}x{} exceeds {}x{}",
+            field, width, height, max, max
+        )));
+    }
+    Ok(())
+}
+
+fn check_chunk(chunk: u64) -> Result<()> {
+    if chunk >= MAX_FILE_CHUNKS {
+        return Err(invalid(format!("Chunk index {} out of range", chunk)));
+    }
+    Ok(())
+}
+
+fn check_file_size(field: &str, size: u64) -> Result<()> {
+    if size > MAX_FILE_SIZE {
+        return Err(invalid(format!(
+            "{} of {} bytes exceeds the {} byte limit",
+            field, size, MAX_FILE_SIZE
+        )));
+    }
+    Ok(())
+}
+
+impl ProtocolMessage {
+    /// Check the fields of a decoded message against the limits above
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            ProtocolMessage::SessionRequest { password, .. } => {
+                check_text("Password", password.as_deref())
+            }
+            ProtocolMessage::SessionResponse { reason, .. } => {
+                check_text("Reason", reason.as_deref())
+            }
+            ProtocolMessage::PairRequest { device_name, .. }
+            | ProtocolMessage::PairChallenge { device_name, .. } => {
+                check_text("Device name", Some(device_name))
+            }
+            ProtocolMessage::DisplayConfig { width, height, .. } => {
+                check_dimensions("View", *width, *height, MAX_DIMENSION)
+            }
+            ProtocolMessage::VideoFrame { data, .. } | ProtocolMessage::AudioFrame { data, .. } => {
+                check_len("Media frame", data.len(), MAX_FRAME_SIZE)
+            }
+            ProtocolMessage::CursorShape {
+                width,
+                height,
+                rgba,
+                ..
+            } => {
+                check_dimensions("Cursor", *width, *height, MAX_CURSOR_SIZE)?;
+                if rgba.len() != (*width * *height * 4) as usize {
+                    return Err(invalid(format!(
+                        "Cursor of {}x{} with {} bytes of RGBA",
+                        width,
+                        height,
+                        rgba.len()
+                    )));
+                }
+                Ok(())
+            }
+            ProtocolMessage::ClipboardChunk { index, count, .. } => {
+                if index >= count {
+                    return Err(invalid(format!("Clipboard chunk {} of {}", index, count)));
+                }
+                Ok(())
+            }
+            ProtocolMessage::ClipboardImage { width, height, .. } => {
+                check_dimensions("Clipboard image", *width, *height, MAX_DIMENSION)
+            }
+            ProtocolMessage::ClipboardFiles { paths } => paths
+                .iter()
+                .try_for_each(|path| check_len("Clipboard path", path.len(), MAX_PATH_LEN)),
+            ProtocolMessage::FileTransferStart {
+                file_name,
+                file_size,
+                ..
+            } => {
+                check_len("File name", file_name.len(), MAX_FILE_NAME_LEN)?;
+                check_file_size("File", *file_size)
+            }
+            ProtocolMessage::FileTransferChunk {
+                chunk_index, data, ..
+            } => {
+                check_chunk(*chunk_index)?;
+                check_len("File chunk", data.len(), MAX_FILE_CHUNK_SIZE)
+            }
+            ProtocolMessage::FileTransferComplete { sha256, .. } => {
+                if sha256.len() != 32 {
+                    return Err(invalid(format!("SHA-256 of {} bytes", sha256.len())));
+                }
+                Ok(())
+            }
+            ProtocolMessage::FileTransferResume {
+                next_chunk, offset, ..
+            } => {
+                check_chunk(*next_chunk)?;
+                check_file_size("Resume offset", *offset)
+            }
+            ProtocolMessage::FileTransferCancel { reason, .. }
+            | ProtocolMessage::Disconnect { reason } => check_text("Reason", Some(reason)),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FRAME_HEADER_LEN;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_out_of_range_fields_rejected() {
+        let transfer_id = Uuid::new_v4();
+        let rejected = [
+            ProtocolMessage::FileTransferStart {
+                file_name: "x".repeat(MAX_FILE_NAME_LEN + 1),
+                file_size: 10,
+                transfer_id,
+            },
+            ProtocolMessage::FileTransferStart {
+                file_name: "a.txt".to_string(),
+                file_size: u64::MAX,
+                transfer_id,
+            },
+            ProtocolMessage::FileTransferChunk {
+                transfer_id,
+                chunk_index: u64::MAX,
+                data: vec![],
+            },
+            ProtocolMessage::FileTransferChunk {
+                transfer_id,
+                chunk_index: 0,
+                data: vec![0; MAX_FILE_CHUNK_SIZE + 1],
+            },
+            ProtocolMessage::FileTransferResume {
+                transfer_id,
+                next_chunk: 0,
+                offset: MAX_FILE_SIZE + 1,
+            },
+            ProtocolMessage::CursorShape {
+                width: 2,
+                height: 2,
+                hotspot_x: 0,
+                hotspot_y: 0,
+                rgba: vec![0; 4],
+            },
+            ProtocolMessage::ClipboardChunk {
+                sequence: 0,
+                index: 2,
+                count: 2,
+                data: vec![],
+            },
+            ProtocolMessage::Disconnect {
+                reason: "x".repeat(MAX_SHORT_TEXT_LEN + 1),
+            },
+        ];
+        for message in rejected {
+            // Encoding does not check, so a faulty peer is simulated
+            let frame = message.to_bytes().unwrap();
+            assert!(
+                matches!(ProtocolMessage::from_bytes(&frame), Err(Error::Decoding(_))),
+                "{:?} accepted",
+                message
+            );
+        }
+
+        let chunk = ProtocolMessage::FileTransferChunk {
+            transfer_id,
+            chunk_index: 7,
+            data: vec![0; MAX_FILE_CHUNK_SIZE],
+        };
+        assert_eq!(
+            ProtocolMessage::from_bytes(&chunk.to_bytes().unwrap()).unwrap(),
+            chunk
+        );
+    }
+
+    #[test]
+    fn test_garbage_rejected_without_panicking() {
+        // xorshift, so failures reproduce
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        for len in 0..2048 {
+            let mut frame: Vec<u8> = (0..len).map(|_| next() as u8).collect();
+            if len >= 5 {
+                // A consistent header, so the body reaches the decoder
+                frame[..4].copy_from_slice(&(len as u32 - 4).to_be_bytes());
+                frame[4] = (next() % 2) as u8;
+            }
+            let _ = ProtocolMessage::from_bytes(&frame);
+        }
+
+        // A video frame claiming far more data than the frame holds
+        let mut frame = ProtocolMessage::VideoFrame {
+            timestamp: 0,
+            captured_at: 0,
+            sent_at: 0,
+            is_keyframe: true,
+            temporal_layer: 0,
+            data: vec![1, 2, 3],
+        }
+        .to_bytes()
+        .unwrap();
+        let len_at = frame.len() - 4;
+        frame.truncate(len_at);
+        // Varint marker for a u64 length, then 2^40
+        frame.push(253);
+        frame.extend_from_slice(&(1u64 << 40).to_le_bytes());
+        let body = (frame.len() - FRAME_HEADER_LEN) as u32;
+        frame[..FRAME_HEADER_LEN].copy_from_slice(&body.to_be_bytes());
+        assert!(ProtocolMessage::from_bytes(&frame).is_err());
+    }
+}
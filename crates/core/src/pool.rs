@@ -0,0 +1,135 @@
+//! Frame buffer pool
+//!
+//! Capture hands out a full-resolution buffer every frame. Rather than
+//! allocating each one, backends take buffers from a [`FramePool`] and the
+//! encoder recycles them once a frame has been consumed, so a steady stream
+//! settles on a handful of allocations.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Buffers kept for reuse by [`FramePool::default`]
+pub const DEFAULT_POOL_SIZE: usize = 4;
+
+struct Shared {
+    idle: Mutex<Vec<Vec<u8>>>,
+    max_idle: usize,
+    allocations: AtomicU64,
+}
+
+/// Shared pool of reusable frame buffers. Clones share the same buffers.
+#[derive(Clone)]
+pub struct FramePool {
+    shared: Arc<Shared>,
+}
+
+impl FramePool {
+    /// Pool keeping at most `max_idle` returned buffers
+    pub fn new(max_idle: usize) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                idle: Mutex::new(Vec::with_capacity(max_idle)),
+                max_idle,
+                allocations: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Empty buffer with room for at least `capacity` bytes, reusing a
+    /// recycled one when there is one
+    pub fn take(&self, capacity: usize) -> Vec<u8> {
+        let recycled = self.shared.idle.lock().unwrap().pop();
+        match recycled {
+            Some(mut buffer) => {
+                if buffer.capacity() < capacity {
+                    self.shared.allocations.fetch_add(1, Ordering::Relaxed);
+                    buffer.reserve_exact(capacity);
+                }
+                buffer
+            }
+            None => {
+                self.shared.allocations.fetch_add(1, Ordering::Relaxed);
+                Vec::with_capacity(capacity)
+            }
+        }
+    }
+
+    /// Return a buffer once its frame is no longer needed. It is cleared;
+    /// buffers beyond the pool size are freed.
+    pub fn recycle(&self, mut buffer: Vec<u8>) {
+        buffer.clear();
+        let mut idle = self.shared.idle.lock().unwrap();
+        if idle.len() < self.shared.max_idle && buffer.capacity() > 0 {
+            idle.push(buffer);
+        }
+    }
+
+    /// Buffers allocated or grown by [`take`](Self::take) so far
+    pub fn allocations(&self) -> u64 {
+        self.shared.allocations.load(Ordering::Relaxed)
+    }
+
+    /// Buffers waiting to be reused
+    pub fn idle(&self) -> usize {
+        self.shared.idle.lock().unwrap().len()
+    }
+}
+
+impl Default for FramePool {
+    fn default() -> Self {
+        Self::new(DEFAULT_POOL_SIZE)
+    }
+}
+
+impl fmt::Debug for FramePool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FramePool")
+            .field("idle", &self.idle())
+            .field("max_idle", &self.shared.max_idle)
+            .field("allocations", &self.allocations())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buffers_reused_across_frames() {
+        let pool = FramePool::new(2);
+        let mut in_flight = Vec::new();
+        for frame in 0..1000u32 {
+            let mut buffer = pool.take(64);
+            assert!(buffer.is_empty());
+            buffer.extend_from_slice(&frame.to_le_bytes().repeat(16));
+            // Two frames in flight at a time, as with capture ahead of encode
+            in_flight.push(buffer);
+            if in_flight.len() == 2 {
+                pool.recycle(in_flight.remove(0));
+            }
+        }
+        assert_eq!(pool.allocations(), 2);
+
+        let first = pool.take(64);
+        let address = first.as_ptr();
+        pool.recycle(first);
+        let again = pool.take(64);
+        assert_eq!(again.as_ptr(), address);
+        assert!(again.is_empty());
+    }
+
+    #[test]
+    fn test_pool_bounded() {
+        let pool = FramePool::new(1);
+        pool.recycle(vec![1; 8]);
+        pool.recycle(vec![2; 8]);
+        assert_eq!(pool.idle(), 1);
+
+        // A larger frame grows the recycled buffer
+        let buffer = pool.take(1024);
+        assert!(buffer.capacity() >= 1024);
+        assert_eq!(pool.allocations(), 1);
+    }
+}
@@ -0,0 +1,152 @@
+//! Long-term identity keys
+//!
+//! Hosts keep one X25519 identity across launches so clients can pin it.
+//! The secret is stored encrypted under a passphrase: Argon2id stretches the
+//! passphrase into a ChaCha20-Poly1305 key.
+//!
+//! File layout: `MAGIC || salt (16) || nonce (12) || ciphertext (32 + 16 tag)`
+
+use crate::{PasswordPolicy, KEY_SIZE, NONCE_SIZE};
+use ada_remote_core::Result;
+use chacha20poly1305::{
+    aead::{Aead, KeyInit},
+    ChaCha20Poly1305, Nonce,
+};
+use rand::RngCore;
+use std::path::Path;
+use x25519_dalek::{PublicKey, SharedSecret, StaticSecret};
+
+const MAGIC: &[u8; 8] = b"ADAID\x00\x00\x01";
+const SALT_SIZE: usize = 16;
+const TAG_SIZE: usize = 16;
+const FILE_SIZE: usize = MAGIC.len() + SALT_SIZE + NONCE_SIZE + KEY_SIZE + TAG_SIZE;
+
+/// Persistent X25519 key pair identifying this machine
+pub struct IdentityKeyPair {
+    secret: StaticSecret,
+    public: PublicKey,
+}
+
+impl IdentityKeyPair {
+    /// Generate a new random identity
+    pub fn generate() -> Self {
+        Self::from_secret(StaticSecret::random_from_rng(rand::thread_rng()))
+    }
+
+    fn from_secret(secret: StaticSecret) -> Self {
+        let public = PublicKey::from(&secret);
+        Self { secret, public }
+    }
+
+    /// Get the public key
+    pub fn public_key(&self) -> &PublicKey {
+        &self.public
+    }
+
+    /// Compute shared secret with a peer's public key, keeping the identity
+    /// usable for later sessions
+    pub fn compute_shared_secret(&self, peer_public: &PublicKey) -> SharedSecret {
+        self.secret.diffie_hellman(peer_public)
+    }
+
+    /// Write the identity to `path`, encrypted under `passphrase`
+    pub fn save_to(&self, path: impl AsRef<Path>, passphrase: &str) -> Result<()> {
+        let mut salt = [0u8; SALT_SIZE];
+        let mut nonce = [0u8; NONCE_SIZE];
+        rand::thread_rng().fill_bytes(&mut salt);
+        rand::thread_rng().fill_bytes(&mut nonce);
+
+        let cipher = passphrase_cipher(passphrase, &salt)?;
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&nonce), self.secret.as_bytes().as_slice())
+            .map_err(|e| ada_remote_core::Error::Session(format!("Encryption failed: {}", e)))?;
+
+        let mut file = Vec::with_capacity(FILE_SIZE);
+        file.extend_from_slice(MAGIC);
+        file.extend_from_slice(&salt);
+        file.extend_from_slice(&nonce);
+        file.extend_from_slice(&ciphertext);
+        write_private(path.as_ref(), &file)?;
+        Ok(())
+    }
+
+    /// Read an identity written by [`IdentityKeyPair::save_to`]
+    pub fn load_from(path: impl AsRef<Path>, passphrase: &str) -> Result<Self> {
+        let file = std::fs::read(path)?;
+        if file.len() != FILE_SIZE || !file.starts_with(MAGIC) {
+            return Err(ada_remote_core::Error::Session(
+                "Not an identity key file".to_string(),
+            ));
+        }
+
+        let (salt, rest) = file[MAGIC.len()..].split_at(SALT_SIZE);
+        let (nonce, ciphertext) = rest.split_at(NONCE_SIZE);
+        let secret = passphrase_cipher(passphrase, salt)?
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| {
+                ada_remote_core::Error::Authentication(
+                    "Wrong passphrase or corrupted identity file".to_string(),
+                )
+            })?;
+
+        let secret: [u8; KEY_SIZE] = secret.try_into().map_err(|_| {
+            ada_remote_core::Error::Session("Invalid identity key length".to_string())
+        })?;
+        Ok(Self::from_secret(StaticSecret::from(secret)))
+    }
+}
+
+/// Stretch a passphrase into a file encryption key
+fn passphrase_cipher(passphrase: &str, salt: &[u8]) -> Result<ChaCha20Poly1305> {
+    let policy = PasswordPolicy::default();
+    let params = argon2::Params::new(
+        policy.memory_kib,
+        policy.iterations,
+        policy.parallelism,
+        Some(KEY_SIZE),
+    )
+    .map_err(|e| ada_remote_core::Error::Session(format!("Invalid KDF parameters: {}", e)))?;
+
+    let mut key = [0u8; KEY_SIZE];
+    argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| ada_remote_core::Error::Session(format!("Key derivation failed: {}", e)))?;
+    Ok(ChaCha20Poly1305::new(&key.into()))
+}
+
+/// Write a file readable only by the current user where supported
+fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_and_load_identity() {
+        let path = std::env::temp_dir().join(format!("ada-remote-identity-{}", std::process::id()));
+        let identity = IdentityKeyPair::generate();
+        let peer = PublicKey::from(&StaticSecret::from([7u8; KEY_SIZE]));
+        identity.save_to(&path, "correct horse").unwrap();
+
+        let loaded = IdentityKeyPair::load_from(&path, "correct horse").unwrap();
+        assert_eq!(loaded.public_key(), identity.public_key());
+        assert_eq!(
+            loaded.compute_shared_secret(&peer).as_bytes(),
+            identity.compute_shared_secret(&peer).as_bytes()
+        );
+
+        assert!(IdentityKeyPair::load_from(&path, "wrong passphrase").is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
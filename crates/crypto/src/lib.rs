@@ -5,21 +5,54 @@
 //! - ChaCha20-Poly1305 for authenticated encryption
 //! - Argon2 for password hashing
 
-use ada_remote_core::Result;
+use ada_remote_core::{Result, SessionId};
 use chacha20poly1305::{
     aead::{Aead, KeyInit, Payload},
     ChaCha20Poly1305, Nonce,
 };
 use rand::RngCore;
+use ring::{digest, hkdf};
 use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 use x25519_dalek::{EphemeralSecret, PublicKey, SharedSecret};
 
+mod identity;
+mod pairing;
+mod registration;
+
+pub use identity::IdentityKeyPair;
+pub use pairing::{pairing_nonce, pairing_proof, verify_pairing_proof, PairedDevice, TrustStore};
+pub use registration::{
+    current_registration_token, registration_token, unix_now, verify_registration_token,
+};
+
 /// Size of encryption keys in bytes
 pub const KEY_SIZE: usize = 32;
 
 /// Size of nonce in bytes
 pub const NONCE_SIZE: usize = 12;
 
+/// Random per-context nonce prefix; the remaining 8 bytes are a counter
+const NONCE_PREFIX_SIZE: usize = NONCE_SIZE - 8;
+
+/// How long messages under the previous key still decrypt after a rekey
+pub const REKEY_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// HKDF salt binding derived keys to this protocol
+const KDF_SALT: &[u8] = b"ada-remote/v1/session";
+
+/// HKDF info labels for the two traffic directions
+const CLIENT_TO_HOST_INFO: &[u8] = b"ada-remote/v1/client->host";
+const HOST_TO_CLIENT_INFO: &[u8] = b"ada-remote/v1/host->client";
+
+/// Which side of the session a context encrypts for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Host,
+    Client,
+}
+
 /// Encrypted message with nonce
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EncryptedMessage {
@@ -29,6 +62,64 @@ pub struct EncryptedMessage {
     pub nonce: [u8; NONCE_SIZE],
 }
 
+/// X25519 public key as raw bytes, for carrying in protocol messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SerializablePublicKey([u8; KEY_SIZE]);
+
+impl SerializablePublicKey {
+    /// Raw key bytes
+    pub fn as_bytes(&self) -> &[u8; KEY_SIZE] {
+        &self.0
+    }
+
+    /// Validate and convert back to a key usable for Diffie-Hellman
+    pub fn to_public_key(&self) -> Result<PublicKey> {
+        public_key_from_bytes(&self.0)
+    }
+}
+
+impl From<&PublicKey> for SerializablePublicKey {
+    fn from(key: &PublicKey) -> Self {
+        Self(key.to_bytes())
+    }
+}
+
+impl From<PublicKey> for SerializablePublicKey {
+    fn from(key: PublicKey) -> Self {
+        Self::from(&key)
+    }
+}
+
+impl From<[u8; KEY_SIZE]> for SerializablePublicKey {
+    fn from(bytes: [u8; KEY_SIZE]) -> Self {
+        Self(bytes)
+    }
+}
+
+/// Rebuild a peer's public key from raw bytes received over the wire
+///
+/// Rejects small-order points, which would force the shared secret to all
+/// zeros regardless of our own key.
+pub fn public_key_from_bytes(bytes: &[u8]) -> Result<PublicKey> {
+    let bytes: [u8; KEY_SIZE] = bytes.try_into().map_err(|_| {
+        ada_remote_core::Error::Authentication(format!(
+            "Invalid public key length: {} bytes",
+            bytes.len()
+        ))
+    })?;
+    let key = PublicKey::from(bytes);
+
+    // Clamped scalars are multiples of the cofactor, so any small-order
+    // point yields a non-contributory (all-zero) result
+    let probe = EphemeralSecret::random_from_rng(rand::thread_rng());
+    if !probe.diffie_hellman(&key).was_contributory() {
+        return Err(ada_remote_core::Error::Authentication(
+            "Invalid public key: small-order point".to_string(),
+        ));
+    }
+    Ok(key)
+}
+
 /// Key pair for X25519 key exchange
 pub struct KeyPair {
     secret: EphemeralSecret,
@@ -49,6 +140,11 @@ impl KeyPair {
         &self.public
     }
 
+    /// Get the public key as raw bytes for sending to the peer
+    pub fn public_key_bytes(&self) -> [u8; KEY_SIZE] {
+        self.public.to_bytes()
+    }
+
     /// Compute shared secret with peer's public key
     pub fn compute_shared_secret(self, peer_public: &PublicKey) -> SharedSecret {
         self.secret.diffie_hellman(peer_public)
@@ -56,24 +152,84 @@ impl KeyPair {
 }
 
 /// Encryption context for a session
+///
+/// Each direction has its own key, derived from the X25519 shared secret
+/// with HKDF-SHA256, so the two peers never encrypt under the same key.
+/// Nonces are a random prefix followed by a message counter, so they never
+/// repeat under one key.
 pub struct EncryptionContext {
-    cipher: ChaCha20Poly1305,
+    role: Role,
+    send: ChaCha20Poly1305,
+    receive: ChaCha20Poly1305,
+    /// Receive cipher replaced by the last rekey, and when that happened
+    previous_receive: Option<(ChaCha20Poly1305, Instant)>,
+    key: [u8; KEY_SIZE],
+    nonce_prefix: [u8; NONCE_PREFIX_SIZE],
+    send_counter: AtomicU64,
 }
 
 impl EncryptionContext {
     /// Create a new encryption context from a shared secret
-    pub fn from_shared_secret(shared_secret: &SharedSecret) -> Result<Self> {
-        // Use the shared secret directly as the key
-        let key = chacha20poly1305::Key::from_slice(shared_secret.as_bytes());
-        let cipher = ChaCha20Poly1305::new(key);
-        Ok(Self { cipher })
+    pub fn from_shared_secret(shared_secret: &SharedSecret, role: Role) -> Result<Self> {
+        Self::from_key(shared_secret.as_bytes(), role)
+    }
+
+    /// Restore an encryption context from a key previously returned by
+    /// [`EncryptionContext::export_key`]
+    pub fn from_key(key: &[u8; KEY_SIZE], role: Role) -> Result<Self> {
+        let (send, receive) = derive_ciphers(key, role)?;
+        Ok(Self {
+            role,
+            send,
+            receive,
+            previous_receive: None,
+            key: *key,
+            nonce_prefix: random_nonce_prefix(),
+            send_counter: AtomicU64::new(0),
+        })
+    }
+
+    /// Switch to keys derived from a new shared secret, agreed on by both
+    /// peers. Messages still in flight under the old key decrypt for
+    /// [`REKEY_GRACE_PERIOD`].
+    pub fn rekey(&mut self, new_shared_secret: &SharedSecret) -> Result<()> {
+        let key = new_shared_secret.as_bytes();
+        let (send, receive) = derive_ciphers(key, self.role)?;
+
+        let previous = std::mem::replace(&mut self.receive, receive);
+        self.previous_receive = Some((previous, Instant::now()));
+        self.send = send;
+        self.key = *key;
+        self.nonce_prefix = random_nonce_prefix();
+        self.send_counter = AtomicU64::new(0);
+        Ok(())
+    }
+
+    /// Export the session key so a trusted reconnect can skip the handshake.
+    ///
+    /// **Sensitive:** anyone holding this key can read and forge session
+    /// traffic. Only persist it in secure storage (OS keychain or similar)
+    /// and discard it once the resume window has passed.
+    pub fn export_key(&self) -> [u8; KEY_SIZE] {
+        self.key
     }
 
     /// Encrypt a message with associated data
+    ///
+    /// Fails once the nonce counter is exhausted; the session must rekey
+    /// before sending more.
     pub fn encrypt(&self, plaintext: &[u8], associated_data: &[u8]) -> Result<EncryptedMessage> {
-        // Generate random nonce
+        let counter = self
+            .send_counter
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_add(1))
+            .map_err(|_| {
+                ada_remote_core::Error::Session(
+                    "Nonce counter exhausted, session must be rekeyed".to_string(),
+                )
+            })?;
         let mut nonce_bytes = [0u8; NONCE_SIZE];
-        rand::thread_rng().fill_bytes(&mut nonce_bytes);
+        nonce_bytes[..NONCE_PREFIX_SIZE].copy_from_slice(&self.nonce_prefix);
+        nonce_bytes[NONCE_PREFIX_SIZE..].copy_from_slice(&counter.to_be_bytes());
         let nonce = Nonce::from_slice(&nonce_bytes);
 
         // Encrypt with associated data
@@ -83,7 +239,7 @@ impl EncryptionContext {
         };
 
         let ciphertext = self
-            .cipher
+            .send
             .encrypt(nonce, payload)
             .map_err(|e| ada_remote_core::Error::Session(format!("Encryption failed: {}", e)))?;
 
@@ -94,40 +250,140 @@ impl EncryptionContext {
     }
 
     /// Decrypt a message with associated data
-    pub fn decrypt(
-        &self,
-        encrypted: &EncryptedMessage,
-        associated_data: &[u8],
-    ) -> Result<Vec<u8>> {
+    pub fn decrypt(&self, encrypted: &EncryptedMessage, associated_data: &[u8]) -> Result<Vec<u8>> {
         let nonce = Nonce::from_slice(&encrypted.nonce);
 
-        let payload = Payload {
+        let payload = || Payload {
             msg: &encrypted.ciphertext,
             aad: associated_data,
         };
 
-        let plaintext = self
-            .cipher
-            .decrypt(nonce, payload)
+        let mut result = self.receive.decrypt(nonce, payload());
+        if let (Err(_), Some((previous, rekeyed))) = (&result, &self.previous_receive) {
+            // Sent by the peer before it switched keys
+            if rekeyed.elapsed() < REKEY_GRACE_PERIOD {
+                result = previous.decrypt(nonce, payload());
+            }
+        }
+        let plaintext = result
             .map_err(|e| ada_remote_core::Error::Session(format!("Decryption failed: {}", e)))?;
 
         Ok(plaintext)
     }
 }
 
-/// Hash a password using Argon2
+/// Derive the (send, receive) ciphers for `role` from a shared secret
+fn derive_ciphers(
+    key: &[u8; KEY_SIZE],
+    role: Role,
+) -> Result<(ChaCha20Poly1305, ChaCha20Poly1305)> {
+    let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, KDF_SALT).extract(key);
+    let client_to_host = derive_cipher(&prk, CLIENT_TO_HOST_INFO)?;
+    let host_to_client = derive_cipher(&prk, HOST_TO_CLIENT_INFO)?;
+
+    Ok(match role {
+        Role::Client => (client_to_host, host_to_client),
+        Role::Host => (host_to_client, client_to_host),
+    })
+}
+
+fn random_nonce_prefix() -> [u8; NONCE_PREFIX_SIZE] {
+    let mut prefix = [0u8; NONCE_PREFIX_SIZE];
+    rand::thread_rng().fill_bytes(&mut prefix);
+    prefix
+}
+
+/// Expand one direction's traffic key from the HKDF pseudorandom key
+fn derive_cipher(prk: &hkdf::Prk, info: &[u8]) -> Result<ChaCha20Poly1305> {
+    let mut key = [0u8; KEY_SIZE];
+    prk.expand(&[info], hkdf::HKDF_SHA256)
+        .and_then(|okm| okm.fill(&mut key))
+        .map_err(|_| ada_remote_core::Error::Session("Key derivation failed".to_string()))?;
+    Ok(ChaCha20Poly1305::new(&key.into()))
+}
+
+/// Short authentication string for users to compare out of band
+///
+/// Six decimal digits derived from both public keys and the session, so a
+/// relay that substituted its own keys would show each side a different
+/// code. Symmetric: both peers get the same string whichever key is local.
+pub fn compute_sas(
+    local_public: &PublicKey,
+    remote_public: &PublicKey,
+    session_id: &SessionId,
+) -> String {
+    let (first, second) = if local_public.as_bytes() <= remote_public.as_bytes() {
+        (local_public, remote_public)
+    } else {
+        (remote_public, local_public)
+    };
+
+    let mut context = digest::Context::new(&digest::SHA256);
+    context.update(b"ada-remote/v1/sas");
+    context.update(first.as_bytes());
+    context.update(second.as_bytes());
+    context.update(session_id.to_string().as_bytes());
+    let hash = context.finish();
+
+    let mut prefix = [0u8; 8];
+    prefix.copy_from_slice(&hash.as_ref()[..8]);
+    format!("{:06}", u64::from_be_bytes(prefix) % 1_000_000)
+}
+
+/// Argon2id cost parameters for password hashing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PasswordPolicy {
+    /// Memory cost in KiB
+    pub memory_kib: u32,
+    /// Number of passes over memory
+    pub iterations: u32,
+    /// Degree of parallelism (lanes)
+    pub parallelism: u32,
+}
+
+impl Default for PasswordPolicy {
+    /// OWASP's minimum Argon2id recommendation: 19 MiB, 2 passes, 1 lane,
+    /// which hashes in tens of milliseconds on desktop hardware
+    fn default() -> Self {
+        Self {
+            memory_kib: argon2::Params::DEFAULT_M_COST,
+            iterations: argon2::Params::DEFAULT_T_COST,
+            parallelism: argon2::Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+/// Hash a password using Argon2 with the default [`PasswordPolicy`]
 pub fn hash_password(password: &str) -> Result<String> {
+    hash_password_with(password, &PasswordPolicy::default())
+}
+
+/// Hash a password using Argon2id with the given cost parameters. The
+/// result is a PHC string carrying the parameters, so
+/// [`verify_password`] works across policy changes.
+pub fn hash_password_with(password: &str, policy: &PasswordPolicy) -> Result<String> {
     use argon2::{
         password_hash::{rand_core::OsRng, PasswordHasher, SaltString},
-        Argon2,
+        Algorithm, Argon2, Params, Version,
     };
 
+    let params = Params::new(
+        policy.memory_kib,
+        policy.iterations,
+        policy.parallelism,
+        None,
+    )
+    .map_err(|e| {
+        ada_remote_core::Error::Authentication(format!("Invalid password policy: {}", e))
+    })?;
     let salt = SaltString::generate(&mut OsRng);
-    let argon2 = Argon2::default();
+    let argon2 = Argon2::new(Algorithm::Argon2id, Version::V0x13, params);
 
     let hash = argon2
         .hash_password(password.as_bytes(), &salt)
-        .map_err(|e| ada_remote_core::Error::Authentication(format!("Password hashing failed: {}", e)))?;
+        .map_err(|e| {
+            ada_remote_core::Error::Authentication(format!("Password hashing failed: {}", e))
+        })?;
 
     Ok(hash.to_string())
 }
@@ -166,8 +422,8 @@ mod tests {
         let alice = KeyPair::generate();
         let bob = KeyPair::generate();
 
-        let alice_public = alice.public_key().clone();
-        let bob_public = bob.public_key().clone();
+        let alice_public = *alice.public_key();
+        let bob_public = *bob.public_key();
 
         let alice_shared = alice.compute_shared_secret(&bob_public);
         let bob_shared = bob.compute_shared_secret(&alice_public);
@@ -175,23 +431,133 @@ mod tests {
         assert_eq!(alice_shared.as_bytes(), bob_shared.as_bytes());
     }
 
+    #[test]
+    fn test_public_key_serialization() {
+        let pair = KeyPair::generate();
+        let serializable = SerializablePublicKey::from(pair.public_key());
+
+        let json = serde_json::to_string(&serializable).unwrap();
+        let decoded: SerializablePublicKey = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, serializable);
+        assert_eq!(decoded.to_public_key().unwrap(), *pair.public_key());
+        assert_eq!(decoded.as_bytes(), &pair.public_key_bytes());
+
+        assert!(public_key_from_bytes(&[0u8; KEY_SIZE]).is_err());
+        assert!(public_key_from_bytes(&[1u8; 31]).is_err());
+    }
+
+    #[test]
+    fn test_sas_is_symmetric() {
+        let session_id = SessionId::from_seed(7);
+        let alice = KeyPair::generate();
+        let bob = KeyPair::generate();
+
+        let alice_sas = compute_sas(alice.public_key(), bob.public_key(), &session_id);
+        let bob_sas = compute_sas(bob.public_key(), alice.public_key(), &session_id);
+        assert_eq!(alice_sas, bob_sas);
+        assert_eq!(alice_sas.len(), 6);
+        assert!(alice_sas.chars().all(|c| c.is_ascii_digit()));
+
+        // A relay substituting its own key shows a different code
+        let mallory = KeyPair::generate();
+        let tampered = compute_sas(alice.public_key(), mallory.public_key(), &session_id);
+        assert_ne!(alice_sas, tampered);
+    }
+
     #[test]
     fn test_encryption_decryption() {
         let mut rng = rand::thread_rng();
         let secret = EphemeralSecret::random_from_rng(&mut rng);
-        let shared_secret = secret.diffie_hellman(&PublicKey::from(&secret));
+        let public = PublicKey::from(&secret);
+        let shared_secret = secret.diffie_hellman(&public);
 
-        let ctx = EncryptionContext::from_shared_secret(&shared_secret).unwrap();
+        let host = EncryptionContext::from_shared_secret(&shared_secret, Role::Host).unwrap();
+        let client = EncryptionContext::from_shared_secret(&shared_secret, Role::Client).unwrap();
 
         let plaintext = b"Hello, Ada Remote!";
         let aad = b"session-123";
 
-        let encrypted = ctx.encrypt(plaintext, aad).unwrap();
-        let decrypted = ctx.decrypt(&encrypted, aad).unwrap();
+        let encrypted = client.encrypt(plaintext, aad).unwrap();
+        let decrypted = host.decrypt(&encrypted, aad).unwrap();
+        assert_eq!(plaintext.as_slice(), decrypted.as_slice());
 
+        let encrypted = host.encrypt(plaintext, aad).unwrap();
+        let decrypted = client.decrypt(&encrypted, aad).unwrap();
         assert_eq!(plaintext.as_slice(), decrypted.as_slice());
     }
 
+    #[test]
+    fn test_directions_use_separate_keys() {
+        let alice = KeyPair::generate();
+        let bob = KeyPair::generate();
+        let shared_secret = alice.compute_shared_secret(bob.public_key());
+
+        let client = EncryptionContext::from_shared_secret(&shared_secret, Role::Client).unwrap();
+        let encrypted = client.encrypt(b"to host", b"").unwrap();
+
+        // A reflected message must not decrypt on the side that sent it
+        assert!(client.decrypt(&encrypted, b"").is_err());
+    }
+
+    #[test]
+    fn test_counter_nonces() {
+        let alice = KeyPair::generate();
+        let bob = KeyPair::generate();
+        let shared_secret = alice.compute_shared_secret(bob.public_key());
+        let ctx = EncryptionContext::from_shared_secret(&shared_secret, Role::Host).unwrap();
+
+        let first = ctx.encrypt(b"frame", b"").unwrap();
+        let second = ctx.encrypt(b"frame", b"").unwrap();
+        assert_ne!(first.nonce, second.nonce);
+        assert_eq!(
+            first.nonce[..NONCE_PREFIX_SIZE],
+            second.nonce[..NONCE_PREFIX_SIZE]
+        );
+
+        ctx.send_counter.store(u64::MAX, Ordering::Relaxed);
+        assert!(ctx.encrypt(b"frame", b"").is_err());
+    }
+
+    #[test]
+    fn test_rekey_keeps_old_key_during_grace_period() {
+        let shared = |a: KeyPair, b: &KeyPair| a.compute_shared_secret(b.public_key());
+        let bob = KeyPair::generate();
+        let secret_a = shared(KeyPair::generate(), &bob);
+        let bob = KeyPair::generate();
+        let secret_b = shared(KeyPair::generate(), &bob);
+
+        let mut host = EncryptionContext::from_shared_secret(&secret_a, Role::Host).unwrap();
+        let mut client = EncryptionContext::from_shared_secret(&secret_a, Role::Client).unwrap();
+
+        let under_a = host.encrypt(b"before", b"").unwrap();
+        host.rekey(&secret_b).unwrap();
+        client.rekey(&secret_b).unwrap();
+        let under_b = host.encrypt(b"after", b"").unwrap();
+
+        assert_eq!(client.decrypt(&under_b, b"").unwrap(), b"after");
+        assert_eq!(client.decrypt(&under_a, b"").unwrap(), b"before");
+
+        // Past the grace window the old key is gone
+        let (_, rekeyed) = client.previous_receive.as_mut().unwrap();
+        *rekeyed -= REKEY_GRACE_PERIOD;
+        assert!(client.decrypt(&under_a, b"").is_err());
+    }
+
+    #[test]
+    fn test_export_import_key() {
+        let alice = KeyPair::generate();
+        let bob = KeyPair::generate();
+        let shared_secret = alice.compute_shared_secret(bob.public_key());
+
+        let original = EncryptionContext::from_shared_secret(&shared_secret, Role::Client).unwrap();
+        let encrypted = original.encrypt(b"resume me", b"session-123").unwrap();
+
+        let restored = EncryptionContext::from_key(&original.export_key(), Role::Host).unwrap();
+        let decrypted = restored.decrypt(&encrypted, b"session-123").unwrap();
+
+        assert_eq!(decrypted, b"resume me");
+    }
+
     #[test]
     fn test_password_hashing() {
         let password = "secure-password-123";
@@ -201,6 +567,30 @@ mod tests {
         assert!(!verify_password("wrong-password", &hash).unwrap());
     }
 
+    #[test]
+    fn test_password_policy() {
+        let policy = PasswordPolicy {
+            memory_kib: 8 * 1024,
+            iterations: 3,
+            parallelism: 2,
+        };
+        let hash = hash_password_with("secure-password-123", &policy).unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(verify_password("secure-password-123", &hash).unwrap());
+
+        let parsed = argon2::PasswordHash::new(&hash).unwrap();
+        let params = argon2::Params::try_from(&parsed).unwrap();
+        assert_eq!(params.m_cost(), policy.memory_kib);
+        assert_eq!(params.t_cost(), policy.iterations);
+        assert_eq!(params.p_cost(), policy.parallelism);
+
+        let too_small = PasswordPolicy {
+            memory_kib: 1,
+            ..policy
+        };
+        assert!(hash_password_with("secure-password-123", &too_small).is_err());
+    }
+
     #[test]
     fn test_session_password_generation() {
         let password = generate_session_password();
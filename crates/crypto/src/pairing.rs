@@ -0,0 +1,219 @@
+//! Device pairing
+//!
+//! Paired devices authenticate with their long-term identities instead of a
+//! password. The host sends a random nonce, and the client answers with the
+//! HMAC-SHA256 of the session and nonce keyed by the X25519 secret of the
+//! two identities, which only the holders of the paired keys can compute.
+//!
+//! Trust store file: one device per line, `hex key<TAB>paired at<TAB>name`
+//! with the pairing time in Unix seconds
+
+use crate::registration::decode_hex;
+use crate::{IdentityKeyPair, KEY_SIZE};
+use ada_remote_core::{Error, Result, SessionId};
+use rand::RngCore;
+use ring::hmac;
+use std::path::Path;
+use x25519_dalek::PublicKey;
+
+const PROOF_LABEL: &[u8] = b"ada-remote/v1/pairing";
+
+/// Fresh nonce for a pairing challenge
+pub fn pairing_nonce() -> [u8; 32] {
+    let mut nonce = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    nonce
+}
+
+/// Answer to the challenge `nonce` in `session_id`, proving `identity` to
+/// the holder of `peer`. Both sides compute the same value.
+pub fn pairing_proof(
+    identity: &IdentityKeyPair,
+    peer: &PublicKey,
+    session_id: &SessionId,
+    nonce: &[u8; 32],
+) -> [u8; 32] {
+    let tag = hmac::sign(&proof_key(identity, peer), &message(session_id, nonce));
+    let mut proof = [0u8; 32];
+    proof.copy_from_slice(tag.as_ref());
+    proof
+}
+
+/// Check a client's answer to the challenge `nonce` in constant time
+pub fn verify_pairing_proof(
+    identity: &IdentityKeyPair,
+    peer: &PublicKey,
+    session_id: &SessionId,
+    nonce: &[u8; 32],
+    proof: &[u8],
+) -> Result<()> {
+    hmac::verify(
+        &proof_key(identity, peer),
+        &message(session_id, nonce),
+        proof,
+    )
+    .map_err(|_| Error::Authentication("Invalid pairing proof".to_string()))
+}
+
+fn proof_key(identity: &IdentityKeyPair, peer: &PublicKey) -> hmac::Key {
+    let shared = identity.compute_shared_secret(peer);
+    hmac::Key::new(hmac::HMAC_SHA256, shared.as_bytes())
+}
+
+fn message(session_id: &SessionId, nonce: &[u8; 32]) -> Vec<u8> {
+    [PROOF_LABEL, session_id.to_string().as_bytes(), nonce].concat()
+}
+
+/// A device whose identity is trusted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PairedDevice {
+    pub public_key: [u8; KEY_SIZE],
+    pub name: String,
+    /// Unix seconds
+    pub paired_at: u64,
+}
+
+/// Identities of paired devices
+#[derive(Debug, Clone, Default)]
+pub struct TrustStore {
+    devices: Vec<PairedDevice>,
+}
+
+impl TrustStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trust `public_key`, replacing an earlier pairing of the same key
+    pub fn pair(&mut self, public_key: [u8; KEY_SIZE], name: &str, paired_at: u64) {
+        self.revoke(&public_key);
+        // Names are stored on one line
+        let name = name.replace(char::is_control, " ");
+        self.devices.push(PairedDevice {
+            public_key,
+            name,
+            paired_at,
+        });
+    }
+
+    pub fn get(&self, public_key: &[u8; KEY_SIZE]) -> Option<&PairedDevice> {
+        self.devices
+            .iter()
+            .find(|device| device.public_key == *public_key)
+    }
+
+    pub fn is_trusted(&self, public_key: &[u8; KEY_SIZE]) -> bool {
+        self.get(public_key).is_some()
+    }
+
+    /// Forget a paired device, returning whether it was paired
+    pub fn revoke(&mut self, public_key: &[u8; KEY_SIZE]) -> bool {
+        let before = self.devices.len();
+        self.devices
+            .retain(|device| device.public_key != *public_key);
+        self.devices.len() != before
+    }
+
+    /// Paired devices, oldest pairing first
+    pub fn devices(&self) -> &[PairedDevice] {
+        &self.devices
+    }
+
+    /// Write the store to `path`
+    pub fn save_to(&self, path: impl AsRef<Path>) -> Result<()> {
+        let contents: String = self
+            .devices
+            .iter()
+            .map(|device| {
+                let key: String = device
+                    .public_key
+                    .iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect();
+                format!("{}\t{}\t{}\n", key, device.paired_at, device.name)
+            })
+            .collect();
+        std::fs::write(path, contents)?;
+        Ok(())
+    }
+
+    /// Read a store written by [`TrustStore::save_to`]; a missing file is an
+    /// empty store
+    pub fn load_from(path: impl AsRef<Path>) -> Result<Self> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut store = Self::new();
+        for (number, line) in contents.lines().enumerate() {
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = || Error::Session(format!("Invalid trust store line {}", number + 1));
+            let mut fields = line.splitn(3, '\t');
+            let public_key = fields
+                .next()
+                .and_then(decode_hex)
+                .and_then(|key| <[u8; KEY_SIZE]>::try_from(key).ok())
+                .ok_or_else(invalid)?;
+            let paired_at = fields
+                .next()
+                .and_then(|at| at.parse().ok())
+                .ok_or_else(invalid)?;
+            let name = fields.next().ok_or_else(invalid)?;
+            store.pair(public_key, name, paired_at);
+        }
+        Ok(store)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_proof_binds_both_identities() {
+        let host = IdentityKeyPair::generate();
+        let client = IdentityKeyPair::generate();
+        let session_id = SessionId::from_seed(11);
+        let nonce = pairing_nonce();
+
+        let proof = pairing_proof(&client, host.public_key(), &session_id, &nonce);
+        assert!(
+            verify_pairing_proof(&host, client.public_key(), &session_id, &nonce, &proof).is_ok()
+        );
+
+        // Another device claiming the client's key cannot answer for it
+        let forger = IdentityKeyPair::generate();
+        let forged = pairing_proof(&forger, host.public_key(), &session_id, &nonce);
+        assert!(
+            verify_pairing_proof(&host, client.public_key(), &session_id, &nonce, &forged).is_err()
+        );
+        // Nor can a proof be replayed for another challenge
+        let other = pairing_nonce();
+        assert!(
+            verify_pairing_proof(&host, client.public_key(), &session_id, &other, &proof).is_err()
+        );
+    }
+
+    #[test]
+    fn test_trust_store_round_trip() {
+        let path = std::env::temp_dir().join(format!("ada-remote-trust-{}", std::process::id()));
+        let mut store = TrustStore::new();
+        store.pair([1; KEY_SIZE], "Work\tlaptop", 1_700_000_000);
+        store.pair([2; KEY_SIZE], "Phone", 1_700_000_100);
+        store.save_to(&path).unwrap();
+
+        let loaded = TrustStore::load_from(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.devices(), store.devices());
+        assert_eq!(loaded.get(&[1; KEY_SIZE]).unwrap().name, "Work laptop");
+
+        store.revoke(&[1; KEY_SIZE]);
+        assert!(!store.is_trusted(&[1; KEY_SIZE]));
+        assert!(!store.revoke(&[1; KEY_SIZE]));
+        assert!(TrustStore::load_from(&path).unwrap().devices().is_empty());
+    }
+}
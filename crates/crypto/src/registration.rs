@@ -0,0 +1,102 @@
+//! Registration tokens
+//!
+//! A managed relay only lets hosts register when they prove they know a
+//! shared secret. The token is `timestamp.mac`, where `mac` is the hex
+//! HMAC-SHA256 of `session_id:timestamp` under the secret and `timestamp` is
+//! in Unix seconds, so a captured token only works for its session and only
+//! until it goes stale.
+
+use ada_remote_core::{Error, Result, SessionId};
+use ring::hmac;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Token allowing the holder of `secret` to register `session_id`, issued at
+/// `timestamp`
+pub fn registration_token(secret: &[u8], session_id: &SessionId, timestamp: u64) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret);
+    let tag = hmac::sign(&key, message(session_id, timestamp).as_bytes());
+    let mac: String = tag.as_ref().iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}.{}", timestamp, mac)
+}
+
+/// Token for `session_id` issued now
+pub fn current_registration_token(secret: &[u8], session_id: &SessionId) -> String {
+    registration_token(secret, session_id, unix_now())
+}
+
+/// Check that `token` was issued for `session_id` under `secret` no more than
+/// `max_age` from `now` (Unix seconds), in either direction to allow for
+/// clock skew
+pub fn verify_registration_token(
+    secret: &[u8],
+    session_id: &SessionId,
+    token: &str,
+    now: u64,
+    max_age: Duration,
+) -> Result<()> {
+    let invalid = || Error::Authentication("Invalid registration token".to_string());
+    let (timestamp, mac) = token.split_once('.').ok_or_else(invalid)?;
+    let timestamp: u64 = timestamp.parse().map_err(|_| invalid())?;
+    let mac = decode_hex(mac).ok_or_else(invalid)?;
+
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret);
+    hmac::verify(&key, message(session_id, timestamp).as_bytes(), &mac).map_err(|_| invalid())?;
+
+    if now.abs_diff(timestamp) > max_age.as_secs() {
+        return Err(Error::Authentication(
+            "Registration token expired".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Current time in Unix seconds
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn message(session_id: &SessionId, timestamp: u64) -> String {
+    format!("{}:{}", session_id, timestamp)
+}
+
+pub(crate) fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &[u8] = b"relay secret";
+    const MAX_AGE: Duration = Duration::from_secs(300);
+
+    #[test]
+    fn test_token_bound_to_session_and_secret() {
+        let session_id = SessionId::new();
+        let token = registration_token(SECRET, &session_id, 1_000);
+        assert!(verify_registration_token(SECRET, &session_id, &token, 1_000, MAX_AGE).is_ok());
+
+        let other = SessionId::new();
+        assert!(verify_registration_token(SECRET, &other, &token, 1_000, MAX_AGE).is_err());
+        assert!(verify_registration_token(b"wrong", &session_id, &token, 1_000, MAX_AGE).is_err());
+        assert!(verify_registration_token(SECRET, &session_id, "1000.zz", 1_000, MAX_AGE).is_err());
+    }
+
+    #[test]
+    fn test_stale_token_rejected() {
+        let session_id = SessionId::new();
+        let token = registration_token(SECRET, &session_id, 1_000);
+        assert!(verify_registration_token(SECRET, &session_id, &token, 1_300, MAX_AGE).is_ok());
+        assert!(verify_registration_token(SECRET, &session_id, &token, 1_301, MAX_AGE).is_err());
+        assert!(verify_registration_token(SECRET, &session_id, &token, 699, MAX_AGE).is_err());
+    }
+}
@@ -0,0 +1,52 @@
+//! Ada Remote Files
+//!
+//! File transfer over the `FileTransfer*` protocol messages: a sender that
+//! streams a file as chunks and a receiver that reassembles them on disk.
+
+use ada_remote_core::{Error, Result};
+
+mod receiver;
+mod sender;
+
+pub use receiver::{FileReceiver, ReceivedFile};
+pub use sender::{CancelHandle, FileSender};
+
+/// Default chunk size
+pub const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Check that a received file name is a bare name that cannot escape the
+/// destination directory
+pub fn sanitize_file_name(name: &str) -> Result<&str> {
+    let unsafe_name =
+        name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\', ':', '\0']);
+    if unsafe_name {
+        return Err(Error::Session(format!(
+            "Refusing unsafe file name {:?}",
+            name
+        )));
+    }
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_file_name() {
+        assert_eq!(sanitize_file_name("report.pdf").unwrap(), "report.pdf");
+        assert_eq!(sanitize_file_name("..hidden").unwrap(), "..hidden");
+        for name in [
+            "",
+            ".",
+            "..",
+            "../etc/passwd",
+            "dir/file",
+            "..\\windows\\system.ini",
+            "C:evil.exe",
+            "file.txt:stream",
+        ] {
+            assert!(sanitize_file_name(name).is_err(), "{:?}", name);
+        }
+    }
+}
@@ -0,0 +1,509 @@
+//! Receiving side of transfers
+//!
+//! A transfer in progress is written to `.<id>.part` in the destination
+//! directory, with its progress in `.<id>.state` beside it so that it can be
+//! resumed after a reconnect or a restart.
+
+use crate::sanitize_file_name;
+use ada_remote_core::{Error, ProtocolMessage, Result};
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use uuid::Uuid;
+
+/// A file written and verified by the receiver
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReceivedFile {
+    pub transfer_id: Uuid,
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+/// Persisted progress of a transfer
+#[derive(Debug, Serialize, Deserialize)]
+struct TransferState {
+    file_name: String,
+    size: u64,
+    received: u64,
+    next_index: u64,
+}
+
+/// Transfer in progress, written to a temporary file until verified
+struct Incoming {
+    path: PathBuf,
+    part_path: PathBuf,
+    state_path: PathBuf,
+    file: tokio::fs::File,
+    state: TransferState,
+    hasher: digest::Context,
+}
+
+impl Incoming {
+    /// Record progress; the part file is flushed first so the state never
+    /// claims more than was written
+    async fn save(&mut self) -> Result<()> {
+        self.file.flush().await?;
+        let tmp_path = self.state_path.with_extension("tmp");
+        tokio::fs::write(&tmp_path, serde_json::to_vec(&self.state)?).await?;
+        tokio::fs::rename(&tmp_path, &self.state_path).await?;
+        Ok(())
+    }
+}
+
+/// Reassembles incoming transfers into a directory. Any number of transfers
+/// may be in progress at once, keyed by transfer ID.
+///
+/// Chunks are written before [`FileReceiver::handle`] returns, so a caller
+/// that awaits it before reading the next message never buffers more than
+/// one chunk, however slow the disk.
+pub struct FileReceiver {
+    dir: PathBuf,
+    transfers: HashMap<Uuid, Incoming>,
+}
+
+impl FileReceiver {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            transfers: HashMap::new(),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Number of transfers in progress
+    pub fn active(&self) -> usize {
+        self.transfers.len()
+    }
+
+    /// Feed a received message, returning the file once it is complete and
+    /// verified. Other messages are ignored. A failed transfer is discarded.
+    pub async fn handle(&mut self, message: &ProtocolMessage) -> Result<Option<ReceivedFile>> {
+        match message {
+            ProtocolMessage::FileTransferStart {
+                file_name,
+                file_size,
+                transfer_id,
+            } => {
+                self.start(*transfer_id, file_name, *file_size).await?;
+                Ok(None)
+            }
+            ProtocolMessage::FileTransferChunk {
+                transfer_id,
+                chunk_index,
+                data,
+            } => {
+                let result = self.write_chunk(*transfer_id, *chunk_index, data).await;
+                self.abort_on_error(*transfer_id, result).await?;
+                Ok(None)
+            }
+            ProtocolMessage::FileTransferComplete {
+                transfer_id,
+                sha256,
+            } => {
+                let result = self.complete(*transfer_id, sha256).await;
+                self.abort_on_error(*transfer_id, result).await.map(Some)
+            }
+            ProtocolMessage::FileTransferCancel {
+                transfer_id,
+                reason,
+            } => {
+                if self.abort(*transfer_id).await {
+                    tracing::info!("File transfer {} cancelled: {}", transfer_id, reason);
+                }
+                Ok(None)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Messages asking the sender to continue each unfinished transfer,
+    /// including ones left on disk by an earlier run
+    pub async fn resume(&mut self) -> Result<Vec<ProtocolMessage>> {
+        let mut entries = tokio::fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name();
+            let Some(transfer_id) = name
+                .to_str()
+                .and_then(|name| name.strip_prefix('.')?.strip_suffix(".state"))
+                .and_then(|id| Uuid::parse_str(id).ok())
+            else {
+                continue;
+            };
+            if self.transfers.contains_key(&transfer_id) {
+                continue;
+            }
+            if let Err(e) = self.load(transfer_id).await {
+                tracing::warn!("Discarding file transfer {}: {}", transfer_id, e);
+                self.remove_files(transfer_id).await;
+            }
+        }
+
+        Ok(self
+            .transfers
+            .iter()
+            .map(
+                |(transfer_id, incoming)| ProtocolMessage::FileTransferResume {
+                    transfer_id: *transfer_id,
+                    next_chunk: incoming.state.next_index,
+                    offset: incoming.state.received,
+                },
+            )
+            .collect())
+    }
+
+    /// Reopen a transfer from its state file, rehashing what was received
+    async fn load(&mut self, transfer_id: Uuid) -> Result<()> {
+        let (part_path, state_path) = self.temp_paths(transfer_id);
+        let state: TransferState = serde_json::from_slice(&tokio::fs::read(&state_path).await?)?;
+        let path = self.dir.join(sanitize_file_name(&state.file_name)?);
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&part_path)
+            .await?;
+        // Drop anything written after the state was last saved
+        file.set_len(state.received).await?;
+
+        let mut hasher = digest::Context::new(&digest::SHA256);
+        let mut buf = vec![0u8; crate::DEFAULT_CHUNK_SIZE];
+        loop {
+            let n = file.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+        }
+
+        self.transfers.insert(
+            transfer_id,
+            Incoming {
+                path,
+                part_path,
+                state_path,
+                file,
+                state,
+                hasher,
+            },
+        );
+        Ok(())
+    }
+
+    /// Abandon a transfer locally, returning the message that tells the
+    /// sender, or `None` if the transfer is unknown
+    pub async fn cancel(&mut self, transfer_id: Uuid) -> Option<ProtocolMessage> {
+        self.abort(transfer_id)
+            .await
+            .then(|| ProtocolMessage::FileTransferCancel {
+                transfer_id,
+                reason: "Cancelled by receiver".to_string(),
+            })
+    }
+
+    async fn start(&mut self, transfer_id: Uuid, file_name: &str, size: u64) -> Result<()> {
+        let file_name = sanitize_file_name(file_name)?;
+        if self.transfers.contains_key(&transfer_id) {
+            return Err(Error::Session(format!(
+                "File transfer {} already started",
+                transfer_id
+            )));
+        }
+
+        let path = self.dir.join(file_name);
+        if tokio::fs::try_exists(&path).await? {
+            return Err(Error::Session(format!(
+                "Refusing to overwrite {}",
+                path.display()
+            )));
+        }
+        let (part_path, state_path) = self.temp_paths(transfer_id);
+        let file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&part_path)
+            .await?;
+
+        let mut incoming = Incoming {
+            path,
+            part_path,
+            state_path,
+            file,
+            state: TransferState {
+                file_name: file_name.to_string(),
+                size,
+                received: 0,
+                next_index: 0,
+            },
+            hasher: digest::Context::new(&digest::SHA256),
+        };
+        let saved = incoming.save().await;
+        self.transfers.insert(transfer_id, incoming);
+        self.abort_on_error(transfer_id, saved).await
+    }
+
+    fn temp_paths(&self, transfer_id: Uuid) -> (PathBuf, PathBuf) {
+        (
+            self.dir.join(format!(".{}.part", transfer_id)),
+            self.dir.join(format!(".{}.state", transfer_id)),
+        )
+    }
+
+    fn transfer(&mut self, transfer_id: Uuid) -> Result<&mut Incoming> {
+        self.transfers
+            .get_mut(&transfer_id)
+            .ok_or_else(|| Error::Session(format!("Unknown file transfer {}", transfer_id)))
+    }
+
+    async fn write_chunk(&mut self, transfer_id: Uuid, index: u64, data: &[u8]) -> Result<()> {
+        let incoming = self.transfer(transfer_id)?;
+        let state = &mut incoming.state;
+        if index != state.next_index {
+            return Err(Error::Session(format!(
+                "Expected chunk {} of transfer {}, got {}",
+                state.next_index, transfer_id, index
+            )));
+        }
+        if state.received + data.len() as u64 > state.size {
+            return Err(Error::Session(format!(
+                "File transfer {} exceeds its announced {} bytes",
+                transfer_id, state.size
+            )));
+        }
+
+        incoming.file.write_all(data).await?;
+        incoming.hasher.update(data);
+        state.received += data.len() as u64;
+        state.next_index += 1;
+        incoming.save().await
+    }
+
+    async fn complete(&mut self, transfer_id: Uuid, sha256: &[u8]) -> Result<ReceivedFile> {
+        let incoming = self.transfer(transfer_id)?;
+        let state = &incoming.state;
+        if state.received != state.size {
+            return Err(Error::Session(format!(
+                "File transfer {} ended after {} of {} bytes",
+                transfer_id, state.received, state.size
+            )));
+        }
+        incoming.file.flush().await?;
+        incoming.file.sync_all().await?;
+        if incoming.hasher.clone().finish().as_ref() != sha256 {
+            return Err(Error::Session(format!(
+                "File transfer {} failed its hash check",
+                transfer_id
+            )));
+        }
+
+        let incoming = self.transfers.remove(&transfer_id).expect("checked above");
+        drop(incoming.file);
+        tokio::fs::rename(&incoming.part_path, &incoming.path).await?;
+        let _ = tokio::fs::remove_file(&incoming.state_path).await;
+        tracing::info!(
+            "Received {} ({} bytes)",
+            incoming.path.display(),
+            incoming.state.size
+        );
+        Ok(ReceivedFile {
+            transfer_id,
+            path: incoming.path,
+            size: incoming.state.size,
+        })
+    }
+
+    async fn abort_on_error<T>(&mut self, transfer_id: Uuid, result: Result<T>) -> Result<T> {
+        if result.is_err() {
+            self.abort(transfer_id).await;
+        }
+        result
+    }
+
+    /// Drop a transfer and its partial file, returning whether it existed
+    async fn abort(&mut self, transfer_id: Uuid) -> bool {
+        let Some(incoming) = self.transfers.remove(&transfer_id) else {
+            return false;
+        };
+        drop(incoming);
+        self.remove_files(transfer_id).await;
+        true
+    }
+
+    async fn remove_files(&self, transfer_id: Uuid) {
+        let (part_path, state_path) = self.temp_paths(transfer_id);
+        for path in [part_path, state_path] {
+            match tokio::fs::remove_file(&path).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => tracing::warn!("Failed to remove {}: {}", path.display(), e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FileSender;
+    use tokio::sync::mpsc;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("ada-remote-files-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("in")).unwrap();
+        std::fs::create_dir_all(dir.join("out")).unwrap();
+        dir
+    }
+
+    fn contents() -> Vec<u8> {
+        (0..4500u32).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    /// Messages `sender` produces, chunked at 1000 bytes
+    async fn sent_messages(sender: FileSender) -> Vec<ProtocolMessage> {
+        let (tx, mut rx) = mpsc::channel(2);
+        let sender = sender.with_chunk_size(1000);
+        let task = tokio::spawn(async move { sender.send(&tx).await });
+
+        let mut messages = Vec::new();
+        while let Some(message) = rx.recv().await {
+            messages.push(message);
+        }
+        task.await.unwrap().unwrap();
+        messages
+    }
+
+    #[tokio::test]
+    async fn test_multi_chunk_round_trip() {
+        let dir = temp_dir("round-trip");
+        let source = dir.join("in").join("data.bin");
+        std::fs::write(&source, contents()).unwrap();
+
+        let messages = sent_messages(FileSender::new(&source)).await;
+        // Start, five chunks, complete
+        assert_eq!(messages.len(), 7);
+
+        let mut receiver = FileReceiver::new(dir.join("out"));
+        let mut received = None;
+        for message in &messages {
+            if let Some(file) = receiver.handle(message).await.unwrap() {
+                received = Some(file);
+            }
+        }
+
+        let received = received.unwrap();
+        assert_eq!(received.path, dir.join("out").join("data.bin"));
+        assert_eq!(received.size, 4500);
+        assert_eq!(std::fs::read(&received.path).unwrap(), contents());
+        assert_eq!(receiver.active(), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_corrupted_chunk_fails_hash_check() {
+        let dir = temp_dir("corrupt");
+        let source = dir.join("in").join("data.bin");
+        std::fs::write(&source, contents()).unwrap();
+
+        let mut messages = sent_messages(FileSender::new(&source)).await;
+        if let ProtocolMessage::FileTransferChunk { data, .. } = &mut messages[2] {
+            data[10] ^= 0xff;
+        }
+
+        let mut receiver = FileReceiver::new(dir.join("out"));
+        let (last, rest) = messages.split_last().unwrap();
+        for message in rest {
+            receiver.handle(message).await.unwrap();
+        }
+        let err = receiver.handle(last).await.unwrap_err();
+        assert!(err.to_string().contains("hash check"));
+
+        // Neither the file nor its partial copy is left behind
+        assert_eq!(receiver.active(), 0);
+        assert_eq!(std::fs::read_dir(dir.join("out")).unwrap().count(), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_resume_after_restart() {
+        let dir = temp_dir("resume");
+        let source = dir.join("in").join("data.bin");
+        std::fs::write(&source, contents()).unwrap();
+        let messages = sent_messages(FileSender::new(&source)).await;
+        let ProtocolMessage::FileTransferStart { transfer_id, .. } = messages[0] else {
+            panic!("expected FileTransferStart");
+        };
+
+        // Start and two chunks arrive before the receiver goes away
+        {
+            let mut receiver = FileReceiver::new(dir.join("out"));
+            for message in &messages[..3] {
+                receiver.handle(message).await.unwrap();
+            }
+        }
+
+        let mut receiver = FileReceiver::new(dir.join("out"));
+        let resume = receiver.resume().await.unwrap();
+        assert_eq!(
+            resume,
+            vec![ProtocolMessage::FileTransferResume {
+                transfer_id,
+                next_chunk: 2,
+                offset: 2000,
+            }]
+        );
+
+        // Only the missing chunks are sent again
+        let resent =
+            sent_messages(FileSender::new(&source).resume_from(transfer_id, 2, 2000)).await;
+        let indices: Vec<u64> = resent
+            .iter()
+            .filter_map(|message| match message {
+                ProtocolMessage::FileTransferChunk { chunk_index, .. } => Some(*chunk_index),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(indices, vec![2, 3, 4]);
+
+        let mut received = None;
+        for message in &resent {
+            if let Some(file) = receiver.handle(message).await.unwrap() {
+                received = Some(file);
+            }
+        }
+        assert_eq!(std::fs::read(received.unwrap().path).unwrap(), contents());
+        assert_eq!(std::fs::read_dir(dir.join("out")).unwrap().count(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_traversal_and_cancel() {
+        let dir = temp_dir("cancel");
+        let mut receiver = FileReceiver::new(dir.join("out"));
+
+        let escape = ProtocolMessage::FileTransferStart {
+            file_name: "../escape.txt".to_string(),
+            file_size: 1,
+            transfer_id: Uuid::new_v4(),
+        };
+        assert!(receiver.handle(&escape).await.is_err());
+
+        let transfer_id = Uuid::new_v4();
+        let start = ProtocolMessage::FileTransferStart {
+            file_name: "notes.txt".to_string(),
+            file_size: 10,
+            transfer_id,
+        };
+        receiver.handle(&start).await.unwrap();
+        assert_eq!(receiver.active(), 1);
+        assert!(receiver.cancel(transfer_id).await.is_some());
+        assert!(receiver.cancel(transfer_id).await.is_none());
+        assert_eq!(std::fs::read_dir(dir.join("out")).unwrap().count(), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
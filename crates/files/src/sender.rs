@@ -0,0 +1,153 @@
+//! Sending side of a transfer
+
+use crate::DEFAULT_CHUNK_SIZE;
+use ada_remote_core::{Error, ProtocolMessage, Result};
+use ring::digest;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::io::AsyncReadExt;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+/// Cancels a running [`FileSender`] from another task
+#[derive(Debug, Clone)]
+pub struct CancelHandle(Arc<AtomicBool>);
+
+impl CancelHandle {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Streams one file to the peer
+pub struct FileSender {
+    transfer_id: Uuid,
+    path: PathBuf,
+    chunk_size: usize,
+    cancelled: Arc<AtomicBool>,
+    /// Next chunk index and byte offset when resuming
+    resume: Option<(u64, u64)>,
+}
+
+impl FileSender {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            transfer_id: Uuid::new_v4(),
+            path: path.into(),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            cancelled: Arc::new(AtomicBool::new(false)),
+            resume: None,
+        }
+    }
+
+    /// Continue an earlier transfer as asked by a `FileTransferResume`
+    pub fn resume_from(mut self, transfer_id: Uuid, next_chunk: u64, offset: u64) -> Self {
+        self.transfer_id = transfer_id;
+        self.resume = Some((next_chunk, offset));
+        self
+    }
+
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    pub fn transfer_id(&self) -> Uuid {
+        self.transfer_id
+    }
+
+    pub fn cancel_handle(&self) -> CancelHandle {
+        CancelHandle(self.cancelled.clone())
+    }
+
+    /// Stream the file into `tx`. A bounded channel provides backpressure:
+    /// the file is only read as fast as the other end drains it.
+    pub async fn send(self, tx: &mpsc::Sender<ProtocolMessage>) -> Result<()> {
+        let send = |message| async {
+            tx.send(message)
+                .await
+                .map_err(|_| Error::ConnectionClosed("File transfer channel closed".to_string()))
+        };
+
+        let file_name = self
+            .path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .ok_or_else(|| Error::Session(format!("Not a file: {}", self.path.display())))?;
+        let mut file = tokio::fs::File::open(&self.path).await?;
+        let file_size = file.metadata().await?.len();
+
+        let mut hasher = digest::Context::new(&digest::SHA256);
+        let mut chunk_index = 0;
+        match self.resume {
+            None => {
+                send(ProtocolMessage::FileTransferStart {
+                    file_name,
+                    file_size,
+                    transfer_id: self.transfer_id,
+                })
+                .await?;
+            }
+            Some((next_chunk, offset)) => {
+                if offset > file_size {
+                    return Err(Error::Session(format!(
+                        "Cannot resume {} at byte {} of {}",
+                        self.path.display(),
+                        offset,
+                        file_size
+                    )));
+                }
+                // The final hash covers the whole file, including the part
+                // the receiver already has
+                let mut skipped = (&mut file).take(offset);
+                let mut buf = vec![0u8; self.chunk_size];
+                loop {
+                    let n = skipped.read(&mut buf).await?;
+                    if n == 0 {
+                        break;
+                    }
+                    hasher.update(&buf[..n]);
+                }
+                chunk_index = next_chunk;
+            }
+        }
+
+        loop {
+            if self.cancelled.load(Ordering::Relaxed) {
+                send(ProtocolMessage::FileTransferCancel {
+                    transfer_id: self.transfer_id,
+                    reason: "Cancelled by sender".to_string(),
+                })
+                .await?;
+                return Err(Error::Session("File transfer cancelled".to_string()));
+            }
+
+            let mut data = Vec::with_capacity(self.chunk_size);
+            (&mut file)
+                .take(self.chunk_size as u64)
+                .read_to_end(&mut data)
+                .await?;
+            if data.is_empty() {
+                break;
+            }
+
+            hasher.update(&data);
+            send(ProtocolMessage::FileTransferChunk {
+                transfer_id: self.transfer_id,
+                chunk_index,
+                data,
+            })
+            .await?;
+            chunk_index += 1;
+        }
+
+        send(ProtocolMessage::FileTransferComplete {
+            transfer_id: self.transfer_id,
+            sha256: hasher.finish().as_ref().to_vec(),
+        })
+        .await?;
+        tracing::info!("Sent {} ({} bytes)", self.path.display(), file_size);
+        Ok(())
+    }
+}
@@ -0,0 +1,129 @@
+//! Mouse move coalescing
+//!
+//! High polling rate mice report hundreds of moves a second, each of which
+//! would otherwise become its own message. [`InputCoalescer`] holds a move
+//! for a short window and replaces it with any later one, so only the latest
+//! position goes out. Every other event first releases the held move, so
+//! nothing is dropped or reordered relative to the moves.
+
+use crate::InputEvent;
+use std::time::{Duration, Instant};
+
+/// How long a move is held by default, about one frame at 120 Hz
+pub const DEFAULT_COALESCE_WINDOW: Duration = Duration::from_millis(8);
+
+/// Collapses bursts of absolute mouse moves into their latest position
+#[derive(Debug)]
+pub struct InputCoalescer {
+    window: Duration,
+    /// Held move and when the first move it replaced arrived
+    held: Option<(InputEvent, Instant)>,
+}
+
+impl InputCoalescer {
+    pub fn new(window: Duration) -> Self {
+        Self { window, held: None }
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Change the window; takes effect for the held move as well
+    pub fn set_window(&mut self, window: Duration) {
+        self.window = window;
+    }
+
+    /// Feed an event arriving at `now`, returning the events to send now in
+    /// order
+    pub fn push(&mut self, event: InputEvent, now: Instant) -> Vec<InputEvent> {
+        if !matches!(event, InputEvent::MouseMove { .. }) {
+            return self.flush().into_iter().chain([event]).collect();
+        }
+
+        let since = self.held.take().map_or(now, |(_, since)| since);
+        if now.duration_since(since) >= self.window {
+            return vec![event];
+        }
+        self.held = Some((event, since));
+        Vec::new()
+    }
+
+    /// When the held move is due, to schedule [`poll`](Self::poll)
+    pub fn deadline(&self) -> Option<Instant> {
+        self.held.as_ref().map(|(_, since)| *since + self.window)
+    }
+
+    /// The held move once it has been held for the window
+    pub fn poll(&mut self, now: Instant) -> Option<InputEvent> {
+        match self.deadline() {
+            Some(deadline) if now >= deadline => self.flush(),
+            _ => None,
+        }
+    }
+
+    /// The held move, regardless of its age
+    pub fn flush(&mut self) -> Option<InputEvent> {
+        self.held.take().map(|(event, _)| event)
+    }
+}
+
+impl Default for InputCoalescer {
+    fn default() -> Self {
+        Self::new(DEFAULT_COALESCE_WINDOW)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MouseButton;
+
+    #[test]
+    fn test_burst_collapses_around_click() {
+        let mut coalescer = InputCoalescer::new(Duration::from_millis(10));
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_micros(ms * 500);
+        let mut sent = Vec::new();
+
+        for i in 0..5 {
+            sent.extend(coalescer.push(InputEvent::MouseMove { x: i, y: i }, at(i as u64)));
+        }
+        let click = InputEvent::MouseButtonPress {
+            button: MouseButton::Left,
+        };
+        sent.extend(coalescer.push(click, at(5)));
+        for i in 6..10 {
+            sent.extend(coalescer.push(InputEvent::MouseMove { x: i, y: i }, at(i as u64)));
+        }
+        assert!(coalescer.poll(at(10)).is_none());
+        sent.extend(coalescer.poll(at(40)));
+
+        assert_eq!(sent.len(), 3);
+        assert!(matches!(sent[0], InputEvent::MouseMove { x: 4, y: 4 }));
+        assert!(matches!(
+            sent[1],
+            InputEvent::MouseButtonPress {
+                button: MouseButton::Left
+            }
+        ));
+        assert!(matches!(sent[2], InputEvent::MouseMove { x: 9, y: 9 }));
+        assert_eq!(coalescer.deadline(), None);
+    }
+
+    #[test]
+    fn test_steady_stream_sent_once_per_window() {
+        let mut coalescer = InputCoalescer::new(Duration::from_millis(8));
+        let start = Instant::now();
+        let sent: usize = (0..100)
+            .map(|ms| {
+                let event = InputEvent::MouseMove { x: ms, y: 0 };
+                coalescer
+                    .push(event, start + Duration::from_millis(ms as u64))
+                    .len()
+            })
+            .sum();
+        // Held moves still go out while the mouse keeps moving
+        assert_eq!(sent, 11);
+    }
+}
@@ -0,0 +1,155 @@
+//! Connection mode enforcement
+//!
+//! Input from a view-only or file-transfer session must never reach the
+//! desktop, whatever the client sends. The guard sits in front of the
+//! platform injector and refuses it. The mode can be changed through a
+//! [`ModeSwitch`] from outside the thread injecting input, taking effect on
+//! the next event.
+
+use crate::{InputEvent, InputInjector};
+use ada_remote_core::{ConnectionMode, Error, Result};
+use std::sync::{Arc, Mutex};
+
+/// Shared connection mode of one or more guards
+#[derive(Debug, Clone)]
+pub struct ModeSwitch(Arc<Mutex<ConnectionMode>>);
+
+impl ModeSwitch {
+    pub fn new(mode: ConnectionMode) -> Self {
+        Self(Arc::new(Mutex::new(mode)))
+    }
+
+    pub fn get(&self) -> ConnectionMode {
+        *self.0.lock().unwrap()
+    }
+
+    /// Apply a mode change, e.g. when the host grants a viewer control
+    pub fn set(&self, mode: ConnectionMode) {
+        let mut current = self.0.lock().unwrap();
+        if mode != *current {
+            tracing::info!("Input guard mode changed from {:?} to {:?}", *current, mode);
+            *current = mode;
+        }
+    }
+}
+
+/// Injector wrapper that only passes input in full-control sessions
+pub struct InputGuard {
+    inner: Box<dyn InputInjector>,
+    mode: ModeSwitch,
+}
+
+impl InputGuard {
+    pub fn new(inner: Box<dyn InputInjector>, mode: ConnectionMode) -> Self {
+        Self::with_switch(inner, ModeSwitch::new(mode))
+    }
+
+    /// Guard following a mode switched elsewhere
+    pub fn with_switch(inner: Box<dyn InputInjector>, mode: ModeSwitch) -> Self {
+        Self { inner, mode }
+    }
+
+    pub fn mode(&self) -> ConnectionMode {
+        self.mode.get()
+    }
+
+    /// Handle that changes this guard's mode
+    pub fn switch(&self) -> ModeSwitch {
+        self.mode.clone()
+    }
+
+    pub fn set_mode(&mut self, mode: ConnectionMode) {
+        self.mode.set(mode);
+    }
+}
+
+impl InputInjector for InputGuard {
+    fn init(&mut self) -> Result<()> {
+        self.inner.init()
+    }
+
+    fn inject(&mut self, event: InputEvent) -> Result<()> {
+        let mode = self.mode.get();
+        if !mode.allows_input() {
+            tracing::warn!("Dropping input in {:?} session: {:?}", mode, event);
+            return Err(Error::InputDenied(format!("session mode is {:?}", mode)));
+        }
+        self.inner.inject(event)
+    }
+
+    fn cleanup(&mut self) -> Result<()> {
+        self.inner.cleanup()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Recorder(Arc<Mutex<usize>>);
+
+    impl InputInjector for Recorder {
+        fn init(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn inject(&mut self, _event: InputEvent) -> Result<()> {
+            *self.0.lock().unwrap() += 1;
+            Ok(())
+        }
+
+        fn cleanup(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn guard(mode: ConnectionMode) -> (InputGuard, Arc<Mutex<usize>>) {
+        let injected = Arc::new(Mutex::new(0));
+        let guard = InputGuard::new(Box::new(Recorder(Arc::clone(&injected))), mode);
+        (guard, injected)
+    }
+
+    #[test]
+    fn test_input_blocked_unless_full_control() {
+        for mode in [ConnectionMode::ViewOnly, ConnectionMode::FileTransfer] {
+            let (mut guard, injected) = guard(mode);
+            let result = guard.inject(InputEvent::MouseMove { x: 1, y: 1 });
+            assert!(matches!(result, Err(Error::InputDenied(_))));
+            assert_eq!(*injected.lock().unwrap(), 0);
+        }
+
+        let (mut guard, injected) = guard(ConnectionMode::FullControl);
+        guard.inject(InputEvent::MouseMove { x: 1, y: 1 }).unwrap();
+        assert_eq!(*injected.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_mode_change_updates_guard() {
+        let (mut guard, injected) = guard(ConnectionMode::ViewOnly);
+        let click = || InputEvent::MouseButtonPress {
+            button: crate::MouseButton::Left,
+        };
+        assert!(guard.inject(click()).is_err());
+
+        guard.set_mode(ConnectionMode::FullControl);
+        guard.inject(click()).unwrap();
+
+        guard.set_mode(ConnectionMode::ViewOnly);
+        assert!(guard.inject(click()).is_err());
+        assert_eq!(*injected.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_switch_from_another_thread() {
+        let (mut guard, injected) = guard(ConnectionMode::FullControl);
+        let switch = guard.switch();
+        guard.inject(InputEvent::MouseMove { x: 1, y: 1 }).unwrap();
+
+        std::thread::spawn(move || switch.set(ConnectionMode::ViewOnly))
+            .join()
+            .unwrap();
+        assert_eq!(guard.mode(), ConnectionMode::ViewOnly);
+        assert!(guard.inject(InputEvent::MouseMove { x: 2, y: 2 }).is_err());
+        assert_eq!(*injected.lock().unwrap(), 1);
+    }
+}
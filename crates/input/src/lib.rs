@@ -5,7 +5,18 @@
 use ada_remote_core::Result;
 use serde::{Deserialize, Serialize};
 
-/// Keyboard key codes (virtual key codes)
+mod coalesce;
+mod guard;
+mod replay;
+mod transform;
+mod wire;
+
+pub use coalesce::{InputCoalescer, DEFAULT_COALESCE_WINDOW};
+pub use guard::{InputGuard, ModeSwitch};
+pub use replay::{InputPlayer, InputRecorder, RecordedEvent, Recording};
+pub use transform::{CoordinateTransform, HostDisplay, ScaledInjector};
+
+/// Keyboard key codes (Windows virtual-key codes, mapped by each backend)
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct KeyCode(pub u32);
 
@@ -28,12 +39,37 @@ pub enum InputEvent {
     KeyRelease { key: KeyCode },
     /// Move mouse to absolute position
     MouseMove { x: i32, y: i32 },
+    /// Move mouse by a delta, for pointer-locked apps
+    MouseMoveRelative { dx: i32, dy: i32 },
+    /// Place the cursor exactly at an absolute position, bypassing pointer
+    /// acceleration
+    MouseWarp { x: i32, y: i32 },
     /// Press a mouse button
     MouseButtonPress { button: MouseButton },
     /// Release a mouse button
     MouseButtonRelease { button: MouseButton },
-    /// Scroll mouse wheel
+    /// Scroll mouse wheel by whole notches; positive is up and right
     MouseScroll { delta_x: i32, delta_y: i32 },
+    /// Type text as-is, independent of the host's keyboard layout
+    TypeText { text: String },
+}
+
+/// Split `text` into runs of at most `max_units` UTF-16 code units without
+/// breaking a character, for platform APIs that take text in bounded pieces
+pub fn text_chunks(text: &str, max_units: usize) -> Vec<&str> {
+    let mut chunks = Vec::new();
+    let (mut start, mut units) = (0, 0);
+    for (index, c) in text.char_indices() {
+        if units + c.len_utf16() > max_units && index > start {
+            chunks.push(&text[start..index]);
+            (start, units) = (index, 0);
+        }
+        units += c.len_utf16();
+    }
+    if start < text.len() {
+        chunks.push(&text[start..]);
+    }
+    chunks
 }
 
 /// Trait for input injection implementations
@@ -48,6 +84,9 @@ pub trait InputInjector: Send + Sync {
     fn cleanup(&mut self) -> Result<()>;
 }
 
+#[cfg(target_os = "macos")]
+pub use macos::{is_accessibility_trusted, open_accessibility_settings};
+
 /// Create a platform-specific input injector
 pub fn create_injector() -> Result<Box<dyn InputInjector>> {
     #[cfg(target_os = "linux")]
@@ -76,37 +115,502 @@ pub fn create_injector() -> Result<Box<dyn InputInjector>> {
 #[cfg(target_os = "linux")]
 mod linux {
     use super::*;
+    use std::os::raw::{c_int, c_uint, c_ulong};
+    use std::ptr;
+    use x11::keysym::*;
+    use x11::{xlib, xtest};
+
+    /// X keysym for a virtual-key code; letters and digits map to themselves
+    pub(crate) fn keysym(key: KeyCode) -> Option<c_uint> {
+        let sym = match key.0 {
+            0x30..=0x39 => key.0,
+            // Lowercase keysym; Shift is a separate key event
+            0x41..=0x5A => key.0 + 0x20,
+            0x60..=0x69 => XK_KP_0 + (key.0 - 0x60),
+            0x70..=0x87 => XK_F1 + (key.0 - 0x70),
+            0x08 => XK_BackSpace,
+            0x09 => XK_Tab,
+            0x0D => XK_Return,
+            0x10 | 0xA0 => XK_Shift_L,
+            0xA1 => XK_Shift_R,
+            0x11 | 0xA2 => XK_Control_L,
+            0xA3 => XK_Control_R,
+            0x12 | 0xA4 => XK_Alt_L,
+            0xA5 => XK_Alt_R,
+            0x13 => XK_Pause,
+            0x14 => XK_Caps_Lock,
+            0x1B => XK_Escape,
+            0x20 => XK_space,
+            0x21 => XK_Prior,
+            0x22 => XK_Next,
+            0x23 => XK_End,
+            0x24 => XK_Home,
+            0x25 => XK_Left,
+            0x26 => XK_Up,
+            0x27 => XK_Right,
+            0x28 => XK_Down,
+            0x2C => XK_Print,
+            0x2D => XK_Insert,
+            0x2E => XK_Delete,
+            0x5B => XK_Super_L,
+            0x5C => XK_Super_R,
+            0x5D => XK_Menu,
+            0x6A => XK_KP_Multiply,
+            0x6B => XK_KP_Add,
+            0x6C => XK_KP_Separator,
+            0x6D => XK_KP_Subtract,
+            0x6E => XK_KP_Decimal,
+            0x6F => XK_KP_Divide,
+            0x90 => XK_Num_Lock,
+            0x91 => XK_Scroll_Lock,
+            0xBA => XK_semicolon,
+            0xBB => XK_equal,
+            0xBC => XK_comma,
+            0xBD => XK_minus,
+            0xBE => XK_period,
+            0xBF => XK_slash,
+            0xC0 => XK_grave,
+            0xDB => XK_bracketleft,
+            0xDC => XK_backslash,
+            0xDD => XK_bracketright,
+            0xDE => XK_apostrophe,
+            _ => return None,
+        };
+        Some(sym)
+    }
+
+    /// Keysym for a character: Latin-1 maps directly, the rest of Unicode
+    /// lives at 0x01000000 + code point
+    fn char_keysym(c: char) -> c_ulong {
+        match c {
+            '\n' | '\r' => XK_Return.into(),
+            '\t' => XK_Tab.into(),
+            '\u{8}' => XK_BackSpace.into(),
+            '\u{20}'..='\u{7e}' | '\u{a0}'..='\u{ff}' => c as c_ulong,
+            _ => 0x0100_0000 + c as c_ulong,
+        }
+    }
+
+    /// Highest keycode with no keysyms bound, for typing arbitrary characters
+    fn spare_keycode(display: *mut xlib::Display) -> Option<c_int> {
+        let (mut min, mut max, mut per_keycode) = (0, 0, 0);
+        unsafe {
+            xlib::XDisplayKeycodes(display, &mut min, &mut max);
+            let count = max - min + 1;
+            let syms = xlib::XGetKeyboardMapping(display, min as u8, count, &mut per_keycode);
+            if syms.is_null() || per_keycode <= 0 {
+                return None;
+            }
+            let mapping = std::slice::from_raw_parts(syms, count as usize * per_keycode as usize);
+            let spare = mapping
+                .chunks_exact(per_keycode as usize)
+                .rposition(|keysyms| keysyms.iter().all(|&sym| sym == 0))
+                .map(|offset| min + offset as c_int);
+            xlib::XFree(syms.cast());
+            spare
+        }
+    }
 
-    pub struct X11Injector {}
+    fn button_number(button: MouseButton) -> c_uint {
+        match button {
+            MouseButton::Left => 1,
+            MouseButton::Middle => 2,
+            MouseButton::Right => 3,
+            MouseButton::X1 => 8,
+            MouseButton::X2 => 9,
+        }
+    }
+
+    pub struct X11Injector {
+        display: *mut xlib::Display,
+        /// Temporarily bound to each character of TypeText
+        spare_keycode: Option<c_int>,
+    }
+
+    // SAFETY: the display connection is only used through &mut self
+    unsafe impl Send for X11Injector {}
+    unsafe impl Sync for X11Injector {}
 
     impl X11Injector {
         pub fn new() -> Result<Self> {
-            Ok(Self {})
+            Ok(Self {
+                display: ptr::null_mut(),
+                spare_keycode: None,
+            })
+        }
+
+        fn display(&self) -> Result<*mut xlib::Display> {
+            if self.display.is_null() {
+                return Err(ada_remote_core::Error::Session(
+                    "Input injector not initialized".to_string(),
+                ));
+            }
+            Ok(self.display)
+        }
+
+        fn key(&self, key: KeyCode, pressed: bool) -> Result<()> {
+            let display = self.display()?;
+            let sym = keysym(key).ok_or_else(|| {
+                ada_remote_core::Error::Session(format!("Unmapped key code {:#x}", key.0))
+            })?;
+            let keycode = unsafe { xlib::XKeysymToKeycode(display, sym.into()) };
+            if keycode == 0 {
+                return Err(ada_remote_core::Error::Session(format!(
+                    "No keycode for keysym {:#x} in the current layout",
+                    sym
+                )));
+            }
+            unsafe { xtest::XTestFakeKeyEvent(display, keycode.into(), pressed.into(), 0) };
+            Ok(())
+        }
+
+        /// Type each character by binding its keysym to the spare keycode
+        fn type_text(&self, text: &str) -> Result<()> {
+            let display = self.display()?;
+            let keycode = self.spare_keycode.ok_or_else(|| {
+                ada_remote_core::Error::Session("No free keycode to type text with".to_string())
+            })?;
+
+            for c in text.chars() {
+                // Same keysym at both levels so a held Shift changes nothing
+                let mut keysyms = [char_keysym(c); 2];
+                unsafe {
+                    xlib::XChangeKeyboardMapping(display, keycode, 2, keysyms.as_mut_ptr(), 1);
+                    xlib::XSync(display, xlib::False);
+                    xtest::XTestFakeKeyEvent(display, keycode as c_uint, xlib::True, 0);
+                    xtest::XTestFakeKeyEvent(display, keycode as c_uint, xlib::False, 0);
+                    xlib::XSync(display, xlib::False);
+                }
+            }
+
+            let mut unbound = [0; 2];
+            unsafe { xlib::XChangeKeyboardMapping(display, keycode, 2, unbound.as_mut_ptr(), 1) };
+            Ok(())
+        }
+
+        fn click(&self, button: c_uint, pressed: bool) -> Result<()> {
+            let display = self.display()?;
+            unsafe { xtest::XTestFakeButtonEvent(display, button, pressed.into(), 0) };
+            Ok(())
+        }
+
+        /// Pointer position on the root window, read back on the same connection
+        #[cfg(test)]
+        pub fn pointer_position(&self) -> Option<(i32, i32)> {
+            let display = self.display().ok()?;
+            let (mut root, mut child) = (0, 0);
+            let (mut x, mut y, mut win_x, mut win_y, mut mask) = (0, 0, 0, 0, 0);
+            let found = unsafe {
+                xlib::XQueryPointer(
+                    display,
+                    xlib::XDefaultRootWindow(display),
+                    &mut root,
+                    &mut child,
+                    &mut x,
+                    &mut y,
+                    &mut win_x,
+                    &mut win_y,
+                    &mut mask,
+                )
+            };
+            (found != 0).then_some((x, y))
         }
     }
 
     impl InputInjector for X11Injector {
         fn init(&mut self) -> Result<()> {
-            tracing::info!("X11 input injector initialized");
+            let display = unsafe { xlib::XOpenDisplay(ptr::null()) };
+            if display.is_null() {
+                return Err(ada_remote_core::Error::Session(
+                    "Failed to open X display".to_string(),
+                ));
+            }
+
+            let (mut event_base, mut error_base, mut major, mut minor) = (0, 0, 0, 0);
+            let present = unsafe {
+                xtest::XTestQueryExtension(
+                    display,
+                    &mut event_base,
+                    &mut error_base,
+                    &mut major,
+                    &mut minor,
+                )
+            };
+            if present == 0 {
+                unsafe { xlib::XCloseDisplay(display) };
+                return Err(ada_remote_core::Error::Session(
+                    "XTest extension not available".to_string(),
+                ));
+            }
+
+            self.cleanup()?;
+            self.display = display;
+            self.spare_keycode = spare_keycode(display);
+            if self.spare_keycode.is_none() {
+                tracing::warn!("No free keycode, text input is unavailable");
+            }
+            tracing::info!("X11 input injector initialized (XTest {}.{})", major, minor);
             Ok(())
         }
 
         fn inject(&mut self, event: InputEvent) -> Result<()> {
-            // TODO: Implement using XTest extension
             tracing::trace!("Injecting input event: {:?}", event);
+            let display = self.display()?;
+
+            match event {
+                InputEvent::KeyPress { key } => self.key(key, true)?,
+                InputEvent::KeyRelease { key } => self.key(key, false)?,
+                InputEvent::MouseMove { x, y } => unsafe {
+                    // Screen -1 is the screen the pointer is on
+                    xtest::XTestFakeMotionEvent(display, -1, x, y, 0);
+                },
+                InputEvent::MouseMoveRelative { dx, dy } => unsafe {
+                    xtest::XTestFakeRelativeMotionEvent(display, -1, dx, dy, 0);
+                },
+                InputEvent::MouseWarp { x, y } => unsafe {
+                    let root = xlib::XDefaultRootWindow(display);
+                    xlib::XWarpPointer(display, 0, root, 0, 0, 0, 0, x, y);
+                },
+                InputEvent::MouseButtonPress { button } => {
+                    self.click(button_number(button), true)?
+                }
+                InputEvent::MouseButtonRelease { button } => {
+                    self.click(button_number(button), false)?
+                }
+                InputEvent::MouseScroll { delta_x, delta_y } => {
+                    // Buttons 4/5 scroll up/down, 6/7 left/right
+                    let vertical = if delta_y > 0 { 4 } else { 5 };
+                    let horizontal = if delta_x > 0 { 7 } else { 6 };
+                    for (button, notches) in [(vertical, delta_y), (horizontal, delta_x)] {
+                        for _ in 0..notches.unsigned_abs() {
+                            self.click(button, true)?;
+                            self.click(button, false)?;
+                        }
+                    }
+                }
+                InputEvent::TypeText { text } => self.type_text(&text)?,
+            }
+
+            unsafe { xlib::XFlush(display) };
             Ok(())
         }
 
         fn cleanup(&mut self) -> Result<()> {
-            tracing::info!("X11 input injector cleaned up");
+            if !self.display.is_null() {
+                unsafe { xlib::XCloseDisplay(self.display) };
+                self.display = ptr::null_mut();
+                tracing::info!("X11 input injector cleaned up");
+            }
             Ok(())
         }
     }
+
+    impl Drop for X11Injector {
+        fn drop(&mut self) {
+            let _ = self.cleanup();
+        }
+    }
 }
 
 #[cfg(target_os = "windows")]
 mod windows {
     use super::*;
+    use ::windows::Win32::UI::Input::KeyboardAndMouse::{
+        MapVirtualKeyW, SendInput, INPUT, INPUT_0, INPUT_KEYBOARD, INPUT_MOUSE, KEYBDINPUT,
+        KEYBD_EVENT_FLAGS, KEYEVENTF_EXTENDEDKEY, KEYEVENTF_KEYUP, KEYEVENTF_UNICODE,
+        MAPVK_VK_TO_VSC, MOUSEEVENTF_ABSOLUTE, MOUSEEVENTF_HWHEEL, MOUSEEVENTF_LEFTDOWN,
+        MOUSEEVENTF_LEFTUP, MOUSEEVENTF_MIDDLEDOWN, MOUSEEVENTF_MIDDLEUP, MOUSEEVENTF_MOVE,
+        MOUSEEVENTF_RIGHTDOWN, MOUSEEVENTF_RIGHTUP, MOUSEEVENTF_VIRTUALDESK, MOUSEEVENTF_WHEEL,
+        MOUSEEVENTF_XDOWN, MOUSEEVENTF_XUP, MOUSEINPUT, MOUSE_EVENT_FLAGS, VIRTUAL_KEY,
+    };
+    use ::windows::Win32::UI::WindowsAndMessaging::{
+        GetSystemMetrics, SetCursorPos, SM_CXVIRTUALSCREEN, SM_CYVIRTUALSCREEN, SM_XVIRTUALSCREEN,
+        SM_YVIRTUALSCREEN, WHEEL_DELTA, XBUTTON1, XBUTTON2,
+    };
+    use std::mem;
+
+    /// Bounds of the virtual desktop spanning all monitors
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub(crate) struct VirtualScreen {
+        pub x: i32,
+        pub y: i32,
+        pub width: i32,
+        pub height: i32,
+    }
+
+    impl VirtualScreen {
+        fn current() -> Self {
+            unsafe {
+                Self {
+                    x: GetSystemMetrics(SM_XVIRTUALSCREEN),
+                    y: GetSystemMetrics(SM_YVIRTUALSCREEN),
+                    width: GetSystemMetrics(SM_CXVIRTUALSCREEN),
+                    height: GetSystemMetrics(SM_CYVIRTUALSCREEN),
+                }
+            }
+        }
+
+        /// Map desktop pixels to the 0..=65535 space of absolute mouse input
+        fn normalize(&self, x: i32, y: i32) -> (i32, i32) {
+            let scale = |value: i32, origin: i32, extent: i32| {
+                let span = (extent as i64 - 1).max(1);
+                ((value as i64 - origin as i64) * 65535 / span).clamp(0, 65535) as i32
+            };
+            (scale(x, self.x, self.width), scale(y, self.y, self.height))
+        }
+    }
+
+    /// Keys that live in the extended (E0) part of the scan code set
+    fn is_extended(vk: u32) -> bool {
+        matches!(
+            vk,
+            0x21..=0x28 | 0x2C | 0x2D | 0x2E | 0x5B | 0x5C | 0x5D | 0x6F | 0x90 | 0xA3 | 0xA5
+        )
+    }
+
+    fn keyboard(key: KeyCode, pressed: bool) -> INPUT {
+        let mut flags = KEYBD_EVENT_FLAGS(0);
+        if is_extended(key.0) {
+            flags |= KEYEVENTF_EXTENDEDKEY;
+        }
+        if !pressed {
+            flags |= KEYEVENTF_KEYUP;
+        }
+        INPUT {
+            r#type: INPUT_KEYBOARD,
+            Anonymous: INPUT_0 {
+                ki: KEYBDINPUT {
+                    wVk: VIRTUAL_KEY(key.0 as u16),
+                    wScan: unsafe { MapVirtualKeyW(key.0, MAPVK_VK_TO_VSC) } as u16,
+                    dwFlags: flags,
+                    time: 0,
+                    dwExtraInfo: 0,
+                },
+            },
+        }
+    }
+
+    /// Most UTF-16 units typed per SendInput call
+    const MAX_TEXT_UNITS: usize = 64;
+
+    fn unicode(unit: u16, pressed: bool) -> INPUT {
+        let flags = if pressed {
+            KEYEVENTF_UNICODE
+        } else {
+            KEYEVENTF_UNICODE | KEYEVENTF_KEYUP
+        };
+        INPUT {
+            r#type: INPUT_KEYBOARD,
+            Anonymous: INPUT_0 {
+                ki: KEYBDINPUT {
+                    wVk: VIRTUAL_KEY(0),
+                    wScan: unit,
+                    dwFlags: flags,
+                    time: 0,
+                    dwExtraInfo: 0,
+                },
+            },
+        }
+    }
+
+    /// A press and release per UTF-16 unit; surrogate pairs stay adjacent
+    fn text_inputs(text: &str) -> Vec<INPUT> {
+        text.encode_utf16()
+            .flat_map(|unit| [unicode(unit, true), unicode(unit, false)])
+            .collect()
+    }
+
+    fn mouse(dx: i32, dy: i32, data: u32, flags: MOUSE_EVENT_FLAGS) -> INPUT {
+        INPUT {
+            r#type: INPUT_MOUSE,
+            Anonymous: INPUT_0 {
+                mi: MOUSEINPUT {
+                    dx,
+                    dy,
+                    mouseData: data,
+                    dwFlags: flags,
+                    time: 0,
+                    dwExtraInfo: 0,
+                },
+            },
+        }
+    }
+
+    fn button(button: MouseButton, pressed: bool) -> INPUT {
+        let (flags, data) = match (button, pressed) {
+            (MouseButton::Left, true) => (MOUSEEVENTF_LEFTDOWN, 0),
+            (MouseButton::Left, false) => (MOUSEEVENTF_LEFTUP, 0),
+            (MouseButton::Right, true) => (MOUSEEVENTF_RIGHTDOWN, 0),
+            (MouseButton::Right, false) => (MOUSEEVENTF_RIGHTUP, 0),
+            (MouseButton::Middle, true) => (MOUSEEVENTF_MIDDLEDOWN, 0),
+            (MouseButton::Middle, false) => (MOUSEEVENTF_MIDDLEUP, 0),
+            (MouseButton::X1, true) => (MOUSEEVENTF_XDOWN, XBUTTON1),
+            (MouseButton::X1, false) => (MOUSEEVENTF_XUP, XBUTTON1),
+            (MouseButton::X2, true) => (MOUSEEVENTF_XDOWN, XBUTTON2),
+            (MouseButton::X2, false) => (MOUSEEVENTF_XUP, XBUTTON2),
+        };
+        mouse(0, 0, data as u32, flags)
+    }
+
+    /// Where a warp puts the cursor with SetCursorPos, instead of SendInput
+    pub(crate) fn warp_position(event: &InputEvent) -> Option<(i32, i32)> {
+        match *event {
+            InputEvent::MouseWarp { x, y } => Some((x, y)),
+            _ => None,
+        }
+    }
+
+    /// SendInput records for an event; empty for warps, which use SetCursorPos
+    pub(crate) fn inputs(event: &InputEvent, screen: &VirtualScreen) -> Vec<INPUT> {
+        match *event {
+            InputEvent::KeyPress { key } => vec![keyboard(key, true)],
+            InputEvent::KeyRelease { key } => vec![keyboard(key, false)],
+            InputEvent::MouseMove { x, y } => {
+                let (dx, dy) = screen.normalize(x, y);
+                vec![mouse(
+                    dx,
+                    dy,
+                    0,
+                    MOUSEEVENTF_MOVE | MOUSEEVENTF_ABSOLUTE | MOUSEEVENTF_VIRTUALDESK,
+                )]
+            }
+            // Without ABSOLUTE the deltas are relative, and accelerated
+            InputEvent::MouseMoveRelative { dx, dy } => vec![mouse(dx, dy, 0, MOUSEEVENTF_MOVE)],
+            InputEvent::MouseWarp { .. } => Vec::new(),
+            InputEvent::MouseButtonPress { button: b } => vec![button(b, true)],
+            InputEvent::MouseButtonRelease { button: b } => vec![button(b, false)],
+            InputEvent::MouseScroll { delta_x, delta_y } => {
+                // Wheel data is a signed count of WHEEL_DELTA units
+                let mut inputs = Vec::new();
+                if delta_y != 0 {
+                    let data = delta_y.wrapping_mul(WHEEL_DELTA as i32) as u32;
+                    inputs.push(mouse(0, 0, data, MOUSEEVENTF_WHEEL));
+                }
+                if delta_x != 0 {
+                    let data = delta_x.wrapping_mul(WHEEL_DELTA as i32) as u32;
+                    inputs.push(mouse(0, 0, data, MOUSEEVENTF_HWHEEL));
+                }
+                inputs
+            }
+            InputEvent::TypeText { ref text } => text_inputs(text),
+        }
+    }
+
+    fn send(inputs: &[INPUT]) -> Result<()> {
+        if inputs.is_empty() {
+            return Ok(());
+        }
+        let sent = unsafe { SendInput(inputs, mem::size_of::<INPUT>() as i32) };
+        if sent as usize != inputs.len() {
+            // Also what a UIPI block looks like; it sets no error code
+            return Err(ada_remote_core::Error::Session(format!(
+                "SendInput inserted {} of {} events",
+                sent,
+                inputs.len()
+            )));
+        }
+        Ok(())
+    }
 
     pub struct WindowsInjector {}
 
@@ -118,14 +622,31 @@ mod windows {
 
     impl InputInjector for WindowsInjector {
         fn init(&mut self) -> Result<()> {
+            // SendInput needs no privileges, but UIPI drops input aimed at
+            // windows of a higher integrity level (elevated apps, UAC
+            // prompts) unless this process runs elevated as well
             tracing::info!("Windows input injector initialized");
             Ok(())
         }
 
         fn inject(&mut self, event: InputEvent) -> Result<()> {
-            // TODO: Implement using SendInput API
             tracing::trace!("Injecting input event: {:?}", event);
-            Ok(())
+
+            if let Some((x, y)) = warp_position(&event) {
+                return unsafe { SetCursorPos(x, y) }.map_err(|e| {
+                    ada_remote_core::Error::Session(format!("SetCursorPos failed: {}", e))
+                });
+            }
+
+            if let InputEvent::TypeText { text } = &event {
+                for chunk in text_chunks(text, MAX_TEXT_UNITS) {
+                    send(&text_inputs(chunk))?;
+                }
+                return Ok(());
+            }
+
+            // Re-read every time since monitors can be attached at any point
+            send(&inputs(&event, &VirtualScreen::current()))
         }
 
         fn cleanup(&mut self) -> Result<()> {
@@ -138,6 +659,34 @@ mod windows {
 #[cfg(target_os = "macos")]
 mod macos {
     use super::*;
+    use core_graphics::display::CGDisplay;
+    use core_graphics::event::{
+        CGEvent, CGEventTapLocation, CGEventType, CGMouseButton, EventField,
+    };
+    use core_graphics::event_source::{CGEventSource, CGEventSourceStateID};
+    use core_graphics::geometry::CGPoint;
+
+    /// CGEventKeyboardSetUnicodeString ignores text beyond 20 UTF-16 units
+    const MAX_TEXT_UNITS: usize = 20;
+
+    #[link(name = "ApplicationServices", kind = "framework")]
+    extern "C" {
+        fn AXIsProcessTrusted() -> bool;
+    }
+
+    /// Whether this process has the Accessibility permission required for
+    /// CGEvent injection
+    pub fn is_accessibility_trusted() -> bool {
+        unsafe { AXIsProcessTrusted() }
+    }
+
+    /// Open System Settings at Privacy & Security > Accessibility
+    pub fn open_accessibility_settings() -> Result<()> {
+        std::process::Command::new("open")
+            .arg("x-apple.systempreferences:com.apple.preference.security?Privacy_Accessibility")
+            .status()?;
+        Ok(())
+    }
 
     pub struct MacOSInjector {}
 
@@ -149,13 +698,62 @@ mod macos {
 
     impl InputInjector for MacOSInjector {
         fn init(&mut self) -> Result<()> {
+            // Without this, CGEventPost silently drops every event
+            if !is_accessibility_trusted() {
+                return Err(ada_remote_core::Error::Session(
+                    "Accessibility permission required".to_string(),
+                ));
+            }
             tracing::info!("macOS input injector initialized");
             Ok(())
         }
 
         fn inject(&mut self, event: InputEvent) -> Result<()> {
-            // TODO: Implement using CGEvent API
+            // TODO: Implement the remaining events using CGEvent API
             tracing::trace!("Injecting input event: {:?}", event);
+
+            if let InputEvent::MouseWarp { x, y } = event {
+                // Moves the cursor without posting an event, so no acceleration
+                return CGDisplay::warp_mouse_cursor_position(CGPoint::new(x as f64, y as f64))
+                    .map_err(|e| {
+                        ada_remote_core::Error::Session(format!(
+                            "CGWarpMouseCursorPosition failed: {}",
+                            e
+                        ))
+                    });
+            }
+
+            let failed = || ada_remote_core::Error::Session("Failed to create CGEvent".to_string());
+            let source =
+                CGEventSource::new(CGEventSourceStateID::HIDSystemState).map_err(|_| failed())?;
+
+            if let InputEvent::MouseMoveRelative { dx, dy } = event {
+                // Pointer-locked apps read the delta fields, others the position
+                let at = CGEvent::new(source.clone())
+                    .map_err(|_| failed())?
+                    .location();
+                let to = CGPoint::new(at.x + dx as f64, at.y + dy as f64);
+                let event = CGEvent::new_mouse_event(
+                    source,
+                    CGEventType::MouseMoved,
+                    to,
+                    CGMouseButton::Left,
+                )
+                .map_err(|_| failed())?;
+                event.set_integer_value_field(EventField::MOUSE_EVENT_DELTA_X, dx as i64);
+                event.set_integer_value_field(EventField::MOUSE_EVENT_DELTA_Y, dy as i64);
+                event.post(CGEventTapLocation::HID);
+            } else if let InputEvent::TypeText { text } = &event {
+                for chunk in text_chunks(text, MAX_TEXT_UNITS) {
+                    // The string replaces whatever the virtual key would type
+                    for keydown in [true, false] {
+                        let event = CGEvent::new_keyboard_event(source.clone(), 0, keydown)
+                            .map_err(|_| failed())?;
+                        event.set_string(chunk);
+                        event.post(CGEventTapLocation::HID);
+                    }
+                }
+            }
             Ok(())
         }
 
@@ -165,3 +763,164 @@ mod macos {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_chunks_keep_characters_whole() {
+        assert_eq!(text_chunks("abcde", 2), vec!["ab", "cd", "e"]);
+        assert_eq!(text_chunks("", 2), Vec::<&str>::new());
+        // The emoji is a surrogate pair and must not be split
+        assert_eq!(text_chunks("é😀x", 2), vec!["é", "😀", "x"]);
+        assert_eq!(text_chunks("😀", 1), vec!["😀"]);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_x11_types_text() {
+        if std::env::var_os("DISPLAY").is_none() {
+            return;
+        }
+
+        let mut injector = create_injector().unwrap();
+        injector.init().unwrap();
+        injector
+            .inject(InputEvent::TypeText {
+                text: "héllo wörld ✓ 日本".to_string(),
+            })
+            .unwrap();
+        injector.cleanup().unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_x11_keysyms() {
+        assert_eq!(linux::keysym(KeyCode(0x41)), Some('a' as u32));
+        assert_eq!(linux::keysym(KeyCode(0x37)), Some('7' as u32));
+        assert_eq!(linux::keysym(KeyCode(0x70)), Some(x11::keysym::XK_F1));
+        assert_eq!(linux::keysym(KeyCode(0xA1)), Some(x11::keysym::XK_Shift_R));
+        assert_eq!(linux::keysym(KeyCode(0xFF)), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_x11_mouse_move() {
+        // Only meaningful with an X server, e.g. under xvfb-run in CI
+        if std::env::var_os("DISPLAY").is_none() {
+            return;
+        }
+
+        let mut injector = linux::X11Injector::new().unwrap();
+        injector.init().unwrap();
+        injector
+            .inject(InputEvent::MouseMove { x: 17, y: 23 })
+            .unwrap();
+        assert_eq!(injector.pointer_position(), Some((17, 23)));
+
+        // Small steps stay under the default acceleration threshold
+        for _ in 0..2 {
+            injector
+                .inject(InputEvent::MouseMoveRelative { dx: 3, dy: -2 })
+                .unwrap();
+        }
+        assert_eq!(injector.pointer_position(), Some((23, 19)));
+
+        injector
+            .inject(InputEvent::MouseWarp { x: 40, y: 50 })
+            .unwrap();
+        assert_eq!(injector.pointer_position(), Some((40, 50)));
+        injector.cleanup().unwrap();
+    }
+
+    #[cfg(target_os = "windows")]
+    #[test]
+    fn test_windows_input_layout() {
+        use ::windows::Win32::UI::Input::KeyboardAndMouse::{
+            INPUT_KEYBOARD, INPUT_MOUSE, KEYEVENTF_KEYUP, MOUSEEVENTF_ABSOLUTE, MOUSEEVENTF_WHEEL,
+            MOUSEEVENTF_XDOWN,
+        };
+
+        let screen = windows::VirtualScreen {
+            x: -1920,
+            y: 0,
+            width: 3840,
+            height: 1080,
+        };
+        let events = [
+            InputEvent::KeyPress { key: KeyCode(0x41) },
+            InputEvent::KeyRelease { key: KeyCode(0x41) },
+            InputEvent::MouseMove { x: 1919, y: 0 },
+            InputEvent::MouseButtonPress {
+                button: MouseButton::X2,
+            },
+            InputEvent::MouseScroll {
+                delta_x: 0,
+                delta_y: -2,
+            },
+            InputEvent::MouseWarp { x: 0, y: 0 },
+            InputEvent::MouseMoveRelative { dx: -4, dy: 7 },
+        ];
+        let inputs: Vec<_> = events
+            .iter()
+            .flat_map(|event| windows::inputs(event, &screen))
+            .collect();
+        assert_eq!(inputs.len(), 6);
+
+        unsafe {
+            assert_eq!(inputs[0].r#type, INPUT_KEYBOARD);
+            assert_eq!(inputs[0].Anonymous.ki.wVk.0, 0x41);
+            assert!(!inputs[0].Anonymous.ki.dwFlags.contains(KEYEVENTF_KEYUP));
+            assert!(inputs[1].Anonymous.ki.dwFlags.contains(KEYEVENTF_KEYUP));
+
+            let mv = inputs[2].Anonymous.mi;
+            assert_eq!(inputs[2].r#type, INPUT_MOUSE);
+            assert!(mv.dwFlags.contains(MOUSEEVENTF_ABSOLUTE));
+            assert_eq!((mv.dx, mv.dy), (65535, 0));
+
+            assert!(inputs[3].Anonymous.mi.dwFlags.contains(MOUSEEVENTF_XDOWN));
+            assert_eq!(inputs[3].Anonymous.mi.mouseData, 2);
+
+            assert!(inputs[4].Anonymous.mi.dwFlags.contains(MOUSEEVENTF_WHEEL));
+            assert_eq!(inputs[4].Anonymous.mi.mouseData as i32, -240);
+
+            let relative = inputs[5].Anonymous.mi;
+            assert!(!relative.dwFlags.contains(MOUSEEVENTF_ABSOLUTE));
+            assert_eq!((relative.dx, relative.dy), (-4, 7));
+        }
+    }
+
+    #[cfg(target_os = "windows")]
+    #[test]
+    fn test_windows_warp_skips_send_input() {
+        let screen = windows::VirtualScreen {
+            x: 0,
+            y: 0,
+            width: 1920,
+            height: 1080,
+        };
+        let warp = InputEvent::MouseWarp { x: -5, y: 30 };
+        assert!(windows::inputs(&warp, &screen).is_empty());
+        assert_eq!(windows::warp_position(&warp), Some((-5, 30)));
+
+        let mv = InputEvent::MouseMove { x: -5, y: 30 };
+        assert_eq!(windows::inputs(&mv, &screen).len(), 1);
+        assert_eq!(windows::warp_position(&mv), None);
+    }
+
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn test_macos_init_requires_accessibility() {
+        let mut injector = create_injector().unwrap();
+        let result = injector.init();
+        if is_accessibility_trusted() {
+            assert!(result.is_ok());
+        } else {
+            let err = result.unwrap_err();
+            assert!(err
+                .to_string()
+                .contains("Accessibility permission required"));
+        }
+    }
+}
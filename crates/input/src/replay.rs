@@ -0,0 +1,246 @@
+//! Input macros
+//!
+//! [`InputRecorder`] captures events with their time since recording began
+//! into a [`Recording`], which is saved as JSON and played back into any
+//! injector by [`InputPlayer`] with the original timing, optionally sped up
+//! or slowed down. Keys and buttons still down when a recording ends are
+//! released, so a replay never leaves them stuck.
+
+use crate::{InputEvent, InputInjector, KeyCode, MouseButton};
+use ada_remote_core::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Event of a recording and when it happened
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedEvent {
+    /// Microseconds since the recording started
+    pub offset_us: u64,
+    pub event: InputEvent,
+}
+
+/// Recorded input sequence, in order
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Recording {
+    pub events: Vec<RecordedEvent>,
+}
+
+impl Recording {
+    /// Time from the start to the last event
+    pub fn duration(&self) -> Duration {
+        self.events
+            .last()
+            .map_or(Duration::ZERO, |last| Duration::from_micros(last.offset_us))
+    }
+
+    pub fn save_to(&self, path: impl AsRef<Path>) -> Result<()> {
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn load_from(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+    }
+}
+
+/// Keys and mouse buttons pressed and not yet released
+#[derive(Debug, Default)]
+struct Held {
+    keys: Vec<KeyCode>,
+    buttons: Vec<MouseButton>,
+}
+
+impl Held {
+    fn track(&mut self, event: &InputEvent) {
+        match *event {
+            InputEvent::KeyPress { key } if !self.keys.contains(&key) => self.keys.push(key),
+            InputEvent::KeyRelease { key } => self.keys.retain(|held| *held != key),
+            InputEvent::MouseButtonPress { button } if !self.buttons.contains(&button) => {
+                self.buttons.push(button)
+            }
+            InputEvent::MouseButtonRelease { button } => {
+                self.buttons.retain(|held| *held != button)
+            }
+            _ => {}
+        }
+    }
+
+    /// Releases for everything held, latest press first
+    fn releases(&mut self) -> Vec<InputEvent> {
+        let keys = self.keys.drain(..).rev();
+        let buttons = self.buttons.drain(..).rev();
+        keys.map(|key| InputEvent::KeyRelease { key })
+            .chain(buttons.map(|button| InputEvent::MouseButtonRelease { button }))
+            .collect()
+    }
+}
+
+/// Captures input events into a [`Recording`]
+#[derive(Debug)]
+pub struct InputRecorder {
+    started: Instant,
+    recording: Recording,
+    held: Held,
+}
+
+impl InputRecorder {
+    /// Start recording at `now`
+    pub fn new(now: Instant) -> Self {
+        Self {
+            started: now,
+            recording: Recording::default(),
+            held: Held::default(),
+        }
+    }
+
+    /// Record `event` as happening at `now`
+    pub fn record(&mut self, event: &InputEvent, now: Instant) {
+        self.held.track(event);
+        let offset = now.saturating_duration_since(self.started);
+        self.recording.events.push(RecordedEvent {
+            offset_us: offset.as_micros() as u64,
+            event: event.clone(),
+        });
+    }
+
+    /// End the recording, releasing anything still held after the last
+    /// event
+    pub fn finish(mut self) -> Recording {
+        let offset_us = self.recording.duration().as_micros() as u64;
+        for event in self.held.releases() {
+            self.recording
+                .events
+                .push(RecordedEvent { offset_us, event });
+        }
+        self.recording
+    }
+}
+
+/// Plays a [`Recording`] back into an injector
+#[derive(Debug, Clone, Copy)]
+pub struct InputPlayer {
+    speed: f64,
+}
+
+impl InputPlayer {
+    /// Player at `speed` times the recorded pace; 2.0 plays twice as fast
+    pub fn new(speed: f64) -> Result<Self> {
+        if !(speed.is_finite() && speed > 0.0) {
+            return Err(Error::Session(format!("Invalid playback speed {}", speed)));
+        }
+        Ok(Self { speed })
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    /// Inject the events of `recording` with their recorded spacing,
+    /// blocking until the last one. Keys and buttons the recording leaves
+    /// held are released at the end, also when an injection fails.
+    pub fn play(&self, recording: &Recording, injector: &mut dyn InputInjector) -> Result<()> {
+        let started = Instant::now();
+        let mut held = Held::default();
+        let mut result = Ok(());
+
+        for recorded in &recording.events {
+            let offset = Duration::from_micros(recorded.offset_us).div_f64(self.speed);
+            if let Some(wait) = (started + offset).checked_duration_since(Instant::now()) {
+                std::thread::sleep(wait);
+            }
+            held.track(&recorded.event);
+            result = injector.inject(recorded.event.clone());
+            if result.is_err() {
+                break;
+            }
+        }
+
+        for release in held.releases() {
+            if let Err(e) = injector.inject(release) {
+                tracing::warn!("Releasing input after playback failed: {}", e);
+            }
+        }
+        result
+    }
+}
+
+impl Default for InputPlayer {
+    fn default() -> Self {
+        Self { speed: 1.0 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// Injector recording what reaches it and when
+    struct Log(Arc<Mutex<Vec<(InputEvent, Instant)>>>);
+
+    impl InputInjector for Log {
+        fn init(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn inject(&mut self, event: InputEvent) -> Result<()> {
+            self.0.lock().unwrap().push((event, Instant::now()));
+            Ok(())
+        }
+
+        fn cleanup(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_record_save_and_replay_in_order() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let shift = KeyCode(0x10);
+        let mut recorder = InputRecorder::new(start);
+        recorder.record(&InputEvent::MouseMove { x: 10, y: 20 }, at(0));
+        recorder.record(&InputEvent::KeyPress { key: shift }, at(40));
+        recorder.record(&InputEvent::KeyPress { key: KeyCode(0x41) }, at(60));
+        recorder.record(&InputEvent::KeyRelease { key: KeyCode(0x41) }, at(80));
+        let button = MouseButton::Left;
+        recorder.record(&InputEvent::MouseButtonPress { button }, at(100));
+        let recording = recorder.finish();
+        // Shift and the button were never released
+        assert_eq!(recording.events.len(), 7);
+        assert_eq!(recording.duration(), Duration::from_millis(100));
+
+        let path = std::env::temp_dir().join(format!("ada-remote-macro-{}", std::process::id()));
+        recording.save_to(&path).unwrap();
+        let loaded = Recording::load_from(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let started = Instant::now();
+        InputPlayer::new(2.0)
+            .unwrap()
+            .play(&loaded, &mut Log(Arc::clone(&log)))
+            .unwrap();
+
+        let log = log.lock().unwrap();
+        let events: Vec<String> = log.iter().map(|(e, _)| format!("{:?}", e)).collect();
+        let expected: Vec<String> = recording
+            .events
+            .iter()
+            .map(|r| format!("{:?}", r.event))
+            .collect();
+        assert_eq!(events, expected);
+        assert!(matches!(log[5].0, InputEvent::KeyRelease { key } if key == shift));
+        assert!(matches!(
+            log[6].0,
+            InputEvent::MouseButtonRelease {
+                button: MouseButton::Left
+            }
+        ));
+        // Twice as fast: the last press comes 50ms in
+        assert!(log[4].1.duration_since(started) >= Duration::from_millis(50));
+
+        assert!(InputPlayer::new(0.0).is_err());
+    }
+}
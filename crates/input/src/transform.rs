@@ -0,0 +1,193 @@
+//! Client view to host coordinate mapping
+//!
+//! Clients send absolute pointer positions in the coordinates of their video
+//! view, which rarely matches the host's capture resolution.
+
+use crate::{InputEvent, InputInjector};
+use ada_remote_core::Result;
+
+/// Captured host display, as the injection backend addresses it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HostDisplay {
+    /// Origin within the virtual desktop; not (0, 0) for secondary monitors
+    pub x: i32,
+    pub y: i32,
+    /// Capture resolution in physical pixels
+    pub width: u32,
+    pub height: u32,
+    /// Physical pixels per injection unit (2.0 where the OS injects in
+    /// logical points on a HiDPI display)
+    pub scale_factor: f64,
+}
+
+/// Maps positions in the client's view onto a host display. The view shows
+/// the whole display fitted with its aspect ratio kept, so a mismatched view
+/// has bars on two sides; positions on the bars clamp to the display edge.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CoordinateTransform {
+    view_width: u32,
+    view_height: u32,
+    display: HostDisplay,
+}
+
+impl CoordinateTransform {
+    pub fn new(view_width: u32, view_height: u32, display: HostDisplay) -> Self {
+        Self {
+            view_width,
+            view_height,
+            display,
+        }
+    }
+
+    /// Host position for a view position
+    pub fn map(&self, x: i32, y: i32) -> (i32, i32) {
+        let display = &self.display;
+        let (host_w, host_h) = (display.width.max(1) as f64, display.height.max(1) as f64);
+        let (view_w, view_h) = (
+            self.view_width.max(1) as f64,
+            self.view_height.max(1) as f64,
+        );
+
+        // View pixels per host pixel, and the bars around the fitted image
+        let scale = (view_w / host_w).min(view_h / host_h);
+        let offset_x = (view_w - host_w * scale) / 2.0;
+        let offset_y = (view_h - host_h * scale) / 2.0;
+
+        let pixel_x = ((x as f64 - offset_x) / scale)
+            .floor()
+            .clamp(0.0, host_w - 1.0);
+        let pixel_y = ((y as f64 - offset_y) / scale)
+            .floor()
+            .clamp(0.0, host_h - 1.0);
+
+        let units = display.scale_factor.max(f64::MIN_POSITIVE);
+        (
+            display.x + (pixel_x / units).floor() as i32,
+            display.y + (pixel_y / units).floor() as i32,
+        )
+    }
+}
+
+/// Injector wrapper that maps absolute positions from client to host space
+pub struct ScaledInjector {
+    inner: Box<dyn InputInjector>,
+    transform: Option<CoordinateTransform>,
+}
+
+impl ScaledInjector {
+    /// Positions pass through unchanged until a transform is set
+    pub fn new(inner: Box<dyn InputInjector>) -> Self {
+        Self {
+            inner,
+            transform: None,
+        }
+    }
+
+    /// Update the mapping, e.g. when the client view is resized or the host
+    /// resolution changes
+    pub fn set_transform(&mut self, transform: CoordinateTransform) {
+        self.transform = Some(transform);
+    }
+}
+
+impl InputInjector for ScaledInjector {
+    fn init(&mut self) -> Result<()> {
+        self.inner.init()
+    }
+
+    fn inject(&mut self, event: InputEvent) -> Result<()> {
+        let event = match (event, &self.transform) {
+            (InputEvent::MouseMove { x, y }, Some(transform)) => {
+                let (x, y) = transform.map(x, y);
+                InputEvent::MouseMove { x, y }
+            }
+            (InputEvent::MouseWarp { x, y }, Some(transform)) => {
+                let (x, y) = transform.map(x, y);
+                InputEvent::MouseWarp { x, y }
+            }
+            (event, _) => event,
+        };
+        self.inner.inject(event)
+    }
+
+    fn cleanup(&mut self) -> Result<()> {
+        self.inner.cleanup()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn display(width: u32, height: u32) -> HostDisplay {
+        HostDisplay {
+            x: 0,
+            y: 0,
+            width,
+            height,
+            scale_factor: 1.0,
+        }
+    }
+
+    #[test]
+    fn test_same_aspect_corners_and_center() {
+        // 1080p view of a 4K host
+        let down = CoordinateTransform::new(1920, 1080, display(3840, 2160));
+        assert_eq!(down.map(0, 0), (0, 0));
+        assert_eq!(down.map(960, 540), (1920, 1080));
+        assert_eq!(down.map(1919, 1079), (3838, 2158));
+
+        // 1080p view of a 720p host
+        let up = CoordinateTransform::new(1920, 1080, display(1280, 720));
+        assert_eq!(up.map(0, 0), (0, 0));
+        assert_eq!(up.map(960, 540), (640, 360));
+        assert_eq!(up.map(1919, 1079), (1279, 719));
+
+        // Identity
+        let same = CoordinateTransform::new(1280, 720, display(1280, 720));
+        assert_eq!(same.map(1279, 719), (1279, 719));
+    }
+
+    #[test]
+    fn test_mismatched_aspect_letterboxes() {
+        // 16:9 host in a square view: bars above and below
+        let t = CoordinateTransform::new(1000, 1000, display(1920, 1080));
+        assert_eq!(t.map(500, 500), (960, 540));
+        assert_eq!(t.map(0, 0), (0, 0));
+        assert_eq!(t.map(999, 999), (1918, 1079));
+
+        // 4:3 host in a 16:9 view: bars left and right
+        let t = CoordinateTransform::new(1920, 1080, display(1024, 768));
+        assert_eq!(t.map(960, 540), (512, 384));
+        assert_eq!(t.map(0, 540), (0, 384));
+        assert_eq!(t.map(1919, 0), (1023, 0));
+    }
+
+    #[test]
+    fn test_out_of_view_positions_clamp() {
+        let t = CoordinateTransform::new(800, 600, display(1600, 1200));
+        assert_eq!(t.map(-50, -50), (0, 0));
+        assert_eq!(t.map(5000, 5000), (1599, 1199));
+    }
+
+    #[test]
+    fn test_monitor_origin_and_dpi_scale() {
+        // Secondary monitor left of the primary
+        let left = HostDisplay {
+            x: -1920,
+            ..display(1920, 1080)
+        };
+        let t = CoordinateTransform::new(960, 540, left);
+        assert_eq!(t.map(0, 0), (-1920, 0));
+        assert_eq!(t.map(480, 270), (-960, 540));
+
+        // Retina panel captured at 2880x1800, injected in 1440x900 points
+        let retina = HostDisplay {
+            scale_factor: 2.0,
+            ..display(2880, 1800)
+        };
+        let t = CoordinateTransform::new(1440, 900, retina);
+        assert_eq!(t.map(720, 450), (720, 450));
+        assert_eq!(t.map(1439, 899), (1439, 899));
+    }
+}
@@ -0,0 +1,168 @@
+//! Input events on the wire
+//!
+//! `ProtocolMessage::InputEvent` carries the event kind and a payload of
+//! little-endian fields: an `u32` key code for keys, two `i32`s for pointer
+//! positions, deltas and scrolling, one byte for a mouse button and UTF-8
+//! for typed text.
+
+use crate::{InputEvent, KeyCode, MouseButton};
+use ada_remote_core::{Error, InputEventType, ProtocolMessage, Result};
+
+impl InputEvent {
+    /// Protocol message carrying this event
+    pub fn to_message(&self) -> ProtocolMessage {
+        let (event_type, data) = match self {
+            InputEvent::KeyPress { key } => {
+                (InputEventType::KeyPress, key.0.to_le_bytes().to_vec())
+            }
+            InputEvent::KeyRelease { key } => {
+                (InputEventType::KeyRelease, key.0.to_le_bytes().to_vec())
+            }
+            InputEvent::MouseMove { x, y } => (InputEventType::MouseMove, pair(*x, *y)),
+            InputEvent::MouseMoveRelative { dx, dy } => {
+                (InputEventType::MouseMoveRelative, pair(*dx, *dy))
+            }
+            InputEvent::MouseWarp { x, y } => (InputEventType::MouseWarp, pair(*x, *y)),
+            InputEvent::MouseButtonPress { button } => {
+                (InputEventType::MouseButtonPress, vec![button_code(*button)])
+            }
+            InputEvent::MouseButtonRelease { button } => (
+                InputEventType::MouseButtonRelease,
+                vec![button_code(*button)],
+            ),
+            InputEvent::MouseScroll { delta_x, delta_y } => {
+                (InputEventType::MouseScroll, pair(*delta_x, *delta_y))
+            }
+            InputEvent::TypeText { text } => (InputEventType::TypeText, text.as_bytes().to_vec()),
+        };
+        ProtocolMessage::InputEvent { event_type, data }
+    }
+
+    /// Decode the payload of an `InputEvent` message
+    pub fn from_message(event_type: InputEventType, data: &[u8]) -> Result<Self> {
+        Ok(match event_type {
+            InputEventType::KeyPress => InputEvent::KeyPress {
+                key: KeyCode(u32::from_le_bytes(fixed(event_type, data)?)),
+            },
+            InputEventType::KeyRelease => InputEvent::KeyRelease {
+                key: KeyCode(u32::from_le_bytes(fixed(event_type, data)?)),
+            },
+            InputEventType::MouseMove => {
+                let (x, y) = unpair(event_type, data)?;
+                InputEvent::MouseMove { x, y }
+            }
+            InputEventType::MouseMoveRelative => {
+                let (dx, dy) = unpair(event_type, data)?;
+                InputEvent::MouseMoveRelative { dx, dy }
+            }
+            InputEventType::MouseWarp => {
+                let (x, y) = unpair(event_type, data)?;
+                InputEvent::MouseWarp { x, y }
+            }
+            InputEventType::MouseButtonPress => InputEvent::MouseButtonPress {
+                button: button(event_type, data)?,
+            },
+            InputEventType::MouseButtonRelease => InputEvent::MouseButtonRelease {
+                button: button(event_type, data)?,
+            },
+            InputEventType::MouseScroll => {
+                let (delta_x, delta_y) = unpair(event_type, data)?;
+                InputEvent::MouseScroll { delta_x, delta_y }
+            }
+            InputEventType::TypeText => InputEvent::TypeText {
+                text: String::from_utf8(data.to_vec())
+                    .map_err(|_| Error::Decoding("Typed text is not valid UTF-8".to_string()))?,
+            },
+        })
+    }
+}
+
+const BUTTONS: [MouseButton; 5] = [
+    MouseButton::Left,
+    MouseButton::Right,
+    MouseButton::Middle,
+    MouseButton::X1,
+    MouseButton::X2,
+];
+
+fn button_code(button: MouseButton) -> u8 {
+    BUTTONS
+        .iter()
+        .position(|b| *b == button)
+        .unwrap_or_default() as u8
+}
+
+fn button(event_type: InputEventType, data: &[u8]) -> Result<MouseButton> {
+    let [code] = fixed(event_type, data)?;
+    BUTTONS
+        .get(code as usize)
+        .copied()
+        .ok_or_else(|| Error::Decoding(format!("Unknown mouse button {}", code)))
+}
+
+fn pair(a: i32, b: i32) -> Vec<u8> {
+    [a.to_le_bytes(), b.to_le_bytes()].concat()
+}
+
+fn unpair(event_type: InputEventType, data: &[u8]) -> Result<(i32, i32)> {
+    let bytes: [u8; 8] = fixed(event_type, data)?;
+    let (a, b) = bytes.split_at(4);
+    Ok((
+        i32::from_le_bytes(a.try_into().unwrap()),
+        i32::from_le_bytes(b.try_into().unwrap()),
+    ))
+}
+
+fn fixed<const N: usize>(event_type: InputEventType, data: &[u8]) -> Result<[u8; N]> {
+    data.try_into().map_err(|_| {
+        Error::Decoding(format!(
+            "{:?} payload is {} bytes, expected {}",
+            event_type,
+            data.len(),
+            N
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_events_round_trip() {
+        let events = [
+            InputEvent::KeyPress { key: KeyCode(0x41) },
+            InputEvent::KeyRelease { key: KeyCode(0x41) },
+            InputEvent::MouseMove { x: 640, y: -20 },
+            InputEvent::MouseMoveRelative { dx: -3, dy: 7 },
+            InputEvent::MouseWarp { x: 1, y: 2 },
+            InputEvent::MouseButtonPress {
+                button: MouseButton::X2,
+            },
+            InputEvent::MouseButtonRelease {
+                button: MouseButton::Middle,
+            },
+            InputEvent::MouseScroll {
+                delta_x: 0,
+                delta_y: -2,
+            },
+            InputEvent::TypeText {
+                text: "héllo".to_string(),
+            },
+        ];
+        for event in events {
+            let ProtocolMessage::InputEvent { event_type, data } = event.to_message() else {
+                panic!("expected an input event");
+            };
+            let decoded = InputEvent::from_message(event_type, &data).unwrap();
+            assert_eq!(format!("{:?}", decoded), format!("{:?}", event));
+        }
+    }
+
+    #[test]
+    fn test_malformed_payload_rejected() {
+        assert!(InputEvent::from_message(InputEventType::MouseMove, &[0; 4]).is_err());
+        assert!(InputEvent::from_message(InputEventType::MouseButtonPress, &[9]).is_err());
+        assert!(InputEvent::from_message(InputEventType::TypeText, &[0xff]).is_err());
+    }
+}
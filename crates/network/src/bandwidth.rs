@@ -0,0 +1,104 @@
+//! Bandwidth accounting
+//!
+//! [`BandwidthMeter`] counts the bytes a connection sends and receives over
+//! a sliding window, which is what a send cap is enforced against: with
+//! [`NetworkConfig::max_send_bitrate`](crate::NetworkConfig::max_send_bitrate)
+//! set, video frames that would push the window over the cap are dropped
+//! before reaching the transport. Keyframes, input and control messages are
+//! always sent, so they may exceed the cap briefly.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Span the meter averages over
+pub const BANDWIDTH_WINDOW: Duration = Duration::from_secs(1);
+
+/// Bytes moved in one direction within the window
+#[derive(Debug, Default)]
+struct Direction {
+    samples: VecDeque<(Instant, usize)>,
+    bytes: usize,
+}
+
+impl Direction {
+    fn record(&mut self, bytes: usize, now: Instant) {
+        self.expire(now);
+        self.samples.push_back((now, bytes));
+        self.bytes += bytes;
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while let Some(&(at, bytes)) = self.samples.front() {
+            if now.saturating_duration_since(at) < BANDWIDTH_WINDOW {
+                break;
+            }
+            self.samples.pop_front();
+            self.bytes -= bytes;
+        }
+    }
+
+    fn kbps(&mut self, now: Instant) -> u64 {
+        self.expire(now);
+        (self.bytes as f64 * 8.0 / BANDWIDTH_WINDOW.as_secs_f64() / 1000.0) as u64
+    }
+}
+
+/// Sent and received rate of a connection over the last [`BANDWIDTH_WINDOW`]
+#[derive(Debug, Default)]
+pub struct BandwidthMeter {
+    sent: Direction,
+    received: Direction,
+}
+
+impl BandwidthMeter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record_sent(&mut self, bytes: usize, now: Instant) {
+        self.sent.record(bytes, now);
+    }
+
+    pub fn record_received(&mut self, bytes: usize, now: Instant) {
+        self.received.record(bytes, now);
+    }
+
+    /// Outgoing rate over the window ending at `now`
+    pub fn send_kbps(&mut self, now: Instant) -> u64 {
+        self.sent.kbps(now)
+    }
+
+    /// Incoming rate over the window ending at `now`
+    pub fn receive_kbps(&mut self, now: Instant) -> u64 {
+        self.received.kbps(now)
+    }
+
+    /// Whether sending `bytes` at `now` keeps the window within `cap_kbps`
+    pub fn allows(&mut self, bytes: usize, cap_kbps: u32, now: Instant) -> bool {
+        self.sent.expire(now);
+        let budget = cap_kbps as f64 * 1000.0 / 8.0 * BANDWIDTH_WINDOW.as_secs_f64();
+        (self.sent.bytes + bytes) as f64 <= budget
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_window_slides() {
+        let mut meter = BandwidthMeter::new();
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        meter.record_sent(5_000, at(0));
+        meter.record_sent(5_000, at(500));
+        meter.record_received(1_000, at(500));
+        assert_eq!(meter.send_kbps(at(900)), 80);
+        assert_eq!(meter.receive_kbps(at(900)), 8);
+        // 80 kbps is 10 kB per second
+        assert!(!meter.allows(1, 80, at(900)));
+        assert!(meter.allows(5_000, 80, at(1_000)));
+        assert_eq!(meter.send_kbps(at(1_500)), 0);
+    }
+}
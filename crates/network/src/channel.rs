@@ -0,0 +1,48 @@
+//! Data channel selection
+//!
+//! Each kind of traffic gets its own WebRTC data channel so a retransmitted
+//! file chunk cannot hold up input, and lost video frames are not resent.
+
+use ada_remote_core::ProtocolMessage;
+
+/// Data channel a message travels on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Channel {
+    /// Video and audio frames; unordered and never retransmitted, since a
+    /// late frame is useless
+    Video,
+    /// Input events; reliable and ordered
+    Input,
+    /// Session, clipboard and file transfer messages; reliable and ordered
+    Control,
+}
+
+impl Channel {
+    /// Every channel, in the order they are created
+    pub const ALL: [Channel; 3] = [Channel::Control, Channel::Input, Channel::Video];
+
+    /// Data channel label
+    pub fn label(self) -> &'static str {
+        match self {
+            Channel::Video => "video",
+            Channel::Input => "input",
+            Channel::Control => "control",
+        }
+    }
+
+    /// Whether the channel retransmits lost messages
+    pub fn is_reliable(self) -> bool {
+        !matches!(self, Channel::Video)
+    }
+
+    /// Channel a message is sent on by default
+    pub fn for_message(message: &ProtocolMessage) -> Self {
+        match message {
+            ProtocolMessage::VideoFrame { .. } | ProtocolMessage::AudioFrame { .. } => {
+                Channel::Video
+            }
+            ProtocolMessage::InputEvent { .. } => Channel::Input,
+            _ => Channel::Control,
+        }
+    }
+}
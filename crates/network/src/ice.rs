@@ -0,0 +1,99 @@
+//! TURN credential refresh
+//!
+//! TURN servers commonly hand out time-limited credentials, which would run
+//! out during a long session. A peer's TURN servers can be replaced at any
+//! time, or fetched again from a [`TurnCredentials`] source when they are
+//! about to expire. Either applies from the next ICE gathering, which every
+//! reconnect starts afresh.
+
+use crate::transport::Link;
+use crate::{NetworkConfig, TurnServer};
+use ada_remote_core::Result;
+use ada_remote_crypto::unix_now;
+use async_trait::async_trait;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Credentials expiring sooner than this are fetched again before use
+pub const TURN_REFRESH_MARGIN: Duration = Duration::from_secs(60);
+
+/// Source of fresh TURN credentials, e.g. a REST endpoint issuing
+/// time-limited ones
+#[async_trait]
+pub trait TurnCredentials: Send + Sync {
+    async fn fetch(&self) -> Result<Vec<TurnServer>>;
+}
+
+/// TURN servers of a peer, shared with its connection task
+#[derive(Default)]
+pub(crate) struct TurnState {
+    /// Replaces the configured servers once set
+    pub(crate) servers: Option<Vec<TurnServer>>,
+    pub(crate) source: Option<Arc<dyn TurnCredentials>>,
+}
+
+/// Whether any of `servers` expires within `margin` of `now` (Unix seconds)
+fn expiring(servers: &[TurnServer], now: u64, margin: Duration) -> bool {
+    servers
+        .iter()
+        .filter_map(|server| server.expires_at)
+        .any(|expires_at| expires_at <= now.saturating_add(margin.as_secs()))
+}
+
+/// `config` with the TURN servers for the next ICE gathering: the latest
+/// set on the peer, fetched again from its source first when missing or
+/// about to expire
+pub(crate) async fn current_config(link: &Link, config: &NetworkConfig) -> NetworkConfig {
+    let (servers, source) = {
+        let turn = link.turn.lock().unwrap();
+        let servers = turn.servers.as_ref().unwrap_or(&config.turn_servers);
+        (servers.clone(), turn.source.clone())
+    };
+
+    let stale = servers.is_empty() || expiring(&servers, unix_now(), TURN_REFRESH_MARGIN);
+    let servers = match source {
+        Some(source) if stale => match source.fetch().await {
+            Ok(fresh) => {
+                tracing::info!("Refreshed {} TURN servers", fresh.len());
+                link.turn.lock().unwrap().servers = Some(fresh.clone());
+                fresh
+            }
+            Err(e) => {
+                tracing::warn!("TURN credential refresh failed: {}", e);
+                servers
+            }
+        },
+        _ => servers,
+    };
+
+    NetworkConfig {
+        turn_servers: servers,
+        ..config.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server(expires_at: Option<u64>) -> TurnServer {
+        TurnServer {
+            url: "turn:turn.example.com:3478".to_string(),
+            username: "user".to_string(),
+            credential: "secret".to_string(),
+            expires_at,
+        }
+    }
+
+    #[test]
+    fn test_expiring_within_margin() {
+        let margin = Duration::from_secs(60);
+        assert!(!expiring(&[server(None)], 1_000, margin));
+        assert!(!expiring(&[server(Some(1_061))], 1_000, margin));
+        assert!(expiring(
+            &[server(None), server(Some(1_060))],
+            1_000,
+            margin
+        ));
+    }
+}
@@ -1,24 +1,45 @@
 //! Ada Remote Networking
 //!
 //! Network layer supporting WebRTC and QUIC protocols for peer-to-peer
-//! remote desktop connections with NAT traversal.
+//! remote desktop connections with NAT traversal. Other transports plug in
+//! through a [`TransportBackend`].
 
-use ada_remote_core::{ProtocolMessage, Result, SessionId};
+pub use ada_remote_core::ConnectionType;
+use ada_remote_core::{Compression, Error, ProtocolMessage, Result, SessionId};
 use serde::{Deserialize, Serialize};
-use std::net::SocketAddr;
-use tokio::sync::mpsc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
 
+mod bandwidth;
+mod channel;
+mod ice;
+pub mod loopback;
+mod ping;
+mod queue;
+pub mod quic;
+mod reconnect;
 pub mod signaling;
+mod stats;
+mod transport;
 pub mod webrtc;
 
-/// Connection type
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum ConnectionType {
-    /// WebRTC data channel (preferred)
-    WebRTC,
-    /// QUIC fallback
-    QUIC,
-}
+pub use bandwidth::{BandwidthMeter, BANDWIDTH_WINDOW};
+pub use channel::Channel;
+pub use ice::{TurnCredentials, TURN_REFRESH_MARGIN};
+pub use ping::{pong_for, RttEstimator};
+use queue::MessageQueue;
+pub use queue::DEFAULT_QUEUE_CAPACITY;
+pub use reconnect::ReconnectPolicy;
+pub use stats::{ConnectionStats, TransportSample, STATS_INTERVAL};
+use transport::{Link, Outgoing};
+pub use transport::{Role, Transport, TransportBackend};
+
+/// How long `disconnect` waits for the `Disconnect` message to go out and
+/// the transport to close cleanly
+const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(1);
 
 /// Network configuration
 #[derive(Debug, Clone)]
@@ -31,6 +52,33 @@ pub struct NetworkConfig {
     pub turn_servers: Vec<TurnServer>,
     /// Enable QUIC fallback
     pub enable_quic_fallback: bool,
+    /// QUIC relay address ("host:port") used for the fallback
+    pub relay_server: String,
+    /// DER certificate to trust for the relay instead of the system roots
+    pub relay_certificate: Option<Vec<u8>>,
+    /// How long to wait for WebRTC before falling back to QUIC
+    pub webrtc_timeout: Duration,
+    /// Maximum number of buffered incoming messages
+    pub receive_queue_capacity: usize,
+    /// Maximum number of outgoing messages waiting for the transport
+    pub send_queue_capacity: usize,
+    /// How a dropped connection is re-established
+    pub reconnect: ReconnectPolicy,
+    /// How often a heartbeat is sent on an established connection
+    pub heartbeat_interval: Duration,
+    /// How long the peer may stay silent before the connection counts as
+    /// lost
+    pub heartbeat_timeout: Duration,
+    /// Secret for signing registrations, for signaling servers that only let
+    /// known hosts register
+    pub registration_secret: Option<String>,
+    /// Cap on the outgoing rate in kbps, e.g. for metered links or a shared
+    /// relay. Video frames other than keyframes are dropped to stay under it;
+    /// input and control messages are never held back.
+    pub max_send_bitrate: Option<u32>,
+    /// Highest temporal layer of video sent. Frames above it are dropped,
+    /// lowering the frame rate without re-encoding; `None` sends all.
+    pub max_temporal_layer: Option<u8>,
 }
 
 impl Default for NetworkConfig {
@@ -43,16 +91,30 @@ impl Default for NetworkConfig {
             ],
             turn_servers: vec![],
             enable_quic_fallback: true,
+            relay_server: "relay.ada-remote.io:4433".to_string(),
+            relay_certificate: None,
+            webrtc_timeout: Duration::from_secs(10),
+            receive_queue_capacity: DEFAULT_QUEUE_CAPACITY,
+            send_queue_capacity: DEFAULT_QUEUE_CAPACITY,
+            reconnect: ReconnectPolicy::default(),
+            heartbeat_interval: Duration::from_secs(2),
+            heartbeat_timeout: Duration::from_secs(10),
+            registration_secret: None,
+            max_send_bitrate: None,
+            max_temporal_layer: None,
         }
     }
 }
 
 /// TURN server configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TurnServer {
     pub url: String,
     pub username: String,
     pub credential: String,
+    /// Unix seconds at which a time-limited credential runs out
+    #[serde(default)]
+    pub expires_at: Option<u64>,
 }
 
 /// Connection state
@@ -68,22 +130,45 @@ pub enum ConnectionState {
 pub struct NetworkPeer {
     session_id: SessionId,
     connection_type: ConnectionType,
-    state: ConnectionState,
-    message_tx: mpsc::UnboundedSender<ProtocolMessage>,
-    message_rx: mpsc::UnboundedReceiver<ProtocolMessage>,
+    relayed: bool,
+    link: Arc<Link>,
+    backend: Option<Arc<dyn TransportBackend>>,
+    outgoing: Option<Arc<MessageQueue<Outgoing>>>,
+    task: Option<JoinHandle<()>>,
 }
 
 impl NetworkPeer {
     /// Create a new network peer
     pub fn new(session_id: SessionId, connection_type: ConnectionType) -> Self {
-        let (message_tx, message_rx) = mpsc::unbounded_channel();
+        Self::with_capacity(session_id, connection_type, DEFAULT_QUEUE_CAPACITY)
+    }
 
+    /// Create a new network peer with a bounded receive queue.
+    ///
+    /// When the queue is full the oldest video frames other than keyframes
+    /// are dropped; keyframes, control and input messages are always
+    /// delivered.
+    pub fn with_capacity(
+        session_id: SessionId,
+        connection_type: ConnectionType,
+        capacity: usize,
+    ) -> Self {
         Self {
             session_id,
             connection_type,
-            state: ConnectionState::Disconnected,
-            message_tx,
-            message_rx,
+            relayed: false,
+            link: Arc::new(Link {
+                state: watch::channel(ConnectionState::Disconnected).0,
+                reason: watch::channel(None).0,
+                turn: Mutex::default(),
+                messages: MessageQueue::new(capacity),
+                stats: Mutex::default(),
+                throttled_frames: AtomicU64::new(0),
+                compression: Mutex::default(),
+            }),
+            backend: None,
+            outgoing: None,
+            task: None,
         }
     }
 
@@ -97,55 +182,278 @@ impl NetworkPeer {
         self.connection_type
     }
 
+    /// Whether traffic is going through a TURN relay
+    pub fn is_relayed(&self) -> bool {
+        self.relayed
+    }
+
     /// Get the connection state
+    ///
+    /// While reconnecting this alternates between `Failed` and `Connecting`.
     pub fn state(&self) -> ConnectionState {
-        self.state
+        *self.link.state.borrow()
+    }
+
+    /// Subscribe to connection state changes
+    pub fn state_changes(&self) -> watch::Receiver<ConnectionState> {
+        self.link.state.subscribe()
+    }
+
+    /// Reason the connection was ended with, by either side, once it has
+    pub fn disconnect_reason(&self) -> Option<String> {
+        self.link.reason.borrow().clone()
+    }
+
+    /// Subscribe to the reason the connection is ended with
+    pub fn disconnect_reasons(&self) -> watch::Receiver<Option<String>> {
+        self.link.reason.subscribe()
+    }
+
+    /// Record a change of the active transport path and notify the consumer
+    /// with a `TransportChanged` message
+    pub fn update_transport(&mut self, connection_type: ConnectionType, relayed: bool) {
+        if connection_type == self.connection_type && relayed == self.relayed {
+            return;
+        }
+
+        tracing::warn!(
+            "Transport changed from {:?}{} to {:?}{}",
+            self.connection_type,
+            if self.relayed { " (relayed)" } else { "" },
+            connection_type,
+            if relayed { " (relayed)" } else { "" },
+        );
+        self.connection_type = connection_type;
+        self.relayed = relayed;
+        self.link.messages.push(ProtocolMessage::TransportChanged {
+            connection_type,
+            relayed,
+        });
+    }
+
+    /// Live transport metrics, sampled every [`STATS_INTERVAL`]
+    ///
+    /// Returns the defaults unless connected.
+    pub fn stats(&self) -> ConnectionStats {
+        match self.state() {
+            ConnectionState::Connected => *self.link.stats.lock().unwrap(),
+            _ => ConnectionStats::default(),
+        }
+    }
+
+    /// Number of video frames dropped because the receive queue was full
+    pub fn dropped_frames(&self) -> u64 {
+        self.link.messages.dropped_frames()
+    }
+
+    /// Number of video frames dropped since connecting because the send
+    /// queue was full
+    pub fn dropped_outgoing_frames(&self) -> u64 {
+        self.outgoing
+            .as_ref()
+            .map_or(0, |outgoing| outgoing.dropped_frames())
+    }
+
+    /// Number of video frames dropped to stay under
+    /// [`max_send_bitrate`](NetworkConfig::max_send_bitrate) or
+    /// [`max_temporal_layer`](NetworkConfig::max_temporal_layer)
+    pub fn throttled_frames(&self) -> u64 {
+        self.link.throttled_frames.load(Ordering::Relaxed)
+    }
+
+    /// Compress large frames from now on, once the peer's `Hello` shows it
+    /// can decompress them
+    pub fn set_compression(&self, compression: Compression) {
+        *self.link.compression.lock().unwrap() = compression;
+    }
+
+    /// Connect through `backend` instead of WebRTC and QUIC, e.g. over an
+    /// existing tunnel or in-process
+    ///
+    /// The connection type is kept as reported and no fallback happens.
+    pub fn set_backend(&mut self, backend: Arc<dyn TransportBackend>) {
+        self.backend = Some(backend);
+    }
+
+    /// Use `servers` instead of the configured TURN servers from the next
+    /// ICE gathering on, e.g. with credentials issued for the next hours
+    ///
+    /// A connection that is up keeps its relay; reconnects gather anew.
+    pub fn set_turn_servers(&self, servers: Vec<TurnServer>) {
+        self.link.turn.lock().unwrap().servers = Some(servers);
+    }
+
+    /// TURN servers set with [`set_turn_servers`](Self::set_turn_servers)
+    /// or last fetched from the credential source
+    pub fn turn_servers(&self) -> Option<Vec<TurnServer>> {
+        self.link.turn.lock().unwrap().servers.clone()
     }
 
-    /// Send a protocol message
+    /// Fetch TURN servers from `source` before ICE gathering whenever there
+    /// are none or they expire within [`TURN_REFRESH_MARGIN`]
+    pub fn set_turn_credentials(&self, source: Arc<dyn TurnCredentials>) {
+        self.link.turn.lock().unwrap().source = Some(source);
+    }
+
+    /// Send a protocol message on the channel suited to its type
     pub fn send(&self, message: ProtocolMessage) -> Result<()> {
-        self.message_tx
-            .send(message)
-            .map_err(|e| ada_remote_core::Error::Network(format!("Failed to send message: {}", e)))
+        self.send_on(Channel::for_message(&message), message)
+    }
+
+    /// Send a protocol message on a specific channel
+    ///
+    /// The QUIC transport has a single stream and ignores the channel.
+    /// While reconnecting, messages are held and sent once connected again.
+    ///
+    /// Never blocks: when the transport falls behind and the send queue is
+    /// full, the oldest video frame that is not a keyframe is dropped.
+    pub fn send_on(&self, channel: Channel, message: ProtocolMessage) -> Result<()> {
+        match &self.outgoing {
+            Some(outgoing) if outgoing.is_closed() => {
+                Err(Error::ConnectionClosed("Transport closed".to_string()))
+            }
+            Some(outgoing) => {
+                outgoing.push((channel, message));
+                Ok(())
+            }
+            None => {
+                self.link.messages.push(message);
+                Ok(())
+            }
+        }
     }
 
     /// Receive a protocol message
     pub async fn receive(&mut self) -> Option<ProtocolMessage> {
-        self.message_rx.recv().await
+        self.link.messages.pop().await
     }
 
     /// Connect to a remote peer
-    pub async fn connect(&mut self, _config: &NetworkConfig) -> Result<()> {
-        self.state = ConnectionState::Connecting;
+    ///
+    /// A WebRTC attempt that fails or exceeds `webrtc_timeout` falls back
+    /// to QUIC through the relay when `enable_quic_fallback` is set.
+    pub async fn connect(&mut self, config: &NetworkConfig) -> Result<()> {
         tracing::info!("Connecting to peer via {:?}", self.connection_type);
+        self.start(Role::Client, config).await
+    }
+
+    /// Wait for a client to join this session and connect to it
+    ///
+    /// Registers the session with the signaling server, answers the
+    /// client's offer and returns once the data channels are open.
+    pub async fn accept(&mut self, config: &NetworkConfig) -> Result<()> {
+        self.start(Role::Host, config).await
+    }
 
-        // TODO: Implement actual connection logic
-        // 1. Connect to signaling server
-        // 2. Exchange SDP offers/answers for WebRTC
-        // 3. Establish ICE candidates
-        // 4. Set up data channels
+    async fn start(&mut self, role: Role, config: &NetworkConfig) -> Result<()> {
+        self.link.state.send_replace(ConnectionState::Connecting);
+        self.link.reason.send_replace(None);
+        *self.link.stats.lock().unwrap() = ConnectionStats::default();
+
+        let config = &ice::current_config(&self.link, config).await;
+        let established = match &self.backend {
+            Some(backend) => backend
+                .connect(role, self.session_id, config)
+                .await
+                .map(|transport| (transport, self.connection_type)),
+            None => {
+                transport::establish(
+                    role,
+                    self.connection_type,
+                    self.session_id,
+                    config,
+                    config.enable_quic_fallback,
+                )
+                .await
+            }
+        };
+        let (transport, connection_type) = match established {
+            Ok(established) => established,
+            Err(e) => {
+                self.link.state.send_replace(ConnectionState::Failed);
+                return Err(e);
+            }
+        };
+        if connection_type != self.connection_type {
+            self.update_transport(connection_type, true);
+        }
 
-        self.state = ConnectionState::Connected;
+        let backend = match &self.backend {
+            Some(backend) => backend.clone(),
+            None => transport::builtin(connection_type),
+        };
+        let outgoing = Arc::new(MessageQueue::new(config.send_queue_capacity));
+        let supervised = transport::supervise(
+            transport,
+            backend,
+            outgoing.clone(),
+            self.link.clone(),
+            role,
+            self.session_id,
+            config.clone(),
+        );
+        let queue = outgoing.clone();
+        self.task = Some(tokio::spawn(async move {
+            supervised.await;
+            // Sends fail once the connection is given up
+            queue.close();
+        }));
+        self.outgoing = Some(outgoing);
+        self.link.state.send_replace(ConnectionState::Connected);
         Ok(())
     }
 
     /// Disconnect from the peer
     pub async fn disconnect(&mut self) -> Result<()> {
-        tracing::info!("Disconnecting from peer");
-        self.state = ConnectionState::Disconnected;
+        self.disconnect_with("Peer disconnected").await
+    }
+
+    /// Disconnect from the peer, telling it `reason`
+    ///
+    /// Messages already queued go out first, followed by a `Disconnect`,
+    /// before the transport is closed.
+    pub async fn disconnect_with(&mut self, reason: &str) -> Result<()> {
+        tracing::info!("Disconnecting from peer: {}", reason);
+        // The connection task closes the transport once the queue drains
+        if let Some(outgoing) = self.outgoing.take() {
+            outgoing.push((
+                Channel::Control,
+                ProtocolMessage::Disconnect {
+                    reason: reason.to_string(),
+                },
+            ));
+            outgoing.close();
+        }
+        if let Some(mut task) = self.task.take() {
+            if tokio::time::timeout(DISCONNECT_TIMEOUT, &mut task)
+                .await
+                .is_err()
+            {
+                task.abort();
+            }
+        }
+        // Unless the other side ended the connection first
+        self.link.reason.send_if_modified(|current| {
+            let unset = current.is_none();
+            if unset {
+                *current = Some(reason.to_string());
+            }
+            unset
+        });
+        self.link.state.send_replace(ConnectionState::Disconnected);
         Ok(())
     }
 }
 
 /// Create a new host peer (waiting for incoming connection)
-pub async fn create_host(config: NetworkConfig) -> Result<NetworkPeer> {
+pub async fn create_host(_config: NetworkConfig) -> Result<NetworkPeer> {
     let session_id = SessionId::new();
     tracing::info!("Creating host with session ID: {}", session_id);
 
-    let mut peer = NetworkPeer::new(session_id, ConnectionType::WebRTC);
-    peer.state = ConnectionState::Connecting;
+    let peer = NetworkPeer::new(session_id, ConnectionType::WebRTC);
+    peer.link.state.send_replace(ConnectionState::Connecting);
 
-    // TODO: Register with signaling server and wait for connection
+    // The caller shows the session ID, then waits for a client with `accept`
 
     Ok(peer)
 }
@@ -154,7 +462,11 @@ pub async fn create_host(config: NetworkConfig) -> Result<NetworkPeer> {
 pub async fn create_client(session_id: SessionId, config: NetworkConfig) -> Result<NetworkPeer> {
     tracing::info!("Creating client for session ID: {}", session_id);
 
-    let mut peer = NetworkPeer::new(session_id, ConnectionType::WebRTC);
+    let mut peer = NetworkPeer::with_capacity(
+        session_id,
+        ConnectionType::WebRTC,
+        config.receive_queue_capacity,
+    );
     peer.connect(&config).await?;
 
     Ok(peer)
@@ -163,6 +475,8 @@ pub async fn create_client(session_id: SessionId, config: NetworkConfig) -> Resu
 #[cfg(test)]
 mod tests {
     use super::*;
+    use signaling::SignalingMessage;
+    use tokio::sync::mpsc;
 
     #[test]
     fn test_network_config_default() {
@@ -178,4 +492,713 @@ mod tests {
         assert_eq!(peer.state(), ConnectionState::Disconnected);
         assert_eq!(peer.connection_type(), ConnectionType::WebRTC);
     }
+
+    /// QUIC listener that echoes every message back, returning its address
+    /// and certificate. The first `drop` connections are closed on accept.
+    fn spawn_quic_echo(drop: usize) -> (String, Vec<u8>) {
+        spawn_quic_stub(drop, true)
+    }
+
+    /// QUIC listener that reads every message and, if `echo` is set, sends
+    /// it back
+    fn spawn_quic_stub(drop: usize, echo: bool) -> (String, Vec<u8>) {
+        let cert = rcgen::generate_simple_self_signed(vec!["127.0.0.1".to_string()]).unwrap();
+        let cert_der = cert.serialize_der().unwrap();
+        let server_config = quinn::ServerConfig::with_single_cert(
+            vec![rustls::Certificate(cert_der.clone())],
+            rustls::PrivateKey(cert.serialize_private_key_der()),
+        )
+        .unwrap();
+        let endpoint =
+            quinn::Endpoint::server(server_config, "127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = endpoint.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            for _ in 0..drop {
+                let connection = endpoint.accept().await.unwrap().await.unwrap();
+                connection.close(0u32.into(), b"dropped");
+            }
+            let connection = endpoint.accept().await.unwrap().await.unwrap();
+            let (mut send, mut recv) = connection.accept_bi().await.unwrap();
+            while let Ok(Some(message)) = quic::read_message(&mut recv).await {
+                if echo {
+                    quic::write_message(&mut send, &message, Compression::Deflate)
+                        .await
+                        .unwrap();
+                }
+            }
+        });
+
+        (addr.to_string(), cert_der)
+    }
+
+    #[tokio::test]
+    async fn test_quic_fallback_when_webrtc_unavailable() {
+        let (relay_server, cert) = spawn_quic_echo(0);
+        let config = NetworkConfig {
+            // Nothing listens here, so the WebRTC attempt fails immediately
+            signaling_server: "ws://127.0.0.1:1".to_string(),
+            relay_server,
+            relay_certificate: Some(cert),
+            ..Default::default()
+        };
+
+        let mut peer = NetworkPeer::new(SessionId::new(), ConnectionType::WebRTC);
+        peer.connect(&config).await.unwrap();
+        assert_eq!(peer.state(), ConnectionState::Connected);
+        assert_eq!(peer.connection_type(), ConnectionType::QUIC);
+        assert!(matches!(
+            peer.receive().await,
+            Some(ProtocolMessage::TransportChanged { .. })
+        ));
+
+        peer.send(ProtocolMessage::Clipboard {
+            content: "over quic".to_string(),
+        })
+        .unwrap();
+        match peer.receive().await {
+            Some(ProtocolMessage::Clipboard { content }) => assert_eq!(content, "over quic"),
+            other => panic!("unexpected message: {:?}", other),
+        }
+
+        // Compressed both ways once negotiated
+        let large = "over quic ".repeat(1000);
+        peer.set_compression(Compression::Deflate);
+        peer.send(ProtocolMessage::Clipboard {
+            content: large.clone(),
+        })
+        .unwrap();
+        match peer.receive().await {
+            Some(ProtocolMessage::Clipboard { content }) => assert_eq!(content, large),
+            other => panic!("unexpected message: {:?}", other),
+        }
+        peer.disconnect().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_flushes_held_messages() {
+        let (relay_server, cert) = spawn_quic_echo(1);
+        let config = NetworkConfig {
+            relay_server,
+            relay_certificate: Some(cert),
+            reconnect: ReconnectPolicy {
+                base_delay: Duration::from_millis(50),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let mut peer = NetworkPeer::new(SessionId::new(), ConnectionType::QUIC);
+        let mut states = peer.state_changes();
+        peer.connect(&config).await.unwrap();
+        assert_eq!(peer.state(), ConnectionState::Connected);
+
+        // The relay drops the first connection
+        states
+            .wait_for(|state| *state != ConnectionState::Connected)
+            .await
+            .unwrap();
+        peer.send(ProtocolMessage::Clipboard {
+            content: "during outage".to_string(),
+        })
+        .unwrap();
+
+        tokio::time::timeout(
+            Duration::from_secs(5),
+            states.wait_for(|state| *state == ConnectionState::Connected),
+        )
+        .await
+        .expect("peer did not reconnect")
+        .unwrap();
+        match peer.receive().await {
+            Some(ProtocolMessage::Clipboard { content }) => assert_eq!(content, "during outage"),
+            other => panic!("unexpected message: {:?}", other),
+        }
+        peer.disconnect().await.unwrap();
+        assert_eq!(peer.state(), ConnectionState::Disconnected);
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_timeout_detects_silent_peer() {
+        for echo in [true, false] {
+            let (relay_server, cert) = spawn_quic_stub(0, echo);
+            let config = NetworkConfig {
+                relay_server,
+                relay_certificate: Some(cert),
+                reconnect: ReconnectPolicy {
+                    max_attempts: 0,
+                    ..Default::default()
+                },
+                heartbeat_interval: Duration::from_millis(50),
+                heartbeat_timeout: Duration::from_millis(300),
+                ..Default::default()
+            };
+
+            let mut peer = NetworkPeer::new(SessionId::new(), ConnectionType::QUIC);
+            let mut states = peer.state_changes();
+            peer.connect(&config).await.unwrap();
+            let failed = tokio::time::timeout(
+                Duration::from_secs(1),
+                states.wait_for(|state| *state == ConnectionState::Failed),
+            )
+            .await
+            .is_ok();
+            // The echoed heartbeats keep the connection alive
+            assert_eq!(failed, !echo);
+            peer.disconnect().await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_no_fallback_when_disabled() {
+        let config = NetworkConfig {
+            signaling_server: "ws://127.0.0.1:1".to_string(),
+            enable_quic_fallback: false,
+            ..Default::default()
+        };
+
+        let mut peer = NetworkPeer::new(SessionId::new(), ConnectionType::WebRTC);
+        assert!(peer.connect(&config).await.is_err());
+        assert_eq!(peer.state(), ConnectionState::Failed);
+        assert_eq!(peer.connection_type(), ConnectionType::WebRTC);
+    }
+
+    /// Signaling stub that acknowledges Register/Join and forwards every
+    /// other message to the other connection, buffering until it arrives
+    async fn spawn_signaling_stub() -> String {
+        use futures::{SinkExt, StreamExt};
+        use std::sync::Mutex;
+        use tokio_tungstenite::tungstenite::Message;
+
+        #[derive(Default)]
+        struct Peers {
+            senders: Vec<mpsc::UnboundedSender<Message>>,
+            pending: Vec<Message>,
+        }
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let peers = Arc::new(Mutex::new(Peers::default()));
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let socket = tokio_tungstenite::accept_async(stream).await.unwrap();
+                let (mut sink, mut stream) = socket.split();
+                let (tx, mut rx) = mpsc::unbounded_channel();
+                let index = {
+                    let mut peers = peers.lock().unwrap();
+                    for message in peers.pending.drain(..) {
+                        tx.send(message).unwrap();
+                    }
+                    peers.senders.push(tx.clone());
+                    peers.senders.len() - 1
+                };
+
+                tokio::spawn(async move {
+                    while let Some(message) = rx.recv().await {
+                        if sink.send(message).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+
+                let peers = peers.clone();
+                tokio::spawn(async move {
+                    while let Some(Ok(message)) = stream.next().await {
+                        let Ok(text) = message.to_text() else {
+                            continue;
+                        };
+                        match serde_json::from_str(text) {
+                            Ok(
+                                SignalingMessage::Register { .. } | SignalingMessage::Join { .. },
+                            ) => {
+                                let ack = SignalingMessage::Success {
+                                    message: "ok".to_string(),
+                                };
+                                let _ =
+                                    tx.send(Message::Text(serde_json::to_string(&ack).unwrap()));
+                            }
+                            _ => {
+                                let mut peers = peers.lock().unwrap();
+                                match peers.senders.iter().enumerate().find(|(i, _)| *i != index) {
+                                    Some((_, other)) => {
+                                        let _ = other.send(message);
+                                    }
+                                    None => peers.pending.push(message),
+                                }
+                            }
+                        }
+                    }
+                });
+            }
+        });
+
+        format!("ws://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn test_signaling_handshake_connects_peers() {
+        let config = NetworkConfig {
+            signaling_server: spawn_signaling_stub().await,
+            // Host candidates only, so the test needs no network access
+            stun_servers: vec![],
+            enable_quic_fallback: false,
+            ..Default::default()
+        };
+
+        let mut host = create_host(config.clone()).await.unwrap();
+        let session_id = host.session_id();
+        let host_config = config.clone();
+        let host_task = tokio::spawn(async move {
+            host.accept(&host_config).await.unwrap();
+            host
+        });
+        // Let the host register before the client joins
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut client = create_client(session_id, config).await.unwrap();
+        let mut host = host_task.await.unwrap();
+        assert_eq!(client.state(), ConnectionState::Connected);
+        assert_eq!(host.state(), ConnectionState::Connected);
+        assert_eq!(client.connection_type(), ConnectionType::WebRTC);
+
+        client
+            .send(ProtocolMessage::Clipboard {
+                content: "hello host".to_string(),
+            })
+            .unwrap();
+        match host.receive().await {
+            Some(ProtocolMessage::Clipboard { content }) => assert_eq!(content, "hello host"),
+            other => panic!("unexpected message: {:?}", other),
+        }
+
+        host.disconnect().await.unwrap();
+        client.disconnect().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_message_exchange_over_loopback_backend() {
+        let config = NetworkConfig {
+            heartbeat_interval: Duration::from_millis(20),
+            ..Default::default()
+        };
+        let (mut host, mut client) = loopback::loopback_pair(&config).await.unwrap();
+        assert_eq!(host.state(), ConnectionState::Connected);
+        assert_eq!(client.connection_type(), ConnectionType::WebRTC);
+
+        for timestamp in 0..3 {
+            client
+                .send(ProtocolMessage::InputEvent {
+                    event_type: ada_remote_core::InputEventType::KeyPress,
+                    data: vec![timestamp as u8],
+                })
+                .unwrap();
+            host.send(ProtocolMessage::VideoFrame {
+                timestamp,
+                captured_at: 0,
+                sent_at: 0,
+                is_keyframe: timestamp == 0,
+                temporal_layer: 0,
+                data: vec![timestamp as u8; 64],
+            })
+            .unwrap();
+        }
+
+        // Heartbeats keep flowing but never reach the consumer
+        for expected in 0..3u8 {
+            match host.receive().await {
+                Some(ProtocolMessage::InputEvent { data, .. }) => assert_eq!(data, [expected]),
+                other => panic!("unexpected message: {:?}", other),
+            }
+            match client.receive().await {
+                Some(ProtocolMessage::VideoFrame {
+                    timestamp, data, ..
+                }) => {
+                    assert_eq!(timestamp, expected as u64);
+                    assert_eq!(data, vec![expected; 64]);
+                }
+                other => panic!("unexpected message: {:?}", other),
+            }
+        }
+
+        client.disconnect().await.unwrap();
+        host.disconnect().await.unwrap();
+        assert_eq!(client.state(), ConnectionState::Disconnected);
+    }
+
+    #[tokio::test]
+    async fn test_stats_defaults_in_every_state() {
+        let config = NetworkConfig {
+            signaling_server: "ws://127.0.0.1:1".to_string(),
+            enable_quic_fallback: false,
+            ..Default::default()
+        };
+        let mut peer = NetworkPeer::new(SessionId::new(), ConnectionType::WebRTC);
+        assert_eq!(peer.stats(), ConnectionStats::default());
+
+        peer.link.state.send_replace(ConnectionState::Connecting);
+        assert_eq!(peer.stats(), ConnectionStats::default());
+
+        assert!(peer.connect(&config).await.is_err());
+        assert_eq!(peer.state(), ConnectionState::Failed);
+        assert_eq!(peer.stats(), ConnectionStats::default());
+
+        // Connected but not yet sampled
+        peer.link.state.send_replace(ConnectionState::Connected);
+        let stats = peer.stats();
+        assert_eq!(stats.rtt_ms, None);
+        assert_eq!(stats.bytes_sent, 0);
+
+        peer.disconnect().await.unwrap();
+        assert_eq!(peer.stats(), ConnectionStats::default());
+    }
+
+    #[tokio::test]
+    async fn test_send_routes_by_message_type() {
+        let mut peer = NetworkPeer::new(SessionId::new(), ConnectionType::WebRTC);
+        let sent = Arc::new(MessageQueue::new(DEFAULT_QUEUE_CAPACITY));
+        peer.outgoing = Some(sent.clone());
+
+        peer.send(ProtocolMessage::VideoFrame {
+            timestamp: 0,
+            captured_at: 0,
+            sent_at: 0,
+            is_keyframe: false,
+            temporal_layer: 0,
+            data: vec![],
+        })
+        .unwrap();
+        peer.send(ProtocolMessage::InputEvent {
+            event_type: ada_remote_core::InputEventType::MouseMove,
+            data: vec![],
+        })
+        .unwrap();
+        peer.send(ProtocolMessage::Heartbeat).unwrap();
+        peer.send_on(Channel::Input, ProtocolMessage::Heartbeat)
+            .unwrap();
+
+        let (channel, _) = sent.pop().await.unwrap();
+        assert_eq!(channel, Channel::Video);
+        assert!(!channel.is_reliable());
+        let (channel, _) = sent.pop().await.unwrap();
+        assert_eq!(channel, Channel::Input);
+        assert!(channel.is_reliable());
+        assert_eq!(sent.pop().await.unwrap().0, Channel::Control);
+        assert_eq!(sent.pop().await.unwrap().0, Channel::Input);
+    }
+
+    #[tokio::test]
+    async fn test_full_send_queue_drops_only_delta_frames() {
+        let mut peer = NetworkPeer::new(SessionId::new(), ConnectionType::WebRTC);
+        let sent = Arc::new(MessageQueue::new(8));
+        peer.outgoing = Some(sent.clone());
+
+        // Nothing drains the queue, as with a stalled network
+        let frame = |timestamp, is_keyframe| ProtocolMessage::VideoFrame {
+            timestamp,
+            captured_at: 0,
+            sent_at: 0,
+            is_keyframe,
+            temporal_layer: 0,
+            data: vec![],
+        };
+        peer.send(frame(0, true)).unwrap();
+        for timestamp in 1..50 {
+            peer.send(frame(timestamp, false)).unwrap();
+            if timestamp % 10 == 0 {
+                peer.send(ProtocolMessage::InputEvent {
+                    event_type: ada_remote_core::InputEventType::KeyPress,
+                    data: vec![timestamp as u8],
+                })
+                .unwrap();
+            }
+        }
+        assert_eq!(peer.dropped_outgoing_frames(), 46);
+
+        sent.close();
+        let mut inputs = Vec::new();
+        let mut frames = Vec::new();
+        while let Some((_, message)) = sent.pop().await {
+            match message {
+                ProtocolMessage::InputEvent { data, .. } => inputs.push(data[0]),
+                ProtocolMessage::VideoFrame { timestamp, .. } => frames.push(timestamp),
+                other => panic!("unexpected message: {:?}", other),
+            }
+        }
+        assert_eq!(inputs, vec![10, 20, 30, 40]);
+        // The keyframe and the newest delta frames
+        assert_eq!(frames, vec![0, 47, 48, 49]);
+
+        peer.outgoing = None;
+        assert_eq!(peer.dropped_outgoing_frames(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_transport_fallback_emits_event() {
+        let mut peer = NetworkPeer::new(SessionId::new(), ConnectionType::WebRTC);
+
+        peer.update_transport(ConnectionType::WebRTC, false);
+        peer.update_transport(ConnectionType::QUIC, true);
+        assert_eq!(peer.connection_type(), ConnectionType::QUIC);
+        assert!(peer.is_relayed());
+
+        match peer.receive().await {
+            Some(ProtocolMessage::TransportChanged {
+                connection_type,
+                relayed,
+            }) => {
+                assert_eq!(connection_type, ConnectionType::QUIC);
+                assert!(relayed);
+            }
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+
+    /// Loopback backend remembering the TURN servers of every connect
+    #[derive(Default)]
+    struct TurnRecorder {
+        loopback: loopback::LoopbackBackend,
+        seen: Mutex<Vec<Vec<TurnServer>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl TransportBackend for TurnRecorder {
+        async fn connect(
+            &self,
+            role: Role,
+            session_id: SessionId,
+            config: &NetworkConfig,
+        ) -> Result<Box<dyn Transport>> {
+            self.seen.lock().unwrap().push(config.turn_servers.clone());
+            self.loopback.connect(role, session_id, config).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_refreshed_turn_servers_used_on_reconnect() {
+        let turn = |credential: &str| TurnServer {
+            url: "turn:turn.example.com:3478".to_string(),
+            username: "1700000000:client".to_string(),
+            credential: credential.to_string(),
+            expires_at: None,
+        };
+        let config = NetworkConfig {
+            turn_servers: vec![turn("first")],
+            reconnect: ReconnectPolicy {
+                base_delay: Duration::from_millis(10),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let backend = Arc::new(TurnRecorder::default());
+        let mut client = NetworkPeer::new(SessionId::new(), ConnectionType::WebRTC);
+        client.set_backend(backend.clone());
+        client.connect(&config).await.unwrap();
+
+        client.set_turn_servers(vec![turn("second")]);
+        assert_eq!(client.turn_servers(), Some(vec![turn("second")]));
+        // Dropping the other end loses the connection
+        let host_end = backend
+            .loopback
+            .connect(Role::Host, client.session_id(), &config)
+            .await
+            .unwrap();
+        drop(host_end);
+
+        let mut state = client.state_changes();
+        tokio::time::timeout(
+            Duration::from_secs(5),
+            state.wait_for(|_| backend.seen.lock().unwrap().len() == 2),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        let seen = backend.seen.lock().unwrap().clone();
+        assert_eq!(seen, [vec![turn("first")], vec![turn("second")]]);
+        client.disconnect().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_reason_reaches_peer() {
+        let (mut host, mut client) = loopback::loopback_pair(&NetworkConfig::default())
+            .await
+            .unwrap();
+
+        // Far sooner than the heartbeat timeout would notice
+        let mut state = host.state_changes();
+        client.disconnect_with("Window closed").await.unwrap();
+        tokio::time::timeout(
+            Duration::from_secs(1),
+            state.wait_for(|state| *state == ConnectionState::Disconnected),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(host.disconnect_reason().as_deref(), Some("Window closed"));
+        assert_eq!(client.disconnect_reason().as_deref(), Some("Window closed"));
+        match host.receive().await {
+            Some(ProtocolMessage::Disconnect { reason }) => assert_eq!(reason, "Window closed"),
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ping_measures_rtt_over_loopback() {
+        let (mut host, mut client) = loopback::loopback_pair(&NetworkConfig::default())
+            .await
+            .unwrap();
+
+        // The loopback transport measures no RTT itself; the first probe
+        // goes out on connect and its result shows at the next sample
+        let rtt = tokio::time::timeout(Duration::from_secs(3), async {
+            loop {
+                if let Some(rtt) = client.stats().rtt_ms {
+                    return rtt;
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert!((0.0..500.0).contains(&rtt), "{} ms", rtt);
+
+        client.send(ProtocolMessage::Heartbeat).unwrap();
+        client
+            .send(ProtocolMessage::Clipboard {
+                content: "after pings".to_string(),
+            })
+            .unwrap();
+        // Probes never reach the consumer
+        assert!(matches!(
+            host.receive().await,
+            Some(ProtocolMessage::Clipboard { .. })
+        ));
+
+        client.disconnect().await.unwrap();
+        host.disconnect().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_send_cap_drops_video_keeps_input() {
+        // 10 kB per second
+        let config = NetworkConfig {
+            max_send_bitrate: Some(80),
+            ..Default::default()
+        };
+        let (mut host, mut client) = loopback::loopback_pair(&config).await.unwrap();
+
+        // 50 kB per second offered for half a second, within one window
+        for timestamp in 0..25 {
+            client
+                .send(ProtocolMessage::VideoFrame {
+                    timestamp,
+                    captured_at: 0,
+                    sent_at: 0,
+                    is_keyframe: false,
+                    temporal_layer: 0,
+                    data: vec![0; 1000],
+                })
+                .unwrap();
+            if timestamp % 5 == 4 {
+                client
+                    .send(ProtocolMessage::InputEvent {
+                        event_type: ada_remote_core::InputEventType::KeyPress,
+                        data: vec![timestamp as u8],
+                    })
+                    .unwrap();
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        let mut video_bytes = 0;
+        let mut inputs = Vec::new();
+        while inputs.len() < 5 {
+            let message = tokio::time::timeout(Duration::from_secs(1), host.receive())
+                .await
+                .unwrap()
+                .unwrap();
+            match message {
+                ProtocolMessage::InputEvent { data, .. } => inputs.push(data[0]),
+                frame @ ProtocolMessage::VideoFrame { .. } => {
+                    video_bytes += frame.encoded_len().unwrap()
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(inputs, [4, 9, 14, 19, 24]);
+        assert!(video_bytes <= 10_000, "{} bytes sent", video_bytes);
+        assert!(client.throttled_frames() >= 15);
+
+        client.disconnect().await.unwrap();
+        host.disconnect().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_upper_temporal_layers_dropped() {
+        let config = NetworkConfig {
+            max_temporal_layer: Some(0),
+            ..Default::default()
+        };
+        let (mut host, mut client) = loopback::loopback_pair(&config).await.unwrap();
+
+        for timestamp in 0..6 {
+            client
+                .send(ProtocolMessage::VideoFrame {
+                    timestamp,
+                    captured_at: 0,
+                    sent_at: 0,
+                    is_keyframe: timestamp == 0,
+                    temporal_layer: (timestamp % 2) as u8,
+                    data: vec![0; 64],
+                })
+                .unwrap();
+        }
+        for expected in [0, 2, 4] {
+            match host.receive().await {
+                Some(ProtocolMessage::VideoFrame { timestamp, .. }) => {
+                    assert_eq!(timestamp, expected)
+                }
+                other => panic!("unexpected message: {:?}", other),
+            }
+        }
+        assert_eq!(client.throttled_frames(), 3);
+
+        client.disconnect().await.unwrap();
+        host.disconnect().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_bounded_queue_drops_video_keeps_control() {
+        let mut peer = NetworkPeer::with_capacity(SessionId::new(), ConnectionType::WebRTC, 4);
+
+        peer.send(ProtocolMessage::Disconnect {
+            reason: "bye".to_string(),
+        })
+        .unwrap();
+        for timestamp in 0..10 {
+            peer.send(ProtocolMessage::VideoFrame {
+                timestamp,
+                captured_at: 0,
+                sent_at: 0,
+                is_keyframe: false,
+                temporal_layer: 0,
+                data: vec![],
+            })
+            .unwrap();
+        }
+
+        assert_eq!(peer.dropped_frames(), 7);
+        assert!(matches!(
+            peer.receive().await,
+            Some(ProtocolMessage::Disconnect { .. })
+        ));
+        for expected in 7..10 {
+            match peer.receive().await {
+                Some(ProtocolMessage::VideoFrame { timestamp, .. }) => {
+                    assert_eq!(timestamp, expected)
+                }
+                other => panic!("unexpected message: {:?}", other),
+            }
+        }
+    }
 }
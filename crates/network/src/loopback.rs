@@ -0,0 +1,194 @@
+//! In-memory transport
+//!
+//! Connects peers in the same process without touching the network, for
+//! tests and for embedding both ends in one application. Messages are still
+//! framed as on the wire.
+
+use crate::stats::TransportSample;
+use crate::{
+    Channel, ConnectionType, NetworkConfig, NetworkPeer, Role, Transport, TransportBackend,
+};
+use ada_remote_core::{Compression, Error, ProtocolMessage, Result, SessionId};
+use async_trait::async_trait;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, Mutex};
+
+/// One end of an in-memory connection
+pub struct LoopbackTransport {
+    /// `None` once closed
+    send: Option<mpsc::UnboundedSender<Vec<u8>>>,
+    recv: mpsc::UnboundedReceiver<Vec<u8>>,
+    bytes_sent: u64,
+    bytes_received: u64,
+}
+
+/// Two connected loopback transports
+pub fn pair() -> (LoopbackTransport, LoopbackTransport) {
+    let (a_tx, a_rx) = mpsc::unbounded_channel();
+    let (b_tx, b_rx) = mpsc::unbounded_channel();
+    let end = |send, recv| LoopbackTransport {
+        send: Some(send),
+        recv,
+        bytes_sent: 0,
+        bytes_received: 0,
+    };
+    (end(a_tx, b_rx), end(b_tx, a_rx))
+}
+
+#[async_trait]
+impl Transport for LoopbackTransport {
+    async fn send(
+        &mut self,
+        _channel: Channel,
+        message: &ProtocolMessage,
+        compression: Compression,
+    ) -> Result<()> {
+        let frame = message.to_bytes_with(compression)?;
+        let len = frame.len() as u64;
+        self.send
+            .as_ref()
+            .and_then(|send| send.send(frame).ok())
+            .ok_or_else(|| Error::ConnectionClosed("Loopback closed".to_string()))?;
+        self.bytes_sent += len;
+        Ok(())
+    }
+
+    async fn recv(&mut self) -> Option<ProtocolMessage> {
+        loop {
+            let frame = self.recv.recv().await?;
+            self.bytes_received += frame.len() as u64;
+            match ProtocolMessage::from_bytes(&frame) {
+                Ok(message) => return Some(message),
+                Err(e) => tracing::warn!("Invalid message from peer: {}", e),
+            }
+        }
+    }
+
+    async fn stats(&mut self) -> TransportSample {
+        TransportSample {
+            rtt: Some(Duration::ZERO),
+            bytes_sent: self.bytes_sent,
+            bytes_received: self.bytes_received,
+            packets_sent: None,
+            packets_lost: None,
+        }
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        self.send = None;
+        Ok(())
+    }
+}
+
+/// Backend connecting each client to a host of the same backend
+///
+/// Every connect by a client, including reconnects, creates a new
+/// [`pair`] and hands one end to the next host connect. Session IDs are
+/// ignored.
+#[derive(Clone)]
+pub struct LoopbackBackend {
+    hosts: mpsc::UnboundedSender<LoopbackTransport>,
+    waiting: Arc<Mutex<mpsc::UnboundedReceiver<LoopbackTransport>>>,
+}
+
+impl LoopbackBackend {
+    pub fn new() -> Self {
+        let (hosts, waiting) = mpsc::unbounded_channel();
+        Self {
+            hosts,
+            waiting: Arc::new(Mutex::new(waiting)),
+        }
+    }
+}
+
+impl Default for LoopbackBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Host and client connected to each other over a new [`LoopbackBackend`],
+/// both with `config`
+pub async fn loopback_pair(config: &NetworkConfig) -> Result<(NetworkPeer, NetworkPeer)> {
+    let backend = Arc::new(LoopbackBackend::new());
+    let session_id = SessionId::new();
+    let mut host = NetworkPeer::new(session_id, ConnectionType::WebRTC);
+    host.set_backend(backend.clone());
+    let host_config = config.clone();
+    let host_task = tokio::spawn(async move {
+        host.accept(&host_config).await?;
+        Ok::<_, Error>(host)
+    });
+    let mut client = NetworkPeer::new(session_id, ConnectionType::WebRTC);
+    client.set_backend(backend);
+    client.connect(config).await?;
+    let host = host_task
+        .await
+        .map_err(|e| Error::Network(format!("Loopback host failed: {}", e)))??;
+    Ok((host, client))
+}
+
+#[async_trait]
+impl TransportBackend for LoopbackBackend {
+    async fn connect(
+        &self,
+        role: Role,
+        _session_id: SessionId,
+        _config: &NetworkConfig,
+    ) -> Result<Box<dyn Transport>> {
+        match role {
+            Role::Host => {
+                // Never closed, as the backend holds a sender itself
+                let transport = self.waiting.lock().await.recv().await;
+                transport
+                    .map(|transport| Box::new(transport) as Box<dyn Transport>)
+                    .ok_or_else(|| Error::Network("Loopback backend closed".to_string()))
+            }
+            Role::Client => {
+                let (host, client) = pair();
+                self.hosts
+                    .send(host)
+                    .map_err(|_| Error::Network("Loopback backend closed".to_string()))?;
+                Ok(Box::new(client))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_pair_carries_messages_until_closed() {
+        let (mut a, mut b) = pair();
+        let message = ProtocolMessage::Clipboard {
+            content: "loopback ".repeat(200),
+        };
+        a.send(Channel::Control, &message, Compression::Deflate)
+            .await
+            .unwrap();
+        match b.recv().await {
+            Some(ProtocolMessage::Clipboard { content }) => {
+                assert_eq!(content, "loopback ".repeat(200))
+            }
+            other => panic!("unexpected message: {:?}", other),
+        }
+
+        let sample = a.stats().await;
+        assert!(sample.bytes_sent > 0);
+        assert_eq!(b.stats().await.bytes_received, sample.bytes_sent);
+
+        a.close().await.unwrap();
+        assert!(b.recv().await.is_none());
+        assert!(a
+            .send(
+                Channel::Control,
+                &ProtocolMessage::Heartbeat,
+                Compression::None
+            )
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,118 @@
+//! Round-trip time probes
+//!
+//! Heartbeats carry nothing to match a reply to, so a connection also sends
+//! a `Ping` every [`STATS_INTERVAL`](crate::STATS_INTERVAL). The other side
+//! echoes it as a `Pong`, and the round trip is folded into a smoothed
+//! estimate. Pongs for probes that are unknown, too old or already answered
+//! are ignored.
+
+use ada_remote_core::ProtocolMessage;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Probes awaiting a reply; older ones count as lost
+const OUTSTANDING_PINGS: usize = 8;
+
+/// Weight of a new sample in the estimate, as for TCP's SRTT
+const SMOOTHING: f64 = 0.125;
+
+/// The reply to a `Ping`, or `None` for other messages
+pub fn pong_for(message: &ProtocolMessage) -> Option<ProtocolMessage> {
+    match *message {
+        ProtocolMessage::Ping { seq, sent_at } => Some(ProtocolMessage::Pong { seq, sent_at }),
+        _ => None,
+    }
+}
+
+/// Smoothed round-trip time from `Ping`/`Pong` exchanges
+#[derive(Debug, Clone)]
+pub struct RttEstimator {
+    /// Origin of the `sent_at` stamps
+    epoch: Instant,
+    next_seq: u64,
+    /// (seq, sent_at) of pings not answered yet
+    outstanding: VecDeque<(u64, u64)>,
+    smoothed_ms: Option<f64>,
+}
+
+impl RttEstimator {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            epoch: now,
+            next_seq: 0,
+            outstanding: VecDeque::with_capacity(OUTSTANDING_PINGS),
+            smoothed_ms: None,
+        }
+    }
+
+    /// Next probe to send, at `now`
+    pub fn ping(&mut self, now: Instant) -> ProtocolMessage {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        let sent_at = now.saturating_duration_since(self.epoch).as_micros() as u64;
+        if self.outstanding.len() == OUTSTANDING_PINGS {
+            self.outstanding.pop_front();
+        }
+        self.outstanding.push_back((seq, sent_at));
+        ProtocolMessage::Ping { seq, sent_at }
+    }
+
+    /// Feed a `Pong` received at `now`, returning its round trip. Other
+    /// messages and pongs not matching a probe in flight are ignored.
+    pub fn handle(&mut self, message: &ProtocolMessage, now: Instant) -> Option<Duration> {
+        let ProtocolMessage::Pong { seq, sent_at } = *message else {
+            return None;
+        };
+        let index = self
+            .outstanding
+            .iter()
+            .position(|&probe| probe == (seq, sent_at))?;
+        self.outstanding.remove(index);
+
+        let round_trip = now
+            .saturating_duration_since(self.epoch)
+            .saturating_sub(Duration::from_micros(sent_at));
+        let sample = round_trip.as_secs_f64() * 1000.0;
+        self.smoothed_ms = Some(match self.smoothed_ms {
+            Some(smoothed) => smoothed + (sample - smoothed) * SMOOTHING,
+            None => sample,
+        });
+        Some(round_trip)
+    }
+
+    /// Smoothed round-trip time, once a probe has been answered
+    pub fn rtt_ms(&self) -> Option<f64> {
+        self.smoothed_ms
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matched_pongs_only() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut estimator = RttEstimator::new(start);
+
+        let first = estimator.ping(at(0));
+        let second = estimator.ping(at(10));
+        let pong = pong_for(&first).unwrap();
+        assert_eq!(
+            estimator.handle(&pong, at(40)),
+            Some(Duration::from_millis(40))
+        );
+        assert_eq!(estimator.rtt_ms(), Some(40.0));
+
+        // Duplicates, forgeries and other messages leave the estimate alone
+        assert_eq!(estimator.handle(&pong, at(50)), None);
+        let forged = ProtocolMessage::Pong { seq: 1, sent_at: 0 };
+        assert_eq!(estimator.handle(&forged, at(50)), None);
+        assert_eq!(estimator.handle(&second, at(50)), None);
+        assert_eq!(estimator.rtt_ms(), Some(40.0));
+
+        estimator.handle(&pong_for(&second).unwrap(), at(130));
+        assert_eq!(estimator.rtt_ms(), Some(50.0));
+    }
+}
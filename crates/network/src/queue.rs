@@ -0,0 +1,115 @@
+//! Bounded message queue
+//!
+//! Backpressure-aware queue between the transport and the consumer, used
+//! for both directions. When full, the oldest video frames that are not
+//! keyframes are dropped to make room; keyframes, control and input messages
+//! are never dropped.
+
+use crate::transport::Outgoing;
+use ada_remote_core::ProtocolMessage;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use tokio::sync::Notify;
+
+/// Default queue capacity in messages
+pub const DEFAULT_QUEUE_CAPACITY: usize = 256;
+
+/// Item the overflow policy may drop
+pub(crate) trait Droppable {
+    fn is_droppable(&self) -> bool;
+}
+
+impl Droppable for ProtocolMessage {
+    fn is_droppable(&self) -> bool {
+        matches!(
+            self,
+            ProtocolMessage::VideoFrame {
+                is_keyframe: false,
+                ..
+            }
+        )
+    }
+}
+
+impl Droppable for Outgoing {
+    fn is_droppable(&self) -> bool {
+        self.1.is_droppable()
+    }
+}
+
+/// Bounded queue of protocol messages with a video-drop overflow policy
+pub(crate) struct MessageQueue<T = ProtocolMessage> {
+    messages: Mutex<VecDeque<T>>,
+    notify: Notify,
+    capacity: usize,
+    dropped_frames: AtomicU64,
+    closed: AtomicBool,
+}
+
+impl<T: Droppable> MessageQueue<T> {
+    /// Create a queue holding up to `capacity` messages
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            messages: Mutex::new(VecDeque::with_capacity(capacity)),
+            notify: Notify::new(),
+            capacity: capacity.max(1),
+            dropped_frames: AtomicU64::new(0),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    /// Push a message, applying the overflow policy when the queue is full
+    pub(crate) fn push(&self, message: T) {
+        let mut messages = self.messages.lock().unwrap();
+
+        if messages.len() >= self.capacity {
+            match messages.iter().position(T::is_droppable) {
+                Some(index) => {
+                    messages.remove(index);
+                    self.dropped_frames.fetch_add(1, Ordering::Relaxed);
+                }
+                None if message.is_droppable() => {
+                    // Queue is full of messages to keep; drop the new frame
+                    self.dropped_frames.fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+                // Other messages may exceed the capacity rather than be lost
+                None => {}
+            }
+        }
+
+        messages.push_back(message);
+        drop(messages);
+        self.notify.notify_one();
+    }
+
+    /// Wait for the next message, or `None` once the queue is closed and
+    /// drained. Cancel safe.
+    pub(crate) async fn pop(&self) -> Option<T> {
+        loop {
+            if let Some(message) = self.messages.lock().unwrap().pop_front() {
+                return Some(message);
+            }
+            if self.is_closed() {
+                return None;
+            }
+            self.notify.notified().await;
+        }
+    }
+
+    /// Stop accepting messages; those queued can still be popped
+    pub(crate) fn close(&self) {
+        self.closed.store(true, Ordering::Relaxed);
+        self.notify.notify_one();
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed)
+    }
+
+    /// Number of video frames dropped due to overflow
+    pub(crate) fn dropped_frames(&self) -> u64 {
+        self.dropped_frames.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,180 @@
+//! QUIC transport used when a WebRTC connection cannot be established
+//!
+//! Protocol messages are carried on a single bidirectional stream as the
+//! length-prefixed binary frames of [`ProtocolMessage::to_bytes`].
+
+use crate::stats::TransportSample;
+use crate::{Channel, Transport};
+use ada_remote_core::{Compression, Error, ProtocolMessage, Result, FRAME_HEADER_LEN};
+use async_trait::async_trait;
+use quinn::{ClientConfig, Endpoint, RecvStream, SendStream};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// Largest message accepted from the peer
+pub const MAX_MESSAGE_SIZE: usize = ada_remote_core::MAX_FRAME_SIZE;
+
+/// QUIC connection to a relay
+pub struct QuicTransport {
+    // The endpoint must outlive the connection's streams
+    _endpoint: Endpoint,
+    connection: quinn::Connection,
+    send: SendStream,
+    incoming: mpsc::UnboundedReceiver<ProtocolMessage>,
+    reader: JoinHandle<()>,
+}
+
+impl QuicTransport {
+    /// Connect to `relay` ("host:port")
+    ///
+    /// The relay's certificate is checked against `trusted_certificate`
+    /// (DER) when given, otherwise against the platform's root store.
+    pub async fn connect(relay: &str, trusted_certificate: Option<&[u8]>) -> Result<Self> {
+        let (host, _) = relay
+            .rsplit_once(':')
+            .ok_or_else(|| Error::Network(format!("Invalid relay address: {}", relay)))?;
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let addr = tokio::net::lookup_host(relay)
+            .await?
+            .next()
+            .ok_or_else(|| Error::Network(format!("Could not resolve relay: {}", relay)))?;
+
+        let client_config = match trusted_certificate {
+            Some(der) => {
+                let mut roots = rustls::RootCertStore::empty();
+                roots
+                    .add(&rustls::Certificate(der.to_vec()))
+                    .map_err(|e| Error::Network(format!("Invalid relay certificate: {}", e)))?;
+                ClientConfig::with_root_certificates(roots)
+            }
+            None => ClientConfig::with_native_roots(),
+        };
+
+        let bind: SocketAddr = if addr.is_ipv6() {
+            (Ipv6Addr::UNSPECIFIED, 0).into()
+        } else {
+            (Ipv4Addr::UNSPECIFIED, 0).into()
+        };
+        let mut endpoint = Endpoint::client(bind)?;
+        endpoint.set_default_client_config(client_config);
+
+        let connection = endpoint
+            .connect(addr, host)
+            .map_err(|e| Error::Network(format!("QUIC connect failed: {}", e)))?
+            .await
+            .map_err(|e| Error::Network(format!("QUIC connect failed: {}", e)))?;
+        let (send, recv) = connection
+            .open_bi()
+            .await
+            .map_err(|e| Error::Network(format!("Failed to open QUIC stream: {}", e)))?;
+
+        tracing::info!("QUIC connection established to {}", addr);
+        let (incoming_tx, incoming) = mpsc::unbounded_channel();
+        // Reads are not cancel-safe, so they get their own task
+        let reader = tokio::spawn(read_all(recv, incoming_tx));
+        Ok(Self {
+            _endpoint: endpoint,
+            connection,
+            send,
+            incoming,
+            reader,
+        })
+    }
+
+    /// Address of the relay
+    pub fn remote_address(&self) -> SocketAddr {
+        self.connection.remote_address()
+    }
+}
+
+impl Drop for QuicTransport {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+#[async_trait]
+impl Transport for QuicTransport {
+    // The stream carries every channel
+    async fn send(
+        &mut self,
+        _channel: Channel,
+        message: &ProtocolMessage,
+        compression: Compression,
+    ) -> Result<()> {
+        write_message(&mut self.send, message, compression).await
+    }
+
+    async fn recv(&mut self) -> Option<ProtocolMessage> {
+        self.incoming.recv().await
+    }
+
+    async fn stats(&mut self) -> TransportSample {
+        let stats = self.connection.stats();
+        TransportSample {
+            rtt: Some(stats.path.rtt),
+            bytes_sent: stats.udp_tx.bytes,
+            bytes_received: stats.udp_rx.bytes,
+            packets_sent: Some(stats.path.sent_packets),
+            packets_lost: Some(stats.path.lost_packets),
+        }
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        self.send
+            .finish()
+            .await
+            .map_err(|e| Error::Network(format!("Failed to finish QUIC stream: {}", e)))
+    }
+}
+
+/// Forward messages from `stream` until it ends or fails
+async fn read_all(mut stream: RecvStream, incoming: mpsc::UnboundedSender<ProtocolMessage>) {
+    loop {
+        match read_message(&mut stream).await {
+            Ok(Some(message)) => {
+                if incoming.send(message).is_err() {
+                    break;
+                }
+            }
+            Ok(None) => break,
+            Err(e) => {
+                tracing::warn!("QUIC receive failed: {}", e);
+                break;
+            }
+        }
+    }
+}
+
+/// Write one framed message to a QUIC stream
+pub async fn write_message(
+    stream: &mut SendStream,
+    message: &ProtocolMessage,
+    compression: Compression,
+) -> Result<()> {
+    stream
+        .write_all(&message.to_bytes_with(compression)?)
+        .await
+        .map_err(|e| Error::Network(format!("QUIC write failed: {}", e)))
+}
+
+/// Read one framed message from a QUIC stream, or `None` once the peer
+/// has finished the stream
+pub async fn read_message(stream: &mut RecvStream) -> Result<Option<ProtocolMessage>> {
+    let mut header = [0u8; FRAME_HEADER_LEN];
+    match stream.read_exact(&mut header).await {
+        Ok(()) => {}
+        Err(quinn::ReadExactError::FinishedEarly) => return Ok(None),
+        Err(e) => return Err(Error::ConnectionClosed(e.to_string())),
+    }
+
+    let len = ProtocolMessage::frame_body_len(header)?;
+    let mut frame = vec![0u8; FRAME_HEADER_LEN + len];
+    frame[..FRAME_HEADER_LEN].copy_from_slice(&header);
+    stream
+        .read_exact(&mut frame[FRAME_HEADER_LEN..])
+        .await
+        .map_err(|e| Error::ConnectionClosed(e.to_string()))?;
+    ProtocolMessage::from_bytes(&frame).map(Some)
+}
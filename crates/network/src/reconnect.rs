@@ -0,0 +1,82 @@
+//! Reconnection policy
+//!
+//! After an established connection drops, the peer retries the handshake
+//! with exponential backoff and holds outbound messages until it is back.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// How a dropped connection is re-established
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    /// Delay before the first attempt, doubled after each failure
+    pub base_delay: Duration,
+    /// Upper bound for the delay between attempts
+    pub max_delay: Duration,
+    /// Attempts before giving up; 0 disables reconnecting
+    pub max_attempts: u32,
+    /// Outbound messages held during the outage; the oldest are dropped
+    /// beyond this
+    pub queue_capacity: usize,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            max_attempts: 8,
+            queue_capacity: 256,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Delay before the given attempt, counting from 1
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.base_delay.saturating_mul(factor).min(self.max_delay)
+    }
+
+    /// Hold `item` for sending after reconnecting, dropping the oldest held
+    /// item beyond the capacity
+    pub(crate) fn hold<T>(&self, queue: &mut VecDeque<T>, item: T) {
+        queue.push_back(item);
+        if queue.len() > self.queue_capacity.max(1) {
+            queue.pop_front();
+            tracing::warn!("Outbound queue full during reconnect, dropped oldest message");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_doubles_up_to_max() {
+        let policy = ReconnectPolicy {
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+            ..Default::default()
+        };
+        assert_eq!(policy.delay(1), Duration::from_millis(100));
+        assert_eq!(policy.delay(2), Duration::from_millis(200));
+        assert_eq!(policy.delay(4), Duration::from_millis(800));
+        assert_eq!(policy.delay(5), Duration::from_secs(1));
+        assert_eq!(policy.delay(100), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_hold_drops_oldest() {
+        let policy = ReconnectPolicy {
+            queue_capacity: 2,
+            ..Default::default()
+        };
+        let mut queue = VecDeque::new();
+        for item in 0..4 {
+            policy.hold(&mut queue, item);
+        }
+        assert_eq!(queue, [2, 3]);
+    }
+}
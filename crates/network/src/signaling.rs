@@ -3,7 +3,10 @@
 //! WebSocket-based signaling for WebRTC connection establishment.
 
 use ada_remote_core::{Result, SessionId};
+use futures::{SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
 
 /// Signaling message types
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -12,67 +15,197 @@ pub enum SignalingMessage {
     /// Register a new session
     Register {
         session_id: SessionId,
+        /// Proof the host may register, for servers that require one
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        token: Option<String>,
     },
     /// Join an existing session
-    Join {
-        session_id: SessionId,
-    },
+    Join { session_id: SessionId },
     /// WebRTC offer
     Offer {
         session_id: SessionId,
         sdp: String,
+        /// Viewer the message comes from or is addressed to; the host's
+        /// unaddressed messages go to every viewer
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        viewer: Option<u32>,
     },
     /// WebRTC answer
     Answer {
         session_id: SessionId,
         sdp: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        viewer: Option<u32>,
     },
     /// ICE candidate
     IceCandidate {
         session_id: SessionId,
         candidate: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        viewer: Option<u32>,
     },
+    /// Acknowledgement from the server
+    Success { message: String },
     /// Error response
-    Error {
-        message: String,
+    Error { message: String },
+    /// The other peer of the session closed its signaling connection. The
+    /// host is told which viewer left.
+    PeerDisconnected {
+        session_id: SessionId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        viewer: Option<u32>,
     },
+    /// The server is shutting down and closes the connection within
+    /// `grace_seconds`; messages are still forwarded until then
+    ShuttingDown { grace_seconds: u64 },
 }
 
 /// Signaling client for WebRTC negotiation
 pub struct SignalingClient {
     server_url: String,
+    socket: Option<WebSocketStream<MaybeTlsStream<TcpStream>>>,
 }
 
 impl SignalingClient {
     /// Create a new signaling client
     pub fn new(server_url: String) -> Self {
-        Self { server_url }
+        Self {
+            server_url,
+            socket: None,
+        }
+    }
+
+    /// Whether the WebSocket is open
+    pub fn is_connected(&self) -> bool {
+        self.socket.is_some()
     }
 
     /// Connect to the signaling server
     pub async fn connect(&mut self) -> Result<()> {
         tracing::info!("Connecting to signaling server: {}", self.server_url);
-        // TODO: Implement WebSocket connection
+        let (socket, _) = tokio_tungstenite::connect_async(self.server_url.as_str())
+            .await
+            .map_err(|e| {
+                ada_remote_core::Error::Network(format!(
+                    "Failed to connect to {}: {}",
+                    self.server_url, e
+                ))
+            })?;
+        self.socket = Some(socket);
         Ok(())
     }
 
+    fn socket(&mut self) -> Result<&mut WebSocketStream<MaybeTlsStream<TcpStream>>> {
+        self.socket.as_mut().ok_or_else(|| {
+            ada_remote_core::Error::Network("Not connected to signaling server".to_string())
+        })
+    }
+
     /// Send a signaling message
-    pub async fn send(&mut self, _message: SignalingMessage) -> Result<()> {
-        // TODO: Send message over WebSocket
-        Ok(())
+    pub async fn send(&mut self, message: SignalingMessage) -> Result<()> {
+        let text = serde_json::to_string(&message)?;
+        self.socket()?
+            .send(Message::Text(text))
+            .await
+            .map_err(|e| ada_remote_core::Error::Network(format!("Signaling send failed: {}", e)))
     }
 
     /// Receive a signaling message
+    ///
+    /// Returns [`Error::ConnectionClosed`](ada_remote_core::Error::ConnectionClosed)
+    /// when the server closes the connection, after which the client has to
+    /// `connect` again.
     pub async fn receive(&mut self) -> Result<SignalingMessage> {
-        // TODO: Receive message from WebSocket
-        Err(ada_remote_core::Error::Network(
-            "Signaling not implemented".to_string(),
-        ))
+        loop {
+            let message = match self.socket()?.next().await {
+                Some(Ok(message)) => message,
+                Some(Err(e)) => {
+                    return Err(ada_remote_core::Error::Network(format!(
+                        "Signaling receive failed: {}",
+                        e
+                    )))
+                }
+                None => {
+                    self.socket = None;
+                    return Err(ada_remote_core::Error::ConnectionClosed(
+                        "Signaling connection ended".to_string(),
+                    ));
+                }
+            };
+
+            match message {
+                Message::Text(text) => return Ok(serde_json::from_str(&text)?),
+                Message::Binary(data) => return Ok(serde_json::from_slice(&data)?),
+                Message::Close(frame) => {
+                    self.socket = None;
+                    let reason = frame
+                        .map(|frame| frame.reason.into_owned())
+                        .filter(|reason| !reason.is_empty())
+                        .unwrap_or_else(|| "Signaling server closed the connection".to_string());
+                    return Err(ada_remote_core::Error::ConnectionClosed(reason));
+                }
+                // Pings are answered by tungstenite itself
+                Message::Ping(_) | Message::Pong(_) | Message::Frame(_) => {}
+            }
+        }
     }
 
     /// Disconnect from the signaling server
     pub async fn disconnect(&mut self) -> Result<()> {
         tracing::info!("Disconnecting from signaling server");
+        if let Some(mut socket) = self.socket.take() {
+            socket.close(None).await.map_err(|e| {
+                ada_remote_core::Error::Network(format!("Signaling close failed: {}", e))
+            })?;
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    /// Accept one WebSocket connection, echo a single message back and close
+    async fn echo_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut socket = tokio_tungstenite::accept_async(stream).await.unwrap();
+            if let Some(Ok(message)) = socket.next().await {
+                socket.send(message).await.unwrap();
+            }
+            socket.close(None).await.unwrap();
+        });
+        format!("ws://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn test_register_round_trip() {
+        let mut client = SignalingClient::new(echo_server().await);
+        client.connect().await.unwrap();
+
+        let session_id = SessionId::new();
+        client
+            .send(SignalingMessage::Register {
+                session_id,
+                token: None,
+            })
+            .await
+            .unwrap();
+        match client.receive().await.unwrap() {
+            SignalingMessage::Register {
+                session_id: echoed, ..
+            } => assert_eq!(echoed, session_id),
+            other => panic!("unexpected message: {:?}", other),
+        }
+
+        assert!(matches!(
+            client.receive().await,
+            Err(ada_remote_core::Error::ConnectionClosed(_))
+        ));
+        assert!(!client.is_connected());
+    }
+}
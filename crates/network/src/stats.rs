@@ -0,0 +1,133 @@
+//! Connection statistics
+//!
+//! The transports expose raw counters; [`StatsTracker`] turns periodic
+//! samples of them into rates and smoothed values.
+
+use std::time::{Duration, Instant};
+
+/// How often transport statistics are sampled
+pub const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Live transport metrics
+///
+/// Metrics the transport cannot provide, or has not measured yet, are `None`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ConnectionStats {
+    /// Round-trip time, from the transport or else from `Ping` probes
+    pub rtt_ms: Option<f64>,
+    /// Share of sent packets that were lost, in percent
+    pub packet_loss_pct: Option<f64>,
+    /// Smoothed variation of the round-trip time
+    pub jitter_ms: Option<f64>,
+    /// Total bytes sent
+    pub bytes_sent: u64,
+    /// Total bytes received
+    pub bytes_received: u64,
+    /// Outgoing bitrate over the last sampling interval
+    pub current_bitrate_kbps: u64,
+    /// Messages sent over the last [`BANDWIDTH_WINDOW`], before compression
+    ///
+    /// [`BANDWIDTH_WINDOW`]: crate::BANDWIDTH_WINDOW
+    pub send_rate_kbps: u64,
+    /// Messages received over the last [`BANDWIDTH_WINDOW`]
+    ///
+    /// [`BANDWIDTH_WINDOW`]: crate::BANDWIDTH_WINDOW
+    pub receive_rate_kbps: u64,
+    /// The configured [`max_send_bitrate`](crate::NetworkConfig::max_send_bitrate),
+    /// which the encoder bitrate should stay below
+    pub max_send_kbps: Option<u32>,
+}
+
+/// Raw counters read from a transport
+///
+/// Counters are totals since the transport was established.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TransportSample {
+    /// Latest round-trip time
+    pub rtt: Option<Duration>,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    /// Packets sent, for transports that see their packets
+    pub packets_sent: Option<u64>,
+    pub packets_lost: Option<u64>,
+}
+
+/// Derives [`ConnectionStats`] from successive transport samples
+#[derive(Debug, Default)]
+pub(crate) struct StatsTracker {
+    last: Option<(Instant, TransportSample)>,
+    jitter_ms: Option<f64>,
+    stats: ConnectionStats,
+}
+
+impl StatsTracker {
+    /// Fold in a sample taken at `now` and return the updated stats
+    pub(crate) fn update(&mut self, sample: TransportSample, now: Instant) -> ConnectionStats {
+        let rtt_ms = sample.rtt.map(|rtt| rtt.as_secs_f64() * 1000.0);
+
+        if let Some((then, last)) = self.last {
+            // RFC 3550 style: J += (|D| - J) / 16
+            if let (Some(rtt), Some(previous)) = (rtt_ms, last.rtt) {
+                let delta = (rtt - previous.as_secs_f64() * 1000.0).abs();
+                let jitter = self.jitter_ms.unwrap_or(delta);
+                self.jitter_ms = Some(jitter + (delta - jitter) / 16.0);
+            }
+
+            let elapsed = now.duration_since(then).as_secs_f64();
+            if elapsed > 0.0 {
+                let sent = sample.bytes_sent.saturating_sub(last.bytes_sent);
+                self.stats.current_bitrate_kbps = (sent as f64 * 8.0 / elapsed / 1000.0) as u64;
+            }
+        }
+
+        self.stats.rtt_ms = rtt_ms;
+        self.stats.jitter_ms = self.jitter_ms;
+        self.stats.packet_loss_pct = match (sample.packets_lost, sample.packets_sent) {
+            (Some(lost), Some(sent)) if sent > 0 => Some(lost as f64 * 100.0 / sent as f64),
+            _ => None,
+        };
+        self.stats.bytes_sent = sample.bytes_sent;
+        self.stats.bytes_received = sample.bytes_received;
+        self.last = Some((now, sample));
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tracker_derives_rates() {
+        let mut tracker = StatsTracker::default();
+        let start = Instant::now();
+
+        let first = tracker.update(
+            TransportSample {
+                rtt: Some(Duration::from_millis(20)),
+                bytes_sent: 1000,
+                packets_sent: Some(100),
+                packets_lost: Some(0),
+                ..Default::default()
+            },
+            start,
+        );
+        assert_eq!(first.rtt_ms, Some(20.0));
+        assert_eq!(first.jitter_ms, None);
+        assert_eq!(first.current_bitrate_kbps, 0);
+
+        let second = tracker.update(
+            TransportSample {
+                rtt: Some(Duration::from_millis(30)),
+                bytes_sent: 126_000,
+                packets_sent: Some(200),
+                packets_lost: Some(5),
+                ..Default::default()
+            },
+            start + Duration::from_secs(1),
+        );
+        assert_eq!(second.jitter_ms, Some(10.0));
+        assert_eq!(second.current_bitrate_kbps, 1000);
+        assert_eq!(second.packet_loss_pct, Some(2.5));
+    }
+}
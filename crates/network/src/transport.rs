@@ -0,0 +1,591 @@
+//! Transports, connection establishment and the connection task
+//!
+//! Once connected, a [`NetworkPeer`](crate::NetworkPeer) hands its
+//! [`Transport`] to [`supervise`], which pumps messages until the transport
+//! is lost and then reconnects through the peer's [`TransportBackend`]
+//! according to the [`ReconnectPolicy`](crate::ReconnectPolicy). A transport
+//! counts as lost as well when nothing, not even a heartbeat, has arrived on
+//! it for `heartbeat_timeout`. A `Disconnect` from the other side ends the
+//! connection for good, without reconnecting.
+
+use crate::bandwidth::BandwidthMeter;
+use crate::ice::{self, TurnState};
+use crate::ping::{pong_for, RttEstimator};
+use crate::queue::{Droppable, MessageQueue};
+use crate::quic::QuicTransport;
+use crate::signaling::{SignalingClient, SignalingMessage};
+use crate::stats::{ConnectionStats, StatsTracker, TransportSample, STATS_INTERVAL};
+use crate::webrtc::WebRtcPeer;
+use crate::{Channel, ConnectionState, ConnectionType, NetworkConfig};
+use ada_remote_core::{Compression, Error, ProtocolMessage, Result, SessionId};
+use ada_remote_crypto::current_registration_token;
+use async_trait::async_trait;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tokio::time::{Interval, MissedTickBehavior};
+
+/// Outbound message and the channel it goes on
+pub(crate) type Outgoing = (Channel, ProtocolMessage);
+
+/// Which side of the handshake a peer plays
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    /// Registers the session and answers offers
+    Host,
+    /// Joins the session and makes the offer
+    Client,
+}
+
+/// State shared between a peer and its connection task
+pub(crate) struct Link {
+    pub(crate) state: watch::Sender<ConnectionState>,
+    /// Why the connection was ended, set before the state changes to
+    /// `Disconnected`
+    pub(crate) reason: watch::Sender<Option<String>>,
+    pub(crate) turn: Mutex<TurnState>,
+    pub(crate) messages: MessageQueue,
+    pub(crate) stats: Mutex<ConnectionStats>,
+    /// Video frames dropped for the send cap
+    pub(crate) throttled_frames: AtomicU64,
+    /// How outgoing frames are compressed
+    pub(crate) compression: Mutex<Compression>,
+}
+
+impl Link {
+    fn compression(&self) -> Compression {
+        *self.compression.lock().unwrap()
+    }
+}
+
+/// An established connection carrying protocol messages
+///
+/// [`WebRtcPeer`] and [`QuicTransport`] are the built-in transports.
+#[async_trait]
+pub trait Transport: Send {
+    /// Send `message` on `channel`, compressing it as negotiated. Transports
+    /// with a single stream ignore the channel.
+    async fn send(
+        &mut self,
+        channel: Channel,
+        message: &ProtocolMessage,
+        compression: Compression,
+    ) -> Result<()>;
+
+    /// Next message from the peer, or `None` once the connection is lost
+    ///
+    /// Must be cancel-safe, as it is raced against outgoing messages.
+    async fn recv(&mut self) -> Option<ProtocolMessage>;
+
+    /// Current counters of the connection
+    async fn stats(&mut self) -> TransportSample;
+
+    /// Close the connection
+    async fn close(&mut self) -> Result<()>;
+}
+
+/// Establishes the [`Transport`] of a [`NetworkPeer`](crate::NetworkPeer)
+///
+/// Peers use WebRTC with the QUIC fallback unless given a backend with
+/// [`NetworkPeer::set_backend`](crate::NetworkPeer::set_backend).
+#[async_trait]
+pub trait TransportBackend: Send + Sync {
+    /// Connect to the other side of session `session_id`. Called again for
+    /// every reconnect.
+    async fn connect(
+        &self,
+        role: Role,
+        session_id: SessionId,
+        config: &NetworkConfig,
+    ) -> Result<Box<dyn Transport>>;
+}
+
+/// Reconnects over the built-in transport a peer ended up on, without
+/// falling back again
+struct Builtin {
+    connection_type: ConnectionType,
+}
+
+#[async_trait]
+impl TransportBackend for Builtin {
+    async fn connect(
+        &self,
+        role: Role,
+        session_id: SessionId,
+        config: &NetworkConfig,
+    ) -> Result<Box<dyn Transport>> {
+        let (transport, _) =
+            establish(role, self.connection_type, session_id, config, false).await?;
+        Ok(transport)
+    }
+}
+
+/// Backend for reconnecting over the built-in `connection_type`
+pub(crate) fn builtin(connection_type: ConnectionType) -> Arc<dyn TransportBackend> {
+    Arc::new(Builtin { connection_type })
+}
+
+/// Why a transport stopped
+enum Outcome {
+    /// The peer was disconnected locally
+    Stopped,
+    /// The connection dropped
+    Lost,
+    /// The other side disconnected, giving a reason
+    Closed(String),
+}
+
+/// Establish a transport, returning it with the connection type in use
+///
+/// A client whose WebRTC attempt fails or exceeds `webrtc_timeout` falls
+/// back to QUIC when `fallback` is set.
+pub(crate) async fn establish(
+    role: Role,
+    connection_type: ConnectionType,
+    session_id: SessionId,
+    config: &NetworkConfig,
+    fallback: bool,
+) -> Result<(Box<dyn Transport>, ConnectionType)> {
+    let result = match (role, connection_type) {
+        (Role::Host, _) => return accept_webrtc(session_id, config).await,
+        (Role::Client, ConnectionType::WebRTC) => {
+            match tokio::time::timeout(config.webrtc_timeout, join_webrtc(session_id, config)).await
+            {
+                Ok(result) => result,
+                Err(_) => Err(Error::Network("WebRTC connection timed out".to_string())),
+            }
+        }
+        (Role::Client, ConnectionType::QUIC) => connect_quic(config).await,
+    };
+
+    match result {
+        Err(e) if connection_type == ConnectionType::WebRTC && fallback => {
+            tracing::warn!("WebRTC connection failed ({}), falling back to QUIC", e);
+            connect_quic(config).await
+        }
+        result => result,
+    }
+}
+
+async fn join_webrtc(
+    session_id: SessionId,
+    config: &NetworkConfig,
+) -> Result<(Box<dyn Transport>, ConnectionType)> {
+    let mut signaling = SignalingClient::new(config.signaling_server.clone());
+    signaling.connect().await?;
+    signaling
+        .send(SignalingMessage::Join { session_id })
+        .await?;
+    expect_success(&mut signaling).await?;
+
+    // The joining client offers; the host answers in `accept_webrtc`
+    let mut peer = WebRtcPeer::new(config).await?;
+    for channel in Channel::ALL {
+        peer.create_data_channel(channel.label(), channel.is_reliable())
+            .await?;
+    }
+    let sdp = peer.create_offer().await?;
+    signaling
+        .send(SignalingMessage::Offer {
+            session_id,
+            sdp,
+            viewer: None,
+        })
+        .await?;
+
+    negotiate(&mut signaling, &mut peer, session_id, None).await?;
+    let _ = signaling.disconnect().await;
+    Ok((Box::new(peer), ConnectionType::WebRTC))
+}
+
+async fn accept_webrtc(
+    session_id: SessionId,
+    config: &NetworkConfig,
+) -> Result<(Box<dyn Transport>, ConnectionType)> {
+    let mut signaling = SignalingClient::new(config.signaling_server.clone());
+    signaling.connect().await?;
+    signaling
+        .send(SignalingMessage::Register {
+            session_id,
+            token: config
+                .registration_secret
+                .as_ref()
+                .map(|secret| current_registration_token(secret.as_bytes(), &session_id)),
+        })
+        .await?;
+    expect_success(&mut signaling).await?;
+    tracing::info!("Session {} registered, waiting for a client", session_id);
+
+    // The first viewer to offer gets this connection
+    let (offer, viewer) = loop {
+        match signaling.receive().await? {
+            SignalingMessage::Offer { sdp, viewer, .. } => break (sdp, viewer),
+            SignalingMessage::Error { message } => {
+                return Err(Error::Network(format!("Signaling error: {}", message)))
+            }
+            // No client will reach this registration any more
+            SignalingMessage::ShuttingDown { .. } => {
+                return Err(Error::Network("Signaling server shutting down".to_string()))
+            }
+            _ => {}
+        }
+    };
+
+    let mut peer = WebRtcPeer::new(config).await?;
+    let sdp = peer.create_answer(&offer).await?;
+    signaling
+        .send(SignalingMessage::Answer {
+            session_id,
+            sdp,
+            viewer,
+        })
+        .await?;
+
+    tokio::time::timeout(
+        config.webrtc_timeout,
+        negotiate(&mut signaling, &mut peer, session_id, viewer),
+    )
+    .await
+    .map_err(|_| Error::Network("WebRTC connection timed out".to_string()))??;
+    let _ = signaling.disconnect().await;
+    Ok((Box::new(peer), ConnectionType::WebRTC))
+}
+
+async fn connect_quic(config: &NetworkConfig) -> Result<(Box<dyn Transport>, ConnectionType)> {
+    let transport =
+        QuicTransport::connect(&config.relay_server, config.relay_certificate.as_deref()).await?;
+    Ok((Box::new(transport), ConnectionType::QUIC))
+}
+
+/// Wait for the signaling server to acknowledge the last request
+async fn expect_success(signaling: &mut SignalingClient) -> Result<()> {
+    match signaling.receive().await? {
+        SignalingMessage::Success { .. } => Ok(()),
+        SignalingMessage::Error { message } => {
+            Err(Error::Network(format!("Signaling error: {}", message)))
+        }
+        other => Err(Error::Network(format!(
+            "Unexpected signaling message: {:?}",
+            other
+        ))),
+    }
+}
+
+/// Trickle ICE candidates both ways until every data channel is open. A
+/// host negotiating with one `viewer` ignores the others' messages.
+async fn negotiate(
+    signaling: &mut SignalingClient,
+    peer: &mut WebRtcPeer,
+    session_id: SessionId,
+    viewer: Option<u32>,
+) -> Result<()> {
+    let mut opened = peer.open_channels();
+    let mut gathering = true;
+    let ours = |from: Option<u32>| viewer.is_none() || from == viewer;
+
+    loop {
+        tokio::select! {
+            // The borrowed value is not Send, so only the outcome leaves the future
+            open = async {
+                opened
+                    .wait_for(|open| Channel::ALL.iter().all(|c| open.contains(c.label())))
+                    .await
+                    .is_ok()
+            } => {
+                return if open {
+                    Ok(())
+                } else {
+                    Err(Error::Network("Peer connection dropped".to_string()))
+                };
+            }
+            candidate = peer.next_local_candidate(), if gathering => match candidate {
+                Some(candidate) => {
+                    signaling
+                        .send(SignalingMessage::IceCandidate {
+                            session_id,
+                            candidate,
+                            viewer,
+                        })
+                        .await?
+                }
+                None => gathering = false,
+            },
+            message = signaling.receive() => match message? {
+                SignalingMessage::Answer { sdp, viewer: from, .. } if ours(from) => {
+                    peer.set_remote_description(&sdp).await?
+                }
+                SignalingMessage::IceCandidate { candidate, viewer: from, .. } if ours(from) => {
+                    peer.add_ice_candidate(&candidate).await?
+                }
+                SignalingMessage::Error { message } => {
+                    return Err(Error::Network(format!("Signaling error: {}", message)))
+                }
+                SignalingMessage::PeerDisconnected { viewer: from, .. } if ours(from) => {
+                    return Err(Error::Network("Peer left the session".to_string()))
+                }
+                // Acknowledgements of our own offers and candidates
+                _ => {}
+            },
+        }
+    }
+}
+
+/// Heartbeats sent on a connection and the traffic seen on it
+struct Keepalive {
+    ticker: Interval,
+    timeout: Duration,
+    last_received: Instant,
+}
+
+impl Keepalive {
+    fn new(config: &NetworkConfig) -> Self {
+        let mut ticker = tokio::time::interval(config.heartbeat_interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        Self {
+            ticker,
+            timeout: config.heartbeat_timeout,
+            last_received: Instant::now(),
+        }
+    }
+
+    /// Note a message from the peer, returning it unless it only kept the
+    /// connection alive
+    fn received(&mut self, message: ProtocolMessage) -> Option<ProtocolMessage> {
+        self.last_received = Instant::now();
+        match message {
+            ProtocolMessage::Heartbeat => None,
+            message => Some(message),
+        }
+    }
+
+    /// Wait until the next heartbeat is due, or return `None` once the peer
+    /// has been silent for the timeout
+    async fn tick(&mut self) -> Option<Outgoing> {
+        self.ticker.tick().await;
+        if self.last_received.elapsed() >= self.timeout {
+            tracing::warn!("No traffic from peer for {:?}", self.timeout);
+            return None;
+        }
+        let heartbeat = ProtocolMessage::Heartbeat;
+        Some((Channel::for_message(&heartbeat), heartbeat))
+    }
+}
+
+/// Drive a connection until the peer disconnects, reconnecting through
+/// `backend` whenever the transport is lost
+pub(crate) async fn supervise(
+    mut transport: Box<dyn Transport>,
+    backend: Arc<dyn TransportBackend>,
+    outgoing: Arc<MessageQueue<Outgoing>>,
+    link: Arc<Link>,
+    role: Role,
+    session_id: SessionId,
+    config: NetworkConfig,
+) {
+    let policy = &config.reconnect;
+    let mut held = VecDeque::new();
+
+    loop {
+        match run(transport, &outgoing, &mut held, &link, &config).await {
+            Outcome::Stopped => return,
+            Outcome::Closed(reason) => {
+                tracing::info!("Peer disconnected: {}", reason);
+                link.reason.send_replace(Some(reason));
+                link.state.send_replace(ConnectionState::Disconnected);
+                return;
+            }
+            Outcome::Lost => {}
+        }
+        link.state.send_replace(ConnectionState::Failed);
+
+        let mut attempt = 0;
+        transport = loop {
+            attempt += 1;
+            if attempt > policy.max_attempts {
+                tracing::warn!("Giving up reconnecting to session {}", session_id);
+                return;
+            }
+
+            let delay = policy.delay(attempt);
+            tracing::warn!(
+                "Connection lost, reconnecting in {:?} (attempt {}/{})",
+                delay,
+                attempt,
+                policy.max_attempts
+            );
+            let sleep = tokio::time::sleep(delay);
+            if holding(sleep, &outgoing, &mut held, &config)
+                .await
+                .is_none()
+            {
+                return;
+            }
+
+            link.state.send_replace(ConnectionState::Connecting);
+            let attempt = async {
+                let config = ice::current_config(&link, &config).await;
+                backend.connect(role, session_id, &config).await
+            };
+            match holding(attempt, &outgoing, &mut held, &config).await {
+                None => return,
+                Some(Ok(transport)) => break transport,
+                Some(Err(e)) => {
+                    tracing::warn!("Reconnect failed: {}", e);
+                    link.state.send_replace(ConnectionState::Failed);
+                }
+            }
+        };
+
+        tracing::info!("Reconnected to session {}", session_id);
+        *link.stats.lock().unwrap() = ConnectionStats::default();
+        link.state.send_replace(ConnectionState::Connected);
+    }
+}
+
+/// Await `future` while holding outbound messages for later, or return
+/// `None` once the peer has disconnected
+async fn holding<F: Future>(
+    future: F,
+    outgoing: &MessageQueue<Outgoing>,
+    held: &mut VecDeque<Outgoing>,
+    config: &NetworkConfig,
+) -> Option<F::Output> {
+    tokio::pin!(future);
+    loop {
+        tokio::select! {
+            output = &mut future => return Some(output),
+            message = outgoing.pop() => config.reconnect.hold(held, message?),
+        }
+    }
+}
+
+/// Deliver the messages that arrived before `transport` failed, returning
+/// the reason if they end with a `Disconnect`
+async fn drain(transport: &mut dyn Transport, link: &Link) -> Option<String> {
+    // A zero timeout still polls once, taking only what is already there
+    while let Ok(Some(message)) = tokio::time::timeout(Duration::ZERO, transport.recv()).await {
+        match message {
+            ProtocolMessage::Disconnect { reason } => {
+                link.messages.push(ProtocolMessage::Disconnect {
+                    reason: reason.clone(),
+                });
+                return Some(reason);
+            }
+            ProtocolMessage::Heartbeat
+            | ProtocolMessage::Ping { .. }
+            | ProtocolMessage::Pong { .. } => {}
+            message => link.messages.push(message),
+        }
+    }
+    None
+}
+
+/// Whether `message` is a video frame of a temporal layer above `max_layer`
+fn above_layer(message: &ProtocolMessage, max_layer: u8) -> bool {
+    matches!(
+        message,
+        ProtocolMessage::VideoFrame { temporal_layer, .. } if *temporal_layer > max_layer
+    )
+}
+
+/// Pump messages over a transport until it is lost or the peer disconnects
+///
+/// Held messages are sent first. A message that fails to send is held
+/// again for the next connection. Video frames that would exceed the send
+/// cap are dropped instead.
+async fn run(
+    mut transport: Box<dyn Transport>,
+    outgoing: &MessageQueue<Outgoing>,
+    held: &mut VecDeque<Outgoing>,
+    link: &Link,
+    config: &NetworkConfig,
+) -> Outcome {
+    let mut keepalive = Keepalive::new(config);
+    let mut tracker = StatsTracker::default();
+    let mut interval = tokio::time::interval(STATS_INTERVAL);
+    let mut meter = BandwidthMeter::new();
+    let mut rtt = RttEstimator::new(Instant::now());
+
+    let outcome = loop {
+        if let Some(message) = held.pop_front() {
+            if let Some(max_layer) = config.max_temporal_layer {
+                if above_layer(&message.1, max_layer) {
+                    link.throttled_frames.fetch_add(1, Ordering::Relaxed);
+                    continue;
+                }
+            }
+            let len = message.1.encoded_len().unwrap_or(0);
+            if let Some(cap) = config.max_send_bitrate {
+                if message.is_droppable() && !meter.allows(len, cap, Instant::now()) {
+                    link.throttled_frames.fetch_add(1, Ordering::Relaxed);
+                    continue;
+                }
+            }
+            let sent = transport.send(message.0, &message.1, link.compression());
+            if let Err(e) = sent.await {
+                tracing::warn!("Send failed: {}", e);
+                held.push_front(message);
+                // The peer may have closed it after saying why
+                match drain(transport.as_mut(), link).await {
+                    Some(reason) => break Outcome::Closed(reason),
+                    None => break Outcome::Lost,
+                }
+            }
+            meter.record_sent(len, Instant::now());
+            continue;
+        }
+
+        tokio::select! {
+            message = outgoing.pop() => {
+                let Some(message) = message else { break Outcome::Stopped };
+                held.push_back(message);
+            }
+            message = transport.recv() => match message {
+                Some(ProtocolMessage::Disconnect { reason }) => {
+                    link.messages.push(ProtocolMessage::Disconnect {
+                        reason: reason.clone(),
+                    });
+                    break Outcome::Closed(reason);
+                }
+                Some(message) => {
+                    meter.record_received(message.encoded_len().unwrap_or(0), Instant::now());
+                    match keepalive.received(message) {
+                        Some(ping @ ProtocolMessage::Ping { .. }) => {
+                            held.extend(pong_for(&ping).map(|pong| (Channel::Control, pong)));
+                        }
+                        Some(pong @ ProtocolMessage::Pong { .. }) => {
+                            rtt.handle(&pong, Instant::now());
+                        }
+                        Some(message) => link.messages.push(message),
+                        None => {}
+                    }
+                }
+                None => break Outcome::Lost,
+            },
+            heartbeat = keepalive.tick() => match heartbeat {
+                Some(heartbeat) => held.push_back(heartbeat),
+                None => break Outcome::Lost,
+            },
+            _ = interval.tick() => {
+                let mut sample = transport.stats().await;
+                // Transports that cannot measure it get the probes' estimate
+                sample.rtt = sample
+                    .rtt
+                    .or(rtt.rtt_ms().map(|ms| Duration::from_secs_f64(ms / 1000.0)));
+                let now = Instant::now();
+                held.push_back((Channel::Control, rtt.ping(now)));
+                let mut stats = tracker.update(sample, now);
+                stats.send_rate_kbps = meter.send_kbps(now);
+                stats.receive_rate_kbps = meter.receive_kbps(now);
+                stats.max_send_kbps = config.max_send_bitrate;
+                *link.stats.lock().unwrap() = stats;
+            }
+        }
+    };
+
+    let _ = transport.close().await;
+    outcome
+}
@@ -1,55 +1,416 @@
 //! WebRTC implementation for peer-to-peer connections
 
-use ada_remote_core::Result;
+use crate::stats::TransportSample;
+use crate::{Channel, ConnectionState, NetworkConfig, Transport};
+use ::webrtc::api::APIBuilder;
+use ::webrtc::data_channel::data_channel_init::RTCDataChannelInit;
+use ::webrtc::data_channel::data_channel_message::DataChannelMessage;
+use ::webrtc::data_channel::RTCDataChannel;
+use ::webrtc::ice_transport::ice_candidate::{RTCIceCandidate, RTCIceCandidateInit};
+use ::webrtc::ice_transport::ice_gathering_state::RTCIceGatheringState;
+use ::webrtc::ice_transport::ice_server::RTCIceServer;
+use ::webrtc::peer_connection::configuration::RTCConfiguration;
+use ::webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
+use ::webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+use ::webrtc::peer_connection::RTCPeerConnection;
+use ::webrtc::stats::StatsReportType;
+use ada_remote_core::{Compression, ProtocolMessage, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, watch};
+
+fn rtc_error(context: &'static str) -> impl FnOnce(::webrtc::Error) -> ada_remote_core::Error {
+    move |e| ada_remote_core::Error::Network(format!("{}: {}", context, e))
+}
+
+/// Data channels of a peer, shared with their event handlers
+struct Channels {
+    channels: Mutex<HashMap<String, Arc<RTCDataChannel>>>,
+    open: watch::Sender<HashSet<String>>,
+    incoming: mpsc::UnboundedSender<Vec<u8>>,
+}
 
 /// WebRTC peer connection
+///
+/// Local ICE candidates are trickled: the SDP from `create_offer` and
+/// `create_answer` carries none, and each one found afterwards is returned
+/// by [`WebRtcPeer::next_local_candidate`] for sending over signaling.
 pub struct WebRtcPeer {
-    // TODO: Add webrtc::peer_connection::RTCPeerConnection
+    connection: Arc<RTCPeerConnection>,
+    state: watch::Receiver<ConnectionState>,
+    candidates: mpsc::UnboundedReceiver<String>,
+    channels: Arc<Channels>,
+    open: watch::Receiver<HashSet<String>>,
+    incoming: mpsc::UnboundedReceiver<Vec<u8>>,
 }
 
 impl WebRtcPeer {
-    /// Create a new WebRTC peer
-    pub fn new() -> Result<Self> {
-        Ok(Self {})
+    /// Create a new WebRTC peer using the configured STUN and TURN servers
+    pub async fn new(config: &NetworkConfig) -> Result<Self> {
+        let mut ice_servers = Vec::new();
+        if !config.stun_servers.is_empty() {
+            ice_servers.push(RTCIceServer {
+                urls: config.stun_servers.clone(),
+                ..Default::default()
+            });
+        }
+        ice_servers.extend(config.turn_servers.iter().map(|turn| RTCIceServer {
+            urls: vec![turn.url.clone()],
+            username: turn.username.clone(),
+            credential: turn.credential.clone(),
+            ..Default::default()
+        }));
+
+        // Data channels only, so no codecs or interceptors are registered
+        let api = APIBuilder::new().build();
+        let connection = Arc::new(
+            api.new_peer_connection(RTCConfiguration {
+                ice_servers,
+                ..Default::default()
+            })
+            .await
+            .map_err(rtc_error("Failed to create peer connection"))?,
+        );
+
+        let (state_tx, state) = watch::channel(ConnectionState::Disconnected);
+        connection.on_peer_connection_state_change(Box::new(move |rtc_state| {
+            let state = match rtc_state {
+                RTCPeerConnectionState::Connecting => ConnectionState::Connecting,
+                RTCPeerConnectionState::Connected => ConnectionState::Connected,
+                RTCPeerConnectionState::Failed => ConnectionState::Failed,
+                _ => ConnectionState::Disconnected,
+            };
+            tracing::info!("WebRTC connection state: {}", rtc_state);
+            let _ = state_tx.send(state);
+            Box::pin(async {})
+        }));
+
+        let (candidate_tx, candidates) = mpsc::unbounded_channel();
+        let candidate_tx = Mutex::new(Some(candidate_tx));
+        connection.on_ice_candidate(Box::new(move |candidate: Option<RTCIceCandidate>| {
+            let mut sender = candidate_tx.lock().unwrap();
+            match candidate.map(|c| c.to_json()) {
+                Some(Ok(init)) => {
+                    if let (Some(sender), Ok(json)) =
+                        (sender.as_ref(), serde_json::to_string(&init))
+                    {
+                        let _ = sender.send(json);
+                    }
+                }
+                Some(Err(e)) => tracing::warn!("Failed to serialize ICE candidate: {}", e),
+                // Gathering finished; closing the channel ends next_local_candidate
+                None => *sender = None,
+            }
+            Box::pin(async {})
+        }));
+
+        let (incoming_tx, incoming) = mpsc::unbounded_channel();
+        let (open_tx, open) = watch::channel(HashSet::new());
+        let channels = Arc::new(Channels {
+            channels: Mutex::new(HashMap::new()),
+            open: open_tx,
+            incoming: incoming_tx,
+        });
+
+        // The answering side receives the channels the offerer created
+        let shared = channels.clone();
+        connection.on_data_channel(Box::new(move |data_channel| {
+            attach_channel(data_channel, &shared);
+            Box::pin(async {})
+        }));
+
+        Ok(Self {
+            connection,
+            state,
+            candidates,
+            channels,
+            open,
+            incoming,
+        })
+    }
+
+    /// Get the connection state
+    pub fn state(&self) -> ConnectionState {
+        *self.state.borrow()
+    }
+
+    /// Subscribe to connection state changes
+    pub fn state_changes(&self) -> watch::Receiver<ConnectionState> {
+        self.state.clone()
+    }
+
+    /// Whether ICE candidate gathering has finished
+    pub fn is_gathering_complete(&self) -> bool {
+        self.connection.ice_gathering_state() == RTCIceGatheringState::Complete
     }
 
     /// Create an SDP offer
     pub async fn create_offer(&mut self) -> Result<String> {
-        // TODO: Implement SDP offer creation
-        Err(ada_remote_core::Error::Network(
-            "WebRTC not implemented".to_string(),
-        ))
+        let offer = self
+            .connection
+            .create_offer(None)
+            .await
+            .map_err(rtc_error("Failed to create offer"))?;
+        self.connection
+            .set_local_description(offer.clone())
+            .await
+            .map_err(rtc_error("Failed to apply local offer"))?;
+        Ok(offer.sdp)
     }
 
     /// Create an SDP answer
-    pub async fn create_answer(&mut self, _offer: &str) -> Result<String> {
-        // TODO: Implement SDP answer creation
-        Err(ada_remote_core::Error::Network(
-            "WebRTC not implemented".to_string(),
-        ))
+    pub async fn create_answer(&mut self, offer: &str) -> Result<String> {
+        let offer =
+            RTCSessionDescription::offer(offer.to_string()).map_err(rtc_error("Invalid offer"))?;
+        self.connection
+            .set_remote_description(offer)
+            .await
+            .map_err(rtc_error("Failed to apply remote offer"))?;
+
+        let answer = self
+            .connection
+            .create_answer(None)
+            .await
+            .map_err(rtc_error("Failed to create answer"))?;
+        self.connection
+            .set_local_description(answer.clone())
+            .await
+            .map_err(rtc_error("Failed to apply local answer"))?;
+        Ok(answer.sdp)
     }
 
-    /// Set remote description
-    pub async fn set_remote_description(&mut self, _sdp: &str) -> Result<()> {
-        // TODO: Implement setting remote SDP
-        Ok(())
+    /// Set remote description from the peer's answer to our offer
+    pub async fn set_remote_description(&mut self, sdp: &str) -> Result<()> {
+        let answer =
+            RTCSessionDescription::answer(sdp.to_string()).map_err(rtc_error("Invalid answer"))?;
+        self.connection
+            .set_remote_description(answer)
+            .await
+            .map_err(rtc_error("Failed to apply remote answer"))
     }
 
     /// Add ICE candidate
-    pub async fn add_ice_candidate(&mut self, _candidate: &str) -> Result<()> {
-        // TODO: Implement adding ICE candidate
-        Ok(())
+    pub async fn add_ice_candidate(&mut self, candidate: &str) -> Result<()> {
+        let candidate: RTCIceCandidateInit = serde_json::from_str(candidate)?;
+        self.connection
+            .add_ice_candidate(candidate)
+            .await
+            .map_err(rtc_error("Failed to add ICE candidate"))
+    }
+
+    /// Next local ICE candidate to send to the peer, or `None` once
+    /// gathering has finished
+    pub async fn next_local_candidate(&mut self) -> Option<String> {
+        self.candidates.recv().await
     }
 
     /// Create a data channel
-    pub async fn create_data_channel(&mut self, _label: &str) -> Result<()> {
-        // TODO: Implement data channel creation
+    ///
+    /// Reliable channels are ordered and retransmit until delivery;
+    /// unreliable ones are unordered and never retransmit. Call this before
+    /// `create_offer` so the offer negotiates the channel.
+    pub async fn create_data_channel(&mut self, label: &str, reliable: bool) -> Result<()> {
+        let options = (!reliable).then(|| RTCDataChannelInit {
+            ordered: Some(false),
+            max_retransmits: Some(0),
+            ..Default::default()
+        });
+        let data_channel = self
+            .connection
+            .create_data_channel(label, options)
+            .await
+            .map_err(rtc_error("Failed to create data channel"))?;
+        attach_channel(data_channel, &self.channels);
         Ok(())
     }
+
+    /// Subscribe to the labels of the currently open data channels
+    pub fn open_channels(&self) -> watch::Receiver<HashSet<String>> {
+        self.open.clone()
+    }
+
+    /// Wait until the data channel `label` is open on both ends
+    pub async fn wait_for_channel(&mut self, label: &str) -> Result<()> {
+        self.open
+            .wait_for(|open| open.contains(label))
+            .await
+            .map(|_| ())
+            .map_err(|_| ada_remote_core::Error::Network("Peer connection dropped".to_string()))
+    }
+
+    /// Send bytes over the data channel `label`
+    pub async fn send(&self, label: &str, data: &[u8]) -> Result<()> {
+        let data_channel = self
+            .channels
+            .channels
+            .lock()
+            .unwrap()
+            .get(label)
+            .filter(|_| self.open.borrow().contains(label))
+            .cloned()
+            .ok_or_else(|| {
+                ada_remote_core::Error::Network(format!("Data channel '{}' is not open", label))
+            })?;
+        data_channel
+            .send(&Bytes::copy_from_slice(data))
+            .await
+            .map_err(rtc_error("Data channel send failed"))?;
+        Ok(())
+    }
+
+    /// Receive the next message from any data channel
+    pub async fn receive(&mut self) -> Option<Vec<u8>> {
+        self.incoming.recv().await
+    }
+
+    /// Read the current transport counters from `getStats`
+    ///
+    /// Data channels carry no RTP, so packet loss is not reported.
+    pub(crate) async fn sample_stats(&self) -> TransportSample {
+        let mut sample = TransportSample::default();
+        for report in self.connection.get_stats().await.reports.into_values() {
+            match report {
+                StatsReportType::CandidatePair(pair)
+                    if pair.nominated && pair.current_round_trip_time > 0.0 =>
+                {
+                    sample.rtt = Some(Duration::from_secs_f64(pair.current_round_trip_time));
+                }
+                StatsReportType::Transport(transport) => {
+                    sample.bytes_sent += transport.bytes_sent as u64;
+                    sample.bytes_received += transport.bytes_received as u64;
+                }
+                _ => {}
+            }
+        }
+        sample
+    }
+
+    /// Close the peer connection
+    pub async fn close(&mut self) -> Result<()> {
+        self.connection
+            .close()
+            .await
+            .map_err(rtc_error("Failed to close peer connection"))
+    }
+}
+
+#[async_trait]
+impl Transport for WebRtcPeer {
+    async fn send(
+        &mut self,
+        channel: Channel,
+        message: &ProtocolMessage,
+        compression: Compression,
+    ) -> Result<()> {
+        let frame = message.to_bytes_with(compression)?;
+        WebRtcPeer::send(self, channel.label(), &frame).await
+    }
+
+    async fn recv(&mut self) -> Option<ProtocolMessage> {
+        let mut state = self.state.clone();
+        loop {
+            let data = tokio::select! {
+                data = self.incoming.recv() => data?,
+                // The borrowed value is not Send, so only the outcome leaves the future
+                _ = async {
+                    let _ = state
+                        .wait_for(|state| {
+                            matches!(state, ConnectionState::Failed | ConnectionState::Disconnected)
+                        })
+                        .await;
+                } => return None,
+            };
+            match ProtocolMessage::from_bytes(&data) {
+                Ok(message) => return Some(message),
+                Err(e) => tracing::warn!("Invalid message from peer: {}", e),
+            }
+        }
+    }
+
+    async fn stats(&mut self) -> TransportSample {
+        self.sample_stats().await
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        WebRtcPeer::close(self).await
+    }
 }
 
-impl Default for WebRtcPeer {
-    fn default() -> Self {
-        Self::new().unwrap()
+/// Route a data channel's events into the peer's shared state
+fn attach_channel(data_channel: Arc<RTCDataChannel>, shared: &Arc<Channels>) {
+    let label = data_channel.label().to_string();
+    tracing::info!("Data channel '{}' attached", label);
+
+    let (open_label, channels) = (label.clone(), shared.clone());
+    data_channel.on_open(Box::new(move || {
+        tracing::info!("Data channel '{}' open", open_label);
+        channels.open.send_modify(|open| {
+            open.insert(open_label);
+        });
+        Box::pin(async {})
+    }));
+    let (close_label, channels) = (label.clone(), shared.clone());
+    data_channel.on_close(Box::new(move || {
+        channels.open.send_modify(|open| {
+            open.remove(&close_label);
+        });
+        Box::pin(async {})
+    }));
+    let incoming = shared.incoming.clone();
+    data_channel.on_message(Box::new(move |message: DataChannelMessage| {
+        let _ = incoming.send(message.data.to_vec());
+        Box::pin(async {})
+    }));
+    shared.channels.lock().unwrap().insert(label, data_channel);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Forward trickled candidates from one peer to the other until
+    /// gathering finishes
+    async fn trickle(from: &mut WebRtcPeer, to: &mut WebRtcPeer) {
+        while let Some(candidate) = from.next_local_candidate().await {
+            to.add_ice_candidate(&candidate).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_loopback_data_channel() {
+        // Host candidates only, so the test needs no network access
+        let config = NetworkConfig {
+            stun_servers: vec![],
+            ..Default::default()
+        };
+        let mut host = WebRtcPeer::new(&config).await.unwrap();
+        let mut client = WebRtcPeer::new(&config).await.unwrap();
+
+        host.create_data_channel("ada-remote", true).await.unwrap();
+        let offer = host.create_offer().await.unwrap();
+        let answer = client.create_answer(&offer).await.unwrap();
+        host.set_remote_description(&answer).await.unwrap();
+        trickle(&mut host, &mut client).await;
+        trickle(&mut client, &mut host).await;
+
+        tokio::time::timeout(Duration::from_secs(10), async {
+            host.wait_for_channel("ada-remote").await.unwrap();
+            client.wait_for_channel("ada-remote").await.unwrap();
+        })
+        .await
+        .expect("data channel did not open");
+        assert_eq!(host.state(), ConnectionState::Connected);
+        assert!(host.is_gathering_complete());
+
+        host.send("ada-remote", b"hello").await.unwrap();
+        assert_eq!(client.receive().await.unwrap(), b"hello");
+        client.send("ada-remote", b"hi back").await.unwrap();
+        assert_eq!(host.receive().await.unwrap(), b"hi back");
+
+        host.close().await.unwrap();
+        client.close().await.unwrap();
     }
 }
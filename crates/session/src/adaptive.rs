@@ -0,0 +1,241 @@
+//! Adaptive bitrate
+//!
+//! [`AdaptiveController`] turns periodic [`ConnectionStats`] into encoder
+//! bitrate changes with an AIMD loop: sustained loss or a saturated RTT cuts
+//! the bitrate by a factor, while a clean link raises it by a fixed step.
+//! Under a send cap the bitrate stays below the cap with room for audio and
+//! input, so the network does not have to drop frames to enforce it.
+
+use ada_remote_codec::{CodecType, EncoderConfig, VideoEncoder};
+use ada_remote_core::{Result, VideoQuality};
+use ada_remote_network::ConnectionStats;
+
+/// Starting encoder parameters for a quality setting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QualityPreset {
+    pub width: u32,
+    pub height: u32,
+    pub fps: u32,
+    pub bitrate_kbps: u32,
+}
+
+impl QualityPreset {
+    /// See [`EncoderConfig::from_quality`]; presets are the same for every
+    /// codec
+    pub fn for_quality(quality: VideoQuality) -> Self {
+        let config = EncoderConfig::from_quality(quality, CodecType::H264);
+        Self {
+            width: config.width,
+            height: config.height,
+            fps: config.fps,
+            bitrate_kbps: config.bitrate,
+        }
+    }
+}
+
+/// Share of a send cap the video may take
+const CAP_SHARE: f64 = 0.8;
+
+/// Tunables of the adaptive loop
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdaptiveConfig {
+    pub min_bitrate_kbps: u32,
+    pub max_bitrate_kbps: u32,
+    /// Added after `clean_samples` clean samples in a row
+    pub increase_kbps: u32,
+    /// Multiplier applied after `congested_samples` congested samples in a row
+    pub decrease_factor: f64,
+    /// Loss above this counts as congestion
+    pub loss_threshold_pct: f64,
+    /// RTT above this counts as congestion, as queues fill before packets drop
+    pub rtt_threshold_ms: f64,
+    pub congested_samples: u32,
+    pub clean_samples: u32,
+}
+
+impl Default for AdaptiveConfig {
+    fn default() -> Self {
+        Self {
+            min_bitrate_kbps: 300,
+            max_bitrate_kbps: 8000,
+            increase_kbps: 200,
+            decrease_factor: 0.7,
+            loss_threshold_pct: 2.0,
+            rtt_threshold_ms: 400.0,
+            congested_samples: 2,
+            clean_samples: 3,
+        }
+    }
+}
+
+/// AIMD bitrate controller, fed one stats sample per [`STATS_INTERVAL`]
+///
+/// [`STATS_INTERVAL`]: ada_remote_network::STATS_INTERVAL
+#[derive(Debug, Clone)]
+pub struct AdaptiveController {
+    config: AdaptiveConfig,
+    bitrate_kbps: u32,
+    congested_streak: u32,
+    clean_streak: u32,
+}
+
+impl AdaptiveController {
+    pub fn new(config: AdaptiveConfig, start_kbps: u32) -> Self {
+        Self {
+            bitrate_kbps: start_kbps.clamp(config.min_bitrate_kbps, config.max_bitrate_kbps),
+            config,
+            congested_streak: 0,
+            clean_streak: 0,
+        }
+    }
+
+    /// Controller starting from the preset for `quality`
+    pub fn for_quality(quality: VideoQuality) -> Self {
+        Self::new(
+            AdaptiveConfig::default(),
+            QualityPreset::for_quality(quality).bitrate_kbps,
+        )
+    }
+
+    /// Start over from `start_kbps`, within the configured range
+    pub fn restart(&mut self, start_kbps: u32) {
+        *self = Self::new(self.config, start_kbps);
+    }
+
+    /// Current target bitrate
+    pub fn bitrate_kbps(&self) -> u32 {
+        self.bitrate_kbps
+    }
+
+    /// Feed a stats sample, returning the new target if it changed
+    pub fn update(&mut self, stats: &ConnectionStats) -> Option<u32> {
+        let config = &self.config;
+        let congested = stats
+            .packet_loss_pct
+            .is_some_and(|loss| loss > config.loss_threshold_pct)
+            || stats
+                .rtt_ms
+                .is_some_and(|rtt| rtt > config.rtt_threshold_ms);
+
+        let previous = self.bitrate_kbps;
+        if congested {
+            self.clean_streak = 0;
+            self.congested_streak += 1;
+            if self.congested_streak >= config.congested_samples {
+                self.congested_streak = 0;
+                // Back off from what actually got through if that is lower
+                let base = match stats.current_bitrate_kbps {
+                    0 => self.bitrate_kbps,
+                    sent => self.bitrate_kbps.min(sent as u32),
+                };
+                self.bitrate_kbps =
+                    ((base as f64 * config.decrease_factor) as u32).max(config.min_bitrate_kbps);
+            }
+        } else {
+            self.congested_streak = 0;
+            self.clean_streak += 1;
+            if self.clean_streak >= config.clean_samples {
+                self.clean_streak = 0;
+                self.bitrate_kbps =
+                    (self.bitrate_kbps + config.increase_kbps).min(config.max_bitrate_kbps);
+            }
+        }
+
+        if let Some(cap) = stats.max_send_kbps {
+            let ceiling = (cap as f64 * CAP_SHARE) as u32;
+            self.bitrate_kbps = self
+                .bitrate_kbps
+                .min(ceiling)
+                .max(config.min_bitrate_kbps.min(ceiling));
+        }
+
+        (self.bitrate_kbps != previous).then_some(self.bitrate_kbps)
+    }
+
+    /// Feed a stats sample and pass any change on to `encoder`
+    pub fn apply(&mut self, stats: &ConnectionStats, encoder: &mut dyn VideoEncoder) -> Result<()> {
+        if let Some(bitrate) = self.update(stats) {
+            tracing::debug!("Adaptive bitrate now {} kbps", bitrate);
+            encoder.set_bitrate(bitrate)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(loss: f64) -> ConnectionStats {
+        ConnectionStats {
+            rtt_ms: Some(40.0),
+            packet_loss_pct: Some(loss),
+            current_bitrate_kbps: 5000,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_backs_off_under_loss_and_recovers() {
+        let mut controller = AdaptiveController::new(AdaptiveConfig::default(), 4000);
+
+        // A single lossy sample is not sustained loss
+        assert_eq!(controller.update(&sample(10.0)), None);
+        assert_eq!(controller.update(&sample(0.0)), None);
+
+        for _ in 0..10 {
+            controller.update(&sample(10.0));
+        }
+        let backed_off = controller.bitrate_kbps();
+        assert!(backed_off < 4000 / 4, "{}", backed_off);
+
+        // Sustained loss never goes below the floor
+        for _ in 0..20 {
+            controller.update(&sample(10.0));
+        }
+        assert_eq!(controller.bitrate_kbps(), 300);
+
+        // Recovery is additive: one step per three clean samples
+        for _ in 0..9 {
+            controller.update(&sample(0.0));
+        }
+        assert_eq!(controller.bitrate_kbps(), 300 + 3 * 200);
+
+        for _ in 0..200 {
+            controller.update(&sample(0.0));
+        }
+        assert_eq!(controller.bitrate_kbps(), 8000);
+    }
+
+    #[test]
+    fn test_rtt_counts_as_congestion() {
+        let mut controller = AdaptiveController::for_quality(VideoQuality::Adaptive);
+        let queued = ConnectionStats {
+            rtt_ms: Some(900.0),
+            ..Default::default()
+        };
+        controller.update(&queued);
+        assert_eq!(controller.update(&queued), Some(1400));
+    }
+
+    #[test]
+    fn test_stays_under_send_cap() {
+        let mut controller = AdaptiveController::new(AdaptiveConfig::default(), 4000);
+        let capped = ConnectionStats {
+            max_send_kbps: Some(1000),
+            ..sample(0.0)
+        };
+        assert_eq!(controller.update(&capped), Some(800));
+        for _ in 0..30 {
+            controller.update(&capped);
+        }
+        assert_eq!(controller.bitrate_kbps(), 800);
+
+        // Caps below the floor win over it
+        let tight = ConnectionStats {
+            max_send_kbps: Some(200),
+            ..sample(0.0)
+        };
+        assert_eq!(controller.update(&tight), Some(160));
+    }
+}
@@ -0,0 +1,256 @@
+//! Host-side checks of session requests
+//!
+//! Wrong passwords are throttled per session: after a few free attempts each
+//! failure locks the session for twice as long as the previous one, and after
+//! `max_failures` it stays locked for the rest of its life. A correct
+//! password clears the count.
+
+use ada_remote_core::{protocol_incompatibility, ProtocolMessage, SessionConfig, SessionId};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// `SessionResponse` reason while a session is locked out
+pub const TOO_MANY_ATTEMPTS: &str = "Too many attempts, try later";
+
+/// How quickly repeated wrong passwords lock a session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockoutPolicy {
+    /// Failures allowed before any lockout
+    pub free_attempts: u32,
+    /// Lockout after the first failure beyond the free ones, doubled after
+    /// each further failure
+    pub base_delay: Duration,
+    /// Failures after which the session is locked for good
+    pub max_failures: u32,
+}
+
+impl Default for LockoutPolicy {
+    fn default() -> Self {
+        Self {
+            free_attempts: 3,
+            base_delay: Duration::from_secs(1),
+            max_failures: 10,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Attempts {
+    failures: u32,
+    locked_until: Option<Instant>,
+}
+
+/// Failed password attempts by session
+#[derive(Debug, Default)]
+pub struct AttemptLimiter {
+    policy: LockoutPolicy,
+    sessions: HashMap<SessionId, Attempts>,
+}
+
+impl AttemptLimiter {
+    pub fn new(policy: LockoutPolicy) -> Self {
+        Self {
+            policy,
+            sessions: HashMap::new(),
+        }
+    }
+
+    /// Whether `session_id` refuses password attempts at `now`
+    pub fn is_locked(&self, session_id: SessionId, now: Instant) -> bool {
+        self.sessions.get(&session_id).is_some_and(|attempts| {
+            attempts.failures >= self.policy.max_failures
+                || attempts.locked_until.is_some_and(|until| now < until)
+        })
+    }
+
+    /// Failed attempts since the last success
+    pub fn failures(&self, session_id: SessionId) -> u32 {
+        self.sessions
+            .get(&session_id)
+            .map_or(0, |attempts| attempts.failures)
+    }
+
+    pub fn record_failure(&mut self, session_id: SessionId, now: Instant) {
+        let policy = self.policy;
+        let attempts = self.sessions.entry(session_id).or_default();
+        attempts.failures += 1;
+        if attempts.failures >= policy.max_failures {
+            tracing::warn!(
+                "Session {} locked after {} wrong passwords",
+                session_id,
+                attempts.failures
+            );
+        } else if attempts.failures > policy.free_attempts {
+            let doublings = attempts.failures - policy.free_attempts - 1;
+            let delay = policy
+                .base_delay
+                .saturating_mul(2u32.saturating_pow(doublings));
+            tracing::warn!(
+                "Session {} locked for {:?} after {} wrong passwords",
+                session_id,
+                delay,
+                attempts.failures
+            );
+            attempts.locked_until = Some(now + delay);
+        }
+    }
+
+    pub fn record_success(&mut self, session_id: SessionId) {
+        self.sessions.remove(&session_id);
+    }
+}
+
+fn response(reason: Option<String>) -> ProtocolMessage {
+    ProtocolMessage::SessionResponse {
+        accepted: reason.is_none(),
+        reason,
+    }
+}
+
+/// The host's `SessionResponse` to a client's `SessionRequest` for the
+/// session described by `config`, or `None` for other messages
+pub fn authorize(
+    config: &SessionConfig,
+    limiter: &mut AttemptLimiter,
+    request: &ProtocolMessage,
+    now: Instant,
+) -> Option<ProtocolMessage> {
+    let ProtocolMessage::SessionRequest {
+        session_id,
+        password,
+        protocol_version,
+        ..
+    } = request
+    else {
+        return None;
+    };
+
+    if let Some(reason) = protocol_incompatibility(*protocol_version) {
+        return Some(response(Some(reason)));
+    }
+    if *session_id != config.session_id {
+        return Some(response(Some(format!("Unknown session {}", session_id))));
+    }
+    let Some(hash) = &config.password_hash else {
+        return Some(response(None));
+    };
+
+    // Checked before the password so a locked session gives nothing away
+    if limiter.is_locked(*session_id, now) {
+        return Some(response(Some(TOO_MANY_ATTEMPTS.to_string())));
+    }
+    let verified = match password {
+        Some(password) => ada_remote_crypto::verify_password(password, hash),
+        None => Ok(false),
+    };
+    Some(match verified {
+        Ok(true) => {
+            limiter.record_success(*session_id);
+            response(None)
+        }
+        Ok(false) => {
+            limiter.record_failure(*session_id, now);
+            response(Some("Wrong password".to_string()))
+        }
+        Err(e) => {
+            tracing::error!("Password check failed: {}", e);
+            response(Some("Password check failed".to_string()))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ada_remote_core::{ConnectionMode, PROTOCOL_VERSION};
+    use ada_remote_crypto::{hash_password_with, PasswordPolicy};
+
+    #[test]
+    fn test_lockout_progression() {
+        let mut limiter = AttemptLimiter::new(LockoutPolicy::default());
+        let session = SessionId::from_seed(1);
+        let other = SessionId::from_seed(2);
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            limiter.record_failure(session, start);
+            assert!(!limiter.is_locked(session, start));
+        }
+
+        // Then 1 s, 2 s, 4 s...
+        let mut now = start;
+        for delay in [1, 2, 4] {
+            limiter.record_failure(session, now);
+            assert!(limiter.is_locked(session, now));
+            assert!(limiter.is_locked(session, now + Duration::from_millis(delay * 1000 - 1)));
+            now += Duration::from_secs(delay);
+            assert!(!limiter.is_locked(session, now));
+        }
+        assert!(!limiter.is_locked(other, now));
+
+        // Locked for good at the tenth failure
+        for _ in 0..4 {
+            limiter.record_failure(session, now);
+        }
+        assert_eq!(limiter.failures(session), 10);
+        assert!(limiter.is_locked(session, now + Duration::from_secs(86_400)));
+
+        limiter.record_success(session);
+        assert!(!limiter.is_locked(session, now));
+        assert_eq!(limiter.failures(session), 0);
+    }
+
+    #[test]
+    fn test_authorize_throttles_and_resets() {
+        let cheap = PasswordPolicy {
+            memory_kib: 8,
+            iterations: 1,
+            parallelism: 1,
+        };
+        let config = SessionConfig {
+            session_id: SessionId::from_seed(3),
+            mode: ConnectionMode::FullControl,
+            password_hash: Some(hash_password_with("hunter2", &cheap).unwrap()),
+            clipboard_sync: false,
+            quality: Default::default(),
+            audio: false,
+            pointer_mode: Default::default(),
+        };
+        let request = |password: &str| ProtocolMessage::SessionRequest {
+            session_id: config.session_id,
+            password: Some(password.to_string()),
+            mode: ConnectionMode::FullControl,
+            protocol_version: PROTOCOL_VERSION,
+        };
+        let reason = |message: Option<ProtocolMessage>| match message {
+            Some(ProtocolMessage::SessionResponse { accepted, reason }) => {
+                assert_eq!(accepted, reason.is_none());
+                reason
+            }
+            other => panic!("unexpected response: {:?}", other),
+        };
+
+        let mut limiter = AttemptLimiter::new(LockoutPolicy {
+            free_attempts: 1,
+            ..Default::default()
+        });
+        let now = Instant::now();
+        assert!(authorize(&config, &mut limiter, &ProtocolMessage::Heartbeat, now).is_none());
+
+        let wrong = reason(authorize(&config, &mut limiter, &request("guess"), now));
+        assert_eq!(wrong.as_deref(), Some("Wrong password"));
+        reason(authorize(&config, &mut limiter, &request("guess"), now));
+
+        // Locked: even the right password is refused until the delay passes
+        let locked = reason(authorize(&config, &mut limiter, &request("hunter2"), now));
+        assert_eq!(locked.as_deref(), Some(TOO_MANY_ATTEMPTS));
+        assert_eq!(limiter.failures(config.session_id), 2);
+
+        let later = now + Duration::from_secs(1);
+        assert_eq!(
+            reason(authorize(&config, &mut limiter, &request("hunter2"), later)),
+            None
+        );
+        assert_eq!(limiter.failures(config.session_id), 0);
+    }
+}
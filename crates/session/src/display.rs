@@ -0,0 +1,122 @@
+//! Stream resolution requested by the client
+//!
+//! A client whose view is smaller than the host screen sends
+//! `DisplayConfig`, and the host encodes at the view's size rather than the
+//! client throwing pixels away. Window resizes arrive in bursts, so a
+//! request only applies once no newer one has come for the debounce period.
+
+use std::time::{Duration, Instant};
+
+/// How long a request has to stand before the encoder follows it
+pub const DISPLAY_CONFIG_DEBOUNCE: Duration = Duration::from_millis(250);
+
+/// Encoded size for a capture of `capture` shown in a view of `view` pixels:
+/// the capture's aspect ratio fitted into the view, no larger than the
+/// capture, with even dimensions as the codecs subsample chroma
+pub fn fit_resolution(capture: (u32, u32), view: (u32, u32)) -> (u32, u32) {
+    let (width, height) = (capture.0.max(1) as f64, capture.1.max(1) as f64);
+    let scale = (view.0 as f64 / width).min(view.1 as f64 / height).min(1.0);
+    if scale >= 1.0 {
+        return capture;
+    }
+    let even = |len: f64| ((len * scale).round() as u32 & !1).max(2);
+    (even(width), even(height))
+}
+
+/// Follows the client's `DisplayConfig` requests
+#[derive(Debug)]
+pub struct DisplayNegotiator {
+    debounce: Duration,
+    /// Latest request in pixels and when it came
+    pending: Option<((u32, u32), Instant)>,
+    view: Option<(u32, u32)>,
+}
+
+impl DisplayNegotiator {
+    pub fn new(debounce: Duration) -> Self {
+        Self {
+            debounce,
+            pending: None,
+            view: None,
+        }
+    }
+
+    /// Take a view of `width`x`height` points at `scale` received at `now`.
+    /// Empty views are ignored.
+    pub fn request(&mut self, width: u32, height: u32, scale: f32, now: Instant) {
+        let scale = if scale.is_finite() && scale > 0.0 {
+            scale as f64
+        } else {
+            1.0
+        };
+        let view = (
+            (width as f64 * scale).round() as u32,
+            (height as f64 * scale).round() as u32,
+        );
+        if view.0 == 0 || view.1 == 0 {
+            return;
+        }
+        self.pending = Some((view, now));
+    }
+
+    /// Client view in pixels currently followed, if any
+    pub fn view(&self) -> Option<(u32, u32)> {
+        self.view
+    }
+
+    /// Size to encode frames captured at `capture` at `now`, applying the
+    /// latest request once it has settled
+    pub fn resolution(&mut self, capture: (u32, u32), now: Instant) -> (u32, u32) {
+        if let Some((view, at)) = self.pending {
+            if now.duration_since(at) >= self.debounce {
+                self.view = Some(view);
+                self.pending = None;
+            }
+        }
+        self.view
+            .map_or(capture, |view| fit_resolution(capture, view))
+    }
+}
+
+impl Default for DisplayNegotiator {
+    fn default() -> Self {
+        Self::new(DISPLAY_CONFIG_DEBOUNCE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fit_keeps_aspect_and_clamps_to_capture() {
+        // Square view of a 16:9 screen
+        assert_eq!(fit_resolution((1920, 1080), (1000, 1000)), (1000, 562));
+        // Larger than the capture
+        assert_eq!(fit_resolution((1280, 720), (3840, 2160)), (1280, 720));
+        // Odd sizes round down to even
+        assert_eq!(fit_resolution((1920, 1080), (999, 999)), (998, 562));
+        assert_eq!(fit_resolution((1920, 1080), (1, 1)), (2, 2));
+    }
+
+    #[test]
+    fn test_burst_of_requests_applies_latest_once_settled() {
+        let mut negotiator = DisplayNegotiator::new(Duration::from_millis(100));
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let capture = (1920, 1080);
+
+        for (i, ms) in [0, 30, 60, 90].into_iter().enumerate() {
+            negotiator.request(800 + i as u32 * 100, 2000, 1.0, at(ms));
+            assert_eq!(negotiator.resolution(capture, at(ms + 10)), capture);
+        }
+        assert_eq!(negotiator.resolution(capture, at(189)), capture);
+        assert_eq!(negotiator.resolution(capture, at(190)), (1100, 618));
+        assert_eq!(negotiator.view(), Some((1100, 2000)));
+
+        // HiDPI clients ask in points
+        negotiator.request(640, 360, 2.0, at(200));
+        negotiator.request(0, 360, 1.0, at(250));
+        assert_eq!(negotiator.resolution(capture, at(300)), (1280, 720));
+    }
+}
@@ -0,0 +1,143 @@
+//! Session events for embedders
+//!
+//! What the session logs for people is also published as [`SessionEvent`]s
+//! for programs such as a GUI or a supervisor. Events go out on a bounded
+//! broadcast channel: a subscriber that falls behind loses the oldest events
+//! and is told how many it missed, and the session never waits for it.
+
+use crate::pipeline::Component;
+use crate::viewers::ViewerId;
+use ada_remote_core::VideoQuality;
+use ada_remote_network::{ConnectionState, NetworkPeer};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+
+/// Events kept for subscribers that fall behind
+pub const EVENT_CAPACITY: usize = 256;
+
+/// Something that happened in a session
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SessionEvent {
+    /// The connection to the peer is up
+    Connected,
+    /// The connection to the peer went down
+    Disconnected {
+        reason: String,
+    },
+    QualityChanged {
+        quality: VideoQuality,
+    },
+    /// A video frame was encoded and handed to the network
+    FrameEncoded {
+        size: usize,
+        is_keyframe: bool,
+    },
+    PeerJoined {
+        viewer: ViewerId,
+    },
+    PeerLeft {
+        viewer: ViewerId,
+    },
+    /// A component failed; `fatal` if it ended the session
+    Error {
+        component: Component,
+        message: String,
+        fatal: bool,
+    },
+}
+
+/// Publisher of a session's events; clones publish to the same subscribers
+#[derive(Debug, Clone)]
+pub struct SessionEvents {
+    sender: broadcast::Sender<SessionEvent>,
+}
+
+impl SessionEvents {
+    pub fn new() -> Self {
+        Self {
+            sender: broadcast::channel(EVENT_CAPACITY).0,
+        }
+    }
+
+    /// Live stream of the events from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<SessionEvent> {
+        self.sender.subscribe()
+    }
+
+    /// Publish `event`; dropped when nobody is subscribed
+    pub fn emit(&self, event: SessionEvent) {
+        let _ = self.sender.send(event);
+    }
+
+    /// Publish `Connected` and `Disconnected` as `peer`'s connection comes
+    /// and goes, until the returned task is aborted or the peer dropped
+    pub fn watch(&self, peer: &NetworkPeer) -> JoinHandle<()> {
+        let mut state = peer.state_changes();
+        let reason = peer.disconnect_reasons();
+        let events = self.clone();
+        tokio::spawn(async move {
+            while state.changed().await.is_ok() {
+                let event = match *state.borrow_and_update() {
+                    ConnectionState::Connected => SessionEvent::Connected,
+                    ConnectionState::Disconnected => SessionEvent::Disconnected {
+                        reason: reason
+                            .borrow()
+                            .clone()
+                            .unwrap_or_else(|| "Disconnected".to_string()),
+                    },
+                    ConnectionState::Failed => SessionEvent::Disconnected {
+                        reason: "Connection failed".to_string(),
+                    },
+                    ConnectionState::Connecting => continue,
+                };
+                events.emit(event);
+            }
+        })
+    }
+}
+
+impl Default for SessionEvents {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ada_remote_core::SessionId;
+    use ada_remote_network::loopback::LoopbackBackend;
+    use ada_remote_network::{ConnectionType, NetworkConfig};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_connect_and_disconnect_published() {
+        let backend = Arc::new(LoopbackBackend::new());
+        let session_id = SessionId::new();
+        let mut host = NetworkPeer::new(session_id, ConnectionType::WebRTC);
+        host.set_backend(backend.clone());
+        let host_task = tokio::spawn(async move {
+            host.accept(&NetworkConfig::default()).await.unwrap();
+            host
+        });
+
+        let events = SessionEvents::new();
+        let mut subscription = events.subscribe();
+        let mut client = NetworkPeer::new(session_id, ConnectionType::WebRTC);
+        client.set_backend(backend);
+        let watcher = events.watch(&client);
+
+        client.connect(&NetworkConfig::default()).await.unwrap();
+        assert_eq!(subscription.recv().await.unwrap(), SessionEvent::Connected);
+        client.disconnect_with("Closing the app").await.unwrap();
+        assert_eq!(
+            subscription.recv().await.unwrap(),
+            SessionEvent::Disconnected {
+                reason: "Closing the app".to_string()
+            }
+        );
+
+        watcher.abort();
+        host_task.await.unwrap().disconnect().await.unwrap();
+    }
+}
@@ -0,0 +1,298 @@
+//! Session setup on a connected peer
+//!
+//! The client asks for the session with a `SessionRequest`, which the host
+//! answers through [`authorize`], cutting off a client it refuses. Once the
+//! session is accepted both peers send `Hello` and keep the
+//! features present in both capability sets. Frames are deflated only when
+//! both sides can inflate them. Each `Hello` carries a fresh public key, from
+//! which both sides get the same short authentication string unless someone
+//! in between replaced the keys.
+
+use crate::auth::{authorize, AttemptLimiter};
+use ada_remote_core::{
+    protocol_incompatibility, Capabilities, ConnectionMode, Error, ProtocolMessage, Result,
+    SessionConfig, PROTOCOL_VERSION,
+};
+use ada_remote_crypto::{compute_sas, public_key_from_bytes, KeyPair};
+use ada_remote_network::NetworkPeer;
+use std::time::Instant;
+
+/// Outcome of a `Hello` exchange
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Handshake {
+    /// Features both peers support
+    pub capabilities: Capabilities,
+    /// Short authentication string for the users to compare
+    pub sas: String,
+}
+
+/// Next message from the peer, failing once the connection is gone
+async fn next_message(peer: &mut NetworkPeer) -> Result<ProtocolMessage> {
+    peer.receive().await.ok_or_else(|| {
+        Error::ConnectionClosed(
+            peer.disconnect_reason()
+                .unwrap_or_else(|| "during session setup".to_string()),
+        )
+    })
+}
+
+/// Send our `Hello` offering `ours` and agree on the features in the
+/// peer's, compressing what we send from then on if both can
+pub async fn exchange_hello(peer: &mut NetworkPeer, ours: Capabilities) -> Result<Handshake> {
+    let keys = KeyPair::generate();
+    peer.send(ProtocolMessage::Hello {
+        protocol_version: PROTOCOL_VERSION,
+        capabilities: ours,
+        public_key: keys.public_key_bytes(),
+    })?;
+    loop {
+        match next_message(peer).await? {
+            ProtocolMessage::Hello {
+                protocol_version,
+                capabilities,
+                public_key,
+            } => {
+                if let Some(reason) = protocol_incompatibility(protocol_version) {
+                    return Err(Error::Session(reason));
+                }
+                let remote = public_key_from_bytes(&public_key)?;
+                let sas = compute_sas(keys.public_key(), &remote, &peer.session_id());
+                let agreed = ours.negotiate(&capabilities);
+                tracing::info!("Negotiated capabilities: {:?}", agreed);
+                peer.set_compression(agreed.frame_compression());
+                return Ok(Handshake {
+                    capabilities: agreed,
+                    sas,
+                });
+            }
+            message => tracing::debug!("Ignoring {:?} before Hello", message),
+        }
+    }
+}
+
+/// Answer the client's `SessionRequest` for the session described by
+/// `config`, then exchange `Hello` offering what `config` allows. A refused
+/// client is disconnected with the reason it was sent.
+pub async fn accept_session(
+    peer: &mut NetworkPeer,
+    config: &SessionConfig,
+    limiter: &mut AttemptLimiter,
+) -> Result<Handshake> {
+    let response = loop {
+        let message = next_message(peer).await?;
+        match authorize(config, limiter, &message, Instant::now()) {
+            Some(response) => break response,
+            None => tracing::debug!("Ignoring {:?} before SessionRequest", message),
+        }
+    };
+    let refusal = match &response {
+        ProtocolMessage::SessionResponse {
+            accepted: false,
+            reason,
+        } => Some(reason.clone().unwrap_or_else(|| "Refused".to_string())),
+        _ => None,
+    };
+    peer.send(response)?;
+    if let Some(reason) = refusal {
+        tracing::warn!("Refused session request: {}", reason);
+        peer.disconnect_with(&reason).await?;
+        return Err(Error::Authentication(reason));
+    }
+
+    exchange_hello(peer, Capabilities::for_session(config)).await
+}
+
+/// Ask the host for its session in `mode`, then exchange `Hello` offering
+/// `capabilities`
+pub async fn join_session(
+    peer: &mut NetworkPeer,
+    password: Option<String>,
+    mode: ConnectionMode,
+    capabilities: Capabilities,
+) -> Result<Handshake> {
+    peer.send(ProtocolMessage::SessionRequest {
+        session_id: peer.session_id(),
+        password,
+        mode,
+        protocol_version: PROTOCOL_VERSION,
+    })?;
+    loop {
+        match next_message(peer).await? {
+            ProtocolMessage::SessionResponse { accepted: true, .. } => break,
+            ProtocolMessage::SessionResponse { reason, .. } => {
+                return Err(Error::Authentication(
+                    reason.unwrap_or_else(|| "Refused".to_string()),
+                ));
+            }
+            message => tracing::debug!("Ignoring {:?} before SessionResponse", message),
+        }
+    }
+
+    exchange_hello(peer, capabilities).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::LockoutPolicy;
+    use ada_remote_crypto::{hash_password_with, PasswordPolicy};
+    use ada_remote_network::loopback::loopback_pair;
+    use ada_remote_network::{NetworkConfig, STATS_INTERVAL};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_hello_negotiates_over_loopback() {
+        let (mut host, mut client) = loopback_pair(&NetworkConfig::default()).await.unwrap();
+
+        // The client has no audio; the host has clipboard sync off
+        let hosting = Capabilities {
+            clipboard: false,
+            ..Capabilities::supported()
+        };
+        let joining = Capabilities {
+            audio: false,
+            ..Capabilities::supported()
+        };
+        let (hosted, joined) = tokio::join!(
+            exchange_hello(&mut host, hosting),
+            exchange_hello(&mut client, joining),
+        );
+        let hosted = hosted.unwrap();
+        assert_eq!(joined.unwrap(), hosted);
+        let agreed = hosted.capabilities;
+        assert!(agreed.input && agreed.file_transfer && agreed.compression);
+        assert!(!agreed.audio && !agreed.clipboard);
+        assert_eq!(hosted.sas.len(), 6);
+    }
+
+    #[tokio::test]
+    async fn test_sas_differs_between_sessions() {
+        let mut codes = Vec::new();
+        for _ in 0..2 {
+            let (mut host, mut client) = loopback_pair(&NetworkConfig::default()).await.unwrap();
+            let (hosted, joined) = tokio::join!(
+                exchange_hello(&mut host, Capabilities::supported()),
+                exchange_hello(&mut client, Capabilities::supported()),
+            );
+            let sas = hosted.unwrap().sas;
+            assert_eq!(joined.unwrap().sas, sas);
+            codes.push(sas);
+        }
+        // Fresh keys each time; equal codes are a one in a million chance
+        assert_ne!(codes[0], codes[1]);
+    }
+
+    #[tokio::test]
+    async fn test_small_order_key_refused() {
+        let (mut host, client) = loopback_pair(&NetworkConfig::default()).await.unwrap();
+        client
+            .send(ProtocolMessage::Hello {
+                protocol_version: PROTOCOL_VERSION,
+                capabilities: Capabilities::supported(),
+                public_key: [0; 32],
+            })
+            .unwrap();
+        assert!(matches!(
+            exchange_hello(&mut host, Capabilities::supported()).await,
+            Err(Error::Authentication(_))
+        ));
+    }
+
+    /// Bytes `client` took to receive a large, compressible message from
+    /// `host`
+    async fn bytes_for_large_message(host: &NetworkPeer, client: &mut NetworkPeer) -> u64 {
+        let content = "compressible ".repeat(5000);
+        host.send(ProtocolMessage::Clipboard {
+            content: content.clone(),
+        })
+        .unwrap();
+        match client.receive().await {
+            Some(ProtocolMessage::Clipboard { content: received }) => {
+                assert_eq!(received, content)
+            }
+            other => panic!("unexpected message: {:?}", other),
+        }
+        // Until the statistics are sampled after it arrived
+        tokio::time::sleep(STATS_INTERVAL + Duration::from_millis(200)).await;
+        client.stats().bytes_received
+    }
+
+    #[tokio::test]
+    async fn test_compression_only_when_agreed() {
+        let size = "compressible ".repeat(5000).len() as u64;
+
+        // A client that cannot inflate frames gets them as they are
+        let (mut host, mut client) = loopback_pair(&NetworkConfig::default()).await.unwrap();
+        let plain = Capabilities {
+            compression: false,
+            ..Capabilities::supported()
+        };
+        let (hosted, joined) = tokio::join!(
+            exchange_hello(&mut host, Capabilities::supported()),
+            exchange_hello(&mut client, plain),
+        );
+        assert!(!hosted.unwrap().capabilities.compression);
+        assert!(!joined.unwrap().capabilities.compression);
+        assert!(bytes_for_large_message(&host, &mut client).await > size);
+
+        let (mut host, mut client) = loopback_pair(&NetworkConfig::default()).await.unwrap();
+        let (hosted, joined) = tokio::join!(
+            exchange_hello(&mut host, Capabilities::supported()),
+            exchange_hello(&mut client, Capabilities::supported()),
+        );
+        assert!(hosted.unwrap().capabilities.compression);
+        assert!(joined.unwrap().capabilities.compression);
+        assert!(bytes_for_large_message(&host, &mut client).await < size / 10);
+    }
+
+    #[tokio::test]
+    async fn test_refused_client_cut_off() {
+        let (mut host, mut client) = loopback_pair(&NetworkConfig::default()).await.unwrap();
+        let cheap = PasswordPolicy {
+            memory_kib: 8,
+            iterations: 1,
+            parallelism: 1,
+        };
+        let config = SessionConfig {
+            session_id: host.session_id(),
+            mode: ConnectionMode::FullControl,
+            password_hash: Some(hash_password_with("secret", &cheap).unwrap()),
+            clipboard_sync: true,
+            quality: Default::default(),
+            audio: false,
+            pointer_mode: Default::default(),
+        };
+        let mut limiter = AttemptLimiter::new(LockoutPolicy::default());
+        let mode = ConnectionMode::FullControl;
+        let offered = Capabilities::supported();
+
+        let (hosted, joined) = tokio::join!(
+            accept_session(&mut host, &config, &mut limiter),
+            join_session(&mut client, Some("wrong".to_string()), mode, offered),
+        );
+        assert!(matches!(hosted, Err(Error::Authentication(_))));
+        match joined {
+            Err(Error::Authentication(reason)) => assert_eq!(reason, "Wrong password"),
+            other => panic!("expected a refusal, got {:?}", other),
+        }
+        assert_eq!(limiter.failures(config.session_id), 1);
+        match client.receive().await {
+            Some(ProtocolMessage::Disconnect { reason }) => assert_eq!(reason, "Wrong password"),
+            other => panic!("expected a disconnect, got {:?}", other),
+        }
+
+        // The right password is let through to Hello
+        let (mut host, mut client) = loopback_pair(&NetworkConfig::default()).await.unwrap();
+        let config = SessionConfig {
+            session_id: host.session_id(),
+            ..config
+        };
+        let (hosted, joined) = tokio::join!(
+            accept_session(&mut host, &config, &mut limiter),
+            join_session(&mut client, Some("secret".to_string()), mode, offered),
+        );
+        let hosted = hosted.unwrap();
+        assert_eq!(joined.unwrap(), hosted);
+        assert!(hosted.capabilities.clipboard && !hosted.capabilities.audio);
+    }
+}
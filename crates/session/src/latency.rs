@@ -0,0 +1,220 @@
+//! End-to-end frame latency
+//!
+//! Frames carry the host clock at capture and at send. The client estimates
+//! the offset between its clock and the host's with `ClockSync` probes, then
+//! splits each frame's capture-to-display time into encode, network and
+//! decode/display stages.
+
+use ada_remote_core::ProtocolMessage;
+use std::collections::VecDeque;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Probes kept for the offset estimate
+const CLOCK_SAMPLES: usize = 8;
+
+/// Weight of a new frame in the smoothed report
+const SMOOTHING: f64 = 0.125;
+
+/// Wall clock in microseconds since the Unix epoch, the unit of `ClockSync`
+/// and the `VideoFrame` latency stamps
+pub fn now_us() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_micros() as u64)
+        .unwrap_or(0)
+}
+
+/// Host side of a probe: the reply to a client's `ClockSync`, or `None` for
+/// other messages and for replies
+pub fn clock_sync_reply(message: &ProtocolMessage, now: u64) -> Option<ProtocolMessage> {
+    match message {
+        ProtocolMessage::ClockSync { t0, t1: 0 } => {
+            Some(ProtocolMessage::ClockSync { t0: *t0, t1: now })
+        }
+        _ => None,
+    }
+}
+
+/// Client side estimate of how far the host clock is ahead of ours
+#[derive(Debug, Clone, Default)]
+pub struct ClockOffsetEstimator {
+    /// (round trip, offset) of recent probes, in microseconds
+    samples: VecDeque<(u64, i64)>,
+}
+
+impl ClockOffsetEstimator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Probe to send to the host
+    pub fn request(now: u64) -> ProtocolMessage {
+        ProtocolMessage::ClockSync { t0: now, t1: 0 }
+    }
+
+    /// Record a probe sent at `t0`, stamped by the host at `t1` and received
+    /// back at `t2`
+    pub fn add_sample(&mut self, t0: u64, t1: u64, t2: u64) {
+        let round_trip = t2.saturating_sub(t0);
+        // The host stamped the probe halfway through the round trip
+        let offset = t1 as i64 - (t0 as i64 + round_trip as i64 / 2);
+        if self.samples.len() == CLOCK_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((round_trip, offset));
+    }
+
+    /// Feed a `ClockSync` reply received at `now`; other messages are ignored
+    pub fn handle(&mut self, message: &ProtocolMessage, now: u64) {
+        if let ProtocolMessage::ClockSync { t0, t1 } = message {
+            if *t1 != 0 {
+                self.add_sample(*t0, *t1, now);
+            }
+        }
+    }
+
+    /// Host clock minus client clock in microseconds, taken from the probe
+    /// with the shortest round trip as it is the least skewed by queuing
+    pub fn offset_us(&self) -> Option<i64> {
+        self.samples
+            .iter()
+            .min_by_key(|(round_trip, _)| *round_trip)
+            .map(|(_, offset)| *offset)
+    }
+}
+
+/// Capture-to-display latency of a frame, by stage
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LatencyReport {
+    /// Capture until the encoded frame was handed to the transport
+    pub capture_to_encode: Duration,
+    /// Host send until client receive
+    pub network: Duration,
+    /// Client receive until the frame was on screen
+    pub decode_to_display: Duration,
+}
+
+impl LatencyReport {
+    /// Breakdown for a frame. `captured_at` and `sent_at` are host clock,
+    /// `received_at` and `displayed_at` client clock, and `offset_us` is the
+    /// host clock minus the client's.
+    pub fn new(
+        captured_at: u64,
+        sent_at: u64,
+        received_at: u64,
+        displayed_at: u64,
+        offset_us: i64,
+    ) -> Self {
+        let received_host = received_at as i64 + offset_us;
+        let micros = |us: i64| Duration::from_micros(us.max(0) as u64);
+        Self {
+            capture_to_encode: micros(sent_at as i64 - captured_at as i64),
+            network: micros(received_host - sent_at as i64),
+            decode_to_display: micros(displayed_at as i64 - received_at as i64),
+        }
+    }
+
+    pub fn total(&self) -> Duration {
+        self.capture_to_encode + self.network + self.decode_to_display
+    }
+}
+
+/// Smoothed latency over the frames a client displays
+#[derive(Debug, Clone, Default)]
+pub struct LatencyMonitor {
+    clock: ClockOffsetEstimator,
+    report: Option<LatencyReport>,
+}
+
+impl LatencyMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clock_mut(&mut self) -> &mut ClockOffsetEstimator {
+        &mut self.clock
+    }
+
+    /// Record a displayed frame; ignored until the clock offset is known
+    pub fn frame_displayed(
+        &mut self,
+        captured_at: u64,
+        sent_at: u64,
+        received_at: u64,
+        displayed_at: u64,
+    ) {
+        let Some(offset) = self.clock.offset_us() else {
+            return;
+        };
+        let sample = LatencyReport::new(captured_at, sent_at, received_at, displayed_at, offset);
+        let smooth =
+            |old: Duration, new: Duration| old.mul_f64(1.0 - SMOOTHING) + new.mul_f64(SMOOTHING);
+        self.report = Some(match self.report {
+            None => sample,
+            Some(report) => LatencyReport {
+                capture_to_encode: smooth(report.capture_to_encode, sample.capture_to_encode),
+                network: smooth(report.network, sample.network),
+                decode_to_display: smooth(report.decode_to_display, sample.decode_to_display),
+            },
+        });
+    }
+
+    /// Smoothed breakdown, `None` before the first measured frame
+    pub fn report(&self) -> Option<LatencyReport> {
+        self.report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clock_offset_from_fastest_probe() {
+        let mut clock = ClockOffsetEstimator::new();
+        assert_eq!(clock.offset_us(), None);
+
+        // Host 5 s ahead, 20 ms symmetric round trip
+        clock.add_sample(1_000_000, 6_010_000, 1_020_000);
+        assert_eq!(clock.offset_us(), Some(5_000_000));
+
+        // A slow, asymmetric probe is outweighed by the fast one
+        clock.add_sample(2_000_000, 7_150_000, 2_200_000);
+        assert_eq!(clock.offset_us(), Some(5_000_000));
+
+        // Host behind the client
+        let mut clock = ClockOffsetEstimator::new();
+        let request = ClockOffsetEstimator::request(10_000_000);
+        let reply = clock_sync_reply(&request, 7_000_500).unwrap();
+        assert!(clock_sync_reply(&reply, 0).is_none());
+        clock.handle(&reply, 10_001_000);
+        assert_eq!(clock.offset_us(), Some(-3_000_000));
+    }
+
+    #[test]
+    fn test_latency_breakdown() {
+        // Host clock 2 s ahead of the client
+        let offset = 2_000_000;
+        let captured_at = 50_000_000;
+        let sent_at = captured_at + 12_000;
+        let received_at = sent_at - offset as u64 + 30_000;
+        let displayed_at = received_at + 8_000;
+
+        let report = LatencyReport::new(captured_at, sent_at, received_at, displayed_at, offset);
+        assert_eq!(report.capture_to_encode, Duration::from_millis(12));
+        assert_eq!(report.network, Duration::from_millis(30));
+        assert_eq!(report.decode_to_display, Duration::from_millis(8));
+        assert_eq!(report.total(), Duration::from_millis(50));
+
+        // Clock error never produces a negative stage
+        let skewed = LatencyReport::new(captured_at, sent_at, received_at, displayed_at, 0);
+        assert_eq!(skewed.network, Duration::ZERO);
+
+        let mut monitor = LatencyMonitor::new();
+        monitor.frame_displayed(captured_at, sent_at, received_at, displayed_at);
+        assert_eq!(monitor.report(), None);
+        monitor.clock_mut().add_sample(0, offset as u64, 0);
+        monitor.frame_displayed(captured_at, sent_at, received_at, displayed_at);
+        assert_eq!(monitor.report(), Some(report));
+    }
+}
@@ -0,0 +1,31 @@
+//! Ada Remote Session
+//!
+//! Glue layer tying screen capture, video encoding, and networking
+//! together into a remote desktop session.
+
+pub mod adaptive;
+pub mod auth;
+pub mod display;
+pub mod events;
+pub mod handshake;
+pub mod latency;
+pub mod metrics;
+pub mod pairing;
+pub mod pipeline;
+pub mod recording;
+pub mod recovery;
+pub mod viewers;
+mod webm;
+
+pub use adaptive::{AdaptiveConfig, AdaptiveController, QualityPreset};
+pub use auth::{authorize, AttemptLimiter, LockoutPolicy};
+pub use display::{DisplayNegotiator, DISPLAY_CONFIG_DEBOUNCE};
+pub use events::{SessionEvent, SessionEvents};
+pub use handshake::{accept_session, exchange_hello, join_session, Handshake};
+pub use latency::{ClockOffsetEstimator, LatencyMonitor, LatencyReport};
+pub use metrics::{MetricsCollector, MetricsRecorder, PipelineMetrics};
+pub use pairing::{PairingClient, PairingHost};
+pub use pipeline::{Component, PipelineStatus, SessionPipeline};
+pub use recording::Recorder;
+pub use recovery::{KeyframeLimiter, KeyframeRecovery, KEYFRAME_REQUEST_INTERVAL};
+pub use viewers::{ViewerGroup, ViewerId};
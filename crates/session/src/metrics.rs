@@ -0,0 +1,137 @@
+//! Pipeline metrics
+//!
+//! Lightweight, lock-free counters updated by the capture → encode → send
+//! stages, with a snapshot for graphing and tuning.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Hooks called by each pipeline stage
+pub trait MetricsRecorder: Send + Sync {
+    /// A frame was captured
+    fn frame_captured(&self);
+
+    /// A frame was encoded, `latency` after it was captured
+    fn frame_encoded(&self, latency: Duration);
+
+    /// A captured frame was dropped before being sent
+    fn frame_dropped(&self);
+
+    /// Current depth of the outgoing frame queue
+    fn queue_depth(&self, depth: usize);
+}
+
+/// Point-in-time snapshot of pipeline metrics
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PipelineMetrics {
+    /// Frames captured
+    pub frames_captured: u64,
+    /// Frames encoded
+    pub frames_encoded: u64,
+    /// Frames dropped
+    pub frames_dropped: u64,
+    /// Last observed outgoing queue depth
+    pub queue_depth: u64,
+    /// Average capture-to-encode latency
+    pub avg_encode_latency: Duration,
+    /// Worst capture-to-encode latency
+    pub max_encode_latency: Duration,
+}
+
+/// Atomic-counter implementation of [`MetricsRecorder`]
+#[derive(Debug, Default)]
+pub struct MetricsCollector {
+    frames_captured: AtomicU64,
+    frames_encoded: AtomicU64,
+    frames_dropped: AtomicU64,
+    queue_depth: AtomicU64,
+    total_latency_us: AtomicU64,
+    max_latency_us: AtomicU64,
+}
+
+impl MetricsCollector {
+    /// Create a collector with all counters at zero
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take a snapshot of the current counters
+    pub fn snapshot(&self) -> PipelineMetrics {
+        let frames_encoded = self.frames_encoded.load(Ordering::Relaxed);
+        let total_latency_us = self.total_latency_us.load(Ordering::Relaxed);
+        let avg_latency_us = total_latency_us.checked_div(frames_encoded).unwrap_or(0);
+
+        PipelineMetrics {
+            frames_captured: self.frames_captured.load(Ordering::Relaxed),
+            frames_encoded,
+            frames_dropped: self.frames_dropped.load(Ordering::Relaxed),
+            queue_depth: self.queue_depth.load(Ordering::Relaxed),
+            avg_encode_latency: Duration::from_micros(avg_latency_us),
+            max_encode_latency: Duration::from_micros(self.max_latency_us.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+impl MetricsRecorder for MetricsCollector {
+    fn frame_captured(&self) {
+        self.frames_captured.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn frame_encoded(&self, latency: Duration) {
+        let latency_us = latency.as_micros() as u64;
+        self.frames_encoded.fetch_add(1, Ordering::Relaxed);
+        self.total_latency_us
+            .fetch_add(latency_us, Ordering::Relaxed);
+        self.max_latency_us.fetch_max(latency_us, Ordering::Relaxed);
+    }
+
+    fn frame_dropped(&self) {
+        self.frames_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn queue_depth(&self, depth: usize) {
+        self.queue_depth.store(depth as u64, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    #[test]
+    fn test_mock_pipeline_accounts_for_every_frame() {
+        const FRAMES: u64 = 100;
+        const QUEUE_CAPACITY: usize = 4;
+
+        let metrics = MetricsCollector::new();
+        let mut queue = VecDeque::new();
+
+        for frame in 0..FRAMES {
+            metrics.frame_captured();
+
+            // Sender drains every other frame, so the queue fills up
+            if queue.len() >= QUEUE_CAPACITY {
+                metrics.frame_dropped();
+            } else {
+                metrics.frame_encoded(Duration::from_micros(1000 + frame));
+                queue.push_back(frame);
+            }
+            if frame % 2 == 0 {
+                queue.pop_front();
+            }
+            metrics.queue_depth(queue.len());
+        }
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.frames_captured, FRAMES);
+        assert!(snapshot.frames_dropped > 0);
+        assert_eq!(
+            snapshot.frames_captured,
+            snapshot.frames_encoded + snapshot.frames_dropped
+        );
+        assert_eq!(snapshot.queue_depth, queue.len() as u64);
+        assert!(snapshot.avg_encode_latency >= Duration::from_micros(1000));
+        assert!(snapshot.max_encode_latency >= snapshot.avg_encode_latency);
+    }
+}
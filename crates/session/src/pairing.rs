@@ -0,0 +1,311 @@
+//! Password-less authentication of paired devices
+//!
+//! A client that got in with the session password once can pair: it sends
+//! its identity key in a `PairRequest`, the host answers with a
+//! `PairChallenge` carrying its own, and the client proves it holds its key
+//! in a `PairResponse`. Both sides then remember each other, and later
+//! sessions authenticate with the same exchange alone.
+
+use ada_remote_core::{Error, ProtocolMessage, Result, SessionId};
+use ada_remote_crypto::{
+    pairing_nonce, pairing_proof, public_key_from_bytes, unix_now, verify_pairing_proof,
+    IdentityKeyPair, TrustStore,
+};
+use std::sync::{Arc, Mutex};
+
+/// Reason given to a device that is not paired and has no password
+pub const NOT_PAIRED: &str = "Device not paired";
+
+fn response(reason: Option<&str>) -> ProtocolMessage {
+    ProtocolMessage::SessionResponse {
+        accepted: reason.is_none(),
+        reason: reason.map(str::to_string),
+    }
+}
+
+/// Challenge sent and not yet answered
+struct Challenge {
+    public_key: [u8; 32],
+    device_name: String,
+    nonce: [u8; 32],
+    /// Whether the device pairs now rather than being paired already
+    pairing: bool,
+}
+
+/// Host side of pairing for one connection
+pub struct PairingHost {
+    identity: Arc<IdentityKeyPair>,
+    trust: Arc<Mutex<TrustStore>>,
+    device_name: String,
+    challenge: Option<Challenge>,
+}
+
+impl PairingHost {
+    /// `trust` is shared by all connections so a revoke applies to each
+    pub fn new(
+        identity: Arc<IdentityKeyPair>,
+        trust: Arc<Mutex<TrustStore>>,
+        device_name: &str,
+    ) -> Self {
+        Self {
+            identity,
+            trust,
+            device_name: device_name.to_string(),
+            challenge: None,
+        }
+    }
+
+    /// The host's reply to a client's pairing message in `session_id`, or
+    /// `None` for other messages. `authorized` is whether the client already
+    /// gave the session password, which unknown devices need to pair.
+    pub fn respond(
+        &mut self,
+        session_id: SessionId,
+        message: &ProtocolMessage,
+        authorized: bool,
+    ) -> Option<ProtocolMessage> {
+        match message {
+            ProtocolMessage::PairRequest {
+                public_key,
+                device_name,
+            } => {
+                let trusted = self.trust.lock().unwrap().is_trusted(public_key);
+                if !trusted && !authorized {
+                    tracing::warn!("Unpaired device {:?} refused", device_name);
+                    return Some(response(Some(NOT_PAIRED)));
+                }
+
+                let nonce = pairing_nonce();
+                self.challenge = Some(Challenge {
+                    public_key: *public_key,
+                    device_name: device_name.clone(),
+                    nonce,
+                    pairing: !trusted,
+                });
+                Some(ProtocolMessage::PairChallenge {
+                    public_key: self.identity.public_key().to_bytes(),
+                    device_name: self.device_name.clone(),
+                    nonce,
+                })
+            }
+            ProtocolMessage::PairResponse { proof } => {
+                let Some(challenge) = self.challenge.take() else {
+                    return Some(response(Some("Unexpected pairing response")));
+                };
+                Some(response(
+                    self.check(session_id, &challenge, proof).err().as_deref(),
+                ))
+            }
+            _ => None,
+        }
+    }
+
+    fn check(
+        &self,
+        session_id: SessionId,
+        challenge: &Challenge,
+        proof: &[u8; 32],
+    ) -> std::result::Result<(), String> {
+        let peer = public_key_from_bytes(&challenge.public_key).map_err(|e| e.to_string())?;
+        verify_pairing_proof(&self.identity, &peer, &session_id, &challenge.nonce, proof)
+            .map_err(|e| e.to_string())?;
+
+        let mut trust = self.trust.lock().unwrap();
+        if challenge.pairing {
+            tracing::info!("Paired device {:?}", challenge.device_name);
+            trust.pair(challenge.public_key, &challenge.device_name, unix_now());
+        } else if !trust.is_trusted(&challenge.public_key) {
+            // Revoked while the challenge was out
+            return Err(NOT_PAIRED.to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Client side of pairing for one connection
+pub struct PairingClient {
+    identity: Arc<IdentityKeyPair>,
+    trust: Arc<Mutex<TrustStore>>,
+    device_name: String,
+}
+
+impl PairingClient {
+    pub fn new(
+        identity: Arc<IdentityKeyPair>,
+        trust: Arc<Mutex<TrustStore>>,
+        device_name: &str,
+    ) -> Self {
+        Self {
+            identity,
+            trust,
+            device_name: device_name.to_string(),
+        }
+    }
+
+    /// The `PairRequest` opening the exchange
+    pub fn request(&self) -> ProtocolMessage {
+        ProtocolMessage::PairRequest {
+            public_key: self.identity.public_key().to_bytes(),
+            device_name: self.device_name.clone(),
+        }
+    }
+
+    /// Answer the host's `PairChallenge`. Without the password
+    /// (`authorized` false) the host must have been paired before, so an
+    /// impostor cannot collect proofs; otherwise a new host is remembered.
+    pub fn respond(
+        &self,
+        session_id: SessionId,
+        challenge: &ProtocolMessage,
+        authorized: bool,
+    ) -> Result<ProtocolMessage> {
+        let ProtocolMessage::PairChallenge {
+            public_key,
+            device_name,
+            nonce,
+        } = challenge
+        else {
+            return Err(Error::Authentication(
+                "Expected a pairing challenge".to_string(),
+            ));
+        };
+
+        let mut trust = self.trust.lock().unwrap();
+        if !trust.is_trusted(public_key) {
+            if !authorized {
+                return Err(Error::Authentication(format!(
+                    "Host {:?} is not paired",
+                    device_name
+                )));
+            }
+            trust.pair(*public_key, device_name, unix_now());
+        }
+
+        let host = public_key_from_bytes(public_key)?;
+        Ok(ProtocolMessage::PairResponse {
+            proof: pairing_proof(&self.identity, &host, &session_id, nonce),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Device {
+        identity: Arc<IdentityKeyPair>,
+        trust: Arc<Mutex<TrustStore>>,
+    }
+
+    impl Device {
+        fn new() -> Self {
+            Self {
+                identity: Arc::new(IdentityKeyPair::generate()),
+                trust: Arc::new(Mutex::new(TrustStore::new())),
+            }
+        }
+
+        fn key(&self) -> [u8; 32] {
+            self.identity.public_key().to_bytes()
+        }
+    }
+
+    /// Run one exchange, returning the host's final `SessionResponse`
+    fn exchange(host: &Device, client: &Device, authorized: bool) -> ProtocolMessage {
+        let session_id = SessionId::from_seed(21);
+        let mut host = PairingHost::new(host.identity.clone(), host.trust.clone(), "Desktop");
+        let client = PairingClient::new(client.identity.clone(), client.trust.clone(), "Laptop");
+
+        let challenge = host
+            .respond(session_id, &client.request(), authorized)
+            .unwrap();
+        if matches!(challenge, ProtocolMessage::SessionResponse { .. }) {
+            return challenge;
+        }
+        let proof = client.respond(session_id, &challenge, authorized).unwrap();
+        host.respond(session_id, &proof, authorized).unwrap()
+    }
+
+    fn accepted(response: &ProtocolMessage) -> bool {
+        matches!(
+            response,
+            ProtocolMessage::SessionResponse { accepted: true, .. }
+        )
+    }
+
+    #[test]
+    fn test_pairing_stores_both_identities() {
+        let (host, client) = (Device::new(), Device::new());
+        assert!(accepted(&exchange(&host, &client, true)));
+
+        let host_trust = host.trust.lock().unwrap();
+        let paired = host_trust.get(&client.key()).unwrap();
+        assert_eq!(paired.name, "Laptop");
+        assert_eq!(
+            client.trust.lock().unwrap().get(&host.key()).unwrap().name,
+            "Desktop"
+        );
+    }
+
+    #[test]
+    fn test_paired_device_authenticates_without_password() {
+        let (host, client) = (Device::new(), Device::new());
+        assert!(accepted(&exchange(&host, &client, true)));
+        assert!(accepted(&exchange(&host, &client, false)));
+        assert_eq!(host.trust.lock().unwrap().devices().len(), 1);
+
+        // The client refuses to answer an unknown host without a password
+        let impostor = PairingHost::new(
+            Arc::new(IdentityKeyPair::generate()),
+            host.trust.clone(),
+            "Desktop",
+        )
+        .respond(
+            SessionId::from_seed(21),
+            &ProtocolMessage::PairRequest {
+                public_key: client.key(),
+                device_name: "Laptop".to_string(),
+            },
+            false,
+        )
+        .unwrap();
+        let client = PairingClient::new(client.identity, client.trust, "Laptop");
+        assert!(client
+            .respond(SessionId::from_seed(21), &impostor, false)
+            .is_err());
+    }
+
+    #[test]
+    fn test_unknown_and_revoked_devices_rejected() {
+        let (host, client) = (Device::new(), Device::new());
+        let not_paired = |response: ProtocolMessage| match response {
+            ProtocolMessage::SessionResponse { accepted, reason } => {
+                !accepted && reason.as_deref() == Some(NOT_PAIRED)
+            }
+            _ => false,
+        };
+        assert!(not_paired(exchange(&host, &client, false)));
+
+        assert!(accepted(&exchange(&host, &client, true)));
+        assert!(host.trust.lock().unwrap().revoke(&client.key()));
+        assert!(not_paired(exchange(&host, &client, false)));
+
+        // A device presenting a paired key without holding it fails the proof
+        assert!(accepted(&exchange(&host, &client, true)));
+        let session_id = SessionId::from_seed(21);
+        let mut pairing = PairingHost::new(host.identity.clone(), host.trust.clone(), "Desktop");
+        let request = ProtocolMessage::PairRequest {
+            public_key: client.key(),
+            device_name: "Laptop".to_string(),
+        };
+        let challenge = pairing.respond(session_id, &request, false).unwrap();
+        let forger = Device::new();
+        forger.trust.lock().unwrap().pair(host.key(), "Desktop", 0);
+        let forged = PairingClient::new(forger.identity, forger.trust, "Laptop")
+            .respond(session_id, &challenge, false)
+            .unwrap();
+        assert!(!accepted(
+            &pairing.respond(session_id, &forged, false).unwrap()
+        ));
+    }
+}
@@ -0,0 +1,1676 @@
+//! Capture → encode → send pipeline
+//!
+//! Owns a screen capturer, a video encoder and a network peer, and drives
+//! frames between them on a background task. Captured audio, if enabled,
+//! is encoded and sent alongside, as is the cursor shape when it is not
+//! drawn into the frames. With an [`AdaptiveController`] the encoder
+//! bitrate follows the connection statistics. Encoded frames can also be
+//! recorded to a file.
+//!
+//! Input from the client goes to an [`InputInjector`] behind the session's
+//! connection mode, and clipboard updates flow both ways while the session
+//! config enables them. Frames are scaled to fit the quality preset and
+//! can follow the client's view size, sent in `DisplayConfig`, and a
+//! keyframe forced when the client's decoder asks for one. Audio and clipboard only flow if the client agreed to them in its
+//! `Hello`. Component errors are reported on one status channel, and
+//! published with the rest of the session's [`SessionEvent`]s.
+
+use crate::adaptive::{AdaptiveController, QualityPreset};
+use crate::auth::AttemptLimiter;
+use crate::display::{fit_resolution, DisplayNegotiator};
+use crate::events::{SessionEvent, SessionEvents};
+use crate::handshake::{accept_session, Handshake};
+use crate::latency::now_us;
+use crate::metrics::{MetricsCollector, MetricsRecorder, PipelineMetrics};
+use crate::recording::Recorder;
+use crate::recovery::KeyframeLimiter;
+use ada_remote_audio::{AudioCapture, AudioSender};
+use ada_remote_capture::{CaptureConfig, CursorSource, CursorTracker, ScreenCapture};
+use ada_remote_clipboard::{Clipboard, ClipboardSync};
+use ada_remote_codec::{
+    scale_rgba, EncodedFrame, EncoderConfig, PixelFormat, RawFrame, VideoEncoder,
+};
+use ada_remote_core::{
+    Capabilities, ConnectionMode, Error, FramePool, ProtocolMessage, Result, SessionConfig,
+    VideoQuality,
+};
+use ada_remote_input::{
+    CoordinateTransform, HostDisplay, InputEvent, InputGuard, InputInjector, ModeSwitch,
+};
+use ada_remote_network::{NetworkPeer, STATS_INTERVAL};
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, oneshot};
+use tokio::task::JoinHandle;
+
+/// How often the local clipboard is checked for changes
+const CLIPBOARD_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Status updates kept for subscribers that fall behind
+const STATUS_CAPACITY: usize = 64;
+
+/// Part of the pipeline an error came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Component {
+    Capture,
+    Encoder,
+    Network,
+    Audio,
+    Cursor,
+    Input,
+    Clipboard,
+    Recording,
+}
+
+/// Change reported on [`SessionPipeline::status`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PipelineStatus {
+    Started,
+    /// A component failed. A fatal error stopped the pipeline; otherwise it
+    /// carried on without that message or component.
+    Error {
+        component: Component,
+        message: String,
+        fatal: bool,
+    },
+    Stopped,
+}
+
+/// Error that ends the pipeline, with the component it came from
+struct Fault {
+    component: Component,
+    error: Error,
+}
+
+trait At<T> {
+    fn at(self, component: Component) -> std::result::Result<T, Fault>;
+}
+
+impl<T> At<T> for Result<T> {
+    fn at(self, component: Component) -> std::result::Result<T, Fault> {
+        self.map_err(|error| Fault { component, error })
+    }
+}
+
+/// Components driven by the pipeline
+struct Parts {
+    capturer: Box<dyn ScreenCapture>,
+    encoder: Box<dyn VideoEncoder>,
+    peer: NetworkPeer,
+    audio: Option<Audio>,
+    cursor: Option<Cursor>,
+    adaptive: Option<AdaptiveController>,
+    /// Whether `adaptive` was set by the caller and outlives quality changes
+    custom_adaptive: bool,
+    input: Option<InputGuard>,
+    clipboard: Option<SharedClipboard>,
+    clipboard_sync: Arc<AtomicBool>,
+    /// Frame size following the client's view
+    view: Option<DisplayNegotiator>,
+    /// Largest frame size of the quality preset
+    max_size: Option<(u32, u32)>,
+    /// From positions on scaled frames to capture pixels, while scaling
+    transform: Option<CoordinateTransform>,
+    keyframes: Option<KeyframeLimiter>,
+    /// Features agreed with the client
+    capabilities: Capabilities,
+    /// Capture buffers, for scaled frames
+    frame_pool: FramePool,
+    recording: Arc<Mutex<Option<Recorder>>>,
+    /// Quality change waiting for the pipeline task
+    quality: Arc<Mutex<Option<VideoQuality>>>,
+    status: broadcast::Sender<PipelineStatus>,
+    events: SessionEvents,
+}
+
+impl Parts {
+    fn report(&self, component: Component, error: impl Display, fatal: bool) {
+        let message = error.to_string();
+        let _ = self.status.send(PipelineStatus::Error {
+            component,
+            message: message.clone(),
+            fatal,
+        });
+        self.events.emit(SessionEvent::Error {
+            component,
+            message,
+            fatal,
+        });
+    }
+
+    /// Whether clipboard sync is agreed on and currently enabled
+    fn clipboard_enabled(&self) -> bool {
+        self.capabilities.clipboard && self.clipboard_sync.load(Ordering::Relaxed)
+    }
+
+    /// Take the preset size and bitrate control of `quality`
+    fn follow_quality(&mut self, quality: VideoQuality) {
+        let preset = QualityPreset::for_quality(quality);
+        self.max_size = Some((preset.width, preset.height));
+        match self.adaptive.as_mut() {
+            Some(controller) if self.custom_adaptive => controller.restart(preset.bitrate_kbps),
+            _ => self.adaptive = adaptive_for(quality),
+        }
+    }
+
+    /// Whether anything consumes messages from the client
+    fn handles_incoming(&self) -> bool {
+        self.input.is_some()
+            || self.clipboard.is_some()
+            || self.view.is_some()
+            || self.keyframes.is_some()
+    }
+}
+
+/// Audio capture and its encoder
+struct Audio {
+    capture: Box<dyn AudioCapture>,
+    sender: AudioSender,
+}
+
+/// Cursor shape source for client-side rendering
+struct Cursor {
+    source: CursorSource,
+    tracker: CursorTracker,
+}
+
+/// Host clipboard kept in sync with the client's
+struct SharedClipboard {
+    clipboard: Box<dyn Clipboard>,
+    sync: ClipboardSync,
+}
+
+/// Handle to the running pipeline task
+struct Running {
+    stop_tx: oneshot::Sender<()>,
+    handle: JoinHandle<(Parts, Result<()>)>,
+    /// Publishes the peer's connection changes
+    watcher: JoinHandle<()>,
+}
+
+/// Assembled capture → encode → send pipeline for a host session
+pub struct SessionPipeline {
+    parts: Option<Parts>,
+    running: Option<Running>,
+    capture_config: CaptureConfig,
+    encoder_config: EncoderConfig,
+    metrics: Arc<MetricsCollector>,
+    recording: Arc<Mutex<Option<Recorder>>>,
+    quality: Arc<Mutex<Option<VideoQuality>>>,
+    mode: ModeSwitch,
+    clipboard_sync: Arc<AtomicBool>,
+    status: broadcast::Sender<PipelineStatus>,
+    events: SessionEvents,
+}
+
+/// Refusal of a change that needs the pipeline stopped
+fn already_running() -> Error {
+    Error::Session("Pipeline already running".to_string())
+}
+
+/// Controller for the qualities whose bitrate follows the connection
+fn adaptive_for(quality: VideoQuality) -> Option<AdaptiveController> {
+    (quality == VideoQuality::Adaptive).then(|| AdaptiveController::for_quality(quality))
+}
+
+impl SessionPipeline {
+    /// Create a pipeline from its components. Client input is refused and
+    /// the clipboard not synchronized until a [`SessionConfig`] allows it.
+    /// Without [`accept`](Self::accept) every feature is taken as
+    /// agreed.
+    pub fn new(
+        capturer: Box<dyn ScreenCapture>,
+        encoder: Box<dyn VideoEncoder>,
+        peer: NetworkPeer,
+        capture_config: CaptureConfig,
+        encoder_config: EncoderConfig,
+    ) -> Self {
+        let recording = Arc::new(Mutex::new(None));
+        let quality = Arc::new(Mutex::new(None));
+        let clipboard_sync = Arc::new(AtomicBool::new(false));
+        let (status, _) = broadcast::channel(STATUS_CAPACITY);
+        let events = SessionEvents::new();
+        Self {
+            parts: Some(Parts {
+                capturer,
+                encoder,
+                peer,
+                audio: None,
+                cursor: None,
+                adaptive: None,
+                custom_adaptive: false,
+                input: None,
+                clipboard: None,
+                clipboard_sync: Arc::clone(&clipboard_sync),
+                view: None,
+                max_size: None,
+                transform: None,
+                keyframes: None,
+                capabilities: Capabilities::supported(),
+                frame_pool: capture_config.frame_pool.clone(),
+                recording: Arc::clone(&recording),
+                quality: Arc::clone(&quality),
+                status: status.clone(),
+                events: events.clone(),
+            }),
+            running: None,
+            capture_config,
+            encoder_config,
+            metrics: Arc::new(MetricsCollector::new()),
+            recording,
+            quality,
+            mode: ModeSwitch::new(ConnectionMode::ViewOnly),
+            clipboard_sync,
+            status,
+            events,
+        }
+    }
+
+    /// Components to set up, refused while running
+    fn stopped_parts(&mut self) -> Result<&mut Parts> {
+        self.parts.as_mut().ok_or_else(already_running)
+    }
+
+    /// Inject the client's input with `injector`, in the modes that allow
+    /// it; only while stopped
+    pub fn set_input_injector(&mut self, injector: Box<dyn InputInjector>) -> Result<()> {
+        let mode = self.mode.clone();
+        self.stopped_parts()?.input = Some(InputGuard::with_switch(injector, mode));
+        Ok(())
+    }
+
+    /// Synchronize `clipboard` with the client's while the config enables
+    /// clipboard sync; only while stopped
+    pub fn set_clipboard(&mut self, clipboard: Box<dyn Clipboard>) -> Result<()> {
+        let parts = self.stopped_parts()?;
+        parts.clipboard = Some(SharedClipboard {
+            clipboard,
+            sync: ClipboardSync::default(),
+        });
+        Ok(())
+    }
+
+    /// Encode at the size of the client's view as sent in `DisplayConfig`,
+    /// once a request has stood for `debounce`; only while stopped. Input
+    /// positions from the client are then taken to be on the scaled frames.
+    pub fn follow_display_config(&mut self, debounce: Duration) -> Result<()> {
+        let parts = self.stopped_parts()?;
+        parts.view = Some(DisplayNegotiator::new(debounce));
+        Ok(())
+    }
+
+    /// Force a keyframe when the client asks for one, at most once per
+    /// `interval`; only while stopped
+    pub fn honor_keyframe_requests(&mut self, interval: Duration) -> Result<()> {
+        let parts = self.stopped_parts()?;
+        parts.keyframes = Some(KeyframeLimiter::new(interval));
+        Ok(())
+    }
+
+    /// Answer the client's session request and exchange `Hello`, keeping to
+    /// the features agreed on; only while stopped. A refused client is
+    /// disconnected.
+    pub async fn accept(
+        &mut self,
+        config: &SessionConfig,
+        limiter: &mut AttemptLimiter,
+    ) -> Result<Handshake> {
+        let parts = self.stopped_parts()?;
+        let handshake = accept_session(&mut parts.peer, config, limiter).await?;
+        parts.capabilities = handshake.capabilities;
+        Ok(handshake)
+    }
+
+    /// Follow the connection mode, video quality and clipboard sync of
+    /// `config`. Applies from the next event while running.
+    pub fn apply_config(&mut self, config: &SessionConfig) {
+        self.mode.set(config.mode);
+        self.clipboard_sync
+            .store(config.clipboard_sync, Ordering::Relaxed);
+        self.set_quality(config.quality);
+    }
+
+    /// Subscribe to start and stop and to component errors
+    pub fn status(&self) -> broadcast::Receiver<PipelineStatus> {
+        self.status.subscribe()
+    }
+
+    /// The session's event stream, to subscribe to or to publish on from
+    /// elsewhere in the session, e.g. a [`ViewerGroup`](crate::ViewerGroup)
+    pub fn events(&self) -> &SessionEvents {
+        &self.events
+    }
+
+    /// Stream audio from `capture` as well; only while stopped
+    pub fn set_audio_capture(&mut self, capture: Box<dyn AudioCapture>) -> Result<()> {
+        let parts = self.stopped_parts()?;
+        parts.audio = Some(Audio {
+            capture,
+            sender: AudioSender::new()?,
+        });
+        Ok(())
+    }
+
+    /// Adjust the encoder bitrate to the connection; only while stopped.
+    /// The controller is kept across quality changes.
+    pub fn set_adaptive_bitrate(&mut self, controller: AdaptiveController) -> Result<()> {
+        let parts = self.stopped_parts()?;
+        parts.adaptive = Some(controller);
+        parts.custom_adaptive = true;
+        Ok(())
+    }
+
+    /// Switch to the preset for `quality`: its frame rate and bitrate, with
+    /// frames scaled down to fit its resolution. The bitrate then follows
+    /// the connection for `Adaptive`, or under a controller from
+    /// [`set_adaptive_bitrate`](Self::set_adaptive_bitrate), which restarts
+    /// from the preset bitrate. Applies from the next frame while running.
+    pub fn set_quality(&mut self, quality: VideoQuality) {
+        let preset = QualityPreset::for_quality(quality);
+        self.capture_config.fps = preset.fps;
+        self.encoder_config.fps = preset.fps;
+        self.encoder_config.bitrate = preset.bitrate_kbps;
+        match self.parts.as_mut() {
+            Some(parts) => {
+                parts.follow_quality(quality);
+                self.events.emit(SessionEvent::QualityChanged { quality });
+            }
+            None => *self.quality.lock().unwrap() = Some(quality),
+        }
+    }
+
+    /// Change what is captured; only while stopped, taking effect on `start`
+    pub fn set_capture_config(&mut self, config: CaptureConfig) -> Result<()> {
+        if self.running.is_some() {
+            return Err(already_running());
+        }
+        self.capture_config = config;
+        Ok(())
+    }
+
+    /// Record the encoded stream to `path`, from the next keyframe on
+    pub fn start_recording(&self, path: impl AsRef<Path>) -> Result<()> {
+        let mut recording = self.recording.lock().unwrap();
+        if recording.is_some() {
+            return Err(ada_remote_core::Error::Session(
+                "Already recording".to_string(),
+            ));
+        }
+        let config = &self.encoder_config;
+        *recording = Some(Recorder::create(
+            path,
+            config.codec,
+            config.width,
+            config.height,
+        )?);
+        Ok(())
+    }
+
+    /// Finish the recording, returning its path if there was one
+    pub fn stop_recording(&self) -> Result<Option<PathBuf>> {
+        let recorder = self.recording.lock().unwrap().take();
+        recorder.map(Recorder::finish).transpose()
+    }
+
+    /// Initialize the capturer and encoder and start the pipeline task
+    pub fn start(&mut self) -> Result<()> {
+        let mut parts = self.parts.take().ok_or_else(already_running)?;
+
+        // Capture draws frame buffers from the pool the encoder returns them to
+        parts
+            .encoder
+            .set_frame_pool(self.capture_config.frame_pool.clone());
+        parts.frame_pool = self.capture_config.frame_pool.clone();
+        let init = parts
+            .capturer
+            .init(self.capture_config.clone())
+            .at(Component::Capture)
+            .and_then(|_| {
+                parts
+                    .encoder
+                    .init(self.encoder_config.clone())
+                    .at(Component::Encoder)
+            })
+            .and_then(|_| match parts.audio.as_mut() {
+                Some(audio) if parts.capabilities.audio => {
+                    audio.capture.start().at(Component::Audio)
+                }
+                _ => Ok(()),
+            })
+            .and_then(|_| match parts.input.as_mut() {
+                Some(input) => input.init().at(Component::Input),
+                None => Ok(()),
+            });
+        if let Err(Fault { component, error }) = init {
+            parts.report(component, &error, true);
+            self.parts = Some(parts);
+            return Err(error);
+        }
+
+        // Without a baked-in cursor the client draws it from CursorShape
+        parts.cursor = if self.capture_config.capture_cursor {
+            None
+        } else {
+            match CursorSource::new() {
+                Ok(source) => Some(Cursor {
+                    source,
+                    tracker: CursorTracker::new(),
+                }),
+                Err(e) => {
+                    tracing::warn!("Cursor shape unavailable: {}", e);
+                    None
+                }
+            }
+        };
+
+        let interval = Duration::from_secs(1) / self.capture_config.fps.max(1);
+        let metrics = Arc::clone(&self.metrics);
+        let (stop_tx, stop_rx) = oneshot::channel();
+        let watcher = self.events.watch(&parts.peer);
+        let handle = tokio::spawn(run(parts, interval, metrics, stop_rx));
+
+        self.running = Some(Running {
+            stop_tx,
+            handle,
+            watcher,
+        });
+        let _ = self.status.send(PipelineStatus::Started);
+        tracing::info!("Session pipeline started");
+        Ok(())
+    }
+
+    /// Stop the pipeline task, returning the error that ended it early, if any
+    pub async fn stop(&mut self) -> Result<()> {
+        let Some(running) = self.running.take() else {
+            return Ok(());
+        };
+
+        // The task may already have exited on error
+        let _ = running.stop_tx.send(());
+        running.watcher.abort();
+        let (mut parts, result) = running
+            .handle
+            .await
+            .map_err(|e| ada_remote_core::Error::Session(format!("Pipeline task failed: {}", e)))?;
+
+        parts.capturer.cleanup()?;
+        parts.encoder.cleanup()?;
+        match parts.audio.as_mut() {
+            Some(audio) if parts.capabilities.audio => audio.capture.stop()?,
+            _ => {}
+        }
+        if let Some(input) = parts.input.as_mut() {
+            input.cleanup()?;
+        }
+        self.parts = Some(parts);
+        self.stop_recording()?;
+        let _ = self.status.send(PipelineStatus::Stopped);
+        tracing::info!("Session pipeline stopped");
+        result
+    }
+
+    /// Whether the pipeline task is running
+    pub fn is_running(&self) -> bool {
+        self.running.is_some()
+    }
+
+    /// Snapshot of the pipeline metrics
+    pub fn metrics(&self) -> PipelineMetrics {
+        self.metrics.snapshot()
+    }
+
+    /// Access the network peer while the pipeline is stopped
+    pub fn peer_mut(&mut self) -> Option<&mut NetworkPeer> {
+        self.parts.as_mut().map(|parts| &mut parts.peer)
+    }
+}
+
+async fn run(
+    mut parts: Parts,
+    interval: Duration,
+    metrics: Arc<MetricsCollector>,
+    mut stop_rx: oneshot::Receiver<()>,
+) -> (Parts, Result<()>) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    let mut stats_ticker = tokio::time::interval(STATS_INTERVAL);
+    stats_ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    let mut clipboard_ticker = tokio::time::interval(CLIPBOARD_POLL_INTERVAL);
+    clipboard_ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+    let result = loop {
+        let incoming = parts.handles_incoming();
+        tokio::select! {
+            _ = &mut stop_rx => break Ok(()),
+            _ = stats_ticker.tick() => {
+                process_adaptive(&mut parts);
+                continue;
+            }
+            _ = clipboard_ticker.tick(), if parts.clipboard.is_some() => {
+                if let Err(fault) = process_clipboard(&mut parts) {
+                    break Err(fault);
+                }
+                continue;
+            }
+            message = parts.peer.receive(), if incoming => {
+                match message {
+                    Some(message) => process_incoming(&mut parts, message),
+                    None => break Err(Fault {
+                        component: Component::Network,
+                        error: Error::ConnectionClosed("Client connection closed".to_string()),
+                    }),
+                }
+                continue;
+            }
+            _ = ticker.tick() => {}
+        }
+
+        if let Some(interval) = process_quality(&mut parts) {
+            ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        }
+        let sent = process_frame(&mut parts, metrics.as_ref())
+            .and_then(|_| process_cursor(&mut parts))
+            .and_then(|_| process_audio(&mut parts));
+        if let Err(fault) = sent {
+            break Err(fault);
+        }
+    };
+
+    match result {
+        Ok(()) => (parts, Ok(())),
+        Err(Fault { component, error }) => {
+            tracing::error!(
+                "Session pipeline stopped on {:?} error: {}",
+                component,
+                error
+            );
+            parts.report(component, &error, true);
+            (parts, Err(error))
+        }
+    }
+}
+
+/// Capture, encode and send a single frame
+fn process_frame(
+    parts: &mut Parts,
+    metrics: &dyn MetricsRecorder,
+) -> std::result::Result<(), Fault> {
+    let captured = parts.capturer.capture_frame().at(Component::Capture)?;
+    let captured_at = Instant::now();
+    let captured_at_us = now_us();
+    metrics.frame_captured();
+
+    let capture_size = (captured.width, captured.height);
+    let mut resolution = parts
+        .max_size
+        .map_or(capture_size, |size| fit_resolution(capture_size, size));
+    if let Some(view) = parts.view.as_mut() {
+        resolution = view.resolution(resolution, captured_at);
+    }
+    parts.transform = (resolution != capture_size).then(|| {
+        let display = HostDisplay {
+            x: 0,
+            y: 0,
+            width: capture_size.0,
+            height: capture_size.1,
+            scale_factor: 1.0,
+        };
+        CoordinateTransform::new(resolution.0, resolution.1, display)
+    });
+    if let Some(config) = parts.encoder.effective_config() {
+        if (config.width, config.height) != resolution {
+            tracing::info!("Encoding at {}x{}", resolution.0, resolution.1);
+            parts
+                .encoder
+                .reconfigure(resolution.0, resolution.1)
+                .at(Component::Encoder)?;
+        }
+    }
+
+    // Capture backends deliver RGBA
+    let data = if resolution == capture_size {
+        captured.data
+    } else {
+        let (width, height) = (resolution.0 as usize, resolution.1 as usize);
+        let mut scaled = parts.frame_pool.take(width * height * 4);
+        scale_rgba(
+            &captured.data,
+            captured.width as usize,
+            captured.height as usize,
+            &mut scaled,
+            width,
+            height,
+        );
+        parts.frame_pool.recycle(captured.data);
+        scaled
+    };
+    let frame = RawFrame {
+        data,
+        format: PixelFormat::Rgba,
+        width: resolution.0,
+        height: resolution.1,
+        timestamp: captured.timestamp,
+    };
+
+    // A new recording starts at a keyframe
+    let recording_starts = parts
+        .recording
+        .lock()
+        .unwrap()
+        .as_ref()
+        .is_some_and(Recorder::wants_keyframe);
+    if recording_starts {
+        parts.encoder.force_keyframe().at(Component::Encoder)?;
+    }
+
+    let encoded = match parts.encoder.encode(frame) {
+        Ok(encoded) => encoded,
+        Err(e) => {
+            tracing::warn!("Dropping frame that failed to encode: {}", e);
+            metrics.frame_dropped();
+            return Ok(());
+        }
+    };
+    metrics.frame_encoded(captured_at.elapsed());
+    record_frame(parts, &encoded);
+    parts.events.emit(SessionEvent::FrameEncoded {
+        size: encoded.data.len(),
+        is_keyframe: encoded.is_keyframe,
+    });
+
+    parts
+        .peer
+        .send(ProtocolMessage::VideoFrame {
+            timestamp: encoded.timestamp,
+            captured_at: captured_at_us,
+            sent_at: now_us(),
+            is_keyframe: encoded.is_keyframe,
+            temporal_layer: encoded.temporal_layer,
+            data: encoded.data,
+        })
+        .at(Component::Network)
+}
+
+/// Hand an encoded frame to the recorder, if recording. A failing recording
+/// is abandoned rather than ending the session.
+fn record_frame(parts: &mut Parts, encoded: &EncodedFrame) {
+    let mut recording = parts.recording.lock().unwrap();
+    let Some(recorder) = recording.as_mut() else {
+        return;
+    };
+    if let Err(e) = recorder.write_frame(encoded) {
+        tracing::warn!("Recording stopped: {}", e);
+        *recording = None;
+        parts.report(Component::Recording, e, false);
+    }
+}
+
+/// Retune the encoder bitrate from the latest connection statistics. An
+/// encoder that cannot change bitrate disables adaptation.
+fn process_adaptive(parts: &mut Parts) {
+    let Some(adaptive) = parts.adaptive.as_mut() else {
+        return;
+    };
+    if let Err(e) = adaptive.apply(&parts.peer.stats(), parts.encoder.as_mut()) {
+        tracing::warn!("Adaptive bitrate disabled: {}", e);
+        parts.adaptive = None;
+        parts.report(Component::Encoder, e, false);
+    }
+}
+
+/// Whether `encoder` has a bitrate target to change, which constant quality
+/// mode lacks
+fn has_bitrate(encoder: &dyn VideoEncoder) -> bool {
+    encoder
+        .effective_config()
+        .is_none_or(|config| config.rate_control().target_kbps().is_some())
+}
+
+/// Apply a change from [`SessionPipeline::set_quality`], returning the new
+/// frame interval. A bitrate the encoder refuses is reported, and the frame
+/// rate changes regardless.
+fn process_quality(parts: &mut Parts) -> Option<Duration> {
+    let quality = parts.quality.lock().unwrap().take()?;
+    let preset = QualityPreset::for_quality(quality);
+    if has_bitrate(parts.encoder.as_ref()) {
+        if let Err(e) = parts.encoder.set_bitrate(preset.bitrate_kbps) {
+            tracing::warn!("Keeping the encoder bitrate: {}", e);
+            parts.report(Component::Encoder, e, false);
+        }
+        parts.follow_quality(quality);
+    } else {
+        parts.max_size = Some((preset.width, preset.height));
+        parts.adaptive = None;
+    }
+    tracing::info!("Video quality set to {:?}", quality);
+    parts.events.emit(SessionEvent::QualityChanged { quality });
+    Some(Duration::from_secs(1) / preset.fps.max(1))
+}
+
+/// Send the cursor shape if it changed since the last tick
+fn process_cursor(parts: &mut Parts) -> std::result::Result<(), Fault> {
+    let Some(cursor) = parts.cursor.as_mut() else {
+        return Ok(());
+    };
+
+    let image = match cursor.source.current() {
+        Ok(image) => image,
+        Err(e) => {
+            tracing::warn!("Cursor shape failed, no longer sending it: {}", e);
+            parts.cursor = None;
+            parts.report(Component::Cursor, e, false);
+            return Ok(());
+        }
+    };
+    if let Some(message) = cursor.tracker.update(image) {
+        parts.peer.send(message).at(Component::Network)?;
+    }
+    Ok(())
+}
+
+/// Encode and send the audio captured since the last tick. A failing audio
+/// source is dropped rather than ending the session.
+fn process_audio(parts: &mut Parts) -> std::result::Result<(), Fault> {
+    if !parts.capabilities.audio {
+        return Ok(());
+    }
+    let Some(audio) = parts.audio.as_mut() else {
+        return Ok(());
+    };
+
+    loop {
+        let buffer = match audio.capture.read() {
+            Ok(Some(buffer)) => buffer,
+            Ok(None) => return Ok(()),
+            Err(e) => {
+                tracing::warn!("Audio capture failed, continuing without audio: {}", e);
+                let _ = audio.capture.stop();
+                parts.audio = None;
+                parts.report(Component::Audio, e, false);
+                return Ok(());
+            }
+        };
+        let message = match audio.sender.encode(&buffer) {
+            Ok(message) => message,
+            Err(e) => {
+                tracing::warn!("Dropping audio that failed to encode: {}", e);
+                continue;
+            }
+        };
+        parts.peer.send(message).at(Component::Network)?;
+    }
+}
+
+/// Inject input, apply clipboard updates and answer keyframe requests from
+/// the client. Messages that fail are reported and dropped.
+fn process_incoming(parts: &mut Parts, message: ProtocolMessage) {
+    match message {
+        ProtocolMessage::InputEvent { event_type, data } => {
+            let Some(input) = parts.input.as_mut() else {
+                return;
+            };
+            let injected = InputEvent::from_message(event_type, &data)
+                .map(|event| to_capture(event, parts.transform.as_ref()))
+                .and_then(|event| input.inject(event));
+            match injected {
+                // The guard logs input the session mode refuses
+                Ok(()) | Err(Error::InputDenied(_)) => {}
+                Err(e) => {
+                    tracing::warn!("Dropping input event: {}", e);
+                    parts.report(Component::Input, e, false);
+                }
+            }
+        }
+        message @ (ProtocolMessage::Clipboard { .. }
+        | ProtocolMessage::ClipboardChunk { .. }
+        | ProtocolMessage::ClipboardImage { .. }
+        | ProtocolMessage::ClipboardFiles { .. }) => {
+            if !parts.clipboard_enabled() {
+                return;
+            }
+            let Some(shared) = parts.clipboard.as_mut() else {
+                return;
+            };
+            if let Err(e) = shared.sync.apply(shared.clipboard.as_mut(), &message) {
+                tracing::warn!("Dropping clipboard update: {}", e);
+                parts.report(Component::Clipboard, e, false);
+            }
+        }
+        ProtocolMessage::DisplayConfig {
+            width,
+            height,
+            scale,
+        } => {
+            if let Some(view) = parts.view.as_mut() {
+                view.request(width, height, scale, Instant::now());
+            }
+        }
+        ProtocolMessage::KeyframeRequest => {
+            let Some(limiter) = parts.keyframes.as_mut() else {
+                return;
+            };
+            if !limiter.allow(Instant::now()) {
+                tracing::debug!("Ignoring keyframe request within the rate limit");
+                return;
+            }
+            if let Err(e) = parts.encoder.force_keyframe() {
+                tracing::warn!("Forcing a requested keyframe failed: {}", e);
+                parts.report(Component::Encoder, e, false);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Map the position of a client pointer event from the scaled frames to
+/// capture pixels
+fn to_capture(event: InputEvent, transform: Option<&CoordinateTransform>) -> InputEvent {
+    let Some(transform) = transform else {
+        return event;
+    };
+    match event {
+        InputEvent::MouseMove { x, y } => {
+            let (x, y) = transform.map(x, y);
+            InputEvent::MouseMove { x, y }
+        }
+        InputEvent::MouseWarp { x, y } => {
+            let (x, y) = transform.map(x, y);
+            InputEvent::MouseWarp { x, y }
+        }
+        event => event,
+    }
+}
+
+/// Send the local clipboard if it changed since the last poll
+fn process_clipboard(parts: &mut Parts) -> std::result::Result<(), Fault> {
+    if !parts.clipboard_enabled() {
+        return Ok(());
+    }
+    let Some(shared) = parts.clipboard.as_mut() else {
+        return Ok(());
+    };
+    let messages = match shared.sync.poll(shared.clipboard.as_mut()) {
+        Ok(messages) => messages,
+        Err(e) => {
+            tracing::warn!("Reading the clipboard failed: {}", e);
+            parts.report(Component::Clipboard, e, false);
+            return Ok(());
+        }
+    };
+    for message in messages {
+        parts.peer.send(message).at(Component::Network)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adaptive::AdaptiveConfig;
+    use crate::handshake::join_session;
+    use crate::recovery::KeyframeRecovery;
+    use ada_remote_audio::{frame_samples, AudioBuffer, CHANNELS, SAMPLE_RATE};
+    use ada_remote_capture::{CapturedFrame, MonitorInfo};
+    use ada_remote_clipboard::{ClipboardContent, MemoryClipboard};
+    use ada_remote_codec::{
+        create_decoder, create_encoder, CodecType, DecoderConfig, EncodedFrame, RateControl,
+    };
+    use ada_remote_core::{FramePool, SessionId};
+    use ada_remote_network::loopback::loopback_pair;
+    use ada_remote_network::{ConnectionType, NetworkConfig};
+
+    #[derive(Default)]
+    struct MockCapturer {
+        next_timestamp: u64,
+        pool: FramePool,
+    }
+
+    impl ScreenCapture for MockCapturer {
+        fn init(&mut self, config: CaptureConfig) -> Result<()> {
+            self.pool = config.frame_pool;
+            Ok(())
+        }
+
+        fn capture_frame(&mut self) -> Result<CapturedFrame> {
+            self.next_timestamp += 1;
+            let mut data = self.pool.take(16);
+            data.resize(16, self.next_timestamp as u8);
+            Ok(CapturedFrame {
+                data,
+                width: 2,
+                height: 2,
+                timestamp: self.next_timestamp,
+                dirty_rects: None,
+            })
+        }
+
+        fn list_monitors(&self) -> Result<Vec<MonitorInfo>> {
+            Ok(vec![])
+        }
+
+        fn cleanup(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_frame_flows_end_to_end() {
+        let pool = FramePool::new(2);
+        let mut pipeline = SessionPipeline::new(
+            Box::new(MockCapturer::default()),
+            create_encoder(CodecType::Raw).unwrap(),
+            NetworkPeer::new(SessionId::from_seed(1), ConnectionType::WebRTC),
+            CaptureConfig {
+                fps: 200,
+                frame_pool: pool.clone(),
+                ..Default::default()
+            },
+            EncoderConfig {
+                codec: CodecType::Raw,
+                width: 2,
+                height: 2,
+                ..Default::default()
+            },
+        );
+
+        let mut events = pipeline.events().subscribe();
+        pipeline.start().unwrap();
+        assert!(pipeline.is_running());
+        while pipeline.metrics().frames_encoded < 3 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        pipeline.stop().await.unwrap();
+        assert!(!pipeline.is_running());
+
+        let metrics = pipeline.metrics();
+        assert_eq!(
+            metrics.frames_captured,
+            metrics.frames_encoded + metrics.frames_dropped
+        );
+        // Every encoded frame handed its buffer back for the next capture
+        assert_eq!(pool.allocations(), 1);
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            SessionEvent::FrameEncoded {
+                is_keyframe: true,
+                ..
+            }
+        ));
+
+        let mut decoder = create_decoder(CodecType::Raw).unwrap();
+        decoder
+            .init(DecoderConfig {
+                codec: CodecType::Raw,
+                ..Default::default()
+            })
+            .unwrap();
+
+        let peer = pipeline.peer_mut().unwrap();
+        let Some(ProtocolMessage::VideoFrame {
+            timestamp,
+            captured_at,
+            sent_at,
+            is_keyframe,
+            temporal_layer,
+            data,
+        }) = peer.receive().await
+        else {
+            panic!("expected a video frame");
+        };
+        assert!(is_keyframe);
+        let decoded = decoder
+            .decode(EncodedFrame {
+                data,
+                timestamp,
+                is_keyframe,
+                temporal_layer,
+            })
+            .unwrap();
+        assert_eq!(decoded.timestamp, 1);
+        assert_eq!(decoded.data, vec![1u8; 16]);
+        assert!(captured_at > 0 && captured_at <= sent_at);
+    }
+
+    struct MockAudio {
+        remaining: usize,
+    }
+
+    impl AudioCapture for MockAudio {
+        fn start(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn read(&mut self) -> Result<Option<AudioBuffer>> {
+            if self.remaining == 0 {
+                return Ok(None);
+            }
+            self.remaining -= 1;
+            Ok(Some(AudioBuffer {
+                samples: vec![0; frame_samples(SAMPLE_RATE) * CHANNELS as usize],
+                sample_rate: SAMPLE_RATE,
+                channels: CHANNELS,
+                timestamp: 7,
+            }))
+        }
+
+        fn stop(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_audio_sent_alongside_video() {
+        let mut pipeline = SessionPipeline::new(
+            Box::new(MockCapturer::default()),
+            create_encoder(CodecType::Raw).unwrap(),
+            NetworkPeer::new(SessionId::from_seed(2), ConnectionType::WebRTC),
+            CaptureConfig::default(),
+            EncoderConfig {
+                codec: CodecType::Raw,
+                width: 2,
+                height: 2,
+                ..Default::default()
+            },
+        );
+        pipeline
+            .set_audio_capture(Box::new(MockAudio { remaining: 1 }))
+            .unwrap();
+
+        pipeline.start().unwrap();
+        while pipeline.metrics().frames_encoded < 1 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        pipeline.stop().await.unwrap();
+
+        let peer = pipeline.peer_mut().unwrap();
+        assert!(matches!(
+            peer.receive().await,
+            Some(ProtocolMessage::VideoFrame { .. })
+        ));
+        let Some(ProtocolMessage::AudioFrame {
+            timestamp, data, ..
+        }) = peer.receive().await
+        else {
+            panic!("expected an audio frame");
+        };
+        assert_eq!(timestamp, 7);
+        assert!(!data.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_features_follow_negotiation() {
+        let (host, mut client) = loopback_pair(&NetworkConfig::default()).await.unwrap();
+        let session_id = host.session_id();
+        let mut pipeline = SessionPipeline::new(
+            Box::new(MockCapturer::default()),
+            create_encoder(CodecType::Raw).unwrap(),
+            host,
+            CaptureConfig::default(),
+            EncoderConfig {
+                codec: CodecType::Raw,
+                width: 2,
+                height: 2,
+                ..Default::default()
+            },
+        );
+        pipeline
+            .set_audio_capture(Box::new(MockAudio { remaining: 4 }))
+            .unwrap();
+        let mut clipboard = SharedMemoryClipboard::default();
+        clipboard
+            .set(ClipboardContent::Text("host only".to_string()))
+            .unwrap();
+        pipeline.set_clipboard(Box::new(clipboard)).unwrap();
+        let config = SessionConfig {
+            session_id,
+            audio: true,
+            ..session_config(ConnectionMode::FullControl)
+        };
+        pipeline.apply_config(&config);
+
+        // The host offers audio and clipboard, the client neither
+        let offered = Capabilities {
+            audio: false,
+            clipboard: false,
+            ..Capabilities::supported()
+        };
+        let mut limiter = AttemptLimiter::default();
+        let (hosted, joined) = tokio::join!(
+            pipeline.accept(&config, &mut limiter),
+            join_session(&mut client, None, ConnectionMode::FullControl, offered),
+        );
+        let hosted = hosted.unwrap();
+        assert_eq!(joined.unwrap(), hosted);
+        let agreed = hosted.capabilities;
+        assert!(agreed.input && !agreed.audio && !agreed.clipboard);
+
+        pipeline.start().unwrap();
+        let mut frames = 0;
+        while frames < 5 {
+            match client.receive().await.unwrap() {
+                ProtocolMessage::VideoFrame { .. } => frames += 1,
+                ProtocolMessage::AudioFrame { .. } => panic!("audio was not agreed on"),
+                ProtocolMessage::Clipboard { .. } => panic!("clipboard was not agreed on"),
+                _ => {}
+            }
+        }
+        pipeline.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_quality_change_while_running() {
+        let mut pipeline = SessionPipeline::new(
+            Box::new(MockCapturer::default()),
+            create_encoder(CodecType::Raw).unwrap(),
+            NetworkPeer::new(SessionId::from_seed(3), ConnectionType::WebRTC),
+            CaptureConfig::default(),
+            EncoderConfig {
+                codec: CodecType::Raw,
+                width: 2,
+                height: 2,
+                ..Default::default()
+            },
+        );
+        pipeline.set_quality(VideoQuality::Adaptive);
+        assert!(pipeline.parts.as_ref().unwrap().adaptive.is_some());
+        assert_eq!(pipeline.encoder_config.bitrate, 2000);
+
+        pipeline.start().unwrap();
+        pipeline.set_quality(VideoQuality::High);
+        assert_eq!(pipeline.capture_config.fps, 60);
+        // The task picks the change up on its next frame
+        while pipeline.quality.lock().unwrap().is_some() {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        pipeline.stop().await.unwrap();
+        assert!(pipeline.parts.as_ref().unwrap().adaptive.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_quality_change_on_constant_quality_encoder() {
+        let mut pipeline = SessionPipeline::new(
+            Box::new(MockCapturer::default()),
+            create_encoder(CodecType::VP9).unwrap(),
+            NetworkPeer::new(SessionId::from_seed(3), ConnectionType::WebRTC),
+            CaptureConfig {
+                fps: 200,
+                ..Default::default()
+            },
+            EncoderConfig {
+                codec: CodecType::VP9,
+                width: 2,
+                height: 2,
+                rate_control: Some(RateControl::ConstantQuality { q: 30 }),
+                ..Default::default()
+            },
+        );
+
+        // The quantizer stays and the frame rate follows the preset
+        pipeline.start().unwrap();
+        pipeline.set_quality(VideoQuality::Adaptive);
+        while pipeline.quality.lock().unwrap().is_some() {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        pipeline.stop().await.unwrap();
+        assert_eq!(pipeline.capture_config.fps, 30);
+        assert!(pipeline.parts.as_ref().unwrap().adaptive.is_none());
+    }
+
+    /// Solid gray 2560x1600 screen
+    #[derive(Default)]
+    struct LargeCapturer {
+        pool: FramePool,
+    }
+
+    impl ScreenCapture for LargeCapturer {
+        fn init(&mut self, config: CaptureConfig) -> Result<()> {
+            self.pool = config.frame_pool;
+            Ok(())
+        }
+
+        fn capture_frame(&mut self) -> Result<CapturedFrame> {
+            let mut data = self.pool.take(2560 * 1600 * 4);
+            data.resize(2560 * 1600 * 4, 128);
+            Ok(CapturedFrame {
+                data,
+                width: 2560,
+                height: 1600,
+                timestamp: 0,
+                dirty_rects: None,
+            })
+        }
+
+        fn list_monitors(&self) -> Result<Vec<MonitorInfo>> {
+            Ok(vec![])
+        }
+
+        fn cleanup(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_quality_preset_scales_frames_and_keeps_controller() {
+        let mut pipeline = SessionPipeline::new(
+            Box::new(LargeCapturer::default()),
+            create_encoder(CodecType::Raw).unwrap(),
+            NetworkPeer::new(SessionId::from_seed(8), ConnectionType::WebRTC),
+            CaptureConfig::default(),
+            EncoderConfig {
+                codec: CodecType::Raw,
+                width: 64,
+                height: 48,
+                ..Default::default()
+            },
+        );
+        let range = AdaptiveConfig {
+            max_bitrate_kbps: 1500,
+            ..Default::default()
+        };
+        pipeline
+            .set_adaptive_bitrate(AdaptiveController::new(range, 1000))
+            .unwrap();
+
+        // The preset's 2000 kbps start is held to the caller's range
+        pipeline.set_quality(VideoQuality::Adaptive);
+        pipeline.set_quality(VideoQuality::Low);
+        pipeline.set_quality(VideoQuality::Adaptive);
+        let parts = pipeline.parts.as_ref().unwrap();
+        assert_eq!(parts.adaptive.as_ref().unwrap().bitrate_kbps(), 1500);
+
+        // Low fits the 16:10 screen into 1280x720
+        pipeline.set_quality(VideoQuality::Low);
+        pipeline.start().unwrap();
+        while pipeline.metrics().frames_encoded < 1 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        pipeline.stop().await.unwrap();
+        let parts = pipeline.parts.as_ref().unwrap();
+        let config = parts.encoder.effective_config().unwrap();
+        assert_eq!((config.width, config.height), (1152, 720));
+        assert_eq!(parts.adaptive.as_ref().unwrap().bitrate_kbps(), 1000);
+    }
+
+    /// Injector remembering what it was given
+    struct RecordingInjector(Arc<Mutex<Vec<InputEvent>>>);
+
+    impl InputInjector for RecordingInjector {
+        fn init(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn inject(&mut self, event: InputEvent) -> Result<()> {
+            self.0.lock().unwrap().push(event);
+            Ok(())
+        }
+
+        fn cleanup(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Clipboard shared with the test
+    #[derive(Clone, Default)]
+    struct SharedMemoryClipboard(Arc<Mutex<MemoryClipboard>>);
+
+    impl Clipboard for SharedMemoryClipboard {
+        fn get(&mut self) -> Result<Option<ClipboardContent>> {
+            self.0.lock().unwrap().get()
+        }
+
+        fn set(&mut self, content: ClipboardContent) -> Result<()> {
+            self.0.lock().unwrap().set(content)
+        }
+    }
+
+    fn session_config(mode: ConnectionMode) -> SessionConfig {
+        SessionConfig {
+            session_id: SessionId::from_seed(4),
+            mode,
+            password_hash: None,
+            clipboard_sync: true,
+            quality: VideoQuality::High,
+            audio: false,
+            pointer_mode: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_client_input_and_clipboard_reach_host() {
+        let mut pipeline = SessionPipeline::new(
+            Box::new(MockCapturer::default()),
+            create_encoder(CodecType::Raw).unwrap(),
+            NetworkPeer::new(SessionId::from_seed(4), ConnectionType::WebRTC),
+            CaptureConfig::default(),
+            EncoderConfig {
+                codec: CodecType::Raw,
+                width: 2,
+                height: 2,
+                ..Default::default()
+            },
+        );
+        let injected = Arc::new(Mutex::new(Vec::new()));
+        let clipboard = SharedMemoryClipboard::default();
+        pipeline
+            .set_input_injector(Box::new(RecordingInjector(Arc::clone(&injected))))
+            .unwrap();
+        pipeline.set_clipboard(Box::new(clipboard.clone())).unwrap();
+        pipeline.apply_config(&session_config(ConnectionMode::FullControl));
+        assert_eq!(pipeline.capture_config.fps, 60);
+
+        // The unconnected peer delivers what is sent on it back to the
+        // pipeline, standing in for the client
+        let peer = pipeline.peer_mut().unwrap();
+        peer.send(InputEvent::MouseMove { x: 5, y: 6 }.to_message())
+            .unwrap();
+        peer.send(ProtocolMessage::Clipboard {
+            content: "from client".to_string(),
+        })
+        .unwrap();
+
+        let mut status = pipeline.status();
+        pipeline.start().unwrap();
+        assert_eq!(status.recv().await.unwrap(), PipelineStatus::Started);
+        while injected.lock().unwrap().is_empty()
+            || clipboard.0.lock().unwrap().get_text().unwrap().is_none()
+            || pipeline.metrics().frames_encoded == 0
+        {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        pipeline.stop().await.unwrap();
+        assert_eq!(status.recv().await.unwrap(), PipelineStatus::Stopped);
+
+        // View-only sessions keep the client's hands off the host
+        pipeline.apply_config(&session_config(ConnectionMode::ViewOnly));
+        let typed = InputEvent::TypeText {
+            text: "ignored".to_string(),
+        };
+        let peer = pipeline.peer_mut().unwrap();
+        peer.send(typed.to_message()).unwrap();
+        pipeline.start().unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        pipeline.stop().await.unwrap();
+
+        let injected = injected.lock().unwrap();
+        assert_eq!(injected.len(), 1);
+        assert!(matches!(injected[0], InputEvent::MouseMove { x: 5, y: 6 }));
+        assert_eq!(
+            clipboard.0.lock().unwrap().get_text().unwrap().as_deref(),
+            Some("from client")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_mode_change_reaches_running_guard() {
+        let (host, client) = loopback_pair(&NetworkConfig::default()).await.unwrap();
+        let mut pipeline = SessionPipeline::new(
+            Box::new(MockCapturer::default()),
+            create_encoder(CodecType::Raw).unwrap(),
+            host,
+            CaptureConfig::default(),
+            EncoderConfig {
+                codec: CodecType::Raw,
+                width: 2,
+                height: 2,
+                ..Default::default()
+            },
+        );
+        let injected = Arc::new(Mutex::new(Vec::new()));
+        pipeline
+            .set_input_injector(Box::new(RecordingInjector(Arc::clone(&injected))))
+            .unwrap();
+        pipeline.apply_config(&session_config(ConnectionMode::ViewOnly));
+        pipeline.start().unwrap();
+
+        client
+            .send(InputEvent::MouseMove { x: 1, y: 1 }.to_message())
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(injected.lock().unwrap().is_empty());
+
+        // Granted control without restarting
+        pipeline.apply_config(&session_config(ConnectionMode::FullControl));
+        client
+            .send(InputEvent::MouseMove { x: 2, y: 2 }.to_message())
+            .unwrap();
+        while injected.lock().unwrap().is_empty() {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        pipeline.stop().await.unwrap();
+        assert!(matches!(
+            injected.lock().unwrap()[..],
+            [InputEvent::MouseMove { x: 2, y: 2 }]
+        ));
+    }
+
+    /// Solid gray 64x48 screen
+    #[derive(Default)]
+    struct WideCapturer {
+        pool: FramePool,
+    }
+
+    impl ScreenCapture for WideCapturer {
+        fn init(&mut self, config: CaptureConfig) -> Result<()> {
+            self.pool = config.frame_pool;
+            Ok(())
+        }
+
+        fn capture_frame(&mut self) -> Result<CapturedFrame> {
+            let mut data = self.pool.take(64 * 48 * 4);
+            data.resize(64 * 48 * 4, 128);
+            Ok(CapturedFrame {
+                data,
+                width: 64,
+                height: 48,
+                timestamp: 0,
+                dirty_rects: None,
+            })
+        }
+
+        fn list_monitors(&self) -> Result<Vec<MonitorInfo>> {
+            Ok(vec![])
+        }
+
+        fn cleanup(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_display_config_resizes_encoder_and_input() {
+        let mut pipeline = SessionPipeline::new(
+            Box::new(WideCapturer::default()),
+            create_encoder(CodecType::Raw).unwrap(),
+            NetworkPeer::new(SessionId::from_seed(6), ConnectionType::WebRTC),
+            CaptureConfig {
+                fps: 200,
+                ..Default::default()
+            },
+            EncoderConfig {
+                codec: CodecType::Raw,
+                width: 64,
+                height: 48,
+                ..Default::default()
+            },
+        );
+        let injected = Arc::new(Mutex::new(Vec::new()));
+        pipeline
+            .set_input_injector(Box::new(RecordingInjector(Arc::clone(&injected))))
+            .unwrap();
+        pipeline.apply_config(&session_config(ConnectionMode::FullControl));
+        pipeline.follow_display_config(Duration::ZERO).unwrap();
+
+        // A 20x20 point view at 2x fits the 4:3 screen in 40x30 pixels
+        let peer = pipeline.peer_mut().unwrap();
+        peer.send(ProtocolMessage::DisplayConfig {
+            width: 20,
+            height: 20,
+            scale: 2.0,
+        })
+        .unwrap();
+        pipeline.start().unwrap();
+        while pipeline.metrics().frames_encoded < 2 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        pipeline.stop().await.unwrap();
+        let encoder = &pipeline.parts.as_ref().unwrap().encoder;
+        let config = encoder.effective_config().unwrap();
+        assert_eq!((config.width, config.height), (40, 30));
+
+        // The client points at the middle of the frames it gets
+        let peer = pipeline.peer_mut().unwrap();
+        peer.send(InputEvent::MouseMove { x: 20, y: 15 }.to_message())
+            .unwrap();
+        pipeline.start().unwrap();
+        while injected.lock().unwrap().is_empty() {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        pipeline.stop().await.unwrap();
+        assert!(matches!(
+            injected.lock().unwrap()[0],
+            InputEvent::MouseMove { x: 32, y: 24 }
+        ));
+    }
+
+    /// Encoder counting the keyframes forced on it
+    struct CountingEncoder {
+        inner: Box<dyn VideoEncoder>,
+        forced: Arc<Mutex<u32>>,
+    }
+
+    impl VideoEncoder for CountingEncoder {
+        fn init(&mut self, config: EncoderConfig) -> Result<()> {
+            self.inner.init(config)
+        }
+
+        fn encode(&mut self, frame: RawFrame) -> Result<EncodedFrame> {
+            self.inner.encode(frame)
+        }
+
+        fn force_keyframe(&mut self) -> Result<()> {
+            *self.forced.lock().unwrap() += 1;
+            self.inner.force_keyframe()
+        }
+
+        fn reconfigure(&mut self, width: u32, height: u32) -> Result<()> {
+            self.inner.reconfigure(width, height)
+        }
+
+        fn set_bitrate(&mut self, bitrate: u32) -> Result<()> {
+            self.inner.set_bitrate(bitrate)
+        }
+
+        fn effective_config(&self) -> Option<EncoderConfig> {
+            self.inner.effective_config()
+        }
+
+        fn cleanup(&mut self) -> Result<()> {
+            self.inner.cleanup()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_keyframe_requests_rate_limited() {
+        let forced = Arc::new(Mutex::new(0));
+        let mut pipeline = SessionPipeline::new(
+            Box::new(MockCapturer::default()),
+            Box::new(CountingEncoder {
+                inner: create_encoder(CodecType::Raw).unwrap(),
+                forced: Arc::clone(&forced),
+            }),
+            NetworkPeer::new(SessionId::from_seed(7), ConnectionType::WebRTC),
+            CaptureConfig {
+                fps: 200,
+                ..Default::default()
+            },
+            EncoderConfig {
+                codec: CodecType::Raw,
+                width: 2,
+                height: 2,
+                ..Default::default()
+            },
+        );
+        pipeline
+            .honor_keyframe_requests(Duration::from_secs(60))
+            .unwrap();
+
+        // A client that lost its reference picture asks for a keyframe, and
+        // a second one asks again straight after
+        let mut recovery = KeyframeRecovery::default();
+        let lost = Error::KeyframeNeeded("reference lost".to_string());
+        let request = recovery.decode_failed(&lost, Instant::now()).unwrap();
+        assert_eq!(request, ProtocolMessage::KeyframeRequest);
+        let peer = pipeline.peer_mut().unwrap();
+        peer.send(request.clone()).unwrap();
+        peer.send(request).unwrap();
+
+        pipeline.start().unwrap();
+        while *forced.lock().unwrap() == 0 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        let encoded = pipeline.metrics().frames_encoded;
+        while pipeline.metrics().frames_encoded < encoded + 3 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        pipeline.stop().await.unwrap();
+        assert_eq!(*forced.lock().unwrap(), 1);
+    }
+
+    struct FailingCapturer;
+
+    impl ScreenCapture for FailingCapturer {
+        fn init(&mut self, _config: CaptureConfig) -> Result<()> {
+            Ok(())
+        }
+
+        fn capture_frame(&mut self) -> Result<CapturedFrame> {
+            Err(Error::TargetClosed("display gone".to_string()))
+        }
+
+        fn list_monitors(&self) -> Result<Vec<MonitorInfo>> {
+            Ok(vec![])
+        }
+
+        fn cleanup(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_component_error_reported_on_status() {
+        let mut pipeline = SessionPipeline::new(
+            Box::new(FailingCapturer),
+            create_encoder(CodecType::Raw).unwrap(),
+            NetworkPeer::new(SessionId::from_seed(5), ConnectionType::WebRTC),
+            CaptureConfig::default(),
+            EncoderConfig {
+                codec: CodecType::Raw,
+                width: 2,
+                height: 2,
+                ..Default::default()
+            },
+        );
+        let mut status = pipeline.status();
+        pipeline.start().unwrap();
+        assert_eq!(status.recv().await.unwrap(), PipelineStatus::Started);
+        match status.recv().await.unwrap() {
+            PipelineStatus::Error {
+                component,
+                message,
+                fatal,
+            } => {
+                assert_eq!(component, Component::Capture);
+                assert!(message.contains("display gone"));
+                assert!(fatal);
+            }
+            other => panic!("unexpected status: {:?}", other),
+        }
+        assert!(matches!(pipeline.stop().await, Err(Error::TargetClosed(_))));
+    }
+}
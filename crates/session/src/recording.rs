@@ -0,0 +1,379 @@
+//! Session recording
+//!
+//! Encoded frames are written to a file as they are, without re-encoding:
+//! H.264 into MP4 and VP9 into WebM. Recording begins at the first keyframe
+//! so the file decodes from its start, and each frame lasts until the next
+//! one's timestamp. The file is finalized when the recorder is finished or
+//! dropped. Audio is not recorded.
+
+use crate::webm::WebmWriter;
+use ada_remote_codec::{CodecType, EncodedFrame};
+use ada_remote_core::{Error, Result};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+/// MP4 track timescale, the usual 90 kHz video clock
+const MP4_TIMESCALE: u32 = 90_000;
+
+/// Duration given to the last frame when there is no interval to go by
+const DEFAULT_FRAME_US: u64 = 33_333;
+
+fn mux_error(e: impl std::fmt::Display) -> Error {
+    Error::Session(format!("Recording failed: {}", e))
+}
+
+/// NAL units of an Annex B H.264 access unit
+fn nal_units(data: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let mut starts = Vec::new();
+    let mut i = 0;
+    while i + 3 <= data.len() {
+        if data[i..i + 3] == [0, 0, 1] {
+            starts.push(i + 3);
+            i += 3;
+        } else {
+            i += 1;
+        }
+    }
+    let ends: Vec<usize> = starts
+        .iter()
+        .skip(1)
+        .map(|&start| {
+            // A four-byte start code leaves a zero before the three-byte one
+            let end = start - 3;
+            if end > 0 && data[end - 1] == 0 {
+                end - 1
+            } else {
+                end
+            }
+        })
+        .chain(std::iter::once(data.len()))
+        .collect();
+    starts
+        .into_iter()
+        .zip(ends)
+        .map(move |(start, end)| &data[start..end])
+        .filter(|nal| !nal.is_empty())
+}
+
+struct Mp4Output {
+    writer: mp4::Mp4Writer<BufWriter<File>>,
+    width: u32,
+    height: u32,
+    track_added: bool,
+}
+
+impl Mp4Output {
+    fn write(&mut self, frame: &EncodedFrame, start_us: u64, duration_us: u64) -> Result<()> {
+        // The parameter sets go in the track header, the rest as
+        // length-prefixed units
+        let mut sps = None;
+        let mut pps = None;
+        let mut sample = Vec::with_capacity(frame.data.len());
+        for nal in nal_units(&frame.data) {
+            match nal[0] & 0x1f {
+                7 => sps = Some(nal.to_vec()),
+                8 => pps = Some(nal.to_vec()),
+                9 => {} // access unit delimiter
+                _ => {
+                    sample.extend_from_slice(&(nal.len() as u32).to_be_bytes());
+                    sample.extend_from_slice(nal);
+                }
+            }
+        }
+
+        if !self.track_added {
+            let (Some(seq_param_set), Some(pic_param_set)) = (sps, pps) else {
+                return Err(mux_error("first keyframe has no SPS/PPS"));
+            };
+            let media_conf = mp4::MediaConfig::AvcConfig(mp4::AvcConfig {
+                width: self.width as u16,
+                height: self.height as u16,
+                seq_param_set,
+                pic_param_set,
+            });
+            self.writer
+                .add_track(&mp4::TrackConfig {
+                    track_type: mp4::TrackType::Video,
+                    timescale: MP4_TIMESCALE,
+                    language: "und".to_string(),
+                    media_conf,
+                })
+                .map_err(mux_error)?;
+            self.track_added = true;
+        }
+
+        let ticks = |us: u64| us * MP4_TIMESCALE as u64 / 1_000_000;
+        self.writer
+            .write_sample(
+                1,
+                &mp4::Mp4Sample {
+                    start_time: ticks(start_us),
+                    // Rounded at both ends so durations sum to the timestamps
+                    duration: (ticks(start_us + duration_us) - ticks(start_us)).max(1) as u32,
+                    rendering_offset: 0,
+                    is_sync: frame.is_keyframe,
+                    bytes: bytes::Bytes::from(sample),
+                },
+            )
+            .map_err(mux_error)
+    }
+}
+
+enum Output {
+    Mp4(Mp4Output),
+    Webm(WebmWriter<BufWriter<File>>),
+}
+
+/// Writes an encoded stream to a file
+pub struct Recorder {
+    path: PathBuf,
+    output: Option<Output>,
+    /// Frame held until the next one gives its duration
+    pending: Option<EncodedFrame>,
+    /// Timestamp of the first recorded frame
+    start_us: Option<u64>,
+    last_duration_us: u64,
+    /// End of the last written frame from the start of the recording
+    end_us: u64,
+    frames: u64,
+}
+
+impl Recorder {
+    /// Create the file for a `codec` stream of the given size
+    pub fn create(
+        path: impl AsRef<Path>,
+        codec: CodecType,
+        width: u32,
+        height: u32,
+    ) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let output = match codec {
+            CodecType::H264 => {
+                let file = BufWriter::new(File::create(&path)?);
+                let config = mp4::Mp4Config {
+                    major_brand: (*b"isom").into(),
+                    minor_version: 512,
+                    compatible_brands: vec![
+                        (*b"isom").into(),
+                        (*b"iso2").into(),
+                        (*b"avc1").into(),
+                        (*b"mp41").into(),
+                    ],
+                    timescale: 1000,
+                };
+                Output::Mp4(Mp4Output {
+                    writer: mp4::Mp4Writer::write_start(file, &config).map_err(mux_error)?,
+                    width,
+                    height,
+                    track_added: false,
+                })
+            }
+            CodecType::VP9 => {
+                let file = BufWriter::new(File::create(&path)?);
+                Output::Webm(WebmWriter::new(file, width, height)?)
+            }
+            other => {
+                return Err(Error::Session(format!(
+                    "Recording {:?} streams is not supported",
+                    other
+                )))
+            }
+        };
+
+        tracing::info!("Recording to {}", path.display());
+        Ok(Self {
+            path,
+            output: Some(output),
+            pending: None,
+            start_us: None,
+            last_duration_us: DEFAULT_FRAME_US,
+            end_us: 0,
+            frames: 0,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Frames written so far
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Whether the recording is still waiting for its first keyframe
+    pub fn wants_keyframe(&self) -> bool {
+        self.start_us.is_none()
+    }
+
+    /// Add the next frame of the stream
+    pub fn write_frame(&mut self, frame: &EncodedFrame) -> Result<()> {
+        if self.start_us.is_none() {
+            if !frame.is_keyframe {
+                return Ok(());
+            }
+            self.start_us = Some(frame.timestamp);
+        }
+        if let Some(pending) = &self.pending {
+            if frame.timestamp <= pending.timestamp {
+                tracing::warn!(
+                    "Skipping frame at {} us, not after {} us",
+                    frame.timestamp,
+                    pending.timestamp
+                );
+                return Ok(());
+            }
+        }
+
+        if let Some(pending) = self.pending.replace(frame.clone()) {
+            let duration_us = frame.timestamp - pending.timestamp;
+            self.last_duration_us = duration_us;
+            self.write(&pending, duration_us)?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, frame: &EncodedFrame, duration_us: u64) -> Result<()> {
+        let start_us = frame.timestamp - self.start_us.unwrap_or(frame.timestamp);
+        match self.output.as_mut() {
+            Some(Output::Mp4(output)) => output.write(frame, start_us, duration_us)?,
+            Some(Output::Webm(output)) => {
+                output.write_frame(start_us / 1000, &frame.data, frame.is_keyframe)?
+            }
+            None => return Err(mux_error("recorder already finished")),
+        }
+        self.frames += 1;
+        self.end_us = start_us + duration_us;
+        Ok(())
+    }
+
+    /// Write the last frame and the index, returning the file path
+    pub fn finish(mut self) -> Result<PathBuf> {
+        self.finalize()?;
+        Ok(self.path.clone())
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        if let Some(pending) = self.pending.take() {
+            self.write(&pending, self.last_duration_us)?;
+        }
+        match self.output.take() {
+            Some(Output::Mp4(mut output)) => {
+                output.writer.write_end().map_err(mux_error)?;
+                std::io::Write::flush(&mut output.writer.into_writer())?;
+            }
+            Some(Output::Webm(output)) => {
+                output.finish(self.end_us as f64 / 1000.0)?;
+            }
+            None => return Ok(()),
+        }
+        tracing::info!("Recorded {} frames to {}", self.frames, self.path.display());
+        Ok(())
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        if let Err(e) = self.finalize() {
+            tracing::error!("Failed to finalize {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ada_remote_codec::{create_encoder, EncoderConfig, PixelFormat, RawFrame};
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("ada-remote-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn test_nal_units() {
+        let data = [
+            0, 0, 0, 1, 0x67, 1, 2, 0, 0, 1, 0x68, 3, 0, 0, 0, 1, 0x65, 4,
+        ];
+        let units: Vec<&[u8]> = nal_units(&data).collect();
+        assert_eq!(units, vec![&[0x67, 1, 2][..], &[0x68, 3], &[0x65, 4]]);
+    }
+
+    #[test]
+    fn test_records_h264_to_mp4() {
+        let mut encoder = create_encoder(CodecType::H264).unwrap();
+        encoder
+            .init(EncoderConfig {
+                width: 64,
+                height: 48,
+                ..Default::default()
+            })
+            .unwrap();
+
+        let path = temp_path("recording.mp4");
+        let mut recorder = Recorder::create(&path, CodecType::H264, 64, 48).unwrap();
+        for i in 0..10u64 {
+            let frame = encoder
+                .encode(RawFrame {
+                    data: vec![(i * 20) as u8; 64 * 48 * 4],
+                    format: PixelFormat::Rgba,
+                    width: 64,
+                    height: 48,
+                    timestamp: i * 33_333,
+                })
+                .unwrap();
+            recorder.write_frame(&frame).unwrap();
+        }
+        recorder.finish().unwrap();
+
+        let size = std::fs::metadata(&path).unwrap().len();
+        let mut reader = mp4::Mp4Reader::read_header(File::open(&path).unwrap(), size).unwrap();
+        assert_eq!(reader.sample_count(1).unwrap(), 10);
+        let track = &reader.tracks()[&1];
+        assert_eq!((track.width(), track.height()), (64, 48));
+        assert!(reader.read_sample(1, 1).unwrap().unwrap().is_sync);
+        // Timing in 90 kHz ticks, the last frame lasting as long as the one before
+        let last = reader.read_sample(1, 10).unwrap().unwrap();
+        assert_eq!((last.start_time, last.duration), (26_999, 3_000));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_records_vp9_to_webm_from_first_keyframe() {
+        let path = temp_path("recording.webm");
+        let mut recorder = Recorder::create(&path, CodecType::VP9, 320, 240).unwrap();
+        for i in 0..12u64 {
+            recorder
+                .write_frame(&EncodedFrame {
+                    data: vec![i as u8; 10],
+                    timestamp: 1_000_000 + i * 40_000,
+                    // Frame 0 is not a keyframe and is skipped
+                    is_keyframe: i == 1 || i == 7,
+                    temporal_layer: 0,
+                })
+                .unwrap();
+        }
+        // Dropping finalizes the file as well
+        drop(recorder);
+
+        let mut file = matroska_demuxer::MatroskaFile::open(File::open(&path).unwrap()).unwrap();
+        assert_eq!(file.tracks()[0].codec_id(), "V_VP9");
+        assert_eq!(file.info().duration(), Some(440.0));
+
+        let mut frame = matroska_demuxer::Frame::default();
+        let mut frames = Vec::new();
+        while file.next_frame(&mut frame).unwrap() {
+            frames.push((frame.timestamp, frame.data[0], frame.is_keyframe));
+        }
+        assert_eq!(frames.len(), 11);
+        assert_eq!(frames[0], (0, 1, Some(true)));
+        assert_eq!(frames[6], (240, 7, Some(true)));
+        assert_eq!(frames[10], (400, 11, Some(false)));
+
+        // Seeking lands on the second keyframe
+        file.seek(240).unwrap();
+        assert!(file.next_frame(&mut frame).unwrap());
+        assert_eq!(frame.data[0], 7);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
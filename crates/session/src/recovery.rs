@@ -0,0 +1,129 @@
+//! Recovery from decode errors
+//!
+//! A client whose decoder loses a reference picture, to a dropped keyframe
+//! or a corrupt frame, would show a frozen picture until the next scheduled
+//! keyframe. Instead it sends `KeyframeRequest` and the host forces one
+//! straight away. Both sides limit the rate: the client repeats a request
+//! only if the keyframe has not arrived within the interval, and the host
+//! ignores requests within the interval of the last keyframe it forced, so
+//! a burst of broken frames or several viewers cost one keyframe.
+
+use ada_remote_codec::DecoderError;
+use ada_remote_core::{Error, ProtocolMessage};
+use std::time::{Duration, Instant};
+
+/// Shortest time between keyframe requests, and between keyframes forced
+/// for them
+pub const KEYFRAME_REQUEST_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Client side: turns decode errors into keyframe requests
+#[derive(Debug)]
+pub struct KeyframeRecovery {
+    interval: Duration,
+    /// When the outstanding request was sent
+    requested_at: Option<Instant>,
+}
+
+impl KeyframeRecovery {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            requested_at: None,
+        }
+    }
+
+    /// Note a failed decode at `now`, returning the request to send if the
+    /// stream needs a keyframe and none was asked for within the interval
+    pub fn decode_failed(&mut self, error: &Error, now: Instant) -> Option<ProtocolMessage> {
+        if DecoderError::classify(error) == DecoderError::Recoverable {
+            return None;
+        }
+        if self
+            .requested_at
+            .is_some_and(|at| now.saturating_duration_since(at) < self.interval)
+        {
+            return None;
+        }
+        self.requested_at = Some(now);
+        Some(ProtocolMessage::KeyframeRequest)
+    }
+
+    /// Note a decoded frame; the stream has recovered, so the next error
+    /// asks again at once
+    pub fn decoded(&mut self) {
+        self.requested_at = None;
+    }
+}
+
+impl Default for KeyframeRecovery {
+    fn default() -> Self {
+        Self::new(KEYFRAME_REQUEST_INTERVAL)
+    }
+}
+
+/// Host side: decides which keyframe requests the encoder honors
+#[derive(Debug)]
+pub struct KeyframeLimiter {
+    interval: Duration,
+    last_forced: Option<Instant>,
+}
+
+impl KeyframeLimiter {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last_forced: None,
+        }
+    }
+
+    /// Whether a request received at `now` should force a keyframe
+    pub fn allow(&mut self, now: Instant) -> bool {
+        if self
+            .last_forced
+            .is_some_and(|at| now.saturating_duration_since(at) < self.interval)
+        {
+            return false;
+        }
+        self.last_forced = Some(now);
+        true
+    }
+}
+
+impl Default for KeyframeLimiter {
+    fn default() -> Self {
+        Self::new(KEYFRAME_REQUEST_INTERVAL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_needs_keyframe_requests_once_per_interval() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut recovery = KeyframeRecovery::default();
+        let lost = Error::KeyframeNeeded("reference lost".to_string());
+
+        let recoverable = Error::Decoding("no picture".to_string());
+        assert_eq!(recovery.decode_failed(&recoverable, at(0)), None);
+        assert_eq!(
+            recovery.decode_failed(&lost, at(0)),
+            Some(ProtocolMessage::KeyframeRequest)
+        );
+        // The frames after it fail too while the keyframe is on its way
+        assert_eq!(recovery.decode_failed(&lost, at(100)), None);
+        assert_eq!(
+            recovery.decode_failed(&lost, at(500)),
+            Some(ProtocolMessage::KeyframeRequest)
+        );
+        recovery.decoded();
+        assert!(recovery.decode_failed(&lost, at(600)).is_some());
+
+        let mut limiter = KeyframeLimiter::default();
+        assert!(limiter.allow(at(0)));
+        assert!(!limiter.allow(at(499)));
+        assert!(limiter.allow(at(500)));
+    }
+}
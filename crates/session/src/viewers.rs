@@ -0,0 +1,272 @@
+//! Several viewers of one host session
+//!
+//! The host keeps a connection per viewer, each negotiated and keyed on its
+//! own, and sends every frame to all of them. A viewer on a slow link can be
+//! capped at a temporal layer, so it gets a lower frame rate of the same
+//! stream. Viewers only watch: their input is dropped unless the host grants
+//! them control, which one viewer holds at a time.
+
+use crate::events::{SessionEvent, SessionEvents};
+use ada_remote_core::{Error, ProtocolMessage, Result};
+use ada_remote_network::NetworkPeer;
+use std::collections::BTreeMap;
+
+/// Viewer number assigned by the signaling server
+pub type ViewerId = u32;
+
+/// Connections to the viewers of a host session
+#[derive(Default)]
+pub struct ViewerGroup {
+    viewers: BTreeMap<ViewerId, NetworkPeer>,
+    /// Highest temporal layer sent to each capped viewer
+    layer_caps: BTreeMap<ViewerId, u8>,
+    controller: Option<ViewerId>,
+    events: Option<SessionEvents>,
+}
+
+impl ViewerGroup {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Publish viewers joining and leaving on `events`
+    pub fn set_events(&mut self, events: SessionEvents) {
+        self.events = Some(events);
+    }
+
+    /// Add a connected viewer, replacing any earlier connection of `id`
+    pub fn add(&mut self, id: ViewerId, peer: NetworkPeer) {
+        tracing::info!("Viewer {} joined", id);
+        self.viewers.insert(id, peer);
+        self.emit(SessionEvent::PeerJoined { viewer: id });
+    }
+
+    /// Take a viewer out of the group, returning its connection
+    pub fn remove(&mut self, id: ViewerId) -> Option<NetworkPeer> {
+        if self.controller == Some(id) {
+            self.controller = None;
+        }
+        self.layer_caps.remove(&id);
+        let peer = self.viewers.remove(&id);
+        if peer.is_some() {
+            tracing::info!("Viewer {} left", id);
+            self.emit(SessionEvent::PeerLeft { viewer: id });
+        }
+        peer
+    }
+
+    fn emit(&self, event: SessionEvent) {
+        if let Some(events) = &self.events {
+            events.emit(event);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.viewers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.viewers.is_empty()
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = ViewerId> + '_ {
+        self.viewers.keys().copied()
+    }
+
+    /// Send `id` only video frames up to `max_layer`, or all with `None`
+    pub fn set_max_temporal_layer(&mut self, id: ViewerId, max_layer: Option<u8>) -> Result<()> {
+        if !self.viewers.contains_key(&id) {
+            return Err(Error::Session(format!("No viewer {}", id)));
+        }
+        match max_layer {
+            Some(layer) => self.layer_caps.insert(id, layer),
+            None => self.layer_caps.remove(&id),
+        };
+        Ok(())
+    }
+
+    /// Let `id` control the host, taking control from any other viewer
+    pub fn grant_control(&mut self, id: ViewerId) -> Result<()> {
+        if !self.viewers.contains_key(&id) {
+            return Err(Error::Session(format!("No viewer {}", id)));
+        }
+        tracing::info!("Viewer {} granted control", id);
+        self.controller = Some(id);
+        Ok(())
+    }
+
+    pub fn revoke_control(&mut self) {
+        if let Some(id) = self.controller.take() {
+            tracing::info!("Control revoked from viewer {}", id);
+        }
+    }
+
+    /// Viewer currently in control
+    pub fn controller(&self) -> Option<ViewerId> {
+        self.controller
+    }
+
+    /// Whether input from `id` reaches the host
+    pub fn accepts_input(&self, id: ViewerId) -> bool {
+        self.controller == Some(id)
+    }
+
+    /// Send a message to every viewer, dropping those whose connection has
+    /// closed. Video frames above a viewer's temporal layer cap are skipped.
+    /// Returns the number of viewers it was sent to.
+    pub fn send_all(&mut self, message: &ProtocolMessage) -> usize {
+        let layer = match message {
+            ProtocolMessage::VideoFrame { temporal_layer, .. } => *temporal_layer,
+            _ => 0,
+        };
+        let mut sent = 0;
+        let mut closed = Vec::new();
+        for (&id, peer) in &self.viewers {
+            if self.layer_caps.get(&id).is_some_and(|&cap| layer > cap) {
+                continue;
+            }
+            match peer.send(message.clone()) {
+                Ok(()) => sent += 1,
+                Err(e) => {
+                    tracing::warn!("Dropping viewer {}: {}", id, e);
+                    closed.push(id);
+                }
+            }
+        }
+        for id in closed {
+            self.remove(id);
+        }
+        sent
+    }
+
+    /// Next message from viewer `id`, skipping input unless it has control.
+    /// Returns `None` for an unknown viewer.
+    pub async fn receive(&mut self, id: ViewerId) -> Option<ProtocolMessage> {
+        let controller = self.controller;
+        let peer = self.viewers.get_mut(&id)?;
+        loop {
+            let message = peer.receive().await?;
+            if matches!(message, ProtocolMessage::InputEvent { .. }) && controller != Some(id) {
+                tracing::debug!("Ignoring input from viewer {} without control", id);
+                continue;
+            }
+            return Some(message);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ada_remote_core::{InputEventType, SessionId};
+    use ada_remote_network::ConnectionType;
+
+    fn viewer() -> NetworkPeer {
+        // Without a transport a peer's sends come back on its own queue
+        NetworkPeer::new(SessionId::new(), ConnectionType::WebRTC)
+    }
+
+    fn input() -> ProtocolMessage {
+        ProtocolMessage::InputEvent {
+            event_type: InputEventType::MouseMove,
+            data: vec![0; 8],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_frames_reach_every_viewer() {
+        let mut group = ViewerGroup::new();
+        group.add(1, viewer());
+        group.add(2, viewer());
+
+        let frame = ProtocolMessage::VideoFrame {
+            timestamp: 42,
+            captured_at: 0,
+            sent_at: 0,
+            is_keyframe: true,
+            temporal_layer: 0,
+            data: vec![1, 2, 3],
+        };
+        assert_eq!(group.send_all(&frame), 2);
+        for id in [1, 2] {
+            match group.receive(id).await {
+                Some(ProtocolMessage::VideoFrame { data, .. }) => assert_eq!(data, vec![1, 2, 3]),
+                other => panic!("unexpected message: {:?}", other),
+            }
+        }
+        assert!(group.receive(3).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_capped_viewer_gets_base_layer_only() {
+        let mut group = ViewerGroup::new();
+        group.add(1, viewer());
+        group.add(2, viewer());
+        group.set_max_temporal_layer(2, Some(0)).unwrap();
+        assert!(group.set_max_temporal_layer(3, Some(0)).is_err());
+
+        let frame = |timestamp| ProtocolMessage::VideoFrame {
+            timestamp,
+            captured_at: 0,
+            sent_at: 0,
+            is_keyframe: timestamp == 0,
+            temporal_layer: (timestamp % 2) as u8,
+            data: vec![],
+        };
+        let sent: Vec<usize> = (0..4).map(|t| group.send_all(&frame(t))).collect();
+        assert_eq!(sent, [2, 1, 2, 1]);
+
+        // Everything else reaches capped viewers too
+        assert_eq!(group.send_all(&ProtocolMessage::Heartbeat), 2);
+
+        for (id, expected) in [(1, vec![0, 1, 2, 3]), (2, vec![0, 2])] {
+            let mut timestamps = Vec::new();
+            while let Some(ProtocolMessage::VideoFrame { timestamp, .. }) = group.receive(id).await
+            {
+                timestamps.push(timestamp);
+            }
+            assert_eq!(timestamps, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_input_needs_control() {
+        let mut group = ViewerGroup::new();
+        let events = SessionEvents::new();
+        let mut subscription = events.subscribe();
+        group.set_events(events);
+        group.add(1, viewer());
+        group.add(2, viewer());
+        assert!(group.grant_control(3).is_err());
+
+        for id in [1, 2] {
+            let peer = group.viewers.get(&id).unwrap();
+            peer.send(input()).unwrap();
+            peer.send(ProtocolMessage::Heartbeat).unwrap();
+        }
+        group.grant_control(2).unwrap();
+        assert!(!group.accepts_input(1));
+        assert!(matches!(
+            group.receive(1).await,
+            Some(ProtocolMessage::Heartbeat)
+        ));
+        assert!(matches!(
+            group.receive(2).await,
+            Some(ProtocolMessage::InputEvent { .. })
+        ));
+
+        // Control goes with the viewer
+        group.remove(2);
+        assert_eq!(group.controller(), None);
+        for expected in [
+            SessionEvent::PeerJoined { viewer: 1 },
+            SessionEvent::PeerJoined { viewer: 2 },
+            SessionEvent::PeerLeft { viewer: 2 },
+        ] {
+            assert_eq!(subscription.try_recv().unwrap(), expected);
+        }
+        group.grant_control(1).unwrap();
+        group.revoke_control();
+        assert!(!group.accepts_input(1));
+    }
+}
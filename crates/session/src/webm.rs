@@ -0,0 +1,265 @@
+//! Minimal WebM (Matroska) muxer for a single VP9 track
+//!
+//! Each keyframe opens a new cluster with a cue point, so players can seek
+//! to any keyframe. Sizes, the duration and the cue position are patched in
+//! by [`WebmWriter::finish`].
+
+use std::io::{Seek, SeekFrom, Write};
+
+const EBML: u32 = 0x1A45DFA3;
+const SEGMENT: u32 = 0x18538067;
+const SEEK_HEAD: u32 = 0x114D9B74;
+const SEEK: u32 = 0x4DBB;
+const SEEK_ID: u32 = 0x53AB;
+const SEEK_POSITION: u32 = 0x53AC;
+const INFO: u32 = 0x1549A966;
+const TIMECODE_SCALE: u32 = 0x2AD7B1;
+const DURATION: u32 = 0x4489;
+const MUXING_APP: u32 = 0x4D80;
+const WRITING_APP: u32 = 0x5741;
+const TRACKS: u32 = 0x1654AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const TRACK_UID: u32 = 0x73C5;
+const TRACK_TYPE: u32 = 0x83;
+const CODEC_ID: u32 = 0x86;
+const VIDEO: u32 = 0xE0;
+const PIXEL_WIDTH: u32 = 0xB0;
+const PIXEL_HEIGHT: u32 = 0xBA;
+const CLUSTER: u32 = 0x1F43B675;
+const TIMECODE: u32 = 0xE7;
+const SIMPLE_BLOCK: u32 = 0xA3;
+const CUES: u32 = 0x1C53BB6B;
+const CUE_POINT: u32 = 0xBB;
+const CUE_TIME: u32 = 0xB3;
+const CUE_TRACK_POSITIONS: u32 = 0xB7;
+const CUE_TRACK: u32 = 0xF7;
+const CUE_CLUSTER_POSITION: u32 = 0xF1;
+
+/// Block timecodes are signed 16-bit offsets from the cluster
+const MAX_CLUSTER_SPAN_MS: u64 = 30_000;
+
+fn put_id(buf: &mut Vec<u8>, id: u32) {
+    let bytes = id.to_be_bytes();
+    let skip = bytes.iter().take_while(|b| **b == 0).count();
+    buf.extend_from_slice(&bytes[skip..]);
+}
+
+/// Sizes always use the 8-byte form so they can be patched in place
+fn put_size(buf: &mut Vec<u8>, size: u64) {
+    buf.push(0x01);
+    buf.extend_from_slice(&size.to_be_bytes()[1..]);
+}
+
+fn put_element(buf: &mut Vec<u8>, id: u32, payload: &[u8]) {
+    put_id(buf, id);
+    put_size(buf, payload.len() as u64);
+    buf.extend_from_slice(payload);
+}
+
+fn put_uint(buf: &mut Vec<u8>, id: u32, value: u64) {
+    let bytes = value.to_be_bytes();
+    let skip = bytes.iter().take_while(|b| **b == 0).count().min(7);
+    put_element(buf, id, &bytes[skip..]);
+}
+
+fn put_str(buf: &mut Vec<u8>, id: u32, value: &str) {
+    put_element(buf, id, value.as_bytes());
+}
+
+/// Cluster being filled
+struct Cluster {
+    timecode_ms: u64,
+    data: Vec<u8>,
+}
+
+/// Writes a VP9 stream as WebM
+pub(crate) struct WebmWriter<W: Write + Seek> {
+    writer: W,
+    segment_size_pos: u64,
+    segment_data_pos: u64,
+    /// Where the Cues offset and the duration go once known
+    cues_position_pos: u64,
+    duration_pos: u64,
+    cluster: Option<Cluster>,
+    /// (timecode, cluster offset in the segment) of each keyframe cluster
+    cues: Vec<(u64, u64)>,
+}
+
+impl<W: Write + Seek> WebmWriter<W> {
+    pub fn new(mut writer: W, width: u32, height: u32) -> std::io::Result<Self> {
+        let mut header = Vec::new();
+        put_uint(&mut header, 0x4286, 1); // EBMLVersion
+        put_uint(&mut header, 0x42F7, 1); // EBMLReadVersion
+        put_uint(&mut header, 0x42F2, 4); // EBMLMaxIDLength
+        put_uint(&mut header, 0x42F3, 8); // EBMLMaxSizeLength
+        put_str(&mut header, 0x4282, "webm"); // DocType
+        put_uint(&mut header, 0x4287, 4); // DocTypeVersion
+        put_uint(&mut header, 0x4285, 2); // DocTypeReadVersion
+        let mut buf = Vec::new();
+        put_element(&mut buf, EBML, &header);
+
+        put_id(&mut buf, SEGMENT);
+        let segment_size_pos = buf.len() as u64;
+        put_size(&mut buf, 0);
+        let segment_data_pos = buf.len() as u64;
+
+        // Info and Tracks are laid out first so the SeekHead can point at them
+        let mut info = Vec::new();
+        put_uint(&mut info, TIMECODE_SCALE, 1_000_000);
+        put_str(&mut info, MUXING_APP, "ada-remote");
+        put_str(&mut info, WRITING_APP, "ada-remote");
+        put_id(&mut info, DURATION);
+        put_size(&mut info, 8);
+        let duration_offset = info.len() as u64;
+        info.extend_from_slice(&0f64.to_be_bytes());
+
+        let mut video = Vec::new();
+        put_uint(&mut video, PIXEL_WIDTH, width as u64);
+        put_uint(&mut video, PIXEL_HEIGHT, height as u64);
+        let mut entry = Vec::new();
+        put_uint(&mut entry, TRACK_NUMBER, 1);
+        put_uint(&mut entry, TRACK_UID, 1);
+        put_uint(&mut entry, TRACK_TYPE, 1);
+        put_str(&mut entry, CODEC_ID, "V_VP9");
+        put_element(&mut entry, VIDEO, &video);
+        let mut tracks = Vec::new();
+        put_element(&mut tracks, TRACK_ENTRY, &entry);
+
+        // SeekHead entries are fixed size: the Cues position is 8 bytes
+        let seek = |id: u32, position: u64| {
+            let mut id_bytes = Vec::new();
+            put_id(&mut id_bytes, id);
+            let mut seek = Vec::new();
+            put_element(&mut seek, SEEK_ID, &id_bytes);
+            put_element(&mut seek, SEEK_POSITION, &position.to_be_bytes());
+            let mut element = Vec::new();
+            put_element(&mut element, SEEK, &seek);
+            element
+        };
+        let seek_head_len = {
+            let mut probe = Vec::new();
+            for id in [INFO, TRACKS, CUES] {
+                probe.extend(seek(id, 0));
+            }
+            let mut element = Vec::new();
+            put_element(&mut element, SEEK_HEAD, &probe);
+            element.len() as u64
+        };
+        let info_offset = seek_head_len;
+        let mut info_element = Vec::new();
+        put_element(&mut info_element, INFO, &info);
+        let tracks_offset = info_offset + info_element.len() as u64;
+
+        let mut seeks = Vec::new();
+        seeks.extend(seek(INFO, info_offset));
+        seeks.extend(seek(TRACKS, tracks_offset));
+        let cues_seek_start = seeks.len();
+        seeks.extend(seek(CUES, 0));
+        let mut seek_head = Vec::new();
+        put_id(&mut seek_head, SEEK_HEAD);
+        put_size(&mut seek_head, seeks.len() as u64);
+        let seeks_start = seek_head.len();
+        seek_head.extend_from_slice(&seeks);
+        // The Cues SeekPosition payload is the last 8 bytes of its Seek
+        let cues_position_pos =
+            segment_data_pos + (seeks_start + cues_seek_start + seek(CUES, 0).len() - 8) as u64;
+
+        let duration_pos = segment_data_pos
+            + info_offset
+            + (info_element.len() - info.len()) as u64
+            + duration_offset;
+
+        buf.extend_from_slice(&seek_head);
+        buf.extend_from_slice(&info_element);
+        put_element(&mut buf, TRACKS, &tracks);
+
+        writer.write_all(&buf)?;
+        Ok(Self {
+            writer,
+            segment_size_pos,
+            segment_data_pos,
+            cues_position_pos,
+            duration_pos,
+            cluster: None,
+            cues: Vec::new(),
+        })
+    }
+
+    /// Append a frame at `timecode_ms` from the start of the recording
+    pub fn write_frame(
+        &mut self,
+        timecode_ms: u64,
+        data: &[u8],
+        is_keyframe: bool,
+    ) -> std::io::Result<()> {
+        let new_cluster = match &self.cluster {
+            None => true,
+            Some(cluster) => {
+                is_keyframe || timecode_ms.saturating_sub(cluster.timecode_ms) > MAX_CLUSTER_SPAN_MS
+            }
+        };
+        if new_cluster {
+            self.flush_cluster()?;
+            if is_keyframe {
+                let position = self.writer.stream_position()? - self.segment_data_pos;
+                self.cues.push((timecode_ms, position));
+            }
+            let mut data = Vec::new();
+            put_uint(&mut data, TIMECODE, timecode_ms);
+            self.cluster = Some(Cluster { timecode_ms, data });
+        }
+
+        let cluster = self.cluster.as_mut().expect("cluster was just opened");
+        let mut block = vec![0x81]; // track 1 as a one-byte vint
+        let relative = timecode_ms.saturating_sub(cluster.timecode_ms) as i16;
+        block.extend_from_slice(&relative.to_be_bytes());
+        block.push(if is_keyframe { 0x80 } else { 0x00 });
+        block.extend_from_slice(data);
+        put_element(&mut cluster.data, SIMPLE_BLOCK, &block);
+        Ok(())
+    }
+
+    fn flush_cluster(&mut self) -> std::io::Result<()> {
+        if let Some(cluster) = self.cluster.take() {
+            let mut element = Vec::new();
+            put_element(&mut element, CLUSTER, &cluster.data);
+            self.writer.write_all(&element)?;
+        }
+        Ok(())
+    }
+
+    /// Write the Cues and patch the header; `duration_ms` is the end of the
+    /// last frame
+    pub fn finish(mut self, duration_ms: f64) -> std::io::Result<W> {
+        self.flush_cluster()?;
+
+        let cues_position = self.writer.stream_position()? - self.segment_data_pos;
+        let mut cues = Vec::new();
+        for (time, position) in &self.cues {
+            let mut positions = Vec::new();
+            put_uint(&mut positions, CUE_TRACK, 1);
+            put_uint(&mut positions, CUE_CLUSTER_POSITION, *position);
+            let mut point = Vec::new();
+            put_uint(&mut point, CUE_TIME, *time);
+            put_element(&mut point, CUE_TRACK_POSITIONS, &positions);
+            put_element(&mut cues, CUE_POINT, &point);
+        }
+        let mut element = Vec::new();
+        put_element(&mut element, CUES, &cues);
+        self.writer.write_all(&element)?;
+
+        let end = self.writer.stream_position()?;
+        let mut size = Vec::new();
+        put_size(&mut size, end - self.segment_data_pos);
+        self.writer.seek(SeekFrom::Start(self.segment_size_pos))?;
+        self.writer.write_all(&size)?;
+        self.writer.seek(SeekFrom::Start(self.cues_position_pos))?;
+        self.writer.write_all(&cues_position.to_be_bytes())?;
+        self.writer.seek(SeekFrom::Start(self.duration_pos))?;
+        self.writer.write_all(&duration_ms.to_be_bytes())?;
+        self.writer.seek(SeekFrom::Start(end))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
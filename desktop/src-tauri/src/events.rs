@@ -0,0 +1,125 @@
+//! Session state events for the frontend
+//!
+//! Every connection state change is emitted as a `session-state` event.
+//! `disconnected` is terminal. `failed` is followed by `connecting` while the
+//! peer reconnects, and is final once it gives up. The first `connected`
+//! carries the short authentication string for the user to compare.
+
+use ada_remote_core::SessionId;
+use ada_remote_network::ConnectionState;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+use tracing::warn;
+
+/// Name of the event the frontend listens for
+pub const SESSION_STATE_EVENT: &str = "session-state";
+
+/// State names as seen by the frontend
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionState {
+    Disconnected,
+    Connecting,
+    Connected,
+    Failed,
+}
+
+impl From<ConnectionState> for SessionState {
+    fn from(state: ConnectionState) -> Self {
+        match state {
+            ConnectionState::Disconnected => SessionState::Disconnected,
+            ConnectionState::Connecting => SessionState::Connecting,
+            ConnectionState::Connected => SessionState::Connected,
+            ConnectionState::Failed => SessionState::Failed,
+        }
+    }
+}
+
+/// Payload of a `session-state` event
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionStatePayload {
+    pub state: SessionState,
+    pub session_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sas: Option<String>,
+}
+
+impl SessionStatePayload {
+    pub fn new(state: ConnectionState, session_id: SessionId, reason: Option<String>) -> Self {
+        Self {
+            state: state.into(),
+            session_id: format!("{}", session_id),
+            reason,
+            sas: None,
+        }
+    }
+
+    /// Payload for a session just set up, with the code both users see
+    pub fn connected(session_id: SessionId, sas: String) -> Self {
+        Self {
+            sas: Some(sas),
+            ..Self::new(ConnectionState::Connected, session_id, None)
+        }
+    }
+
+    /// Payload for a change reported by the peer itself
+    pub fn from_peer(state: ConnectionState, session_id: SessionId) -> Self {
+        let reason = match state {
+            ConnectionState::Failed => Some("Connection lost".to_string()),
+            _ => None,
+        };
+        Self::new(state, session_id, reason)
+    }
+}
+
+/// Send a state change to every window
+pub fn emit_session_state(app: &AppHandle, payload: SessionStatePayload) {
+    if let Err(e) = app.emit_all(SESSION_STATE_EVENT, payload) {
+        warn!("Failed to emit session state: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_payload_for_each_state() {
+        let session_id = SessionId::new();
+        let cases = [
+            (ConnectionState::Disconnected, "disconnected", None),
+            (ConnectionState::Connecting, "connecting", None),
+            (ConnectionState::Connected, "connected", None),
+            (ConnectionState::Failed, "failed", Some("Connection lost")),
+        ];
+        for (state, name, reason) in cases {
+            let payload = SessionStatePayload::from_peer(state, session_id);
+            let json = serde_json::to_value(&payload).unwrap();
+            assert_eq!(json["state"], name);
+            assert_eq!(json["session_id"], format!("{}", session_id));
+            assert_eq!(json.get("reason").and_then(|r| r.as_str()), reason);
+            assert!(json.get("sas").is_none());
+        }
+
+        let json = serde_json::to_value(SessionStatePayload::connected(
+            session_id,
+            "123456".to_string(),
+        ))
+        .unwrap();
+        assert_eq!(json["state"], "connected");
+        assert_eq!(json["sas"], "123456");
+
+        let payload = SessionStatePayload::new(
+            ConnectionState::Disconnected,
+            session_id,
+            Some("Host ended the session".to_string()),
+        );
+        let json = serde_json::to_string(&payload).unwrap();
+        assert_eq!(
+            serde_json::from_str::<SessionStatePayload>(&json).unwrap(),
+            payload
+        );
+    }
+}
@@ -1,15 +1,265 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-use ada_remote_core::{SessionId, SessionConfig, ConnectionMode, VideoQuality};
+mod events;
+mod monitors;
+
+use ada_remote_capture::{create_capturer, CaptureConfig, CaptureTarget};
+use ada_remote_codec::{create_encoder, probe_codecs, CodecCapability, CodecType, EncoderConfig};
+use ada_remote_core::{Capabilities, SessionId, SessionConfig, ConnectionMode, PointerMode, VideoQuality};
+use ada_remote_input::{create_injector, ModeSwitch};
+use ada_remote_network::{ConnectionState, ConnectionType, NetworkConfig, NetworkPeer};
+use ada_remote_session::{join_session, AttemptLimiter, Handshake, SessionPipeline};
+use events::{emit_session_state, SessionStatePayload};
+use monitors::{monitor_payloads, select_monitor, MonitorPayload};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
+use tauri::AppHandle;
 use tokio::sync::Mutex;
-use tracing::{info, error};
+use tokio::task::JoinHandle;
+use tracing::{info, error, warn};
 
 /// Application state
 struct AppState {
     current_session: Option<SessionConfig>,
+    /// Connected peer of a client session
+    peer: Option<NetworkPeer>,
+    /// Features agreed with the host of a client session
+    capabilities: Option<Capabilities>,
+    /// Wrong passwords given to host sessions
+    attempts: Arc<Mutex<AttemptLimiter>>,
+    /// Screen streaming of a host session, set once a client has connected
+    pipeline: Option<SessionPipeline>,
+    /// Monitor shared by host sessions
+    capture_monitor: usize,
+    quality: VideoQuality,
+    /// Connection mode of new sessions
+    mode: ModeSwitch,
+    /// Task connecting the peer and forwarding its state changes
+    session_task: Option<JoinHandle<()>>,
+    codecs: Vec<CodecCapability>,
+}
+
+impl AppState {
+    /// Bring the host pipeline in line with the session config
+    fn apply_session_config(&mut self) {
+        if let (Some(config), Some(pipeline)) =
+            (self.current_session.as_ref(), self.pipeline.as_mut())
+        {
+            pipeline.apply_config(config);
+        }
+    }
+}
+
+/// Which end of a session this app is
+enum Side {
+    Host,
+    /// Client, with the password to ask for the session with
+    Client(Option<String>),
+}
+
+/// Connect a peer for `session_id`, then report its state changes until the
+/// session ends
+async fn run_session(
+    app: AppHandle,
+    state: Arc<Mutex<AppState>>,
+    session_id: SessionId,
+    side: Side,
+) {
+    let host = matches!(side, Side::Host);
+    let mut peer = NetworkPeer::new(session_id, ConnectionType::WebRTC);
+    let mut changes = peer.state_changes();
+    emit_session_state(
+        &app,
+        SessionStatePayload::new(ConnectionState::Connecting, session_id, None),
+    );
+
+    let network = NetworkConfig::default();
+    let result = if host {
+        peer.accept(&network).await
+    } else {
+        peer.connect(&network).await
+    };
+    if let Err(e) = result {
+        error!("Session {} failed to connect: {}", session_id, e);
+        emit_session_state(
+            &app,
+            SessionStatePayload::new(ConnectionState::Failed, session_id, Some(e.to_string())),
+        );
+        state.lock().await.current_session = None;
+        return;
+    }
+
+    let (config, monitor, attempts) = {
+        let app_state = state.lock().await;
+        (
+            app_state.current_session.clone(),
+            app_state.capture_monitor,
+            Arc::clone(&app_state.attempts),
+        )
+    };
+    let Some(config) = config else {
+        return;
+    };
+    // Negotiated before taking the lock, which the commands need meanwhile
+    let connected = match side {
+        Side::Host => {
+            let mut attempts = attempts.lock().await;
+            open_pipeline(peer, &config, monitor, &mut attempts)
+                .await
+                .map(|(pipeline, sas)| Connected::Host(pipeline, sas))
+        }
+        Side::Client(password) => match join_session(
+            &mut peer,
+            password,
+            config.mode,
+            Capabilities::supported(),
+        )
+        .await
+        {
+            Ok(handshake) => Ok(Connected::Client(peer, handshake)),
+            Err(e) => {
+                disconnect_peer(&mut peer).await;
+                Err(e.to_string())
+            }
+        },
+    };
+
+    // Held while the pipeline starts so a disconnect cannot interleave
+    let mut app_state = state.lock().await;
+    let started = match connected {
+        Ok(Connected::Host(pipeline, sas)) => start_pipeline(pipeline).await.map(|pipeline| {
+            app_state.pipeline = Some(pipeline);
+            sas
+        }),
+        Ok(Connected::Client(peer, handshake)) => {
+            app_state.peer = Some(peer);
+            app_state.capabilities = Some(handshake.capabilities);
+            Ok(handshake.sas)
+        }
+        Err(e) => Err(e),
+    };
+    let sas = match started {
+        Ok(sas) => sas,
+        Err(e) => {
+            error!("Session {} failed to start: {}", session_id, e);
+            emit_session_state(
+                &app,
+                SessionStatePayload::new(ConnectionState::Failed, session_id, Some(e)),
+            );
+            app_state.current_session = None;
+            return;
+        }
+    };
+    drop(app_state);
+
+    changes.borrow_and_update();
+    emit_session_state(&app, SessionStatePayload::connected(session_id, sas));
+
+    // Drops and reconnects happen inside the peer; this ends when it is dropped
+    while changes.changed().await.is_ok() {
+        let connection_state = *changes.borrow_and_update();
+        emit_session_state(&app, SessionStatePayload::from_peer(connection_state, session_id));
+    }
+}
+
+fn capture_config(monitor: usize) -> CaptureConfig {
+    CaptureConfig {
+        target: CaptureTarget::Monitor(monitor),
+        ..Default::default()
+    }
+}
+
+async fn disconnect_peer(peer: &mut NetworkPeer) {
+    if let Err(e) = peer.disconnect().await {
+        warn!("Error while disconnecting: {}", e);
+    }
+}
+
+/// Session set up on a connected peer, to be started under the state lock
+enum Connected {
+    /// Pipeline and the short authentication string
+    Host(SessionPipeline, String),
+    Client(NetworkPeer, Handshake),
+}
+
+/// Pipeline streaming `monitor` to the connected client once it is let in,
+/// with the features agreed on, and the short authentication string; the
+/// peer is disconnected otherwise
+async fn open_pipeline(
+    mut peer: NetworkPeer,
+    config: &SessionConfig,
+    monitor: usize,
+    attempts: &mut AttemptLimiter,
+) -> Result<(SessionPipeline, String), String> {
+    let media = create_capturer()
+        .and_then(|capturer| Ok((capturer, create_encoder(CodecType::H264)?)));
+    let (capturer, encoder) = match media {
+        Ok(media) => media,
+        Err(e) => {
+            disconnect_peer(&mut peer).await;
+            return Err(e.to_string());
+        }
+    };
+
+    let mut pipeline = SessionPipeline::new(
+        capturer,
+        encoder,
+        peer,
+        capture_config(monitor),
+        EncoderConfig::default(),
+    );
+    pipeline.apply_config(config);
+    let injector = create_injector().and_then(|injector| pipeline.set_input_injector(injector));
+    if let Err(e) = injector {
+        warn!("Client input unavailable: {}", e);
+    }
+    match pipeline.accept(config, attempts).await {
+        Ok(handshake) => Ok((pipeline, handshake.sas)),
+        Err(e) => {
+            if let Some(peer) = pipeline.peer_mut() {
+                disconnect_peer(peer).await;
+            }
+            Err(e.to_string())
+        }
+    }
+}
+
+/// Start streaming; the peer is disconnected if capture cannot start
+async fn start_pipeline(mut pipeline: SessionPipeline) -> Result<SessionPipeline, String> {
+    if let Err(e) = pipeline.start() {
+        if let Some(peer) = pipeline.peer_mut() {
+            disconnect_peer(peer).await;
+        }
+        return Err(e.to_string());
+    }
+    Ok(pipeline)
+}
+
+/// Stop the running session, if any, and emit its terminal event
+async fn end_session(app: &AppHandle, app_state: &mut AppState, reason: Option<String>) {
+    // Stop forwarding first so the user's disconnect is the only terminal event
+    if let Some(task) = app_state.session_task.take() {
+        task.abort();
+    }
+    if let Some(mut pipeline) = app_state.pipeline.take() {
+        if let Err(e) = pipeline.stop().await {
+            warn!("Capture pipeline ended with an error: {}", e);
+        }
+        if let Some(peer) = pipeline.peer_mut() {
+            disconnect_peer(peer).await;
+        }
+    }
+    if let Some(mut peer) = app_state.peer.take() {
+        disconnect_peer(&mut peer).await;
+    }
+    app_state.capabilities = None;
+    if let Some(config) = app_state.current_session.take() {
+        emit_session_state(
+            app,
+            SessionStatePayload::new(ConnectionState::Disconnected, config.session_id, reason),
+        );
+    }
 }
 
 /// Session information for the UI
@@ -18,12 +268,15 @@ struct SessionInfo {
     session_id: String,
     mode: String,
     status: String,
+    /// Monitor shared when hosting
+    monitor: usize,
 }
 
 /// Start hosting a remote session
 #[tauri::command]
 async fn start_host_session(
     password: Option<String>,
+    app: AppHandle,
     state: tauri::State<'_, Arc<Mutex<AppState>>>,
 ) -> Result<SessionInfo, String> {
     info!("Starting host session");
@@ -35,21 +288,31 @@ async fn start_host_session(
         None
     };
 
+    let mut app_state = state.lock().await;
     let config = SessionConfig {
         session_id,
-        mode: ConnectionMode::FullControl,
+        mode: app_state.mode.get(),
         password_hash,
         clipboard_sync: true,
-        quality: VideoQuality::Adaptive,
+        quality: app_state.quality,
+        audio: true,
+        pointer_mode: PointerMode::Absolute,
     };
 
-    let mut app_state = state.lock().await;
+    end_session(&app, &mut app_state, Some("Replaced by a new session".to_string())).await;
     app_state.current_session = Some(config.clone());
+    app_state.session_task = Some(tokio::spawn(run_session(
+        app.clone(),
+        state.inner().clone(),
+        session_id,
+        Side::Host,
+    )));
 
     Ok(SessionInfo {
         session_id: format!("{}", session_id),
         mode: "host".to_string(),
         status: "waiting".to_string(),
+        monitor: app_state.capture_monitor,
     })
 }
 
@@ -58,41 +321,53 @@ async fn start_host_session(
 async fn connect_to_session(
     session_id: String,
     password: Option<String>,
+    app: AppHandle,
     state: tauri::State<'_, Arc<Mutex<AppState>>>,
 ) -> Result<SessionInfo, String> {
     info!("Connecting to session: {}", session_id);
 
     let session_id = SessionId::from_string(&session_id).map_err(|e| e.to_string())?;
 
+    let mut app_state = state.lock().await;
     let config = SessionConfig {
         session_id,
-        mode: ConnectionMode::FullControl,
-        password_hash: password.map(|pwd| ada_remote_crypto::hash_password(&pwd).ok()).flatten(),
+        mode: app_state.mode.get(),
+        // Only hosts check passwords; the client sends its own in the session request
+        password_hash: None,
         clipboard_sync: true,
-        quality: VideoQuality::Adaptive,
+        quality: app_state.quality,
+        audio: true,
+        pointer_mode: PointerMode::Absolute,
     };
 
-    let mut app_state = state.lock().await;
+    end_session(&app, &mut app_state, Some("Replaced by a new session".to_string())).await;
     app_state.current_session = Some(config.clone());
-
-    // TODO: Establish actual network connection
+    // Progress is reported through session-state events
+    app_state.session_task = Some(tokio::spawn(run_session(
+        app.clone(),
+        state.inner().clone(),
+        session_id,
+        Side::Client(password),
+    )));
 
     Ok(SessionInfo {
         session_id: format!("{}", session_id),
         mode: "client".to_string(),
         status: "connecting".to_string(),
+        monitor: app_state.capture_monitor,
     })
 }
 
 /// Disconnect from current session
 #[tauri::command]
 async fn disconnect_session(
+    app: AppHandle,
     state: tauri::State<'_, Arc<Mutex<AppState>>>,
 ) -> Result<(), String> {
     info!("Disconnecting session");
 
     let mut app_state = state.lock().await;
-    app_state.current_session = None;
+    end_session(&app, &mut app_state, None).await;
 
     Ok(())
 }
@@ -102,19 +377,123 @@ async fn disconnect_session(
 async fn get_session_info(
     state: tauri::State<'_, Arc<Mutex<AppState>>>,
 ) -> Result<Option<SessionInfo>, String> {
-    let app_state = state.lock().await;
+    let mut app_state = state.lock().await;
+    let app_state = &mut *app_state;
 
     if let Some(config) = &app_state.current_session {
+        let peer = match app_state.pipeline.as_mut() {
+            Some(pipeline) => pipeline.peer_mut(),
+            None => app_state.peer.as_mut(),
+        };
+        let status = peer.map_or(ConnectionState::Connecting, |peer| peer.state());
         Ok(Some(SessionInfo {
             session_id: format!("{}", config.session_id),
             mode: format!("{:?}", config.mode),
-            status: "connected".to_string(),
+            status: format!("{:?}", status).to_lowercase(),
+            monitor: app_state.capture_monitor,
         }))
     } else {
         Ok(None)
     }
 }
 
+/// List the monitors that can be shared
+#[tauri::command]
+async fn list_monitors() -> Result<Vec<MonitorPayload>, String> {
+    let monitors = create_capturer()
+        .and_then(|capturer| capturer.list_monitors())
+        .map_err(|e| e.to_string())?;
+    Ok(monitor_payloads(&monitors))
+}
+
+/// Choose the monitor shared by host sessions, moving a running capture to it.
+/// If capture cannot start there it goes back to the previous monitor, and the
+/// session ends when that fails too.
+#[tauri::command]
+async fn set_capture_monitor(
+    index: usize,
+    app: AppHandle,
+    state: tauri::State<'_, Arc<Mutex<AppState>>>,
+) -> Result<(), String> {
+    let monitors = create_capturer()
+        .and_then(|capturer| capturer.list_monitors())
+        .map_err(|e| e.to_string())?;
+    let index = select_monitor(&monitors, index)?;
+    info!("Sharing monitor {}", index);
+
+    let mut app_state = state.lock().await;
+    let previous = std::mem::replace(&mut app_state.capture_monitor, index);
+    let Some(pipeline) = app_state.pipeline.as_mut() else {
+        return Ok(());
+    };
+    if let Err(e) = pipeline.stop().await {
+        warn!("Capture pipeline ended with an error: {}", e);
+    }
+    let Err(e) = restart_capture(pipeline, index) else {
+        return Ok(());
+    };
+
+    warn!("Capture of monitor {} failed to start: {}", index, e);
+    let restored = restart_capture(pipeline, previous);
+    app_state.capture_monitor = previous;
+    if let Err(restore_error) = restored {
+        error!("Capture of monitor {} failed to restart: {}", previous, restore_error);
+        end_session(&app, &mut app_state, Some(restore_error)).await;
+    }
+    Err(e)
+}
+
+/// Start the stopped capture of `pipeline` again on `monitor`
+fn restart_capture(pipeline: &mut SessionPipeline, monitor: usize) -> Result<(), String> {
+    pipeline
+        .set_capture_config(capture_config(monitor))
+        .and_then(|_| pipeline.start())
+        .map_err(|e| e.to_string())
+}
+
+/// Set the video quality, applying it to a running host session
+#[tauri::command]
+async fn set_video_quality(
+    quality: String,
+    state: tauri::State<'_, Arc<Mutex<AppState>>>,
+) -> Result<(), String> {
+    let quality: VideoQuality = quality.parse().map_err(|e: ada_remote_core::Error| e.to_string())?;
+    info!("Video quality set to {:?}", quality);
+
+    let mut app_state = state.lock().await;
+    app_state.quality = quality;
+    if let Some(config) = app_state.current_session.as_mut() {
+        config.quality = quality;
+    }
+    app_state.apply_session_config();
+    Ok(())
+}
+
+/// Set the connection mode; input stops at once when leaving FullControl
+#[tauri::command]
+async fn set_connection_mode(
+    mode: String,
+    state: tauri::State<'_, Arc<Mutex<AppState>>>,
+) -> Result<(), String> {
+    let mode: ConnectionMode = mode.parse().map_err(|e: ada_remote_core::Error| e.to_string())?;
+
+    let mut app_state = state.lock().await;
+    app_state.mode.set(mode);
+    if let Some(config) = app_state.current_session.as_mut() {
+        config.mode = mode;
+    }
+    app_state.apply_session_config();
+    Ok(())
+}
+
+/// Get the codecs available on this machine
+#[tauri::command]
+async fn get_codec_capabilities(
+    state: tauri::State<'_, Arc<Mutex<AppState>>>,
+) -> Result<Vec<CodecCapability>, String> {
+    Ok(state.lock().await.codecs.clone())
+}
+
 fn main() {
     // Initialize logging
     tracing_subscriber::fmt()
@@ -123,8 +502,29 @@ fn main() {
 
     info!("Ada Remote Desktop starting");
 
+    let codecs = probe_codecs();
+    for codec in &codecs {
+        info!("Codec capability: {:?}", codec);
+    }
+    match codecs.iter().find(|c| c.codec == CodecType::H264) {
+        Some(h264) if h264.can_encode && !h264.hardware_encode => {
+            warn!("No H.264 hardware encoder available, using software encoding")
+        }
+        Some(h264) if h264.can_encode => {}
+        _ => error!("H.264 encoding unavailable"),
+    }
+
     let app_state = Arc::new(Mutex::new(AppState {
         current_session: None,
+        peer: None,
+        capabilities: None,
+        attempts: Arc::default(),
+        pipeline: None,
+        capture_monitor: 0,
+        quality: VideoQuality::Adaptive,
+        mode: ModeSwitch::new(ConnectionMode::FullControl),
+        session_task: None,
+        codecs,
     }));
 
     tauri::Builder::default()
@@ -134,6 +534,11 @@ fn main() {
             connect_to_session,
             disconnect_session,
             get_session_info,
+            list_monitors,
+            set_capture_monitor,
+            set_video_quality,
+            set_connection_mode,
+            get_codec_capabilities,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -0,0 +1,105 @@
+//! Monitor selection for host sessions
+
+use ada_remote_capture::MonitorInfo;
+use serde::{Deserialize, Serialize};
+
+/// Monitor as listed in the UI
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MonitorPayload {
+    pub index: usize,
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    pub is_primary: bool,
+    pub scale_factor: f64,
+}
+
+impl From<&MonitorInfo> for MonitorPayload {
+    fn from(monitor: &MonitorInfo) -> Self {
+        Self {
+            index: monitor.index,
+            name: monitor.name.clone(),
+            width: monitor.width,
+            height: monitor.height,
+            is_primary: monitor.is_primary,
+            scale_factor: monitor.scale_factor,
+        }
+    }
+}
+
+pub fn monitor_payloads(monitors: &[MonitorInfo]) -> Vec<MonitorPayload> {
+    monitors.iter().map(MonitorPayload::from).collect()
+}
+
+/// Check that `index` names one of `monitors`
+pub fn select_monitor(monitors: &[MonitorInfo], index: usize) -> Result<usize, String> {
+    if monitors.iter().any(|monitor| monitor.index == index) {
+        Ok(index)
+    } else {
+        Err(format!(
+            "Monitor {} does not exist ({} available)",
+            index,
+            monitors.len()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn monitors() -> Vec<MonitorInfo> {
+        vec![
+            MonitorInfo {
+                index: 0,
+                name: "eDP-1".to_string(),
+                x: 0,
+                y: 0,
+                width: 2880,
+                height: 1800,
+                is_primary: true,
+                scale_factor: 2.0,
+            },
+            MonitorInfo {
+                index: 1,
+                name: "HDMI-1".to_string(),
+                x: 2880,
+                y: 0,
+                width: 1920,
+                height: 1080,
+                is_primary: false,
+                scale_factor: 1.0,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_monitor_payloads() {
+        let payloads = monitor_payloads(&monitors());
+        assert_eq!(payloads.len(), 2);
+        assert_eq!(
+            payloads[1],
+            MonitorPayload {
+                index: 1,
+                name: "HDMI-1".to_string(),
+                width: 1920,
+                height: 1080,
+                is_primary: false,
+                scale_factor: 1.0,
+            }
+        );
+
+        let json = serde_json::to_value(&payloads[0]).unwrap();
+        assert_eq!(json["name"], "eDP-1");
+        assert_eq!(json["is_primary"], true);
+        assert_eq!(json["scale_factor"], 2.0);
+    }
+
+    #[test]
+    fn test_select_monitor() {
+        assert_eq!(select_monitor(&monitors(), 1), Ok(1));
+        let error = select_monitor(&monitors(), 2).unwrap_err();
+        assert!(error.contains("Monitor 2"), "{}", error);
+        assert!(select_monitor(&[], 0).is_err());
+    }
+}
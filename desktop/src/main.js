@@ -1,6 +1,33 @@
 import { invoke } from '@tauri-apps/api/tauri';
+import { listen } from '@tauri-apps/api/event';
 import './style.css';
 
+const STATE_TEXT = {
+  connecting: 'Connecting...',
+  connected: 'Connected',
+  failed: 'Connection failed',
+  disconnected: 'Disconnected',
+};
+
+// Live session state from the backend
+listen('session-state', event => {
+  const { state, session_id, reason, sas } = event.payload;
+  const statusText = document.getElementById('status-text');
+
+  statusText.textContent = reason ? `${STATE_TEXT[state]}: ${reason}` : STATE_TEXT[state];
+  if (state === 'connected') {
+    statusText.textContent += ` - session ${session_id}`;
+  }
+  // Both users should see the same code; a different one means the keys were swapped
+  if (sas) {
+    statusText.textContent += `, verification code ${sas}`;
+  }
+  if (state === 'disconnected') {
+    document.getElementById('session-info').style.display = 'none';
+    document.getElementById('start-host-btn').style.display = 'block';
+  }
+});
+
 // Tab switching
 document.querySelectorAll('.tab-button').forEach(button => {
   button.addEventListener('click', () => {
@@ -14,6 +41,56 @@ document.querySelectorAll('.tab-button').forEach(button => {
   });
 });
 
+// Monitor selection
+const monitorSelect = document.getElementById('host-monitor');
+// The host keeps sharing this one when a new choice fails
+let sharedMonitor;
+
+async function loadMonitors() {
+  try {
+    const monitors = await invoke('list_monitors');
+    monitorSelect.innerHTML = '';
+    for (const monitor of monitors) {
+      const option = document.createElement('option');
+      option.value = monitor.index;
+      option.textContent = `${monitor.name} (${monitor.width}x${monitor.height})${monitor.is_primary ? ' - primary' : ''}`;
+      monitorSelect.appendChild(option);
+    }
+    sharedMonitor = monitorSelect.value;
+  } catch (error) {
+    console.error(error);
+  }
+}
+
+monitorSelect.addEventListener('change', async () => {
+  try {
+    await invoke('set_capture_monitor', { index: Number(monitorSelect.value) });
+    sharedMonitor = monitorSelect.value;
+  } catch (error) {
+    monitorSelect.value = sharedMonitor;
+    document.getElementById('status-text').textContent = `Error: ${error}`;
+    console.error(error);
+  }
+});
+
+loadMonitors();
+
+// Quality and access mode apply to the running session as well
+for (const [id, command, arg] of [
+  ['host-quality', 'set_video_quality', 'quality'],
+  ['host-mode', 'set_connection_mode', 'mode'],
+]) {
+  const select = document.getElementById(id);
+  select.addEventListener('change', async () => {
+    try {
+      await invoke(command, { [arg]: select.value });
+    } catch (error) {
+      document.getElementById('status-text').textContent = `Error: ${error}`;
+      console.error(error);
+    }
+  });
+}
+
 // Start hosting session
 document.getElementById('start-host-btn').addEventListener('click', async () => {
   const password = document.getElementById('host-password').value;
@@ -29,8 +106,6 @@ document.getElementById('start-host-btn').addEventListener('click', async () =>
     document.getElementById('session-id').textContent = sessionInfo.session_id;
     document.getElementById('session-info').style.display = 'block';
     document.getElementById('start-host-btn').style.display = 'none';
-
-    statusText.textContent = 'Session active - Waiting for connection';
   } catch (error) {
     statusText.textContent = `Error: ${error}`;
     console.error(error);
@@ -49,14 +124,10 @@ document.getElementById('connect-btn').addEventListener('click', async () => {
   }
 
   try {
-    statusText.textContent = 'Connecting...';
-
-    const sessionInfo = await invoke('connect_to_session', {
+    await invoke('connect_to_session', {
       sessionId,
       password: password || null,
     });
-
-    statusText.textContent = `Connected to session ${sessionInfo.session_id}`;
   } catch (error) {
     statusText.textContent = `Connection failed: ${error}`;
     console.error(error);
@@ -70,11 +141,7 @@ document.getElementById('disconnect-btn').addEventListener('click', async () =>
   try {
     await invoke('disconnect_session');
 
-    document.getElementById('session-info').style.display = 'none';
-    document.getElementById('start-host-btn').style.display = 'block';
     document.getElementById('host-password').value = '';
-
-    statusText.textContent = 'Disconnected';
   } catch (error) {
     statusText.textContent = `Error: ${error}`;
     console.error(error);
@@ -1,21 +1,33 @@
 //! Ada Remote Relay Server
 //!
 //! WebSocket-based signaling server for WebRTC connection establishment.
-//! Also provides TURN relay functionality for NAT traversal.
+//! Also provides TURN relay functionality for NAT traversal, enabled by
+//! giving it TURN users.
 
 use ada_remote_core::SessionId;
+use ada_remote_crypto::{unix_now, verify_registration_token};
 use anyhow::Result;
 use clap::Parser;
 use futures::{SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
-use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::RwLock;
+use std::time::{Duration, Instant};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::{mpsc, watch, RwLock};
+use tokio::task::JoinSet;
 use tokio_tungstenite::{accept_async, tungstenite::Message};
 use tracing::{error, info, warn};
 
+mod metrics;
+mod rate_limit;
+mod turn_relay;
+
+use metrics::Metrics;
+use rate_limit::TokenBucket;
+use turn_relay::TurnConfig;
+
 /// Command-line arguments
 #[derive(Parser, Debug)]
 #[command(name = "Ada Remote Relay Server")]
@@ -25,6 +37,80 @@ struct Args {
     #[arg(short, long, default_value = "0.0.0.0:8080")]
     bind: SocketAddr,
 
+    /// Seconds without signaling activity before a session is evicted
+    #[arg(long, default_value_t = 3600)]
+    session_ttl: u64,
+
+    /// Maximum number of concurrent sessions
+    #[arg(long, default_value_t = 10_000)]
+    max_sessions: usize,
+
+    /// New connections allowed per IP address per minute
+    #[arg(long, default_value_t = 30)]
+    connections_per_minute: u32,
+
+    /// Signaling messages allowed per connection per second
+    #[arg(long, default_value_t = 20)]
+    messages_per_second: u32,
+
+    /// Seconds a session code stays joinable after registration
+    #[arg(long, default_value_t = 600)]
+    code_ttl: u64,
+
+    /// Reject further joins once a client has joined a session
+    #[arg(long)]
+    single_use: bool,
+
+    /// Clients allowed in one session at a time
+    #[arg(long, default_value_t = 16)]
+    max_viewers: usize,
+
+    /// UDP address of the TURN relay
+    #[arg(long, default_value = "0.0.0.0:3478")]
+    turn_bind: SocketAddr,
+
+    /// Address clients reach TURN relay ports at
+    #[arg(long)]
+    turn_public_ip: Option<IpAddr>,
+
+    /// TURN user as username:password; may be repeated. TURN is off without
+    /// any.
+    #[arg(long = "turn-user", value_parser = turn_relay::parse_user)]
+    turn_users: Vec<(String, String)>,
+
+    /// TURN realm
+    #[arg(long, default_value = "ada-remote")]
+    turn_realm: String,
+
+    /// Lowest TURN relay port
+    #[arg(long, default_value_t = 49152)]
+    turn_min_port: u16,
+
+    /// Highest TURN relay port
+    #[arg(long, default_value_t = 65535)]
+    turn_max_port: u16,
+
+    /// Bytes per second one TURN allocation may relay; 0 for no cap
+    #[arg(long, default_value_t = 2_000_000)]
+    turn_bandwidth: u32,
+
+    /// Seconds connections get to finish after SIGINT or SIGTERM
+    #[arg(long, default_value_t = 30)]
+    shutdown_grace: u64,
+
+    /// Address to serve Prometheus metrics on at /metrics
+    #[arg(long)]
+    metrics_bind: Option<SocketAddr>,
+
+    /// Only accept registrations carrying a token signed with this secret
+    #[arg(long)]
+    register_secret: Option<String>,
+
+    /// Seconds a registration token's timestamp may differ from the server
+    /// clock
+    #[arg(long, default_value_t = 300)]
+    register_token_max_age: u64,
+
     /// Enable verbose logging
     #[arg(short, long)]
     verbose: bool,
@@ -35,22 +121,30 @@ struct Args {
 #[serde(tag = "type", rename_all = "snake_case")]
 enum SignalingMessage {
     Register {
-        session_id: String,
+        session_id: SessionId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        token: Option<String>,
     },
     Join {
-        session_id: String,
+        session_id: SessionId,
     },
     Offer {
-        session_id: String,
+        session_id: SessionId,
         sdp: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        viewer: Option<u32>,
     },
     Answer {
-        session_id: String,
+        session_id: SessionId,
         sdp: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        viewer: Option<u32>,
     },
     IceCandidate {
-        session_id: String,
+        session_id: SessionId,
         candidate: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        viewer: Option<u32>,
     },
     Success {
         message: String,
@@ -58,30 +152,183 @@ enum SignalingMessage {
     Error {
         message: String,
     },
+    PeerDisconnected {
+        session_id: SessionId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        viewer: Option<u32>,
+    },
+    ShuttingDown {
+        grace_seconds: u64,
+    },
+}
+
+impl SignalingMessage {
+    /// Viewer a forwarded message is addressed to or comes from
+    fn viewer_mut(&mut self) -> Option<&mut Option<u32>> {
+        match self {
+            SignalingMessage::Offer { viewer, .. }
+            | SignalingMessage::Answer { viewer, .. }
+            | SignalingMessage::IceCandidate { viewer, .. }
+            | SignalingMessage::PeerDisconnected { viewer, .. } => Some(viewer),
+            _ => None,
+        }
+    }
+}
+
+/// Outbound message queue of a WebSocket connection
+type Outbound = mpsc::UnboundedSender<Message>;
+
+/// Client joined to a session
+struct Viewer {
+    /// Number the host addresses this client by
+    id: u32,
+    tx: Outbound,
 }
 
 /// Active session
 struct Session {
-    session_id: SessionId,
     host_addr: Option<SocketAddr>,
-    client_addr: Option<SocketAddr>,
+    host_tx: Option<Outbound>,
+    /// Joined clients by address
+    viewers: HashMap<SocketAddr, Viewer>,
+    next_viewer: u32,
+    registered_at: Instant,
+    last_activity: Instant,
+    joined: bool,
+}
+
+/// Abuse limits
+#[derive(Debug, Clone)]
+struct Limits {
+    max_sessions: usize,
+    connections_per_minute: u32,
+    messages_per_second: u32,
+    max_viewers: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_sessions: 10_000,
+            connections_per_minute: 30,
+            messages_per_second: 20,
+            max_viewers: 16,
+        }
+    }
+}
+
+/// Lifetime of session codes
+#[derive(Debug, Clone)]
+struct CodePolicy {
+    /// How long a code stays joinable after registration
+    ttl: Duration,
+    /// Whether a code is spent by the first successful join
+    single_use: bool,
+}
+
+impl Default for CodePolicy {
+    fn default() -> Self {
+        Self {
+            ttl: Duration::from_secs(600),
+            single_use: false,
+        }
+    }
+}
+
+/// Who may register sessions
+#[derive(Debug, Clone)]
+struct RegistrationAuth {
+    /// Secret registration tokens are signed with
+    secret: String,
+    /// How far a token's timestamp may be from the server clock
+    max_age: Duration,
+}
+
+impl RegistrationAuth {
+    fn verify(&self, session_id: &SessionId, token: Option<&str>) -> ada_remote_core::Result<()> {
+        let token = token.ok_or_else(|| {
+            ada_remote_core::Error::Authentication("Registration token required".to_string())
+        })?;
+        verify_registration_token(
+            self.secret.as_bytes(),
+            session_id,
+            token,
+            unix_now(),
+            self.max_age,
+        )
+    }
 }
 
 /// Server state
 struct ServerState {
-    sessions: HashMap<String, Session>,
+    sessions: HashMap<SessionId, Session>,
+    limits: Limits,
+    codes: CodePolicy,
+    /// Registrations are open to anyone without it
+    registration: Option<RegistrationAuth>,
+    connection_buckets: HashMap<IpAddr, TokenBucket>,
+    metrics: Arc<Metrics>,
 }
 
 impl ServerState {
-    fn new() -> Self {
+    fn new(limits: Limits, codes: CodePolicy) -> Self {
         Self {
             sessions: HashMap::new(),
+            limits,
+            codes,
+            registration: None,
+            connection_buckets: HashMap::new(),
+            metrics: Arc::default(),
         }
     }
+
+    /// Take a connection token for `ip`
+    fn allow_connection(&mut self, ip: IpAddr) -> bool {
+        let per_minute = self.limits.connections_per_minute;
+        self.connection_buckets
+            .entry(ip)
+            .or_insert_with(|| TokenBucket::new(per_minute, per_minute as f64 / 60.0))
+            .try_take()
+    }
+
+    /// Bucket limiting the messages of one connection
+    fn message_bucket(&self) -> TokenBucket {
+        let per_second = self.limits.messages_per_second;
+        TokenBucket::new(per_second, per_second as f64)
+    }
 }
 
 type SharedState = Arc<RwLock<ServerState>>;
 
+/// Shutdown signal observed by the accept loop and every connection
+#[derive(Clone)]
+struct Shutdown {
+    signal: watch::Receiver<bool>,
+    /// How long connections may stay open once shutdown starts
+    grace: Duration,
+}
+
+impl Shutdown {
+    /// A shutdown handle and the sender that starts it
+    fn new(grace: Duration) -> (watch::Sender<bool>, Self) {
+        let (tx, signal) = watch::channel(false);
+        (tx, Self { signal, grace })
+    }
+
+    /// Resolve once shutdown starts, or never if the sender is gone first
+    async fn started(&mut self) {
+        if self.signal.wait_for(|&stop| stop).await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+/// How often idle sessions are looked for
+const EVICTION_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long a closing connection may take to send its last messages
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
@@ -97,35 +344,180 @@ async fn main() -> Result<()> {
 
     info!("Ada Remote Relay Server starting on {}", args.bind);
 
-    let state = Arc::new(RwLock::new(ServerState::new()));
+    let limits = Limits {
+        max_sessions: args.max_sessions,
+        connections_per_minute: args.connections_per_minute,
+        messages_per_second: args.messages_per_second,
+        max_viewers: args.max_viewers,
+    };
+    let codes = CodePolicy {
+        ttl: Duration::from_secs(args.code_ttl),
+        single_use: args.single_use,
+    };
+    let mut state = ServerState::new(limits, codes);
+    state.registration = args.register_secret.map(|secret| RegistrationAuth {
+        secret,
+        max_age: Duration::from_secs(args.register_token_max_age),
+    });
+    let state = Arc::new(RwLock::new(state));
     let listener = TcpListener::bind(args.bind).await?;
 
     info!("Relay server listening on {}", args.bind);
 
-    while let Ok((stream, addr)) = listener.accept().await {
+    let turn = if args.turn_users.is_empty() {
+        None
+    } else {
+        let Some(public_ip) = args.turn_public_ip else {
+            anyhow::bail!("--turn-public-ip is required with --turn-user");
+        };
+        let config = TurnConfig {
+            public_ip,
+            realm: args.turn_realm,
+            users: args.turn_users,
+            ports: args.turn_min_port..=args.turn_max_port,
+            bandwidth: args.turn_bandwidth,
+        };
+        let socket = UdpSocket::bind(args.turn_bind).await?;
+        let metrics = Arc::clone(&state.read().await.metrics);
+        Some(turn_relay::start(socket, config, metrics).await?)
+    };
+    if let Some(metrics_bind) = args.metrics_bind {
+        let listener = TcpListener::bind(metrics_bind).await?;
+        tokio::spawn(metrics::serve(listener, Arc::clone(&state)));
+    }
+
+    tokio::spawn(sweep_sessions(
+        Arc::clone(&state),
+        Duration::from_secs(args.session_ttl),
+    ));
+
+    let (stop, shutdown) = Shutdown::new(Duration::from_secs(args.shutdown_grace));
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        info!(
+            "Shutting down, connections have {}s to finish",
+            args.shutdown_grace
+        );
+        let _ = stop.send(true);
+    });
+    serve(listener, state, shutdown).await;
+
+    if let Some(turn) = turn {
+        turn.close().await?;
+    }
+    info!("Relay server stopped");
+    Ok(())
+}
+
+/// Wait for SIGINT, or SIGTERM on Unix
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+                return;
+            }
+            Err(e) => warn!("Cannot listen for SIGTERM: {}", e),
+        }
+    }
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Accept connections until the listener fails
+async fn serve(listener: TcpListener, state: SharedState, mut shutdown: Shutdown) {
+    let mut connections = JoinSet::new();
+    loop {
+        let (stream, addr) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(_) => break,
+            },
+            () = shutdown.started() => break,
+            Some(_) = connections.join_next(), if !connections.is_empty() => continue,
+        };
+        if !state.write().await.allow_connection(addr.ip()) {
+            warn!("Connection rate exceeded by {}, refusing", addr.ip());
+            continue;
+        }
         info!("New connection from {}", addr);
         let state = Arc::clone(&state);
-        tokio::spawn(async move {
-            if let Err(e) = handle_connection(stream, addr, state).await {
+        let shutdown = shutdown.clone();
+        connections.spawn(async move {
+            let opened = Instant::now();
+            let metrics = Arc::clone(&state.read().await.metrics);
+            metrics.connection_opened();
+            if let Err(e) = handle_connection(stream, addr, Arc::clone(&state), shutdown).await {
                 error!("Error handling connection from {}: {}", addr, e);
             }
+            remove_connection(addr, &state).await;
+            metrics.connection_closed(opened.elapsed());
         });
     }
 
-    Ok(())
+    drop(listener);
+    if connections.is_empty() {
+        return;
+    }
+    info!("Waiting for {} connections to close", connections.len());
+    let drain = async { while connections.join_next().await.is_some() {} };
+    let drained = tokio::time::timeout(shutdown.grace + FLUSH_TIMEOUT, drain).await;
+    if drained.is_err() {
+        let open = connections.len();
+        warn!("Closing {} connections after the grace period", open);
+        connections.abort_all();
+    }
 }
 
 async fn handle_connection(
     stream: TcpStream,
     addr: SocketAddr,
     state: SharedState,
+    mut shutdown: Shutdown,
 ) -> Result<()> {
     let ws_stream = accept_async(stream).await?;
     info!("WebSocket connection established with {}", addr);
 
     let (mut ws_sender, mut ws_receiver) = ws_stream.split();
+    let mut bucket = state.read().await.message_bucket();
+
+    // Replies and messages forwarded by the other peer share one queue
+    let (tx, mut rx) = mpsc::unbounded_channel::<Message>();
+    let mut writer = tokio::spawn(async move {
+        while let Some(msg) = rx.recv().await {
+            let close = msg.is_close();
+            if ws_sender.send(msg).await.is_err() || close {
+                break;
+            }
+        }
+    });
 
-    while let Some(msg) = ws_receiver.next().await {
+    // Armed once shutdown starts
+    let deadline = tokio::time::sleep(Duration::ZERO);
+    tokio::pin!(deadline);
+    let mut draining = false;
+
+    loop {
+        let msg = tokio::select! {
+            msg = ws_receiver.next() => match msg {
+                Some(msg) => msg,
+                None => break,
+            },
+            () = shutdown.started(), if !draining => {
+                draining = true;
+                deadline.as_mut().reset(tokio::time::Instant::now() + shutdown.grace);
+                let notice = SignalingMessage::ShuttingDown {
+                    grace_seconds: shutdown.grace.as_secs(),
+                };
+                tx.send(Message::Text(serde_json::to_string(&notice)?))?;
+                continue;
+            }
+            () = &mut deadline, if draining => break,
+        };
         let msg = match msg {
             Ok(msg) => msg,
             Err(e) => {
@@ -134,6 +526,15 @@ async fn handle_connection(
             }
         };
 
+        // Every frame counts, so binary and ping floods are limited too
+        if !bucket.try_take() {
+            warn!("Message rate exceeded by {}, closing", addr);
+            let error_msg = SignalingMessage::Error {
+                message: "Rate limit exceeded".to_string(),
+            };
+            tx.send(Message::Text(serde_json::to_string(&error_msg)?))?;
+            break;
+        }
         if !msg.is_text() {
             continue;
         }
@@ -147,87 +548,897 @@ async fn handle_connection(
                     message: "Invalid message format".to_string(),
                 };
                 let response = serde_json::to_string(&error_msg)?;
-                ws_sender.send(Message::Text(response)).await?;
+                tx.send(Message::Text(response))?;
                 continue;
             }
         };
 
-        let response = handle_signaling_message(signaling_msg, addr, &state).await?;
-        let response_text = serde_json::to_string(&response)?;
-        ws_sender.send(Message::Text(response_text)).await?;
+        if let SignalingMessage::Register { session_id, token } = &signaling_msg {
+            let state = state.read().await;
+            if let Some(Err(e)) = state
+                .registration
+                .as_ref()
+                .map(|auth| auth.verify(session_id, token.as_deref()))
+            {
+                warn!("Rejected registration from {}: {}", addr, e);
+                let error_msg = SignalingMessage::Error {
+                    message: e.to_string(),
+                };
+                tx.send(Message::Text(serde_json::to_string(&error_msg)?))?;
+                break;
+            }
+        }
+
+        let joining = matches!(signaling_msg, SignalingMessage::Join { .. });
+        if let Some(response) = handle_signaling_message(signaling_msg, addr, &tx, &state).await? {
+            if joining && matches!(response, SignalingMessage::Error { .. }) {
+                state.read().await.metrics.join_failed();
+            }
+            let response_text = serde_json::to_string(&response)?;
+            tx.send(Message::Text(response_text))?;
+        }
     }
 
+    // Let the last replies, such as why the connection is closed, go out
+    let _ = tx.send(Message::Close(None));
+    let flushed = tokio::time::timeout(FLUSH_TIMEOUT, &mut writer).await;
+    if flushed.is_err() {
+        writer.abort();
+    }
     info!("Connection closed for {}", addr);
     Ok(())
 }
 
+/// Handle a message, returning the reply for the sender if there is one.
+/// Offers, answers and ICE candidates are forwarded to the other peer.
 async fn handle_signaling_message(
     msg: SignalingMessage,
     addr: SocketAddr,
+    tx: &Outbound,
     state: &SharedState,
-) -> Result<SignalingMessage> {
+) -> Result<Option<SignalingMessage>> {
     match msg {
-        SignalingMessage::Register { session_id } => {
+        SignalingMessage::Register { session_id, .. } => {
             info!("Registering new session: {} from {}", session_id, addr);
             let mut state = state.write().await;
 
-            let parsed_session_id = SessionId::from_string(&session_id)
-                .map_err(|_| anyhow::anyhow!("Invalid session ID"))?;
+            // Codes are short enough for two hosts to draw the same one
+            if let Some(existing) = state.sessions.get(&session_id) {
+                if existing.host_addr != Some(addr) {
+                    warn!(
+                        "Session {} is already registered, rejecting {}",
+                        session_id, addr
+                    );
+                    return Ok(Some(SignalingMessage::Error {
+                        message: "Session ID already in use".to_string(),
+                    }));
+                }
+            } else if state.sessions.len() >= state.limits.max_sessions {
+                warn!("Session limit reached, rejecting {}", session_id);
+                return Ok(Some(SignalingMessage::Error {
+                    message: "Server is at capacity".to_string(),
+                }));
+            }
 
-            state.sessions.insert(
-                session_id.clone(),
+            let previous = state.sessions.insert(
+                session_id,
                 Session {
-                    session_id: parsed_session_id,
                     host_addr: Some(addr),
-                    client_addr: None,
+                    host_tx: Some(tx.clone()),
+                    viewers: HashMap::new(),
+                    next_viewer: 1,
+                    registered_at: Instant::now(),
+                    last_activity: Instant::now(),
+                    joined: false,
                 },
             );
+            // Registering again starts over, ending the session viewers joined
+            if let Some(previous) = previous {
+                info!(
+                    "Session {} registered again, ending it for its viewers",
+                    session_id
+                );
+                for viewer in previous.viewers.values() {
+                    notify_peer_disconnected(&viewer.tx, session_id, None);
+                }
+                state
+                    .metrics
+                    .session_ended(previous.registered_at.elapsed());
+            }
+            state.metrics.session_registered();
 
-            Ok(SignalingMessage::Success {
+            Ok(Some(SignalingMessage::Success {
                 message: format!("Session {} registered", session_id),
-            })
+            }))
         }
         SignalingMessage::Join { session_id } => {
             info!("Client joining session: {} from {}", session_id, addr);
             let mut state = state.write().await;
+            let codes = state.codes.clone();
+            let max_viewers = state.limits.max_viewers;
 
-            if let Some(session) = state.sessions.get_mut(&session_id) {
-                session.client_addr = Some(addr);
-                Ok(SignalingMessage::Success {
-                    message: format!("Joined session {}", session_id),
-                })
-            } else {
-                Ok(SignalingMessage::Error {
+            let Some(session) = state.sessions.get_mut(&session_id) else {
+                return Ok(Some(SignalingMessage::Error {
                     message: "Session not found".to_string(),
-                })
+                }));
+            };
+            // Like the sweeper, only codes nobody used expire
+            if !session.joined && session.registered_at.elapsed() >= codes.ttl {
+                return Ok(Some(SignalingMessage::Error {
+                    message: "Session expired".to_string(),
+                }));
+            }
+            if codes.single_use && session.joined {
+                return Ok(Some(SignalingMessage::Error {
+                    message: "Session code already used".to_string(),
+                }));
             }
+
+            if !session.viewers.contains_key(&addr) && session.viewers.len() >= max_viewers {
+                return Ok(Some(SignalingMessage::Error {
+                    message: "Session is full".to_string(),
+                }));
+            }
+
+            let id = match session.viewers.get(&addr) {
+                Some(viewer) => viewer.id,
+                None => {
+                    let id = session.next_viewer;
+                    session.next_viewer += 1;
+                    session.viewers.insert(addr, Viewer { id, tx: tx.clone() });
+                    id
+                }
+            };
+            session.last_activity = Instant::now();
+            session.joined = true;
+            Ok(Some(SignalingMessage::Success {
+                message: format!("Joined session {} as viewer {}", session_id, id),
+            }))
         }
-        SignalingMessage::Offer { session_id, sdp } => {
+        SignalingMessage::Offer { session_id, .. } => {
             info!("Received offer for session: {}", session_id);
-            // TODO: Forward offer to the other peer
-            Ok(SignalingMessage::Success {
-                message: "Offer received".to_string(),
-            })
+            forward(msg, &session_id, addr, state).await
         }
-        SignalingMessage::Answer { session_id, sdp } => {
+        SignalingMessage::Answer { session_id, .. } => {
             info!("Received answer for session: {}", session_id);
-            // TODO: Forward answer to the other peer
-            Ok(SignalingMessage::Success {
-                message: "Answer received".to_string(),
-            })
+            forward(msg, &session_id, addr, state).await
         }
-        SignalingMessage::IceCandidate {
-            session_id,
-            candidate,
-        } => {
+        SignalingMessage::IceCandidate { session_id, .. } => {
             info!("Received ICE candidate for session: {}", session_id);
-            // TODO: Forward ICE candidate to the other peer
-            Ok(SignalingMessage::Success {
-                message: "ICE candidate received".to_string(),
-            })
+            forward(msg, &session_id, addr, state).await
         }
-        _ => Ok(SignalingMessage::Error {
+        _ => Ok(Some(SignalingMessage::Error {
             message: "Invalid message type".to_string(),
-        }),
+        })),
+    }
+}
+
+/// Deliver a message to the other side of the session, returning an error
+/// reply for the sender if that is not possible. Messages from a client
+/// reach the host tagged with its viewer number; the host's go to the viewer
+/// they name, or to every viewer.
+async fn forward(
+    mut msg: SignalingMessage,
+    session_id: &SessionId,
+    addr: SocketAddr,
+    state: &SharedState,
+) -> Result<Option<SignalingMessage>> {
+    let mut state = state.write().await;
+    let Some(session) = state.sessions.get_mut(session_id) else {
+        return Ok(Some(SignalingMessage::Error {
+            message: "Session not found".to_string(),
+        }));
+    };
+
+    session.last_activity = Instant::now();
+    let targets: Vec<&Outbound> = if session.host_addr == Some(addr) {
+        let addressed = msg.viewer_mut().and_then(|viewer| *viewer);
+        session
+            .viewers
+            .values()
+            .filter(|viewer| addressed.is_none_or(|id| viewer.id == id))
+            .map(|viewer| &viewer.tx)
+            .collect()
+    } else if let Some(viewer) = session.viewers.get(&addr) {
+        // Clients cannot pose as another viewer
+        if let Some(tag) = msg.viewer_mut() {
+            *tag = Some(viewer.id);
+        }
+        session.host_tx.iter().collect()
+    } else {
+        return Ok(Some(SignalingMessage::Error {
+            message: "Not a member of this session".to_string(),
+        }));
+    };
+
+    let text = serde_json::to_string(&msg)?;
+    let delivered = targets
+        .into_iter()
+        .filter(|peer| peer.send(Message::Text(text.clone())).is_ok())
+        .count();
+    if delivered == 0 {
+        return Ok(Some(SignalingMessage::Error {
+            message: "Peer not connected".to_string(),
+        }));
+    }
+    state.metrics.message_forwarded(text.len(), delivered);
+    Ok(None)
+}
+
+/// Take a closed connection out of its sessions and tell the remaining
+/// peers. A session ends with its host; a departed client leaves it open
+/// for others.
+async fn remove_connection(addr: SocketAddr, state: &SharedState) {
+    let mut state = state.write().await;
+    let mut ended = Vec::new();
+
+    for (session_id, session) in state.sessions.iter_mut() {
+        let peers: Vec<(Outbound, Option<u32>)> = if session.host_addr == Some(addr) {
+            ended.push(*session_id);
+            session
+                .viewers
+                .drain()
+                .map(|(_, viewer)| (viewer.tx, None))
+                .collect()
+        } else if let Some(viewer) = session.viewers.remove(&addr) {
+            session
+                .host_tx
+                .iter()
+                .map(|host| (host.clone(), Some(viewer.id)))
+                .collect()
+        } else {
+            continue;
+        };
+
+        info!("Peer {} left session {}", addr, session_id);
+        for (peer, viewer) in peers {
+            notify_peer_disconnected(&peer, *session_id, viewer);
+        }
+    }
+
+    for session_id in ended {
+        if let Some(session) = state.sessions.remove(&session_id) {
+            state.metrics.session_ended(session.registered_at.elapsed());
+        }
+    }
+}
+
+/// Tell `peer` that the host of `session_id` left, or the given viewer
+fn notify_peer_disconnected(peer: &Outbound, session_id: SessionId, viewer: Option<u32>) {
+    let msg = SignalingMessage::PeerDisconnected { session_id, viewer };
+    if let Ok(text) = serde_json::to_string(&msg) {
+        let _ = peer.send(Message::Text(text));
+    }
+}
+
+/// Periodically drop sessions without signaling activity for `ttl` and
+/// sessions whose code expired before anyone joined
+async fn sweep_sessions(state: SharedState, ttl: Duration) {
+    let code_ttl = state.read().await.codes.ttl;
+    let period = EVICTION_INTERVAL
+        .min(ttl)
+        .min(code_ttl)
+        .max(Duration::from_secs(1));
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        evict_sessions_older_than(&state, ttl).await;
+        expire_unjoined_sessions(&state).await;
+    }
+}
+
+async fn evict_sessions_older_than(state: &SharedState, ttl: Duration) {
+    let mut state = state.write().await;
+    let metrics = Arc::clone(&state.metrics);
+    state.sessions.retain(|session_id, session| {
+        let idle = session.last_activity.elapsed() >= ttl;
+        if idle {
+            info!("Evicting idle session {}", session_id);
+            metrics.session_ended(session.registered_at.elapsed());
+        }
+        !idle
+    });
+    // A full bucket holds no more than a fresh one would
+    state
+        .connection_buckets
+        .retain(|_, bucket| !bucket.is_full());
+}
+
+/// Remove sessions whose code expired without a join and tell their hosts
+async fn expire_unjoined_sessions(state: &SharedState) {
+    let mut state = state.write().await;
+    let ttl = state.codes.ttl;
+    let metrics = Arc::clone(&state.metrics);
+    state.sessions.retain(|session_id, session| {
+        let expired = !session.joined && session.registered_at.elapsed() >= ttl;
+        if expired {
+            info!("Session {} expired before anyone joined", session_id);
+            metrics.session_ended(session.registered_at.elapsed());
+            let msg = SignalingMessage::Error {
+                message: "Session expired".to_string(),
+            };
+            if let (Some(host), Ok(text)) = (&session.host_tx, serde_json::to_string(&msg)) {
+                let _ = host.send(Message::Text(text));
+            }
+        }
+        !expired
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+
+    type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    async fn start_server() -> (String, SharedState) {
+        start_server_with(Limits::default(), CodePolicy::default()).await
+    }
+
+    async fn start_server_with(limits: Limits, codes: CodePolicy) -> (String, SharedState) {
+        start_server_on(ServerState::new(limits, codes)).await
+    }
+
+    async fn start_server_on(state: ServerState) -> (String, SharedState) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let state = Arc::new(RwLock::new(state));
+        let (_, shutdown) = Shutdown::new(Duration::from_secs(1));
+        tokio::spawn(serve(listener, Arc::clone(&state), shutdown));
+        (format!("ws://{}", addr), state)
+    }
+
+    /// Connect a host and a client to a fresh session
+    async fn open_session(url: &str, session_id: SessionId) -> (Client, Client) {
+        let (mut host, _) = connect_async(url).await.unwrap();
+        let (mut client, _) = connect_async(url).await.unwrap();
+
+        let register = SignalingMessage::Register {
+            session_id,
+            token: None,
+        };
+        assert!(matches!(
+            request(&mut host, &register).await,
+            SignalingMessage::Success { .. }
+        ));
+        let join = SignalingMessage::Join { session_id };
+        assert!(matches!(
+            request(&mut client, &join).await,
+            SignalingMessage::Success { .. }
+        ));
+        (host, client)
+    }
+
+    async fn request(client: &mut Client, msg: &SignalingMessage) -> SignalingMessage {
+        client
+            .send(Message::Text(serde_json::to_string(msg).unwrap()))
+            .await
+            .unwrap();
+        receive(client).await
+    }
+
+    async fn receive(client: &mut Client) -> SignalingMessage {
+        let msg = client.next().await.unwrap().unwrap();
+        serde_json::from_str(msg.to_text().unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_offer_forwarded_to_client() {
+        let (url, _) = start_server().await;
+        let session_id = SessionId::new();
+        let (mut host, mut client) = open_session(&url, session_id).await;
+
+        let offer = SignalingMessage::Offer {
+            session_id,
+            sdp: "v=0".to_string(),
+            viewer: None,
+        };
+        host.send(Message::Text(serde_json::to_string(&offer).unwrap()))
+            .await
+            .unwrap();
+        match receive(&mut client).await {
+            SignalingMessage::Offer { sdp, .. } => assert_eq!(sdp, "v=0"),
+            other => panic!("unexpected message: {:?}", other),
+        }
+
+        let answer = SignalingMessage::Answer {
+            session_id,
+            sdp: "answer".to_string(),
+            viewer: None,
+        };
+        client
+            .send(Message::Text(serde_json::to_string(&answer).unwrap()))
+            .await
+            .unwrap();
+        assert!(matches!(
+            receive(&mut host).await,
+            SignalingMessage::Answer { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_host_disconnect_ends_session() {
+        let (url, state) = start_server().await;
+        let session_id = SessionId::new();
+        let (host, mut client) = open_session(&url, session_id).await;
+
+        drop(host);
+        match receive(&mut client).await {
+            SignalingMessage::PeerDisconnected { session_id: id, .. } => {
+                assert_eq!(id, session_id)
+            }
+            other => panic!("unexpected message: {:?}", other),
+        }
+        assert!(state.read().await.sessions.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_registering_again_disconnects_viewers() {
+        let (url, state) = start_server().await;
+        let session_id = SessionId::new();
+        let (mut host, mut client) = open_session(&url, session_id).await;
+
+        let register = SignalingMessage::Register {
+            session_id,
+            token: None,
+        };
+        assert!(matches!(
+            request(&mut host, &register).await,
+            SignalingMessage::Success { .. }
+        ));
+        match receive(&mut client).await {
+            SignalingMessage::PeerDisconnected {
+                session_id: id,
+                viewer: None,
+            } => assert_eq!(id, session_id),
+            other => panic!("unexpected message: {:?}", other),
+        }
+        assert!(state.read().await.sessions[&session_id].viewers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_idle_sessions_evicted() {
+        let (url, state) = start_server().await;
+        let session_id = SessionId::new();
+        let _peers = open_session(&url, session_id).await;
+
+        evict_sessions_older_than(&state, Duration::from_secs(60)).await;
+        assert_eq!(state.read().await.sessions.len(), 1);
+        evict_sessions_older_than(&state, Duration::ZERO).await;
+        assert!(state.read().await.sessions.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_registration_beyond_cap_rejected() {
+        let limits = Limits {
+            max_sessions: 2,
+            ..Default::default()
+        };
+        let (url, state) = start_server_with(limits, CodePolicy::default()).await;
+        let (mut host, _) = connect_async(url.as_str()).await.unwrap();
+
+        for expected_ok in [true, true, false] {
+            let register = SignalingMessage::Register {
+                session_id: SessionId::new(),
+                token: None,
+            };
+            match request(&mut host, &register).await {
+                SignalingMessage::Success { .. } => assert!(expected_ok),
+                SignalingMessage::Error { message } => {
+                    assert!(!expected_ok);
+                    assert_eq!(message, "Server is at capacity");
+                }
+                other => panic!("unexpected message: {:?}", other),
+            }
+        }
+        assert_eq!(state.read().await.sessions.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_binary_flood_rate_limited() {
+        let limits = Limits {
+            messages_per_second: 3,
+            ..Default::default()
+        };
+        let (url, _) = start_server_with(limits, CodePolicy::default()).await;
+        let (mut client, _) = connect_async(url.as_str()).await.unwrap();
+
+        for _ in 0..4 {
+            client.send(Message::Binary(vec![0; 16])).await.unwrap();
+        }
+        match receive(&mut client).await {
+            SignalingMessage::Error { message } => assert_eq!(message, "Rate limit exceeded"),
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_join_after_expiry_rejected() {
+        let codes = CodePolicy {
+            ttl: Duration::ZERO,
+            ..Default::default()
+        };
+        let (url, state) = start_server_with(Limits::default(), codes).await;
+        let session_id = SessionId::new();
+        let (mut host, _) = connect_async(url.as_str()).await.unwrap();
+        let (mut client, _) = connect_async(url.as_str()).await.unwrap();
+
+        let register = SignalingMessage::Register {
+            session_id,
+            token: None,
+        };
+        assert!(matches!(
+            request(&mut host, &register).await,
+            SignalingMessage::Success { .. }
+        ));
+        let join = SignalingMessage::Join { session_id };
+        match request(&mut client, &join).await {
+            SignalingMessage::Error { message } => assert_eq!(message, "Session expired"),
+            other => panic!("unexpected message: {:?}", other),
+        }
+
+        expire_unjoined_sessions(&state).await;
+        match receive(&mut host).await {
+            SignalingMessage::Error { message } => assert_eq!(message, "Session expired"),
+            other => panic!("unexpected message: {:?}", other),
+        }
+        assert!(state.read().await.sessions.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_late_join_of_live_session_accepted() {
+        let (url, state) = start_server().await;
+        let session_id = SessionId::new();
+        let _peers = open_session(&url, session_id).await;
+
+        // The code has expired, but the session it opened is live
+        state.write().await.codes.ttl = Duration::ZERO;
+        let (mut second, _) = connect_async(url.as_str()).await.unwrap();
+        let join = SignalingMessage::Join { session_id };
+        assert!(matches!(
+            request(&mut second, &join).await,
+            SignalingMessage::Success { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_single_use_code_consumed_by_join() {
+        let codes = CodePolicy {
+            single_use: true,
+            ..Default::default()
+        };
+        let (url, _) = start_server_with(Limits::default(), codes).await;
+        let session_id = SessionId::new();
+        let _peers = open_session(&url, session_id).await;
+
+        let (mut second, _) = connect_async(url.as_str()).await.unwrap();
+        let join = SignalingMessage::Join { session_id };
+        match request(&mut second, &join).await {
+            SignalingMessage::Error { message } => assert_eq!(message, "Session code already used"),
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_displayed_code_joins_session() {
+        let (url, state) = start_server().await;
+        let session_id = SessionId::new();
+        let (mut host, _) = connect_async(url.as_str()).await.unwrap();
+        let (mut client, _) = connect_async(url.as_str()).await.unwrap();
+
+        let register = SignalingMessage::Register {
+            session_id,
+            token: None,
+        };
+        assert!(matches!(
+            request(&mut host, &register).await,
+            SignalingMessage::Success { .. }
+        ));
+
+        // Exactly what the host's UI shows, typed in by the user
+        let shown = session_id.to_string();
+        let join = format!(r#"{{"type":"join","session_id":"{}"}}"#, shown);
+        client.send(Message::Text(join)).await.unwrap();
+        assert!(matches!(
+            receive(&mut client).await,
+            SignalingMessage::Success { .. }
+        ));
+        let parsed = SessionId::from_string(&shown).unwrap();
+        assert!(state.read().await.sessions[&parsed].joined);
+    }
+
+    #[tokio::test]
+    async fn test_colliding_registration_rejected() {
+        let (url, _) = start_server().await;
+        let session_id = SessionId::new();
+        let (mut first, _) = connect_async(url.as_str()).await.unwrap();
+        let (mut second, _) = connect_async(url.as_str()).await.unwrap();
+
+        let register = SignalingMessage::Register {
+            session_id,
+            token: None,
+        };
+        assert!(matches!(
+            request(&mut first, &register).await,
+            SignalingMessage::Success { .. }
+        ));
+        match request(&mut second, &register).await {
+            SignalingMessage::Error { message } => assert_eq!(message, "Session ID already in use"),
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_two_viewers_receive_offer() {
+        let (url, state) = start_server().await;
+        let session_id = SessionId::new();
+        let (mut host, mut first) = open_session(&url, session_id).await;
+        let (mut second, _) = connect_async(url.as_str()).await.unwrap();
+        let join = SignalingMessage::Join { session_id };
+        match request(&mut second, &join).await {
+            SignalingMessage::Success { message } => assert!(message.ends_with("as viewer 2")),
+            other => panic!("unexpected message: {:?}", other),
+        }
+        assert_eq!(state.read().await.sessions[&session_id].viewers.len(), 2);
+
+        let offer = SignalingMessage::Offer {
+            session_id,
+            sdp: "v=0".to_string(),
+            viewer: None,
+        };
+        host.send(Message::Text(serde_json::to_string(&offer).unwrap()))
+            .await
+            .unwrap();
+        for viewer in [&mut first, &mut second] {
+            match receive(viewer).await {
+                SignalingMessage::Offer { sdp, .. } => assert_eq!(sdp, "v=0"),
+                other => panic!("unexpected message: {:?}", other),
+            }
+        }
+
+        // Answers reach the host tagged with the viewer, whatever it claims
+        let answer = SignalingMessage::Answer {
+            session_id,
+            sdp: "answer".to_string(),
+            viewer: Some(1),
+        };
+        second
+            .send(Message::Text(serde_json::to_string(&answer).unwrap()))
+            .await
+            .unwrap();
+        match receive(&mut host).await {
+            SignalingMessage::Answer { viewer, .. } => assert_eq!(viewer, Some(2)),
+            other => panic!("unexpected message: {:?}", other),
+        }
+
+        // A candidate for one viewer reaches only that one
+        let candidate = SignalingMessage::IceCandidate {
+            session_id,
+            candidate: "candidate".to_string(),
+            viewer: Some(2),
+        };
+        host.send(Message::Text(serde_json::to_string(&candidate).unwrap()))
+            .await
+            .unwrap();
+        assert!(matches!(
+            receive(&mut second).await,
+            SignalingMessage::IceCandidate { .. }
+        ));
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), first.next())
+                .await
+                .is_err()
+        );
+
+        drop(first);
+        match receive(&mut host).await {
+            SignalingMessage::PeerDisconnected { viewer, .. } => assert_eq!(viewer, Some(1)),
+            other => panic!("unexpected message: {:?}", other),
+        }
+        assert_eq!(state.read().await.sessions[&session_id].viewers.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_join_beyond_viewer_limit_rejected() {
+        let limits = Limits {
+            max_viewers: 1,
+            ..Default::default()
+        };
+        let (url, _) = start_server_with(limits, CodePolicy::default()).await;
+        let session_id = SessionId::new();
+        let _peers = open_session(&url, session_id).await;
+
+        let (mut second, _) = connect_async(url.as_str()).await.unwrap();
+        let join = SignalingMessage::Join { session_id };
+        match request(&mut second, &join).await {
+            SignalingMessage::Error { message } => assert_eq!(message, "Session is full"),
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+
+    async fn scrape(addr: SocketAddr, path: &str) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!("GET {} HTTP/1.1\r\nHost: relay\r\n\r\n", path);
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn test_metrics_count_registration() {
+        let (url, state) = start_server().await;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let metrics_addr = listener.local_addr().unwrap();
+        tokio::spawn(metrics::serve(listener, Arc::clone(&state)));
+
+        let (mut host, _) = connect_async(url.as_str()).await.unwrap();
+        let register = SignalingMessage::Register {
+            session_id: SessionId::new(),
+            token: None,
+        };
+        assert!(matches!(
+            request(&mut host, &register).await,
+            SignalingMessage::Success { .. }
+        ));
+        let (mut client, _) = connect_async(url.as_str()).await.unwrap();
+        let join = SignalingMessage::Join {
+            session_id: SessionId::new(),
+        };
+        assert!(matches!(
+            request(&mut client, &join).await,
+            SignalingMessage::Error { .. }
+        ));
+
+        let response = scrape(metrics_addr, "/metrics").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("\nada_relay_active_sessions 1\n"));
+        assert!(response.contains("\nada_relay_registrations_total 1\n"));
+        assert!(response.contains("\nada_relay_join_failures_total 1\n"));
+        assert!(response.contains("\nada_relay_connections_total 2\n"));
+        assert!(response.contains("ada_relay_session_duration_seconds_count 0\n"));
+
+        let response = scrape(metrics_addr, "/").await;
+        assert!(response.starts_with("HTTP/1.1 404 Not Found"));
+    }
+
+    const SECRET: &str = "relay secret";
+
+    async fn start_authenticated_server() -> String {
+        let mut state = ServerState::new(Limits::default(), CodePolicy::default());
+        state.registration = Some(RegistrationAuth {
+            secret: SECRET.to_string(),
+            max_age: Duration::from_secs(300),
+        });
+        start_server_on(state).await.0
+    }
+
+    /// Register with `token`, returning the error if the server refused
+    async fn register_with(url: &str, token: Option<String>) -> Option<String> {
+        let (mut host, _) = connect_async(url).await.unwrap();
+        let register = SignalingMessage::Register {
+            session_id: SessionId::new(),
+            token,
+        };
+        match request(&mut host, &register).await {
+            SignalingMessage::Success { .. } => None,
+            SignalingMessage::Error { message } => {
+                // Refused hosts are disconnected
+                assert!(host.next().await.is_none_or(|msg| msg.unwrap().is_close()));
+                Some(message)
+            }
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_signed_registration_accepted() {
+        let url = start_authenticated_server().await;
+        let (mut host, _) = connect_async(url.as_str()).await.unwrap();
+        let session_id = SessionId::new();
+        let register = SignalingMessage::Register {
+            session_id,
+            token: Some(ada_remote_crypto::current_registration_token(
+                SECRET.as_bytes(),
+                &session_id,
+            )),
+        };
+        assert!(matches!(
+            request(&mut host, &register).await,
+            SignalingMessage::Success { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_registration_token_rejected() {
+        let url = start_authenticated_server().await;
+
+        let missing = register_with(&url, None).await.unwrap();
+        assert!(missing.contains("Registration token required"));
+
+        // Signed for another session, and with the wrong secret
+        let stolen =
+            ada_remote_crypto::current_registration_token(SECRET.as_bytes(), &SessionId::new());
+        let forged = ada_remote_crypto::current_registration_token(b"guess", &SessionId::new());
+        for token in [stolen, forged, "garbage".to_string()] {
+            let message = register_with(&url, Some(token)).await.unwrap();
+            assert!(message.contains("Invalid registration token"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stale_registration_token_rejected() {
+        let url = start_authenticated_server().await;
+        let (mut host, _) = connect_async(url.as_str()).await.unwrap();
+        let session_id = SessionId::new();
+        let issued = ada_remote_crypto::unix_now() - 301;
+        let register = SignalingMessage::Register {
+            session_id,
+            token: Some(ada_remote_crypto::registration_token(
+                SECRET.as_bytes(),
+                &session_id,
+                issued,
+            )),
+        };
+        match request(&mut host, &register).await {
+            SignalingMessage::Error { message } => {
+                assert!(message.contains("Registration token expired"))
+            }
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_notifies_clients_and_stops_accepting() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let state = ServerState::new(Limits::default(), CodePolicy::default());
+        let (stop, shutdown) = Shutdown::new(Duration::from_secs(1));
+        let server = tokio::spawn(serve(listener, Arc::new(RwLock::new(state)), shutdown));
+
+        let session_id = SessionId::new();
+        let (mut host, mut client) = open_session(&url, session_id).await;
+        stop.send(true).unwrap();
+        for peer in [&mut host, &mut client] {
+            match receive(peer).await {
+                SignalingMessage::ShuttingDown { grace_seconds } => assert_eq!(grace_seconds, 1),
+                other => panic!("unexpected message: {:?}", other),
+            }
+        }
+
+        // Messages are still forwarded while connections drain
+        let offer = SignalingMessage::Offer {
+            session_id,
+            sdp: "v=0".to_string(),
+            viewer: None,
+        };
+        host.send(Message::Text(serde_json::to_string(&offer).unwrap()))
+            .await
+            .unwrap();
+        assert!(matches!(
+            receive(&mut client).await,
+            SignalingMessage::Offer { .. }
+        ));
+        assert!(connect_async(url.as_str()).await.is_err());
+
+        // Connections still open after the grace period are closed
+        tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .unwrap()
+            .unwrap();
+        // The client may be gone first, leaving the host a peer_disconnected
+        let closed = async {
+            while let Some(Ok(msg)) = host.next().await {
+                if msg.is_close() {
+                    break;
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(1), closed)
+            .await
+            .unwrap();
     }
 }
@@ -0,0 +1,271 @@
+//! Prometheus metrics
+//!
+//! Counters and histograms are plain atomics updated where things happen;
+//! `/metrics` on the metrics listener renders them in the Prometheus text
+//! format together with gauges read from the server state.
+
+use crate::SharedState;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, info};
+
+/// Upper bounds of the duration buckets, in seconds
+const DURATION_BUCKETS: &[f64] = &[1.0, 10.0, 60.0, 300.0, 900.0, 3600.0, 14_400.0];
+
+/// Upper bounds of the message size buckets, in bytes
+const SIZE_BUCKETS: &[f64] = &[128.0, 512.0, 1024.0, 4096.0, 16_384.0, 65_536.0];
+
+/// Longest request head the endpoint reads
+const MAX_REQUEST: usize = 8 * 1024;
+
+/// Distribution of observed values over fixed buckets
+pub struct Histogram {
+    bounds: &'static [f64],
+    /// Per bucket, the last one for values above every bound
+    counts: Vec<AtomicU64>,
+    /// `f64` bits
+    sum: AtomicU64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            counts: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum: AtomicU64::new(0f64.to_bits()),
+        }
+    }
+
+    pub fn observe(&self, value: f64) {
+        let bucket = self
+            .bounds
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(self.bounds.len());
+        self.counts[bucket].fetch_add(1, Ordering::Relaxed);
+        let _ = self
+            .sum
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |sum| {
+                Some((f64::from_bits(sum) + value).to_bits())
+            });
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        let mut cumulative = 0;
+        for (i, count) in self.counts.iter().enumerate() {
+            cumulative += count.load(Ordering::Relaxed);
+            match self.bounds.get(i) {
+                Some(bound) => {
+                    let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative);
+                }
+                None => {
+                    let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, cumulative);
+                }
+            }
+        }
+        let sum = f64::from_bits(self.sum.load(Ordering::Relaxed));
+        let _ = writeln!(out, "{}_sum {}", name, sum);
+        let _ = writeln!(out, "{}_count {}", name, cumulative);
+    }
+}
+
+/// Relay-wide counters
+pub struct Metrics {
+    registrations: AtomicU64,
+    join_failures: AtomicU64,
+    connections: AtomicU64,
+    messages_forwarded: AtomicU64,
+    bytes_relayed: AtomicU64,
+    connection_duration: Histogram,
+    session_duration: Histogram,
+    message_size: Histogram,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            registrations: AtomicU64::new(0),
+            join_failures: AtomicU64::new(0),
+            connections: AtomicU64::new(0),
+            messages_forwarded: AtomicU64::new(0),
+            bytes_relayed: AtomicU64::new(0),
+            connection_duration: Histogram::new(DURATION_BUCKETS),
+            session_duration: Histogram::new(DURATION_BUCKETS),
+            message_size: Histogram::new(SIZE_BUCKETS),
+        }
+    }
+}
+
+impl Metrics {
+    pub fn session_registered(&self) {
+        self.registrations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn join_failed(&self) {
+        self.join_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn session_ended(&self, lifetime: Duration) {
+        self.session_duration.observe(lifetime.as_secs_f64());
+    }
+
+    pub fn connection_opened(&self) {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn connection_closed(&self, lifetime: Duration) {
+        self.connection_duration.observe(lifetime.as_secs_f64());
+    }
+
+    /// A signaling message of `size` bytes forwarded to `recipients` peers
+    pub fn message_forwarded(&self, size: usize, recipients: usize) {
+        self.messages_forwarded
+            .fetch_add(recipients as u64, Ordering::Relaxed);
+        self.message_size.observe(size as f64);
+        self.relayed((size * recipients) as u64);
+    }
+
+    /// Bytes passed between peers, by signaling or TURN
+    pub fn relayed(&self, bytes: u64) {
+        self.bytes_relayed.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Prometheus text exposition
+    pub fn render(&self, active_sessions: usize) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: u64| {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            let _ = writeln!(out, "{} {}", name, value);
+        };
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+
+        metric(
+            "ada_relay_active_sessions",
+            "gauge",
+            "Registered sessions",
+            active_sessions as u64,
+        );
+        metric(
+            "ada_relay_registrations_total",
+            "counter",
+            "Sessions registered",
+            load(&self.registrations),
+        );
+        metric(
+            "ada_relay_join_failures_total",
+            "counter",
+            "Rejected joins",
+            load(&self.join_failures),
+        );
+        metric(
+            "ada_relay_connections_total",
+            "counter",
+            "Signaling connections accepted",
+            load(&self.connections),
+        );
+        metric(
+            "ada_relay_messages_forwarded_total",
+            "counter",
+            "Signaling messages delivered to peers",
+            load(&self.messages_forwarded),
+        );
+        metric(
+            "ada_relay_bytes_relayed_total",
+            "counter",
+            "Bytes passed between peers by signaling and TURN",
+            load(&self.bytes_relayed),
+        );
+        self.connection_duration.render(
+            &mut out,
+            "ada_relay_connection_duration_seconds",
+            "Lifetime of signaling connections",
+        );
+        self.session_duration.render(
+            &mut out,
+            "ada_relay_session_duration_seconds",
+            "Lifetime of sessions from registration",
+        );
+        self.message_size.render(
+            &mut out,
+            "ada_relay_message_size_bytes",
+            "Size of forwarded signaling messages",
+        );
+        out
+    }
+}
+
+/// Answer `GET /metrics` on `listener` until it fails; other requests get
+/// a 404
+pub async fn serve(listener: TcpListener, state: SharedState) {
+    if let Ok(addr) = listener.local_addr() {
+        info!("Metrics listening on http://{}/metrics", addr);
+    }
+    while let Ok((stream, addr)) = listener.accept().await {
+        let state = state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = respond(stream, &state).await {
+                debug!("Metrics request from {} failed: {}", addr, e);
+            }
+        });
+    }
+}
+
+async fn respond(mut stream: TcpStream, state: &SharedState) -> std::io::Result<()> {
+    let mut head = Vec::new();
+    let mut buf = [0; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") && head.len() < MAX_REQUEST {
+        let len = stream.read(&mut buf).await?;
+        if len == 0 {
+            break;
+        }
+        head.extend_from_slice(&buf[..len]);
+    }
+
+    let request_line = head.split(|&b| b == b'\r').next().unwrap_or_default();
+    let mut parts = request_line.split(|&b| b == b' ');
+    let response = match (parts.next(), parts.next()) {
+        (Some(b"GET"), Some(b"/metrics")) => {
+            let body = {
+                let state = state.read().await;
+                state.metrics.render(state.sessions.len())
+            };
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+        }
+        _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+    };
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_buckets_are_cumulative() {
+        let metrics = Metrics::default();
+        metrics.message_forwarded(100, 2);
+        metrics.message_forwarded(2000, 1);
+        metrics.message_forwarded(100_000, 1);
+
+        let text = metrics.render(3);
+        assert!(text.contains("ada_relay_active_sessions 3\n"));
+        assert!(text.contains("ada_relay_messages_forwarded_total 4\n"));
+        assert!(text.contains("ada_relay_bytes_relayed_total 102200\n"));
+        assert!(text.contains("ada_relay_message_size_bytes_bucket{le=\"128\"} 1\n"));
+        assert!(text.contains("ada_relay_message_size_bytes_bucket{le=\"4096\"} 2\n"));
+        assert!(text.contains("ada_relay_message_size_bytes_bucket{le=\"+Inf\"} 3\n"));
+        assert!(text.contains("ada_relay_message_size_bytes_sum 102100\n"));
+        assert!(text.contains("ada_relay_message_size_bytes_count 3\n"));
+    }
+}
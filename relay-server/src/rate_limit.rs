@@ -0,0 +1,83 @@
+//! Token bucket rate limiting
+//!
+//! Buckets start full, so a client may burst up to the capacity before it is
+//! held to the refill rate.
+
+use std::time::Instant;
+
+/// Token bucket refilled continuously at a fixed rate
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    refill_per_sec: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// Create a full bucket holding `capacity` tokens
+    pub fn new(capacity: u32, refill_per_sec: f64) -> Self {
+        Self {
+            capacity: capacity as f64,
+            tokens: capacity as f64,
+            refill_per_sec,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Take a token, returning false if the bucket is empty
+    pub fn try_take(&mut self) -> bool {
+        self.try_take_at(Instant::now())
+    }
+
+    /// Take `count` tokens at once, or none if there are fewer
+    pub fn try_take_n(&mut self, count: u32) -> bool {
+        self.try_take_n_at(count, Instant::now())
+    }
+
+    fn try_take_at(&mut self, now: Instant) -> bool {
+        self.try_take_n_at(1, now)
+    }
+
+    fn try_take_n_at(&mut self, count: u32, now: Instant) -> bool {
+        self.refill(now);
+        if self.tokens >= count as f64 {
+            self.tokens -= count as f64;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Whether the bucket has refilled completely, i.e. it is idle
+    pub fn is_full(&mut self) -> bool {
+        self.refill(Instant::now());
+        self.tokens >= self.capacity
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        self.last_refill = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_bucket_refills_over_time() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(2, 1.0);
+
+        assert!(bucket.try_take_at(start));
+        assert!(bucket.try_take_at(start));
+        assert!(!bucket.try_take_at(start));
+        assert!(!bucket.try_take_at(start + Duration::from_millis(500)));
+        assert!(bucket.try_take_at(start + Duration::from_millis(1000)));
+    }
+}
@@ -0,0 +1,382 @@
+//! TURN relay for peers that cannot reach each other directly
+//!
+//! Speaks the RFC 5766 essentials (Allocate, Refresh, CreatePermission,
+//! ChannelBind, Send and Data) over UDP, authenticated with long-term
+//! credentials: the `username`/`credential` pairs clients carry in their
+//! `TurnServer` config. Each peer of a session allocates its own relay port
+//! from the configured range, and datagrams beyond an allocation's byte rate
+//! are dropped.
+
+use crate::metrics::Metrics;
+use crate::rate_limit::TokenBucket;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::ops::RangeInclusive;
+use std::sync::{Arc, Mutex};
+use tokio::net::UdpSocket;
+use tracing::{debug, info, warn};
+use turn::auth::{generate_auth_key, AuthHandler};
+use turn::relay::RelayAddressGenerator;
+use turn::server::config::{ConnConfig, ServerConfig};
+use turn::server::Server;
+use webrtc_util::Conn;
+
+/// TURN relay settings
+#[derive(Debug, Clone)]
+pub struct TurnConfig {
+    /// Address clients reach relay ports at
+    pub public_ip: IpAddr,
+    pub realm: String,
+    /// Accepted `(username, password)` pairs
+    pub users: Vec<(String, String)>,
+    /// Ports relay addresses are allocated from
+    pub ports: RangeInclusive<u16>,
+    /// Bytes per second one allocation may relay, both directions together;
+    /// 0 for no cap
+    pub bandwidth: u32,
+}
+
+/// Parse a `username:password` command-line value
+pub fn parse_user(value: &str) -> Result<(String, String), String> {
+    match value.split_once(':') {
+        Some((username, password)) if !username.is_empty() && !password.is_empty() => {
+            Ok((username.to_string(), password.to_string()))
+        }
+        _ => Err(format!("Expected username:password, got '{}'", value)),
+    }
+}
+
+/// Serve TURN on `socket` until the returned server is closed, counting
+/// relayed bytes in `metrics`
+pub async fn start(
+    socket: UdpSocket,
+    config: TurnConfig,
+    metrics: Arc<Metrics>,
+) -> anyhow::Result<Server> {
+    info!(
+        "TURN relay listening on {}, relay ports {}-{}",
+        socket.local_addr()?,
+        config.ports.start(),
+        config.ports.end()
+    );
+    let keys = config
+        .users
+        .iter()
+        .map(|(username, password)| {
+            let key = generate_auth_key(username, &config.realm, password);
+            (username.clone(), key)
+        })
+        .collect();
+
+    let server = Server::new(ServerConfig {
+        conn_configs: vec![ConnConfig {
+            conn: Arc::new(socket),
+            relay_addr_generator: Box::new(PortRange {
+                public_ip: config.public_ip,
+                next: Mutex::new(*config.ports.start()),
+                ports: config.ports,
+                bandwidth: config.bandwidth,
+                metrics,
+            }),
+        }],
+        realm: config.realm,
+        auth_handler: Arc::new(StaticUsers { keys }),
+        // The library default of 10 minutes
+        channel_bind_timeout: std::time::Duration::ZERO,
+        alloc_close_notify: None,
+    })
+    .await?;
+    Ok(server)
+}
+
+/// Fixed set of users
+struct StaticUsers {
+    keys: HashMap<String, Vec<u8>>,
+}
+
+impl AuthHandler for StaticUsers {
+    fn auth_handle(
+        &self,
+        username: &str,
+        _realm: &str,
+        src_addr: SocketAddr,
+    ) -> Result<Vec<u8>, turn::Error> {
+        self.keys.get(username).cloned().ok_or_else(|| {
+            warn!(
+                "TURN request from {} for unknown user {}",
+                src_addr, username
+            );
+            turn::Error::Other(format!("Unknown user {}", username))
+        })
+    }
+}
+
+/// Allocates relay sockets from a port range, each with its own byte rate
+struct PortRange {
+    public_ip: IpAddr,
+    ports: RangeInclusive<u16>,
+    /// Where the search for a free port starts, so ports are reused last
+    next: Mutex<u16>,
+    bandwidth: u32,
+    metrics: Arc<Metrics>,
+}
+
+impl PortRange {
+    fn throttled(
+        &self,
+        socket: UdpSocket,
+    ) -> Result<(Arc<dyn Conn + Send + Sync>, SocketAddr), turn::Error> {
+        let mut relay_addr = socket.local_addr()?;
+        relay_addr.set_ip(self.public_ip);
+        debug!("Allocated relay address {}", relay_addr);
+        let bucket = (self.bandwidth > 0)
+            .then(|| Mutex::new(TokenBucket::new(self.bandwidth, self.bandwidth as f64)));
+        let conn = Throttled {
+            socket,
+            bucket,
+            metrics: Arc::clone(&self.metrics),
+        };
+        Ok((Arc::new(conn), relay_addr))
+    }
+}
+
+#[async_trait]
+impl RelayAddressGenerator for PortRange {
+    fn validate(&self) -> Result<(), turn::Error> {
+        if self.ports.is_empty() || *self.ports.start() == 0 {
+            return Err(turn::Error::Other("Empty relay port range".to_string()));
+        }
+        Ok(())
+    }
+
+    async fn allocate_conn(
+        &self,
+        use_ipv4: bool,
+        requested_port: u16,
+    ) -> Result<(Arc<dyn Conn + Send + Sync>, SocketAddr), turn::Error> {
+        let ip = if use_ipv4 {
+            IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+        } else {
+            IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+        };
+        if requested_port != 0 {
+            if !self.ports.contains(&requested_port) {
+                return Err(turn::Error::Other(format!(
+                    "Port {} is outside the relay range",
+                    requested_port
+                )));
+            }
+            let socket = UdpSocket::bind(SocketAddr::new(ip, requested_port)).await?;
+            return self.throttled(socket);
+        }
+
+        let (first, last) = (*self.ports.start(), *self.ports.end());
+        let count = last - first + 1;
+        let start = *self.next.lock().unwrap();
+        for offset in 0..count {
+            let port = first + (start - first + offset) % count;
+            if let Ok(socket) = UdpSocket::bind(SocketAddr::new(ip, port)).await {
+                *self.next.lock().unwrap() = if port == last { first } else { port + 1 };
+                return self.throttled(socket);
+            }
+        }
+        warn!("No free relay port in {}-{}", first, last);
+        Err(turn::Error::ErrMaxRetriesExceeded)
+    }
+}
+
+/// Relay socket of one allocation, dropping datagrams over its byte rate
+struct Throttled {
+    socket: UdpSocket,
+    bucket: Option<Mutex<TokenBucket>>,
+    metrics: Arc<Metrics>,
+}
+
+impl Throttled {
+    fn allow(&self, len: usize) -> bool {
+        let allowed = match &self.bucket {
+            Some(bucket) => bucket.lock().unwrap().try_take_n(len as u32),
+            None => true,
+        };
+        if allowed {
+            self.metrics.relayed(len as u64);
+        }
+        allowed
+    }
+}
+
+#[async_trait]
+impl Conn for Throttled {
+    async fn connect(&self, addr: SocketAddr) -> webrtc_util::Result<()> {
+        Ok(self.socket.connect(addr).await?)
+    }
+
+    async fn recv(&self, buf: &mut [u8]) -> webrtc_util::Result<usize> {
+        Ok(self.socket.recv(buf).await?)
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> webrtc_util::Result<(usize, SocketAddr)> {
+        loop {
+            let (len, from) = self.socket.recv_from(buf).await?;
+            if self.allow(len) {
+                return Ok((len, from));
+            }
+            debug!(
+                "Relay bandwidth exceeded, dropped {} bytes from {}",
+                len, from
+            );
+        }
+    }
+
+    async fn send(&self, buf: &[u8]) -> webrtc_util::Result<usize> {
+        Ok(self.socket.send(buf).await?)
+    }
+
+    async fn send_to(&self, buf: &[u8], target: SocketAddr) -> webrtc_util::Result<usize> {
+        if !self.allow(buf.len()) {
+            debug!(
+                "Relay bandwidth exceeded, dropped {} bytes to {}",
+                buf.len(),
+                target
+            );
+            // Lost like any other datagram
+            return Ok(buf.len());
+        }
+        Ok(self.socket.send_to(buf, target).await?)
+    }
+
+    fn local_addr(&self) -> webrtc_util::Result<SocketAddr> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    fn remote_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+
+    async fn close(&self) -> webrtc_util::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use turn::client::{Client, ClientConfig};
+
+    async fn start_relay(ports: RangeInclusive<u16>, bandwidth: u32) -> (Server, String) {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap().to_string();
+        let config = TurnConfig {
+            public_ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            realm: "ada-remote".to_string(),
+            users: vec![("peer".to_string(), "secret".to_string())],
+            ports,
+            bandwidth,
+        };
+        let server = start(socket, config, Arc::default()).await.unwrap();
+        (server, addr)
+    }
+
+    async fn client(server: &str, password: &str) -> Client {
+        let client = Client::new(ClientConfig {
+            stun_serv_addr: server.to_string(),
+            turn_serv_addr: server.to_string(),
+            username: "peer".to_string(),
+            password: password.to_string(),
+            realm: "ada-remote".to_string(),
+            software: String::new(),
+            rto_in_ms: 0,
+            conn: Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap()),
+            vnet: None,
+        })
+        .await
+        .unwrap();
+        client.listen().await.unwrap();
+        client
+    }
+
+    async fn receive(socket: &UdpSocket) -> (Vec<u8>, SocketAddr) {
+        let mut buf = [0; 1500];
+        let (len, from) = tokio::time::timeout(Duration::from_secs(2), socket.recv_from(&mut buf))
+            .await
+            .expect("no datagram")
+            .unwrap();
+        (buf[..len].to_vec(), from)
+    }
+
+    #[test]
+    fn test_parse_user() {
+        assert_eq!(
+            parse_user("alice:pa:ss"),
+            Ok(("alice".to_string(), "pa:ss".to_string()))
+        );
+        assert!(parse_user("alice").is_err());
+        assert!(parse_user(":secret").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_peers_exchange_packets_through_allocation() {
+        let (server, addr) = start_relay(40_000..=40_099, 0).await;
+        let client = client(&addr, "secret").await;
+        let relay = client.allocate().await.unwrap();
+        let relay_addr = relay.local_addr().unwrap();
+        assert_eq!(relay_addr.ip(), IpAddr::V4(Ipv4Addr::LOCALHOST));
+        assert!((40_000..=40_099).contains(&relay_addr.port()));
+
+        let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let peer_addr = peer.local_addr().unwrap();
+
+        // Sending creates the permission that lets the peer's replies in
+        relay.send_to(b"hello", peer_addr).await.unwrap();
+        assert_eq!(receive(&peer).await, (b"hello".to_vec(), relay_addr));
+
+        peer.send_to(b"hi back", relay_addr).await.unwrap();
+        let mut buf = [0; 1500];
+        let (len, from) = tokio::time::timeout(Duration::from_secs(2), relay.recv_from(&mut buf))
+            .await
+            .expect("no relayed datagram")
+            .unwrap();
+        assert_eq!((&buf[..len], from), (&b"hi back"[..], peer_addr));
+
+        client.close().await.unwrap();
+        server.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_wrong_password_refused() {
+        let (server, addr) = start_relay(40_100..=40_199, 0).await;
+        let client = client(&addr, "guess").await;
+        assert!(client.allocate().await.is_err());
+        client.close().await.unwrap();
+        server.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_allocation_bandwidth_capped() {
+        let (server, addr) = start_relay(40_200..=40_299, 4_000).await;
+        let client = client(&addr, "secret").await;
+        let relay = client.allocate().await.unwrap();
+        let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+        // 10 KB at once against 4 KB per second: the rest is dropped
+        for i in 0..10u8 {
+            relay
+                .send_to(&[i; 1_000], peer.local_addr().unwrap())
+                .await
+                .unwrap();
+        }
+        let mut received = Vec::new();
+        let mut buf = [0; 1500];
+        while let Ok(Ok((_, _))) =
+            tokio::time::timeout(Duration::from_millis(200), peer.recv_from(&mut buf)).await
+        {
+            received.push(buf[0]);
+        }
+        assert_eq!(received, vec![0, 1, 2, 3]);
+
+        client.close().await.unwrap();
+        server.close().await.unwrap();
+    }
+}